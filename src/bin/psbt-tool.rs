@@ -0,0 +1,19 @@
+//! Decodes, combines, finalizes, extracts and converts psbts from the
+//! command line, see [`rust_sob_poc::poc::cli`].
+
+use std::io;
+use std::process::ExitCode;
+
+use rust_sob_poc::poc::cli;
+
+fn main() -> ExitCode {
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    match cli::run(std::env::args().skip(1), &mut stdin, &mut stdout) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("psbt-tool: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,37 +1,51 @@
+use crate::poc::poc::PartiallySignedTransactionInner;
+use crate::poc::validation::ValidationReport;
+use crate::prelude::*;
+
 /// A Partially Signed Transaction.
+///
+/// The fields are only mutable through the methods of a
+/// `PartiallySignedTransaction<PsbtUnchecked>`. A consuming
+/// [`PartiallySignedTransaction::validate`] turns it into a
+/// `PartiallySignedTransaction<PsbtChecked>`, whose methods can rely on the
+/// psbt being valid since it can't be changed any more.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "serde", serde(crate = "actual_serde"))]
-pub struct PartiallySignedTransaction<V = PsbtUnchecked>
+pub struct PartiallySignedTransaction<V = PsbtUnchecked, R = Creator>
 where
     V: PsbtValidation,
+    R: PsbtRole,
 {
     /// The unsigned transaction, scriptSigs and witnesses for each input must be empty.
-    pub unsigned_tx: Option<Transaction>,
+    unsigned_tx: Option<Transaction>,
     /// The version number of this PSBT. If omitted, the version number is 0.
     /// See https://github.com/rust-bitcoin/rust-bitcoin/pull/1218
-    pub version: Version,
+    version: Version,
     /// A global map from extended public keys to the used key fingerprint and
     /// derivation path as defined by BIP 32.
-    pub xpub: BTreeMap<ExtendedPubKey, KeySource>,
+    xpub: BTreeMap<ExtendedPubKey, KeySource>,
     /// Global proprietary key-value pairs.
     #[cfg_attr(
         feature = "serde",
         serde(with = "crate::serde_utils::btreemap_as_seq_byte_values")
     )]
-    pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
+    proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
     /// Unknown global key-value pairs.
     #[cfg_attr(
         feature = "serde",
         serde(with = "crate::serde_utils::btreemap_as_seq_byte_values")
     )]
-    pub unknown: BTreeMap<raw::Key, Vec<u8>>,
+    unknown: BTreeMap<raw::Key, Vec<u8>>,
 
     /// The corresponding key-value map for each input in the unsigned transaction.
-    pub inputs: Vec<Input>,
+    inputs: Vec<Input>,
     /// The corresponding key-value map for each output in the unsigned transaction.
-    pub outputs: Vec<Output>,
+    outputs: Vec<Output>,
     // More Optional psbtv2 fields go here
+    /// The validation state and the role allowed to act on the psbt.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    state: PhantomData<(V, R)>,
 }
 
 pub trait PsbtValidation {
@@ -41,8 +55,12 @@ pub trait PsbtValidation {
 pub enum PsbtChecked {}
 pub enum PsbtUnchecked {}
 
-impl PsbtValidation for PsbtChecked {}
-impl PsbtValidation for PsbtUnchecked {}
+impl PsbtValidation for PsbtChecked {
+    const IS_VALIDATED: bool = true;
+}
+impl PsbtValidation for PsbtUnchecked {
+    const IS_VALIDATED: bool = false;
+}
 
 // pub struct Psbt<V = PsbtChecked>
 // where
@@ -51,36 +69,273 @@ impl PsbtValidation for PsbtUnchecked {}
 //     inner: PartiallySignedTransactionInner,
 // }
 
-impl<PsbtValidation> PartiallySignedTransaction<PsbtValidation> {
-    pub fn validate(&self) -> Result<PartiallySignedTransaction<PsbtChecked>, String> {
-        match validate_psbt_inner(psbt) {
-            Ok(()) => Ok(Psbt { inner: psbt }),
-            Err(err) => Err(err),
+impl<V: PsbtValidation, R: PsbtRole> PartiallySignedTransaction<V, R> {
+    pub fn unsigned_tx(&self) -> Option<&Transaction> {
+        self.unsigned_tx.as_ref()
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn xpub(&self) -> &BTreeMap<ExtendedPubKey, KeySource> {
+        &self.xpub
+    }
+
+    pub fn proprietary(&self) -> &BTreeMap<raw::ProprietaryKey, Vec<u8>> {
+        &self.proprietary
+    }
+
+    pub fn unknown(&self) -> &BTreeMap<raw::Key, Vec<u8>> {
+        &self.unknown
+    }
+
+    pub fn inputs(&self) -> &[Input] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[Output] {
+        &self.outputs
+    }
+}
+
+impl<R: PsbtRole> PartiallySignedTransaction<PsbtUnchecked, R> {
+    /// Validates the psbt with [`PartiallySignedTransactionInner::validate`],
+    /// after which it can't be modified any more.
+    ///
+    /// This PoC doesn't model the PsbtV2 global fields, so version 2 psbts
+    /// are reported as missing them.
+    pub fn validate(self) -> Result<PartiallySignedTransaction<PsbtChecked, R>, ValidationReport> {
+        let inner = self.into_inner();
+        let report = inner.validate();
+        if !report.is_valid() {
+            return Err(report);
+        }
+        Ok(PartiallySignedTransaction::from_inner(inner))
+    }
+
+    pub fn set_unsigned_tx(&mut self, unsigned_tx: Option<Transaction>) {
+        self.unsigned_tx = unsigned_tx;
+    }
+
+    pub fn set_version(&mut self, version: Version) {
+        self.version = version;
+    }
+
+    pub fn xpub_mut(&mut self) -> &mut BTreeMap<ExtendedPubKey, KeySource> {
+        &mut self.xpub
+    }
+
+    pub fn proprietary_mut(&mut self) -> &mut BTreeMap<raw::ProprietaryKey, Vec<u8>> {
+        &mut self.proprietary
+    }
+
+    pub fn unknown_mut(&mut self) -> &mut BTreeMap<raw::Key, Vec<u8>> {
+        &mut self.unknown
+    }
+
+    pub fn inputs_mut(&mut self) -> &mut [Input] {
+        &mut self.inputs
+    }
+
+    pub fn outputs_mut(&mut self) -> &mut [Output] {
+        &mut self.outputs
+    }
+}
+
+impl<R: PsbtRole> PartiallySignedTransaction<PsbtChecked, R> {
+    // Methods only available for the checked Psbt
+
+    /// Gives up the validation to modify the psbt again.
+    pub fn into_unchecked(self) -> PartiallySignedTransaction<PsbtUnchecked, R> {
+        self.into_state()
+    }
+
+    /// Calculates the fee paid by the transaction.
+    ///
+    /// Only version 0 psbts, which carry their unsigned tx, are supported.
+    pub fn fee(&self) -> Result<Amount, String> {
+        let tx = self.checked_unsigned_tx()?;
+        let mut input_value = Amount::ZERO;
+        for (index, (txin, input)) in tx.input.iter().zip(&self.inputs).enumerate() {
+            let utxo = input
+                .spent_utxo(&txin.previous_output)
+                .ok_or_else(|| format!("Missing utxo of input {}", index))?;
+            input_value = checked_add(input_value, utxo.value)?;
         }
+        let output_value = tx
+            .output
+            .iter()
+            .try_fold(Amount::ZERO, |total, txout| checked_add(total, txout.value))?;
+        input_value
+            .checked_sub(output_value)
+            .ok_or_else(|| String::from("Outputs spend more than the inputs provide"))
     }
 
-    fn validate_psbt_inner(psbt: &PartiallySignedTransactionInner) -> Result<(), String> {
-        match psbt.version {
-            Version::PsbtV0 => {
-                // Some code to validate Psbt as a version 0 Psbt
-                // let valid = validate(psbt);
-                if !valid {
-                    Err(String::from("Error parsing psbtv0"))
-                }
-            }
-            Version::Psbtv2 => {
-                // Some code to validate Psbt as a version 2 Psbt
-                // let valid = validate(psbt);
-                if !valid {
-                    Err(String::from("Error parsing psbtv2"))
-                }
-            }
+    /// Returns the unsigned tx of a version 0 psbt. Version 2 psbts keep
+    /// their transaction fields in the maps, which this PoC doesn't model.
+    fn checked_unsigned_tx(&self) -> Result<&Transaction, String> {
+        match self.version {
+            Version::PsbtV0 => self
+                .unsigned_tx
+                .as_ref()
+                .ok_or_else(|| String::from("Missing unsigned transaction")),
+            Version::PsbtV2 => Err(String::from("Version 2 psbts are not supported")),
         }
+    }
+}
+
+/// Adds two amounts of the psbt, failing if they exceed the maximum amount
+/// of money.
+fn checked_add(a: Amount, b: Amount) -> Result<Amount, String> {
+    a.checked_add(b)
+        .filter(|total| *total <= Amount::MAX_MONEY)
+        .ok_or_else(|| String::from("Amounts exceed the maximum amount of money"))
+}
+
+/// The BIP 174 role allowed to act on a psbt. Each role only exposes the
+/// methods that role may call, so e.g. signing a psbt that is still missing
+/// inputs, or extracting one that isn't finalized, doesn't compile.
+pub trait PsbtRole {}
+
+/// Creates the psbt.
+pub enum Creator {}
+/// Adds inputs, outputs and the data needed to sign them.
+pub enum Updater {}
+/// Adds signatures.
+pub enum Signer {}
+/// Builds the final scriptSigs and witnesses.
+pub enum Finalizer {}
+/// Extracts the final transaction.
+pub enum Extractor {}
+
+impl PsbtRole for Creator {}
+impl PsbtRole for Updater {}
+impl PsbtRole for Signer {}
+impl PsbtRole for Finalizer {}
+impl PsbtRole for Extractor {}
+
+impl<V: PsbtValidation, R: PsbtRole> PartiallySignedTransaction<V, R> {
+    /// Hands the psbt over to the role `T`, keeping all of its data.
+    fn into_role<T: PsbtRole>(self) -> PartiallySignedTransaction<V, T> {
+        self.into_state()
+    }
+
+    /// Moves the maps of the psbt into a [`PartiallySignedTransactionInner`].
+    fn into_inner(self) -> PartiallySignedTransactionInner {
+        PartiallySignedTransactionInner {
+            unsigned_tx: self.unsigned_tx,
+            version: self.version,
+            xpub: self.xpub,
+            proprietary: self.proprietary,
+            unknown: self.unknown,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            tx_version: None,
+            fallback_locktime: None,
+            tx_modifiable: None,
+            sp_ecdh_shares: BTreeMap::new(),
+            sp_dleq_proofs: BTreeMap::new(),
+        }
+    }
+
+    /// Moves the maps of `inner` back into a psbt, dropping the fields this
+    /// PoC doesn't model.
+    fn from_inner(inner: PartiallySignedTransactionInner) -> Self {
+        PartiallySignedTransaction {
+            unsigned_tx: inner.unsigned_tx,
+            version: inner.version,
+            xpub: inner.xpub,
+            proprietary: inner.proprietary,
+            unknown: inner.unknown,
+            inputs: inner.inputs,
+            outputs: inner.outputs,
+            state: PhantomData,
+        }
+    }
+
+    /// Moves the psbt into another validation state and role.
+    fn into_state<W: PsbtValidation, T: PsbtRole>(self) -> PartiallySignedTransaction<W, T> {
+        PartiallySignedTransaction {
+            unsigned_tx: self.unsigned_tx,
+            version: self.version,
+            xpub: self.xpub,
+            proprietary: self.proprietary,
+            unknown: self.unknown,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            state: PhantomData,
+        }
+    }
+}
+
+impl PartiallySignedTransaction<PsbtUnchecked, Creator> {
+    /// Creates an empty psbt of the given version.
+    pub fn new(version: Version) -> Self {
+        PartiallySignedTransaction {
+            unsigned_tx: None,
+            version,
+            xpub: BTreeMap::new(),
+            proprietary: BTreeMap::new(),
+            unknown: BTreeMap::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            state: PhantomData,
+        }
+    }
+
+    /// Hands the psbt over to the updater.
+    pub fn into_updater(self) -> PartiallySignedTransaction<PsbtUnchecked, Updater> {
+        self.into_role()
+    }
+}
+
+impl PartiallySignedTransaction<PsbtUnchecked, Updater> {
+    pub fn add_input(&mut self, input: Input) {
+        // Code to also add the input to the unsigned tx of a version 0 Psbt
+        self.inputs.push(input);
+    }
+
+    pub fn add_output(&mut self, output: Output) {
+        // Code to also add the output to the unsigned tx of a version 0 Psbt
+        self.outputs.push(output);
+    }
+
+    /// Hands the psbt over to the signers, no more inputs or outputs can be
+    /// added.
+    pub fn into_signer(self) -> PartiallySignedTransaction<PsbtUnchecked, Signer> {
+        self.into_role()
+    }
+}
+
+impl PartiallySignedTransaction<PsbtUnchecked, Signer> {
+    pub fn sign_input(&mut self, index: usize, secret_key: &SecretKey) -> Result<(), String> {
+        // Code to compute the sighash of the input and add the signature
+        // to its partial_sigs
         Ok(())
     }
+
+    /// Hands the psbt over to the finalizer once every signer is done.
+    pub fn into_finalizer(self) -> PartiallySignedTransaction<PsbtUnchecked, Finalizer> {
+        self.into_role()
+    }
 }
 
-impl PartiallySignedTransaction<PsbtChecked> {
-    // Methods only available for the checked Psbt
-    // ...
+impl PartiallySignedTransaction<PsbtUnchecked, Finalizer> {
+    /// Finalizes and validates every input, the psbt can then only be
+    /// extracted.
+    pub fn finalize(self) -> Result<PartiallySignedTransaction<PsbtChecked, Extractor>, String> {
+        // Code to build the final scriptSig and witness of every input
+        self.into_role::<Extractor>()
+            .validate()
+            .map_err(|report| report.to_string())
+    }
+}
+
+impl PartiallySignedTransaction<PsbtChecked, Extractor> {
+    /// Extracts the final transaction of a version 0 psbt.
+    pub fn extract_tx(&self) -> Result<Transaction, String> {
+        // Code to move the final scriptSigs and witnesses into the unsigned tx
+        self.checked_unsigned_tx().cloned()
+    }
 }
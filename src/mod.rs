@@ -1 +1,21 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
+// Named like in rust-bitcoin, whose `serde` feature the psbt types follow
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde as actual_serde;
+
 pub mod poc;
+
+/// The `alloc` types used throughout the crate, which `no_std` builds don't
+/// get from the std prelude.
+pub(crate) mod prelude {
+    pub use alloc::borrow::ToOwned;
+    pub use alloc::boxed::Box;
+    pub use alloc::collections::{BTreeMap, BTreeSet};
+    pub use alloc::string::{String, ToString};
+    pub use alloc::sync;
+    pub use alloc::vec::Vec;
+}
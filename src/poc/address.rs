@@ -0,0 +1,66 @@
+//! Decoding of output scripts into addresses, for displaying where a psbt
+//! sends its funds.
+
+use core::fmt;
+
+use crate::address::Address;
+use crate::blockdata::script::{Script, ScriptBuf};
+use crate::network::Network;
+use crate::prelude::*;
+
+use super::output::Output;
+use super::poc::Psbt;
+
+/// Errors encountered while decoding the address of an output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    /// The output has no script yet, like a silent payment output before
+    /// the signers derived it.
+    MissingScript,
+    /// The script doesn't match any standard address type.
+    NonStandard(ScriptBuf),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::MissingScript => write!(f, "output has no script"),
+            AddressError::NonStandard(script) => {
+                write!(f, "script {} has no address form", script)
+            }
+        }
+    }
+}
+
+impl Output {
+    /// Decodes the PsbtV2 `script` field into an address on `network`, see
+    /// [`Psbt::output_addresses`] for outputs of a PsbtV0.
+    pub fn address(&self, network: Network) -> Result<Address, AddressError> {
+        let script = self.script.as_ref().ok_or(AddressError::MissingScript)?;
+        script_address(script, network)
+    }
+}
+
+impl Psbt {
+    /// Decodes the script of every output into an address on `network`,
+    /// taken from the unsigned transaction of a PsbtV0 or the `script` fields
+    /// of a PsbtV2.
+    ///
+    /// P2PKH, P2SH, P2WPKH, P2WSH, P2TR and future witness versions decode,
+    /// other scripts such as `OP_RETURN` outputs are
+    /// [`AddressError::NonStandard`].
+    pub fn output_addresses(&self, network: Network) -> Vec<Result<Address, AddressError>> {
+        (0..self.inner.outputs.len())
+            .map(|index| {
+                let script = self
+                    .output_script_pubkey(index)
+                    .ok_or(AddressError::MissingScript)?;
+                script_address(script, network)
+            })
+            .collect()
+    }
+}
+
+fn script_address(script: &Script, network: Network) -> Result<Address, AddressError> {
+    Address::from_script(script, network).map_err(|_| AddressError::NonStandard(script.to_owned()))
+}
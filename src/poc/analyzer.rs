@@ -0,0 +1,209 @@
+use crate::blockdata::locktime::absolute;
+use crate::blockdata::script::Script;
+use crate::blockdata::transaction::TxIn;
+use crate::crypto::key::PublicKey;
+use crate::prelude::*;
+use crate::taproot::TapLeafHash;
+use crate::Amount;
+
+use super::finalizer::{parse_multisig, pays_to_key, satisfy_input, taproot_satisfied};
+use super::input::Input;
+use super::poc::Psbt;
+
+/// The psbt role that has to act next, in workflow order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    /// UTXO data or scripts are missing.
+    Updater,
+    /// Signatures are missing.
+    Signer,
+    /// Everything needed is present, the input can be finalized.
+    Finalizer,
+    /// The input is finalized.
+    Extractor,
+}
+
+/// The data an input still lacks.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MissingData {
+    /// Neither `witness_utxo` nor `non_witness_utxo` is set.
+    pub utxo: bool,
+    /// The input spends a P2SH output but has no `redeem_script`.
+    pub redeem_script: bool,
+    /// The input spends a P2WSH output but has no `witness_script`.
+    pub witness_script: bool,
+    /// Neither `bip32_derivation` nor `tap_key_origins` has any entry, so
+    /// signers can't tell which of their keys are involved.
+    pub key_origins: bool,
+    /// Public keys of the script that haven't signed yet. Only known for
+    /// ECDSA inputs whose keys appear in the script or in `bip32_derivation`.
+    pub signatures: Vec<PublicKey>,
+}
+
+/// The analysis of a single psbt input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputAnalysis {
+    /// Whether the UTXO spent by the input is known.
+    pub has_utxo: bool,
+    /// Whether the input is finalized.
+    pub is_final: bool,
+    /// Whether the input has everything the finalizer needs, that is whether
+    /// [`Psbt::finalize_input`] would succeed.
+    pub is_finalizable: bool,
+    /// The role that has to act next on this input.
+    pub next_role: Role,
+    /// Number of signatures the script requires, `None` if the script isn't
+    /// a single key, multisig or taproot one.
+    pub signatures_needed: Option<usize>,
+    /// Number of relevant signatures already present.
+    pub signatures_present: usize,
+    /// What the input still lacks.
+    pub missing: MissingData,
+    /// Hashes of the `tap_scripts` leaves whose control block doesn't prove
+    /// them against the key of the spent output, all of them if the spent
+    /// output is unknown. Signers refuse to sign for them.
+    pub unprovable_leaves: Vec<TapLeafHash>,
+}
+
+/// The result of [`Psbt::analyze`], similar to Bitcoin Core's `analyzepsbt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PsbtAnalysis {
+    /// The analysis of every input, in order.
+    pub inputs: Vec<InputAnalysis>,
+    /// The role that has to act next on the psbt as a whole.
+    pub next_role: Role,
+    /// The fee, if every input's UTXO is known.
+    pub fee: Option<Amount>,
+    /// The estimated final virtual size, if it can be estimated.
+    pub estimated_vsize: Option<u64>,
+    /// Whether every input is finalized and the transaction can be extracted.
+    pub is_extractable: bool,
+}
+
+impl Psbt {
+    /// Reports, per input, which data is missing and which role has to act
+    /// next, and whether the whole psbt is extractable.
+    pub fn analyze(&self) -> Result<PsbtAnalysis, String> {
+        let tx = self.unsigned_tx()?;
+
+        let inputs: Vec<InputAnalysis> = tx
+            .input
+            .iter()
+            .zip(&self.inner.inputs)
+            .enumerate()
+            .map(|(index, (txin, input))| analyze_input(input, txin, tx.lock_time, index))
+            .collect();
+        let next_role = inputs
+            .iter()
+            .map(|input| input.next_role)
+            .min()
+            .unwrap_or(Role::Extractor);
+
+        Ok(PsbtAnalysis {
+            is_extractable: inputs.iter().all(|input| input.is_final),
+            inputs,
+            next_role,
+            fee: self.fee().ok(),
+            estimated_vsize: self.estimate_vsize().ok(),
+        })
+    }
+}
+
+fn analyze_input(
+    input: &Input,
+    txin: &TxIn,
+    lock_time: absolute::LockTime,
+    index: usize,
+) -> InputAnalysis {
+    let mut analysis = InputAnalysis {
+        has_utxo: false,
+        is_final: input.is_finalized(),
+        is_finalizable: false,
+        next_role: Role::Updater,
+        signatures_needed: None,
+        signatures_present: 0,
+        missing: MissingData::default(),
+        unprovable_leaves: vec![],
+    };
+
+    let utxo = input.spent_utxo(&txin.previous_output);
+    analysis.has_utxo = utxo.is_some();
+    analysis.unprovable_leaves = input.unprovable_tap_leaves(utxo);
+    if analysis.is_final {
+        analysis.next_role = Role::Extractor;
+        return analysis;
+    }
+    let script_pubkey = match utxo {
+        Some(utxo) => &utxo.script_pubkey,
+        None => {
+            analysis.missing.utxo = true;
+            return analysis;
+        }
+    };
+
+    analysis.missing.key_origins =
+        input.bip32_derivation.is_empty() && input.tap_key_origins.is_empty();
+
+    let mut script = script_pubkey.as_script();
+    if script.is_p2sh() {
+        match input.redeem_script {
+            Some(ref redeem_script) => script = redeem_script,
+            None => analysis.missing.redeem_script = true,
+        }
+    }
+    if script.is_p2wsh() {
+        match input.witness_script {
+            Some(ref witness_script) => script = witness_script,
+            None => analysis.missing.witness_script = true,
+        }
+    }
+    if analysis.missing.redeem_script || analysis.missing.witness_script {
+        return analysis;
+    }
+
+    analyze_signatures(input, script, &mut analysis);
+    // Ask the finalizer rather than the signature counts, so any script it
+    // can satisfy, such as a hashlock, is reported as finalizable
+    analysis.is_finalizable = satisfy_input(input, txin, lock_time, index).is_ok();
+    analysis.next_role = if analysis.is_finalizable {
+        Role::Finalizer
+    } else {
+        Role::Signer
+    };
+    analysis
+}
+
+/// Fills the signature counts and missing signers of `analysis` for an input
+/// whose innermost script is `script`.
+fn analyze_signatures(input: &Input, script: &Script, analysis: &mut InputAnalysis) {
+    if script.is_p2tr() {
+        analysis.signatures_needed = Some(1);
+        analysis.signatures_present = taproot_satisfied(input) as usize;
+    } else if script.is_p2pkh() || script.is_p2wpkh() {
+        analysis.signatures_needed = Some(1);
+        analysis.signatures_present = input
+            .partial_sigs
+            .keys()
+            .filter(|key| pays_to_key(script, key))
+            .count()
+            .min(1);
+        if analysis.signatures_present == 0 {
+            analysis.missing.signatures = input
+                .bip32_derivation
+                .keys()
+                .map(|key| PublicKey::new(*key))
+                .filter(|key| pays_to_key(script, key))
+                .collect();
+        }
+    } else if let Some((threshold, keys)) = parse_multisig(script) {
+        analysis.signatures_needed = Some(threshold);
+        analysis.signatures_present = keys
+            .iter()
+            .filter(|key| input.partial_sigs.contains_key(key))
+            .count();
+        analysis.missing.signatures = keys
+            .into_iter()
+            .filter(|key| !input.partial_sigs.contains_key(key))
+            .collect();
+    }
+}
@@ -0,0 +1,409 @@
+//! [`Arbitrary`] implementations generating structurally valid psbts, for
+//! property testing code built on this crate, with the `arbitrary` feature.
+//!
+//! A generated [`PartiallySignedTransactionInner`] passes
+//! [`validate`](PartiallySignedTransactionInner::validate) for its version:
+//! a PsbtV0 carries a matching unsigned transaction, a PsbtV2 the per-input
+//! and per-output fields instead. Inputs spend P2WPKH, P2WSH or P2TR outputs
+//! of the keys in their key origins and are unsigned, partially signed or
+//! finalized. Keys are valid points and preimages hash to their keys, but
+//! signatures are random and don't verify.
+//!
+//! The inputs of a generated psbt are worth at most [`Amount::MAX_MONEY`]
+//! together and its outputs at most as much as its inputs, so computing its
+//! fee neither overflows nor fails.
+
+use arbitrary::{Arbitrary, Unstructured};
+use secp256k1::{schnorr, PublicKey as SecpPublicKey};
+
+use crate::bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource};
+use crate::blockdata::locktime::absolute;
+use crate::blockdata::opcodes::all::OP_CHECKSIG;
+use crate::blockdata::script::{Builder, ScriptBuf};
+use crate::blockdata::transaction::{self, OutPoint, Sequence, Transaction, TxIn, TxOut};
+use crate::blockdata::witness::Witness;
+use crate::crypto::ecdsa;
+use crate::crypto::key::PublicKey;
+use crate::hashes::{sha256, Hash};
+use crate::prelude::sync::Arc;
+use crate::prelude::*;
+use crate::psbt::{raw, PsbtSighashType, Version};
+use crate::sighash::{EcdsaSighashType, TapSighashType};
+use crate::taproot;
+use crate::{Amount, Txid};
+
+use super::input::Input;
+use super::output::Output;
+use super::poc::PartiallySignedTransactionInner;
+
+/// The most inputs and outputs a generated psbt has.
+const MAX_MAP_COUNT: u8 = 4;
+
+/// Type values of unknown pairs, above every type this crate knows of in
+/// any map and below the proprietary type.
+const UNKNOWN_TYPES: core::ops::RangeInclusive<u8> = 0x20..=0xef;
+
+impl<'a> Arbitrary<'a> for Input {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let key = public_key(u)?;
+        let source = key_source(u)?;
+        let value = Amount::from_sat(u.int_in_range(0..=Amount::MAX_MONEY.to_sat())?);
+        let mut input = Input {
+            proprietary: proprietary(u)?,
+            unknown: unknown(u)?,
+            ..Default::default()
+        };
+
+        let script_pubkey = match u.int_in_range(0..=2u8)? {
+            0 => {
+                let hash = PublicKey::new(key)
+                    .wpubkey_hash()
+                    .expect("secp256k1 keys are compressed");
+                input.bip32_derivation.insert(key, source);
+                ScriptBuf::new_p2wpkh(&hash)
+            }
+            1 => {
+                let witness_script = Builder::new()
+                    .push_key(&PublicKey::new(key))
+                    .push_opcode(OP_CHECKSIG)
+                    .into_script();
+                let script_pubkey = ScriptBuf::new_p2wsh(&witness_script.wscript_hash());
+                input.witness_script = Some(witness_script);
+                input.bip32_derivation.insert(key, source);
+                script_pubkey
+            }
+            _ => {
+                let (internal_key, _) = key.x_only_public_key();
+                input.tap_internal_key = Some(internal_key);
+                input.tap_key_origins.insert(internal_key, (vec![], source));
+                ScriptBuf::new_p2tr_tweaked(
+                    input
+                        .taproot_output_key()
+                        .expect("tap_internal_key was just set"),
+                )
+            }
+        };
+        input.witness_utxo = Some(TxOut {
+            value,
+            script_pubkey,
+        });
+
+        let signed = u.arbitrary()?;
+        if input.tap_internal_key.is_some() {
+            let sighash_type = *u.choose(&[TapSighashType::Default, TapSighashType::All])?;
+            if u.arbitrary()? {
+                input.sighash_type = Some(PsbtSighashType::from(sighash_type));
+            }
+            if signed {
+                input.tap_key_sig = Some(taproot::Signature {
+                    sig: schnorr_signature(u)?,
+                    hash_ty: sighash_type,
+                });
+            }
+        } else {
+            // SIGHASH_SINGLE is left out, it needs an output at the index
+            // of the input
+            let sighash_type = *u.choose(&[
+                EcdsaSighashType::All,
+                EcdsaSighashType::None,
+                EcdsaSighashType::AllPlusAnyoneCanPay,
+                EcdsaSighashType::NonePlusAnyoneCanPay,
+            ])?;
+            if u.arbitrary()? {
+                input.sighash_type = Some(PsbtSighashType::from(sighash_type));
+            }
+            if signed {
+                let sig = ecdsa_signature(u)?;
+                input.partial_sigs.insert(
+                    PublicKey::new(key),
+                    ecdsa::Signature {
+                        sig,
+                        hash_ty: sighash_type,
+                    },
+                );
+            }
+        }
+
+        for preimage in Vec::<Vec<u8>>::arbitrary(u)? {
+            input
+                .sha256_preimages
+                .insert(sha256::Hash::hash(&preimage), preimage);
+        }
+
+        if signed && u.arbitrary()? {
+            finalize(&mut input, u)?;
+        }
+        Ok(input)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Output {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut output = Output {
+            proprietary: proprietary(u)?,
+            unknown: unknown(u)?,
+            ..Default::default()
+        };
+        // Outputs to foreign addresses come without key origins
+        match u.int_in_range(0..=2u8)? {
+            0 => {}
+            1 => {
+                output
+                    .bip32_derivation
+                    .insert(public_key(u)?, key_source(u)?);
+            }
+            _ => {
+                let (internal_key, _) = public_key(u)?.x_only_public_key();
+                output.tap_internal_key = Some(internal_key);
+                output
+                    .tap_key_origins
+                    .insert(internal_key, (vec![], key_source(u)?));
+            }
+        }
+        Ok(output)
+    }
+}
+
+impl<'a> Arbitrary<'a> for PartiallySignedTransactionInner {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let version = if u.arbitrary()? {
+            Version::PsbtV2
+        } else {
+            Version::PsbtV0
+        };
+
+        let input_count = u.int_in_range(1..=MAX_MAP_COUNT)?;
+        let input_total = u.int_in_range(0..=Amount::MAX_MONEY.to_sat())?;
+        let input_values = split_amount(input_total, input_count.into(), u)?;
+        let mut inputs = Vec::with_capacity(input_count.into());
+        let mut txins = Vec::with_capacity(input_count.into());
+        for value in input_values {
+            let mut input = Input::arbitrary(u)?;
+            if let Some(ref mut utxo) = input.witness_utxo {
+                utxo.value = value;
+            }
+            let previous_output = if u.arbitrary()? {
+                spend_from_previous_tx(&mut input, u)?
+            } else {
+                OutPoint::new(Txid::from_byte_array(u.arbitrary()?), u.arbitrary()?)
+            };
+            txins.push(TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_consensus(u.arbitrary()?),
+                witness: Witness::new(),
+            });
+            inputs.push(input);
+        }
+
+        let output_count = u.int_in_range(1..=MAX_MAP_COUNT)?;
+        let output_total = u.int_in_range(0..=input_total)?;
+        let output_values = split_amount(output_total, output_count.into(), u)?;
+        let mut outputs = Vec::with_capacity(output_count.into());
+        let mut txouts = Vec::with_capacity(output_count.into());
+        for value in output_values {
+            let output = Output::arbitrary(u)?;
+            txouts.push(TxOut {
+                value,
+                script_pubkey: output_script(&output, u)?,
+            });
+            outputs.push(output);
+        }
+
+        let lock_time: u32 = u.arbitrary()?;
+        let mut inner = PartiallySignedTransactionInner {
+            unsigned_tx: None,
+            version,
+            xpub: BTreeMap::new(),
+            proprietary: proprietary(u)?,
+            unknown: unknown(u)?,
+            inputs,
+            outputs,
+            tx_version: None,
+            fallback_locktime: None,
+            tx_modifiable: None,
+            sp_ecdh_shares: BTreeMap::new(),
+            sp_dleq_proofs: BTreeMap::new(),
+        };
+        match version {
+            Version::PsbtV0 => {
+                inner.unsigned_tx = Some(Transaction {
+                    version: transaction::Version::TWO,
+                    lock_time: absolute::LockTime::from_consensus(lock_time),
+                    input: txins,
+                    output: txouts,
+                });
+            }
+            Version::PsbtV2 => {
+                for (input, txin) in inner.inputs.iter_mut().zip(txins) {
+                    input.previous_tx_id = Some(txin.previous_output.txid.into());
+                    input.output_index = Some(txin.previous_output.vout);
+                    input.sequence = Some(txin.sequence);
+                }
+                for (output, txout) in inner.outputs.iter_mut().zip(txouts) {
+                    output.amount = Some(txout.value);
+                    output.script = Some(txout.script_pubkey);
+                }
+                inner.tx_version = Some(2);
+                if u.arbitrary()? {
+                    inner.fallback_locktime = Some(lock_time);
+                }
+            }
+        }
+        Ok(inner)
+    }
+}
+
+/// Sets the `non_witness_utxo` of `input` to a transaction paying its
+/// `witness_utxo` among other outputs, returning the outpoint spent.
+fn spend_from_previous_tx(input: &mut Input, u: &mut Unstructured) -> arbitrary::Result<OutPoint> {
+    let utxo = input
+        .witness_utxo
+        .clone()
+        .expect("generated inputs have a witness_utxo");
+    // The other outputs only get what the spent one leaves of the money
+    let others = u.int_in_range(0..=(Amount::MAX_MONEY - utxo.value).to_sat())?;
+    let mut output = Vec::new();
+    for value in split_amount(others, u.int_in_range(0..=2u8)?.into(), u)? {
+        output.push(TxOut {
+            value,
+            script_pubkey: ScriptBuf::new_p2wpkh(
+                &PublicKey::new(public_key(u)?)
+                    .wpubkey_hash()
+                    .expect("secp256k1 keys are compressed"),
+            ),
+        });
+    }
+    let vout = u.choose_index(output.len() + 1)?;
+    output.insert(vout, utxo);
+
+    let previous_tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array(u.arbitrary()?), u.arbitrary()?),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output,
+    };
+    let outpoint = OutPoint::new(previous_tx.txid(), vout as u32);
+    input.non_witness_utxo = Some(Arc::new(previous_tx));
+    Ok(outpoint)
+}
+
+/// Splits `total` satoshis into `count` amounts adding up to at most
+/// `total`, all of it unless `count` is zero.
+fn split_amount(total: u64, count: usize, u: &mut Unstructured) -> arbitrary::Result<Vec<Amount>> {
+    let mut remaining = total;
+    let mut amounts = Vec::with_capacity(count);
+    for position in 0..count {
+        let amount = if position + 1 == count {
+            remaining
+        } else {
+            u.int_in_range(0..=remaining)?
+        };
+        remaining -= amount;
+        amounts.push(Amount::from_sat(amount));
+    }
+    Ok(amounts)
+}
+
+/// Returns the script `output` pays to: the key of its taproot origin or of
+/// its bip32 origin, or another key if it has neither.
+fn output_script(output: &Output, u: &mut Unstructured) -> arbitrary::Result<ScriptBuf> {
+    if let Some(output_key) = output.taproot_output_key() {
+        return Ok(ScriptBuf::new_p2tr_tweaked(output_key));
+    }
+    let key = match output.bip32_derivation.keys().next() {
+        Some(key) => *key,
+        None => public_key(u)?,
+    };
+    let hash = PublicKey::new(key)
+        .wpubkey_hash()
+        .expect("secp256k1 keys are compressed");
+    Ok(ScriptBuf::new_p2wpkh(&hash))
+}
+
+/// Moves `input` to its finalized state, clearing what the finalizer
+/// clears and setting a random witness.
+fn finalize(input: &mut Input, u: &mut Unstructured) -> arbitrary::Result<()> {
+    let witness = Vec::<Vec<u8>>::arbitrary(u)?;
+    input.final_script_witness = Some(Witness::from_slice(&witness));
+    input.partial_sigs.clear();
+    input.sighash_type = None;
+    input.redeem_script = None;
+    input.witness_script = None;
+    input.bip32_derivation.clear();
+    input.tap_key_sig = None;
+    input.tap_key_origins.clear();
+    input.tap_internal_key = None;
+    Ok(())
+}
+
+/// Generates a valid public key, drawing bytes until they encode a point.
+fn public_key(u: &mut Unstructured) -> arbitrary::Result<SecpPublicKey> {
+    loop {
+        let mut bytes: [u8; 33] = u.arbitrary()?;
+        bytes[0] = 0x02 | (bytes[0] & 1);
+        if let Ok(key) = SecpPublicKey::from_slice(&bytes) {
+            return Ok(key);
+        }
+        // Out of data every draw is the same invalid encoding
+        if u.is_empty() {
+            return Err(arbitrary::Error::NotEnoughData);
+        }
+    }
+}
+
+fn key_source(u: &mut Unstructured) -> arbitrary::Result<KeySource> {
+    let fingerprint = Fingerprint::from(u.arbitrary::<[u8; 4]>()?);
+    let mut path = Vec::new();
+    for _ in 0..u.int_in_range(0..=5u8)? {
+        path.push(ChildNumber::from(u.arbitrary::<u32>()?));
+    }
+    Ok((fingerprint, DerivationPath::from(path)))
+}
+
+fn ecdsa_signature(u: &mut Unstructured) -> arbitrary::Result<secp256k1::ecdsa::Signature> {
+    loop {
+        let bytes: [u8; 64] = u.arbitrary()?;
+        if let Ok(sig) = secp256k1::ecdsa::Signature::from_compact(&bytes) {
+            return Ok(sig);
+        }
+        if u.is_empty() {
+            return Err(arbitrary::Error::NotEnoughData);
+        }
+    }
+}
+
+fn schnorr_signature(u: &mut Unstructured) -> arbitrary::Result<schnorr::Signature> {
+    let bytes: [u8; 64] = u.arbitrary()?;
+    Ok(schnorr::Signature::from_slice(&bytes).expect("64 bytes"))
+}
+
+fn proprietary(u: &mut Unstructured) -> arbitrary::Result<BTreeMap<raw::ProprietaryKey, Vec<u8>>> {
+    let mut pairs = BTreeMap::new();
+    for _ in 0..u.int_in_range(0..=2u8)? {
+        let key = raw::ProprietaryKey {
+            prefix: u.arbitrary()?,
+            subtype: u.arbitrary()?,
+            key: u.arbitrary()?,
+        };
+        pairs.insert(key, u.arbitrary()?);
+    }
+    Ok(pairs)
+}
+
+fn unknown(u: &mut Unstructured) -> arbitrary::Result<BTreeMap<raw::Key, Vec<u8>>> {
+    let mut pairs = BTreeMap::new();
+    for _ in 0..u.int_in_range(0..=2u8)? {
+        let key = raw::Key {
+            type_value: u.int_in_range(UNKNOWN_TYPES)?,
+            key: u.arbitrary()?,
+        };
+        pairs.insert(key, u.arbitrary()?);
+    }
+    Ok(pairs)
+}
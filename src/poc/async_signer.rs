@@ -0,0 +1,76 @@
+//! Non-blocking signer abstraction for hardware wallets and remote HSMs.
+
+use core::future::Future;
+
+use crate::prelude::*;
+
+use super::poc::Psbt;
+use super::signer::{PsbtSigner, SignError, SignOptions, SignReport, SignedKey};
+
+/// Progress of an asynchronous signing session, reported once per input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignProgress {
+    /// Index of the input that was just processed.
+    pub index: usize,
+    /// Total number of inputs in the psbt.
+    pub total: usize,
+    /// The keys the input was signed for, empty if it was skipped.
+    pub signed: Vec<SignedKey>,
+}
+
+/// The asynchronous counterpart of [`PsbtSigner`], for devices that may take
+/// seconds to respond, such as USB hardware wallets or network HSMs.
+pub trait AsyncPsbtSigner {
+    /// Signs every input of `psbt` this signer holds keys for and is allowed
+    /// to sign by `options`, calling `progress` after each input.
+    fn sign<'a>(
+        &'a self,
+        psbt: &'a mut Psbt,
+        options: SignOptions,
+        progress: &'a mut (dyn FnMut(SignProgress) + Send),
+    ) -> impl Future<Output = Result<SignReport, SignError>> + Send + 'a;
+}
+
+/// Adapts a blocking [`PsbtSigner`] to [`AsyncPsbtSigner`], signing one input
+/// at a time so progress can be reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Blocking<S>(pub S);
+
+impl<S: PsbtSigner + Sync> AsyncPsbtSigner for Blocking<S> {
+    fn sign<'a>(
+        &'a self,
+        psbt: &'a mut Psbt,
+        options: SignOptions,
+        progress: &'a mut (dyn FnMut(SignProgress) + Send),
+    ) -> impl Future<Output = Result<SignReport, SignError>> + Send + 'a {
+        async move {
+            let total = psbt.inner.inputs.len();
+            let mut report = SignReport::default();
+            for index in 0..total {
+                if !options.includes(index) {
+                    continue;
+                }
+                let input_options = SignOptions {
+                    inputs: Some(vec![index]),
+                    ..options.clone()
+                };
+                let signed: Vec<SignedKey> = self
+                    .0
+                    .sign(psbt, input_options)?
+                    .signed
+                    .into_values()
+                    .flatten()
+                    .collect();
+                for key in &signed {
+                    report.record(index, *key);
+                }
+                progress(SignProgress {
+                    index,
+                    total,
+                    signed,
+                });
+            }
+            Ok(report)
+        }
+    }
+}
@@ -0,0 +1,741 @@
+//! Builders for input and output maps, and the Creator role of BIP-174
+//! building whole psbts.
+//!
+//! The map builders set one field per call and check at `build` time that
+//! the fields fit the version of the psbt the map is meant for, instead of
+//! spelling out every field of the map in a struct literal.
+
+use core::fmt;
+
+use secp256k1::XOnlyPublicKey;
+
+use crate::address::Address;
+use crate::bip32::KeySource;
+use crate::blockdata::locktime::absolute;
+use crate::blockdata::script::ScriptBuf;
+use crate::blockdata::transaction::{self, OutPoint, Sequence, Transaction, TxIn, TxOut, Txid};
+use crate::blockdata::witness::Witness;
+use crate::consensus::encode::VarInt;
+use crate::prelude::sync::Arc;
+use crate::prelude::*;
+use crate::psbt::{PsbtSighashType, Version};
+use crate::taproot::{TapLeafHash, TapNodeHash, TapTree};
+use crate::{Amount, FeeRate, Weight};
+
+use super::coin_selection::{CoinSelectionError, Selection};
+use super::descriptor::DerivedScript;
+use super::dust::{dust_threshold, DEFAULT_DUST_RELAY_FEE};
+use super::fee::{checked_sum, FeeError};
+use super::input::Input;
+use super::output::{Output, OutputScriptError};
+use super::poc::{PartiallySignedTransactionInner, Psbt};
+use super::taproot::TaprootError;
+use super::validation::{InputVersionField, ValidationReport};
+
+/// Errors encountered while building an input map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputBuildError {
+    /// These fields are forbidden (PsbtV0) or missing (PsbtV2) for the
+    /// version the input is built for.
+    VersionFields {
+        version: Version,
+        fields: Vec<InputVersionField>,
+    },
+    /// The `non_witness_utxo` isn't the transaction of the previous outpoint.
+    NonWitnessUtxoMismatch,
+}
+
+impl fmt::Display for InputBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputBuildError::VersionFields { version, fields } => {
+                match version {
+                    Version::PsbtV0 => write!(f, "input of a PsbtV0 must not have")?,
+                    Version::PsbtV2 => write!(f, "input of a PsbtV2 is missing")?,
+                }
+                for (index, field) in fields.iter().enumerate() {
+                    let separator = if index == 0 { " the " } else { ", the " };
+                    write!(f, "{}{}", separator, field)?;
+                }
+                Ok(())
+            }
+            InputBuildError::NonWitnessUtxoMismatch => write!(
+                f,
+                "non-witness utxo isn't the transaction of the previous outpoint"
+            ),
+        }
+    }
+}
+
+/// Builds an [`Input`] one field at a time.
+#[derive(Debug, Clone, Default)]
+pub struct InputBuilder {
+    input: Input,
+}
+
+impl InputBuilder {
+    /// Creates a builder of an empty input.
+    pub fn new() -> Self {
+        InputBuilder::default()
+    }
+
+    /// Sets the transaction the input spends from, either owned or shared
+    /// with other inputs.
+    pub fn non_witness_utxo<T: Into<Arc<Transaction>>>(mut self, tx: T) -> Self {
+        self.input.non_witness_utxo = Some(tx.into());
+        self
+    }
+
+    /// Sets the output the input spends.
+    pub fn witness_utxo(mut self, txout: TxOut) -> Self {
+        self.input.witness_utxo = Some(txout);
+        self
+    }
+
+    /// Sets the outpoint the input spends, a PsbtV2 field.
+    pub fn previous_outpoint(mut self, txid: Txid, vout: u32) -> Self {
+        self.input.previous_tx_id = Some(txid.into());
+        self.input.output_index = Some(vout);
+        self
+    }
+
+    /// Sets the sequence number of the input, a PsbtV2 field.
+    pub fn sequence(mut self, sequence: Sequence) -> Self {
+        self.input.sequence = Some(sequence);
+        self
+    }
+
+    /// Sets the minimum time-based locktime the input requires, a PsbtV2
+    /// field.
+    pub fn required_time_locktime(mut self, locktime: u32) -> Self {
+        self.input.required_time_locktime = Some(locktime);
+        self
+    }
+
+    /// Sets the minimum height-based locktime the input requires, a PsbtV2
+    /// field.
+    pub fn required_height_locktime(mut self, locktime: u32) -> Self {
+        self.input.required_height_locktime = Some(locktime);
+        self
+    }
+
+    /// Sets the sighash type signatures of the input must use.
+    pub fn sighash_type(mut self, sighash_type: PsbtSighashType) -> Self {
+        self.input.sighash_type = Some(sighash_type);
+        self
+    }
+
+    /// Sets the redeem script of a P2SH input.
+    pub fn redeem_script(mut self, script: ScriptBuf) -> Self {
+        self.input.redeem_script = Some(script);
+        self
+    }
+
+    /// Sets the witness script of a P2WSH input.
+    pub fn witness_script(mut self, script: ScriptBuf) -> Self {
+        self.input.witness_script = Some(script);
+        self
+    }
+
+    /// Adds the origin of a key signing the input.
+    pub fn bip32_derivation(mut self, key: secp256k1::PublicKey, source: KeySource) -> Self {
+        self.input.bip32_derivation.insert(key, source);
+        self
+    }
+
+    /// Sets the taproot internal key of the output the input spends.
+    pub fn tap_internal_key(mut self, key: XOnlyPublicKey) -> Self {
+        self.input.tap_internal_key = Some(key);
+        self
+    }
+
+    /// Sets the taproot merkle root of the output the input spends.
+    pub fn tap_merkle_root(mut self, merkle_root: TapNodeHash) -> Self {
+        self.input.tap_merkle_root = Some(merkle_root);
+        self
+    }
+
+    /// Adds the origin of a taproot key and the leaves it's used in.
+    pub fn tap_key_origin(
+        mut self,
+        key: XOnlyPublicKey,
+        leaf_hashes: Vec<TapLeafHash>,
+        source: KeySource,
+    ) -> Self {
+        self.input
+            .tap_key_origins
+            .insert(key, (leaf_hashes, source));
+        self
+    }
+
+    /// Returns the input, checking its fields fit a psbt of `version`.
+    pub fn build(self, version: Version) -> Result<Input, InputBuildError> {
+        let fields: Vec<InputVersionField> = self
+            .input
+            .version_field_errors(version, 0)
+            .into_iter()
+            .map(|err| err.field)
+            .collect();
+        if !fields.is_empty() {
+            return Err(InputBuildError::VersionFields { version, fields });
+        }
+        if let (Some(tx), Some(txid)) = (&self.input.non_witness_utxo, self.input.previous_tx_id) {
+            if tx.txid() != txid.to_txid() {
+                return Err(InputBuildError::NonWitnessUtxoMismatch);
+            }
+        }
+        Ok(self.input)
+    }
+}
+
+/// Errors encountered while building an output map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputBuildError {
+    /// No amount was given.
+    MissingAmount,
+    /// Neither an address nor a taproot key was given.
+    MissingScript,
+    /// The address doesn't pay to the taproot output key.
+    ScriptMismatch,
+    /// The script can't be stored in the output.
+    Script(OutputScriptError),
+}
+
+impl fmt::Display for OutputBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputBuildError::MissingAmount => write!(f, "output has no amount"),
+            OutputBuildError::MissingScript => write!(f, "output has no address or taproot key"),
+            OutputBuildError::ScriptMismatch => {
+                write!(f, "address doesn't pay to the taproot output key")
+            }
+            OutputBuildError::Script(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Builds an [`Output`] one field at a time.
+#[derive(Debug, Clone, Default)]
+pub struct OutputBuilder {
+    output: Output,
+    address_script: Option<ScriptBuf>,
+}
+
+impl OutputBuilder {
+    /// Creates a builder of an empty output.
+    pub fn new() -> Self {
+        OutputBuilder::default()
+    }
+
+    /// Pays the output to `address`.
+    pub fn to_address(mut self, address: &Address) -> Self {
+        self.address_script = Some(address.script_pubkey());
+        self
+    }
+
+    /// Sets the amount of the output.
+    pub fn amount(mut self, amount: Amount) -> Self {
+        self.output.amount = Some(amount);
+        self
+    }
+
+    /// Adds the origin of a key the output pays to.
+    pub fn with_derivation(mut self, key: secp256k1::PublicKey, source: KeySource) -> Self {
+        self.output.bip32_derivation.insert(key, source);
+        self
+    }
+
+    /// Pays the output to `internal_key` tweaked with the root of `tree`, if
+    /// any.
+    pub fn taproot(mut self, internal_key: XOnlyPublicKey, tree: Option<TapTree>) -> Self {
+        self.output.tap_internal_key = Some(internal_key);
+        self.output.tap_tree = tree;
+        self
+    }
+
+    /// Returns the output of the unsigned transaction of a PsbtV0 this output
+    /// stands for.
+    pub fn txout(&self) -> Result<TxOut, OutputBuildError> {
+        Ok(TxOut {
+            value: self.output.amount.ok_or(OutputBuildError::MissingAmount)?,
+            script_pubkey: self.script()?,
+        })
+    }
+
+    /// Returns the output, with the `amount` and `script` fields set for a
+    /// PsbtV2. For a PsbtV0 they belong in the unsigned transaction instead,
+    /// see [`OutputBuilder::txout`].
+    pub fn build(self, version: Version) -> Result<Output, OutputBuildError> {
+        let txout = self.txout()?;
+        let mut output = self.output;
+        match version {
+            Version::PsbtV0 => output.amount = None,
+            Version::PsbtV2 => output
+                .set_script(txout.script_pubkey)
+                .map_err(OutputBuildError::Script)?,
+        }
+        Ok(output)
+    }
+
+    /// The script paid to, from the address or the taproot output key.
+    fn script(&self) -> Result<ScriptBuf, OutputBuildError> {
+        let taproot_script = self
+            .output
+            .taproot_output_key()
+            .map(ScriptBuf::new_p2tr_tweaked);
+        match (&self.address_script, taproot_script) {
+            (Some(address_script), Some(taproot_script)) if *address_script != taproot_script => {
+                Err(OutputBuildError::ScriptMismatch)
+            }
+            (Some(script), _) => Ok(script.clone()),
+            (None, Some(script)) => Ok(script),
+            (None, None) => Err(OutputBuildError::MissingScript),
+        }
+    }
+}
+
+/// An output a psbt built by [`PsbtBuilder`] spends.
+#[derive(Debug, Clone)]
+pub struct Utxo {
+    /// The outpoint of the output.
+    pub outpoint: OutPoint,
+    /// The output itself.
+    pub txout: TxOut,
+    /// The transaction holding the output, required to spend non-segwit
+    /// outputs, and P2SH ones unless `descriptor` shows they nest segwit.
+    /// Shared with the inputs of every psbt built from the utxo.
+    pub non_witness_utxo: Option<Arc<Transaction>>,
+    /// The script of the descriptor the output pays to, filling the scripts
+    /// and key origins of the input.
+    pub descriptor: Option<DerivedScript>,
+}
+
+impl Utxo {
+    /// Creates a spendable output without its transaction or descriptor.
+    pub fn new(outpoint: OutPoint, txout: TxOut) -> Self {
+        Utxo {
+            outpoint,
+            txout,
+            non_witness_utxo: None,
+            descriptor: None,
+        }
+    }
+}
+
+/// The smallest surplus [`PsbtBuilder::build`] refuses to leave to the fee
+/// without a change output, the dust threshold of a P2WPKH output, the
+/// cheapest change output to spend, at [`DEFAULT_DUST_RELAY_FEE`].
+const EXCESS_FEE_THRESHOLD: Amount = Amount::from_sat(294);
+
+/// Errors encountered while building a psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PsbtBuildError {
+    /// The utxo at this index pays to a non-segwit script, or to P2SH
+    /// without a descriptor showing it nests segwit, but its transaction
+    /// wasn't given.
+    MissingNonWitnessUtxo { index: usize },
+    /// The input at this index could not be built.
+    Input {
+        index: usize,
+        error: InputBuildError,
+    },
+    /// The descriptor of the utxo at this index has an invalid tap tree.
+    Descriptor { index: usize, error: TaprootError },
+    /// The recipient at this index could not be built.
+    Recipient {
+        index: usize,
+        error: OutputBuildError,
+    },
+    /// The change output could not be built.
+    Change(OutputBuildError),
+    /// No selection of the candidate utxos pays the recipients.
+    CoinSelection(CoinSelectionError),
+    /// The size of the transaction could not be estimated.
+    Fee(FeeError),
+    /// The utxos can't pay the recipients and the fee.
+    InsufficientFunds { needed: Amount, available: Amount },
+    /// Without a change output, the utxos are worth `excess` more than the
+    /// recipients and the fee, which would go to the fee. Only returned
+    /// without [`PsbtBuilder::allow_excess_fee`].
+    ExcessFee { excess: Amount },
+    /// The psbt failed validation.
+    Invalid(ValidationReport),
+}
+
+impl fmt::Display for PsbtBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PsbtBuildError::MissingNonWitnessUtxo { index } => {
+                write!(f, "utxo {} is not segwit and lacks its transaction", index)
+            }
+            PsbtBuildError::Input { index, error } => write!(f, "input {}: {}", index, error),
+            PsbtBuildError::Descriptor { index, error } => {
+                write!(f, "descriptor of utxo {}: {}", index, error)
+            }
+            PsbtBuildError::Recipient { index, error } => {
+                write!(f, "recipient {}: {}", index, error)
+            }
+            PsbtBuildError::Change(err) => write!(f, "change output: {}", err),
+            PsbtBuildError::CoinSelection(err) => write!(f, "coin selection: {}", err),
+            PsbtBuildError::Fee(err) => write!(f, "can't estimate the fee: {:?}", err),
+            PsbtBuildError::InsufficientFunds { needed, available } => {
+                write!(f, "{} needed but only {} available", needed, available)
+            }
+            PsbtBuildError::ExcessFee { excess } => {
+                write!(f, "{} would go to the fee without a change output", excess)
+            }
+            PsbtBuildError::Invalid(report) => write!(f, "invalid psbt: {}", report),
+        }
+    }
+}
+
+/// Creates an unsigned psbt spending a set of utxos to a set of recipients.
+///
+/// The inputs get their utxos and, with a descriptor, their scripts and key
+/// origins. The fee is estimated from the size of the finalized transaction
+/// and whatever the recipients and the fee leave goes to the change output,
+/// unless it would be dust. Without a change output, building fails if more
+/// than dust would be left to the fee, see [`PsbtBuilder::allow_excess_fee`].
+#[derive(Debug, Clone)]
+pub struct PsbtBuilder {
+    version: Version,
+    tx_version: i32,
+    lock_time: absolute::LockTime,
+    sequence: Sequence,
+    fee_rate: FeeRate,
+    utxos: Vec<Utxo>,
+    recipients: Vec<OutputBuilder>,
+    change: Option<OutputBuilder>,
+    drain: bool,
+    allow_excess_fee: bool,
+}
+
+impl PsbtBuilder {
+    /// Creates a builder of a psbt of `version`, for a version 2 transaction
+    /// without locktime signaling replaceability and paying 1 sat/vB.
+    pub fn new(version: Version) -> Self {
+        PsbtBuilder {
+            version,
+            tx_version: 2,
+            lock_time: absolute::LockTime::ZERO,
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            fee_rate: FeeRate::from_sat_per_vb_unchecked(1),
+            utxos: vec![],
+            recipients: vec![],
+            change: None,
+            drain: false,
+            allow_excess_fee: false,
+        }
+    }
+
+    /// Sets the version of the transaction.
+    pub fn tx_version(mut self, tx_version: i32) -> Self {
+        self.tx_version = tx_version;
+        self
+    }
+
+    /// Sets the locktime of the transaction.
+    pub fn lock_time(mut self, lock_time: absolute::LockTime) -> Self {
+        self.lock_time = lock_time;
+        self
+    }
+
+    /// Sets the sequence number of every input.
+    pub fn sequence(mut self, sequence: Sequence) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Sets the fee rate the transaction pays.
+    pub fn fee_rate(mut self, fee_rate: FeeRate) -> Self {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    /// Adds an output to spend.
+    pub fn add_utxo(mut self, utxo: Utxo) -> Self {
+        self.utxos.push(utxo);
+        self
+    }
+
+    /// Adds an output paying a recipient.
+    pub fn add_recipient(mut self, recipient: OutputBuilder) -> Self {
+        self.recipients.push(recipient);
+        self
+    }
+
+    /// Adds the utxos `select` chooses among `candidates`.
+    ///
+    /// `select` is one of the algorithms of
+    /// [`coin_selection`](super::coin_selection), given the candidates, the
+    /// amount to cover and the fee rate. The amount covers the recipients,
+    /// the fee of the transaction without inputs and the effective value of
+    /// the utxos already added comes off it. Candidates already added are
+    /// left out, so no outpoint is spent twice.
+    pub fn select_utxos<F>(self, candidates: &[Utxo], select: F) -> Result<Self, PsbtBuildError>
+    where
+        F: FnOnce(&[Utxo], Amount, FeeRate) -> Result<Selection, CoinSelectionError>,
+    {
+        let mut weight = TX_OVERHEAD_WEIGHT;
+        let mut target = Amount::ZERO;
+        for (index, recipient) in self.recipients.iter().enumerate() {
+            let txout = recipient
+                .txout()
+                .map_err(|error| PsbtBuildError::Recipient { index, error })?;
+            weight += txout_weight(&txout);
+            target = checked_sum([target, txout.value]).map_err(PsbtBuildError::Fee)?;
+        }
+        if let Some(ref change) = self.change {
+            let txout = change
+                .clone()
+                .amount(Amount::ZERO)
+                .txout()
+                .map_err(PsbtBuildError::Change)?;
+            weight += txout_weight(&txout);
+        }
+        let fee = self.fee_rate.fee_wu(weight).unwrap_or(Amount::MAX);
+        target = target.checked_add(fee).unwrap_or(Amount::MAX);
+        for utxo in &self.utxos {
+            let value = utxo.effective_value(self.fee_rate).unwrap_or(Amount::ZERO);
+            target = target.checked_sub(value).unwrap_or(Amount::ZERO);
+        }
+        if target == Amount::ZERO {
+            return Ok(self);
+        }
+
+        let candidates: Vec<Utxo> = candidates
+            .iter()
+            .filter(|candidate| {
+                !self
+                    .utxos
+                    .iter()
+                    .any(|utxo| utxo.outpoint == candidate.outpoint)
+            })
+            .cloned()
+            .collect();
+        let selection =
+            select(&candidates, target, self.fee_rate).map_err(PsbtBuildError::CoinSelection)?;
+        Ok(selection
+            .selected
+            .into_iter()
+            .fold(self, PsbtBuilder::add_utxo))
+    }
+
+    /// Sets the output receiving the change, placed after the recipients.
+    /// Its amount is computed by [`PsbtBuilder::build`].
+    pub fn change(mut self, change: OutputBuilder) -> Self {
+        self.change = Some(change);
+        self.drain = false;
+        self
+    }
+
+    /// Sends whatever the utxos are worth beyond the recipients and the fee
+    /// to `address`, replacing the change output.
+    ///
+    /// Unlike change, the output is always created, and [`PsbtBuilder::build`]
+    /// fails if it would be dust. Without recipients this sweeps the utxos.
+    pub fn drain_to(mut self, address: &Address) -> Self {
+        self.change = Some(OutputBuilder::new().to_address(address));
+        self.drain = true;
+        self
+    }
+
+    /// Lets [`PsbtBuilder::build`] leave whatever the recipients and the fee
+    /// don't spend to the fee when there is no change output, instead of
+    /// failing with [`PsbtBuildError::ExcessFee`].
+    pub fn allow_excess_fee(mut self) -> Self {
+        self.allow_excess_fee = true;
+        self
+    }
+
+    /// Builds the psbt.
+    pub fn build(self) -> Result<Psbt, PsbtBuildError> {
+        let version = self.version;
+        let mut inputs = Vec::with_capacity(self.utxos.len());
+        let mut txins = Vec::with_capacity(self.utxos.len());
+        for (index, utxo) in self.utxos.iter().enumerate() {
+            inputs.push(self.input(index, utxo)?);
+            txins.push(TxIn {
+                previous_output: utxo.outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: self.sequence,
+                witness: Witness::default(),
+            });
+        }
+
+        let mut outputs = Vec::with_capacity(self.recipients.len() + 1);
+        let mut txouts = Vec::with_capacity(self.recipients.len() + 1);
+        for (index, recipient) in self.recipients.into_iter().enumerate() {
+            let error = |error| PsbtBuildError::Recipient { index, error };
+            txouts.push(recipient.txout().map_err(error)?);
+            outputs.push(recipient.build(version).map_err(error)?);
+        }
+        let sent =
+            checked_sum(txouts.iter().map(|txout| txout.value)).map_err(PsbtBuildError::Fee)?;
+        let available = checked_sum(self.utxos.iter().map(|utxo| utxo.txout.value))
+            .map_err(PsbtBuildError::Fee)?;
+
+        let change_script = match self.change {
+            Some(change) => {
+                let change = change.amount(Amount::ZERO);
+                let txout = change.txout().map_err(PsbtBuildError::Change)?;
+                txouts.push(txout.clone());
+                outputs.push(change.build(version).map_err(PsbtBuildError::Change)?);
+                Some(txout.script_pubkey)
+            }
+            None => None,
+        };
+
+        let (unsigned_tx, tx_version, fallback_locktime) = match version {
+            Version::PsbtV0 => (
+                Some(Transaction {
+                    version: transaction::Version(self.tx_version),
+                    lock_time: self.lock_time,
+                    input: txins,
+                    output: txouts,
+                }),
+                None,
+                None,
+            ),
+            Version::PsbtV2 => (
+                None,
+                Some(self.tx_version),
+                Some(self.lock_time.to_consensus_u32()),
+            ),
+        };
+        let mut psbt = Psbt {
+            inner: PartiallySignedTransactionInner {
+                unsigned_tx,
+                version,
+                xpub: Default::default(),
+                proprietary: Default::default(),
+                unknown: Default::default(),
+                inputs,
+                outputs,
+                tx_version,
+                fallback_locktime,
+                tx_modifiable: None,
+                sp_ecdh_shares: Default::default(),
+                sp_dleq_proofs: Default::default(),
+            },
+            fee_policy: None,
+            network: None,
+        };
+
+        let insufficient = |fee: Amount| PsbtBuildError::InsufficientFunds {
+            needed: sent.checked_add(fee).unwrap_or(Amount::MAX),
+            available,
+        };
+        let fee = estimate_fee(&psbt, self.fee_rate)?;
+        let remaining = available
+            .checked_sub(sent)
+            .and_then(|left| left.checked_sub(fee));
+        if let Some(change_script) = change_script {
+            let change_index = psbt.inner.outputs.len() - 1;
+            let dust = dust_threshold(&change_script, DEFAULT_DUST_RELAY_FEE);
+            if self.drain {
+                // The fee depends on the size of the final transaction, so
+                // settle the amount once setting it no longer raises the fee
+                let mut fee = fee;
+                loop {
+                    let amount = available
+                        .checked_sub(sent)
+                        .and_then(|left| left.checked_sub(fee))
+                        .filter(|amount| *amount >= dust)
+                        .ok_or_else(|| {
+                            insufficient(fee.checked_add(dust).unwrap_or(Amount::MAX))
+                        })?;
+                    psbt.set_output_amount(change_index, amount);
+                    let new_fee = estimate_fee(&psbt, self.fee_rate)?;
+                    if new_fee <= fee {
+                        break;
+                    }
+                    fee = new_fee;
+                }
+                return Psbt::from_inner(psbt.inner).map_err(PsbtBuildError::Invalid);
+            }
+            match remaining {
+                Some(change) if change >= dust => {
+                    psbt.set_output_amount(change_index, change);
+                }
+                _ => {
+                    // Leave what's left to the fee rather than create dust
+                    psbt.inner.outputs.pop();
+                    if let Some(ref mut tx) = psbt.inner.unsigned_tx {
+                        tx.output.pop();
+                    }
+                    let fee = estimate_fee(&psbt, self.fee_rate)?;
+                    if available
+                        .checked_sub(sent)
+                        .and_then(|left| left.checked_sub(fee))
+                        .is_none()
+                    {
+                        return Err(insufficient(fee));
+                    }
+                }
+            }
+        } else {
+            match remaining {
+                None => return Err(insufficient(fee)),
+                Some(excess) if excess >= EXCESS_FEE_THRESHOLD && !self.allow_excess_fee => {
+                    return Err(PsbtBuildError::ExcessFee { excess });
+                }
+                Some(_) => {}
+            }
+        }
+
+        Psbt::from_inner(psbt.inner).map_err(PsbtBuildError::Invalid)
+    }
+
+    /// Builds the input spending `utxo`, the utxo at `index`.
+    fn input(&self, index: usize, utxo: &Utxo) -> Result<Input, PsbtBuildError> {
+        let script_pubkey = &utxo.txout.script_pubkey;
+        // A P2SH output is only segwit if its redeem script is a witness
+        // program, which only the descriptor tells
+        let nested_segwit = script_pubkey.is_p2sh()
+            && utxo
+                .descriptor
+                .as_ref()
+                .and_then(|descriptor| descriptor.redeem_script.as_ref())
+                .map_or(false, |redeem_script| redeem_script.is_witness_program());
+        let mut builder = InputBuilder::new();
+        if script_pubkey.is_witness_program() || nested_segwit {
+            builder = builder.witness_utxo(utxo.txout.clone());
+        } else if utxo.non_witness_utxo.is_none() {
+            return Err(PsbtBuildError::MissingNonWitnessUtxo { index });
+        }
+        if let Some(ref tx) = utxo.non_witness_utxo {
+            builder = builder.non_witness_utxo(Arc::clone(tx));
+        }
+        if self.version == Version::PsbtV2 {
+            builder = builder
+                .previous_outpoint(utxo.outpoint.txid, utxo.outpoint.vout)
+                .sequence(self.sequence);
+        }
+        let mut input = builder
+            .build(self.version)
+            .map_err(|error| PsbtBuildError::Input { index, error })?;
+        if let Some(ref descriptor) = utxo.descriptor {
+            input
+                .update_with_descriptor(descriptor)
+                .map_err(|error| PsbtBuildError::Descriptor { index, error })?;
+        }
+        Ok(input)
+    }
+}
+
+/// The weight of the version, locktime, input and output counts and segwit
+/// marker of a transaction.
+const TX_OVERHEAD_WEIGHT: Weight = Weight::from_wu((4 + 4 + 1 + 1) * 4 + 2);
+
+/// The weight `txout` adds to a transaction.
+fn txout_weight(txout: &TxOut) -> Weight {
+    let script_len = txout.script_pubkey.len();
+    Weight::from_wu(((8 + VarInt(script_len as u64).size() + script_len) * 4) as u64)
+}
+
+/// The fee `psbt` pays at `fee_rate` once finalized.
+fn estimate_fee(psbt: &Psbt, fee_rate: FeeRate) -> Result<Amount, PsbtBuildError> {
+    let vsize = psbt.estimate_vsize().map_err(PsbtBuildError::Fee)?;
+    Ok(fee_rate.fee_vb(vsize).unwrap_or(Amount::MAX))
+}
@@ -0,0 +1,255 @@
+//! The `psbt-tool` command line interface, built with the `cli` feature.
+//!
+//! ```text
+//! psbt-tool decode   [FILE]
+//! psbt-tool analyze  [FILE]
+//! psbt-tool combine  FILE FILE...
+//! psbt-tool finalize [FILE]
+//! psbt-tool extract  [FILE]
+//! psbt-tool convert  --to <v0|v2> [FILE]
+//! ```
+//!
+//! Psbts are read from the given files, or from stdin if there's none or
+//! it's `-`, which can be given once. Binary psbts are recognized by their
+//! magic bytes, text made only of an even number of hex digits is read as
+//! hex and anything else as base64. Psbts are written as base64, extracted
+//! transactions as hex.
+
+use core::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+
+use crate::consensus::encode;
+use crate::prelude::*;
+
+use super::analyzer::PsbtAnalysis;
+use super::encoding::PsbtParseError;
+use super::poc::Psbt;
+use super::serialize::PSBT_MAGIC;
+
+const USAGE: &str = "usage: psbt-tool <decode|analyze|combine|finalize|extract|convert> [ARGS]
+  decode   [FILE]              print a report of the psbt
+  analyze  [FILE]              print what each input lacks and who acts next
+  combine  FILE FILE...        merge psbts of the same transaction
+  finalize [FILE]              finalize every input
+  extract  [FILE]              print the final transaction as hex
+  convert  --to <v0|v2> [FILE] change the psbt version";
+
+/// Errors encountered while running a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliError {
+    /// The arguments don't form a valid command, with the reason.
+    Usage(String),
+    /// Reading the input or writing the output failed.
+    Io(io::ErrorKind, String),
+    /// The input isn't a psbt in any supported encoding.
+    Parse { source: String, err: PsbtParseError },
+    /// The command failed on a valid psbt, with the reason.
+    Command {
+        command: &'static str,
+        reason: String,
+    },
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(reason) => write!(f, "{}\n{}", reason, USAGE),
+            CliError::Io(_, err) => write!(f, "{}", err),
+            CliError::Parse { source, err } => write!(f, "{}: {}", source, err),
+            CliError::Command { command, reason } => write!(f, "{} failed: {}", command, reason),
+        }
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(err: io::Error) -> Self {
+        CliError::Io(err.kind(), err.to_string())
+    }
+}
+
+impl CliError {
+    fn command<E: fmt::Display>(command: &'static str, err: E) -> Self {
+        CliError::Command {
+            command,
+            reason: err.to_string(),
+        }
+    }
+}
+
+/// Runs the command given by `args`, without the program name, reading
+/// psbts from stdin where no file is given and writing the result to
+/// `stdout`.
+pub fn run<I, R, W>(args: I, stdin: &mut R, stdout: &mut W) -> Result<(), CliError>
+where
+    I: IntoIterator<Item = String>,
+    R: Read,
+    W: Write,
+{
+    let mut args = args.into_iter();
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Err(CliError::Usage("missing command".to_string())),
+    };
+    let args: Vec<String> = args.collect();
+
+    match command.as_str() {
+        "decode" => {
+            let psbt = read_single(&args, stdin)?;
+            writeln!(stdout, "{}", psbt.to_pretty_string())?;
+        }
+        "analyze" => {
+            let psbt = read_single(&args, stdin)?;
+            let analysis = psbt
+                .analyze()
+                .map_err(|err| CliError::command("analyze", err))?;
+            write_analysis(stdout, &analysis)?;
+        }
+        "combine" => {
+            if args.len() < 2 {
+                return Err(CliError::Usage(
+                    "combine needs at least two psbts".to_string(),
+                ));
+            }
+            if args.iter().filter(|path| *path == "-").count() > 1 {
+                return Err(CliError::Usage("stdin can only be read once".to_string()));
+            }
+            let mut psbts = args.iter().map(|path| read_psbt(path, stdin));
+            let mut combined = psbts.next().expect("checked above")?;
+            for psbt in psbts {
+                combined
+                    .combine(psbt?)
+                    .map_err(|err| CliError::command("combine", err))?;
+            }
+            writeln!(stdout, "{}", combined.to_base64())?;
+        }
+        "finalize" => {
+            let mut psbt = read_single(&args, stdin)?;
+            psbt.finalize()
+                .map_err(|err| CliError::command("finalize", err))?;
+            writeln!(stdout, "{}", psbt.to_base64())?;
+        }
+        "extract" => {
+            let psbt = read_single(&args, stdin)?;
+            let tx = psbt
+                .extract_tx()
+                .map_err(|err| CliError::command("extract", format!("{:?}", err)))?;
+            writeln!(stdout, "{}", encode::serialize_hex(&tx))?;
+        }
+        "convert" => {
+            let (target, args) = match args.split_first() {
+                Some((flag, rest)) if flag == "--to" && !rest.is_empty() => {
+                    (rest[0].as_str(), &rest[1..])
+                }
+                _ => return Err(CliError::Usage("convert needs --to <v0|v2>".to_string())),
+            };
+            let mut psbt = read_single(args, stdin)?;
+            match target {
+                "v0" => {
+                    psbt.inner = psbt
+                        .downgraded()
+                        .map_err(|err| CliError::command("convert", err))?;
+                }
+                "v2" => psbt
+                    .upgrade_to_v2()
+                    .map_err(|err| CliError::command("convert", err))?,
+                other => {
+                    return Err(CliError::Usage(format!("unknown version {}", other)));
+                }
+            }
+            writeln!(stdout, "{}", psbt.to_base64())?;
+        }
+        "help" | "--help" | "-h" => writeln!(stdout, "{}", USAGE)?,
+        other => return Err(CliError::Usage(format!("unknown command {}", other))),
+    }
+    Ok(())
+}
+
+/// Reads the psbt of a command taking at most one file.
+fn read_single<R: Read>(args: &[String], stdin: &mut R) -> Result<Psbt, CliError> {
+    match args {
+        [] => read_psbt("-", stdin),
+        [path] => read_psbt(path, stdin),
+        _ => Err(CliError::Usage("too many arguments".to_string())),
+    }
+}
+
+/// Reads and decodes the psbt in the file at `path`, or on stdin if it's
+/// `-`.
+fn read_psbt<R: Read>(path: &str, stdin: &mut R) -> Result<Psbt, CliError> {
+    let bytes = if path == "-" {
+        let mut bytes = Vec::new();
+        stdin.read_to_end(&mut bytes)?;
+        bytes
+    } else {
+        fs::read(path)?
+    };
+    let source = if path == "-" { "stdin" } else { path };
+    decode(&bytes).map_err(|err| CliError::Parse {
+        source: source.to_string(),
+        err,
+    })
+}
+
+/// Decodes a binary, base64 or hex psbt.
+fn decode(bytes: &[u8]) -> Result<Psbt, PsbtParseError> {
+    if bytes.starts_with(&PSBT_MAGIC) {
+        return Psbt::deserialize(bytes).map_err(PsbtParseError::PsbtEncoding);
+    }
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim();
+    // Base64 psbts start with `cHNidP8`, so they are never all hex digits,
+    // while hex ones of the right length would decode as base64
+    if is_hex(text) {
+        Psbt::from_hex(text)
+    } else {
+        Psbt::from_base64(text)
+    }
+}
+
+/// Returns `true` if `text` is a non-empty, even number of hex digits.
+fn is_hex(text: &str) -> bool {
+    !text.is_empty() && text.len() % 2 == 0 && text.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn write_analysis<W: Write>(stdout: &mut W, analysis: &PsbtAnalysis) -> io::Result<()> {
+    for (index, input) in analysis.inputs.iter().enumerate() {
+        write!(stdout, "input {}: next {:?}", index, input.next_role)?;
+        let missing = &input.missing;
+        let mut lacks = vec![];
+        if missing.utxo {
+            lacks.push("utxo".to_string());
+        }
+        if missing.redeem_script {
+            lacks.push("redeem_script".to_string());
+        }
+        if missing.witness_script {
+            lacks.push("witness_script".to_string());
+        }
+        if missing.key_origins {
+            lacks.push("key origins".to_string());
+        }
+        for key in &missing.signatures {
+            lacks.push(format!("signature of {}", key));
+        }
+        if lacks.is_empty() {
+            writeln!(stdout)?;
+        } else {
+            writeln!(stdout, ", missing {}", lacks.join(", "))?;
+        }
+    }
+    writeln!(stdout, "next role: {:?}", analysis.next_role)?;
+    match analysis.fee {
+        Some(fee) => writeln!(stdout, "fee: {}", fee)?,
+        None => writeln!(stdout, "fee: unknown")?,
+    }
+    match analysis.estimated_vsize {
+        Some(vsize) => writeln!(stdout, "estimated vsize: {} vB", vsize)?,
+        None => writeln!(stdout, "estimated vsize: unknown")?,
+    }
+    writeln!(
+        stdout,
+        "extractable: {}",
+        if analysis.is_extractable { "yes" } else { "no" }
+    )
+}
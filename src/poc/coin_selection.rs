@@ -0,0 +1,266 @@
+//! Coin selection: choosing which utxos a psbt spends to pay a target amount
+//! at a fee rate.
+//!
+//! Every algorithm weighs a candidate by its effective value, its amount
+//! minus the fee of spending it, the weight of its input being estimated
+//! with [`Input::max_satisfaction_weight`]. Candidates worth less than the
+//! fee of spending them are never selected. The selection is passed on to
+//! [`PsbtBuilder::select_utxos`](super::builder::PsbtBuilder::select_utxos).
+
+use core::fmt;
+
+#[cfg(feature = "rand")]
+use rand::seq::SliceRandom;
+#[cfg(feature = "rand")]
+use rand::Rng;
+
+use crate::prelude::*;
+use crate::{Amount, FeeRate, Weight};
+
+use super::builder::Utxo;
+use super::fee::checked_sum;
+use super::input::Input;
+
+/// The weight of an input without its scriptSig and witness: the outpoint
+/// and the sequence number.
+const TXIN_BASE_WEIGHT: u64 = (32 + 4 + 4) * 4;
+
+/// The number of selections [`branch_and_bound`] tries before giving up,
+/// as in Bitcoin Core.
+const BNB_TOTAL_TRIES: usize = 100_000;
+
+/// Errors encountered while selecting coins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoinSelectionError {
+    /// The weight of the input spending the candidate at this index can't
+    /// be estimated.
+    UnknownWeight { index: usize },
+    /// The effective value of all candidates doesn't cover the target.
+    InsufficientFunds { needed: Amount, available: Amount },
+    /// [`branch_and_bound`] found no selection avoiding change.
+    NoExactMatch,
+    /// The candidates are worth more than [`Amount::MAX_MONEY`] together.
+    AmountOverflow,
+}
+
+impl fmt::Display for CoinSelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoinSelectionError::UnknownWeight { index } => {
+                write!(
+                    f,
+                    "can't estimate the weight of spending candidate {}",
+                    index
+                )
+            }
+            CoinSelectionError::InsufficientFunds { needed, available } => write!(
+                f,
+                "{} needed but candidates are only worth {}",
+                needed, available
+            ),
+            CoinSelectionError::NoExactMatch => write!(f, "no selection avoids change"),
+            CoinSelectionError::AmountOverflow => {
+                write!(f, "candidates exceed the maximum amount of money")
+            }
+        }
+    }
+}
+
+/// The utxos chosen by a coin selection algorithm.
+#[derive(Debug, Clone)]
+pub struct Selection {
+    /// The chosen utxos, in the order they were selected.
+    pub selected: Vec<Utxo>,
+    /// The sum of the amounts of the chosen utxos.
+    pub amount: Amount,
+    /// The sum of the effective values of the chosen utxos, at least the
+    /// target.
+    pub effective_value: Amount,
+}
+
+impl Utxo {
+    /// Returns the weight the input spending this utxo adds to a
+    /// transaction, or `None` if its satisfaction is unknown.
+    pub fn input_weight(&self) -> Option<Weight> {
+        let mut input = Input {
+            witness_utxo: Some(self.txout.clone()),
+            ..Default::default()
+        };
+        if let Some(ref descriptor) = self.descriptor {
+            input.update_with_descriptor(descriptor).ok()?;
+        }
+        let satisfaction = input.max_satisfaction_weight(&self.outpoint)?;
+        Some(Weight::from_wu(TXIN_BASE_WEIGHT) + satisfaction)
+    }
+
+    /// Returns the amount of the utxo minus the fee of spending it at
+    /// `fee_rate`, or `None` if the fee exceeds the amount.
+    pub fn effective_value(&self, fee_rate: FeeRate) -> Option<Amount> {
+        let fee = fee_rate.fee_wu(self.input_weight()?)?;
+        self.txout.value.checked_sub(fee)
+    }
+}
+
+/// Selects the candidates of largest effective value until they cover
+/// `target`.
+pub fn largest_first(
+    candidates: &[Utxo],
+    target: Amount,
+    fee_rate: FeeRate,
+) -> Result<Selection, CoinSelectionError> {
+    let mut pool = effective_values(candidates, fee_rate)?;
+    pool.sort_by(|(_, a), (_, b)| b.cmp(a));
+    select_until(candidates, pool, target)
+}
+
+/// Selects candidates in random order until they cover `target`.
+#[cfg(feature = "rand")]
+pub fn single_random_draw<R: Rng + ?Sized>(
+    candidates: &[Utxo],
+    target: Amount,
+    fee_rate: FeeRate,
+    rng: &mut R,
+) -> Result<Selection, CoinSelectionError> {
+    let mut pool = effective_values(candidates, fee_rate)?;
+    pool.shuffle(rng);
+    select_until(candidates, pool, target)
+}
+
+/// Searches for the candidates whose effective value exceeds `target` by at
+/// most `cost_of_change`, so the transaction needs no change output.
+///
+/// Among the selections found, the one exceeding the target the least is
+/// returned. The search stops after a fixed number of tries, failing with
+/// [`CoinSelectionError::NoExactMatch`] if it found nothing by then.
+pub fn branch_and_bound(
+    candidates: &[Utxo],
+    target: Amount,
+    fee_rate: FeeRate,
+    cost_of_change: Amount,
+) -> Result<Selection, CoinSelectionError> {
+    let mut pool = effective_values(candidates, fee_rate)?;
+    pool.sort_by(|(_, a), (_, b)| b.cmp(a));
+    check_available(&pool, target)?;
+
+    let values: Vec<u64> = pool.iter().map(|(_, value)| value.to_sat()).collect();
+    let target = target.to_sat();
+    let upper_bound = target.saturating_add(cost_of_change.to_sat());
+    // The effective value of the candidates after each position
+    let mut remaining = vec![0u64; values.len() + 1];
+    for position in (0..values.len()).rev() {
+        remaining[position] = remaining[position + 1] + values[position];
+    }
+
+    let mut best: Option<(u64, Vec<usize>)> = None;
+    let mut included: Vec<usize> = Vec::new();
+    let mut value = 0u64;
+    let mut position = 0;
+    for _ in 0..BNB_TOTAL_TRIES {
+        let backtrack = if value > upper_bound || value + remaining[position] < target {
+            true
+        } else if value >= target {
+            let excess = value - target;
+            if best
+                .as_ref()
+                .map_or(true, |(best_excess, _)| excess < *best_excess)
+            {
+                best = Some((excess, included.clone()));
+            }
+            true
+        } else {
+            position == values.len()
+        };
+
+        if backtrack {
+            // Drop the last included candidate and try without it
+            match included.pop() {
+                Some(last) => {
+                    value -= values[last];
+                    position = last + 1;
+                }
+                None => break,
+            }
+        } else {
+            included.push(position);
+            value += values[position];
+            position += 1;
+        }
+    }
+
+    let (_, positions) = best.ok_or(CoinSelectionError::NoExactMatch)?;
+    selection(
+        candidates,
+        positions.into_iter().map(|position| pool[position]),
+    )
+}
+
+/// The index and effective value of every candidate worth spending.
+fn effective_values(
+    candidates: &[Utxo],
+    fee_rate: FeeRate,
+) -> Result<Vec<(usize, Amount)>, CoinSelectionError> {
+    // Bounding the total keeps every later sum of candidates from overflowing
+    checked_sum(candidates.iter().map(|candidate| candidate.txout.value))
+        .map_err(|_| CoinSelectionError::AmountOverflow)?;
+    let mut pool = Vec::with_capacity(candidates.len());
+    for (index, candidate) in candidates.iter().enumerate() {
+        if candidate.input_weight().is_none() {
+            return Err(CoinSelectionError::UnknownWeight { index });
+        }
+        if let Some(value) = candidate.effective_value(fee_rate) {
+            if value > Amount::ZERO {
+                pool.push((index, value));
+            }
+        }
+    }
+    Ok(pool)
+}
+
+/// Fails if the whole `pool` doesn't cover `target`.
+fn check_available(pool: &[(usize, Amount)], target: Amount) -> Result<(), CoinSelectionError> {
+    let available = checked_sum(pool.iter().map(|(_, value)| *value))
+        .map_err(|_| CoinSelectionError::AmountOverflow)?;
+    if available < target {
+        return Err(CoinSelectionError::InsufficientFunds {
+            needed: target,
+            available,
+        });
+    }
+    Ok(())
+}
+
+/// Selects the candidates of `pool` in order until they cover `target`.
+fn select_until(
+    candidates: &[Utxo],
+    pool: Vec<(usize, Amount)>,
+    target: Amount,
+) -> Result<Selection, CoinSelectionError> {
+    check_available(&pool, target)?;
+    let mut value = Amount::ZERO;
+    let chosen = pool.into_iter().take_while(|(_, effective_value)| {
+        let covered = value >= target;
+        value = value.checked_add(*effective_value).unwrap_or(Amount::MAX);
+        !covered
+    });
+    selection(candidates, chosen)
+}
+
+fn selection(
+    candidates: &[Utxo],
+    chosen: impl Iterator<Item = (usize, Amount)>,
+) -> Result<Selection, CoinSelectionError> {
+    let mut selection = Selection {
+        selected: vec![],
+        amount: Amount::ZERO,
+        effective_value: Amount::ZERO,
+    };
+    for (index, effective_value) in chosen {
+        let utxo = candidates[index].clone();
+        selection.amount = checked_sum([selection.amount, utxo.txout.value])
+            .map_err(|_| CoinSelectionError::AmountOverflow)?;
+        selection.effective_value = checked_sum([selection.effective_value, effective_value])
+            .map_err(|_| CoinSelectionError::AmountOverflow)?;
+        selection.selected.push(utxo);
+    }
+    Ok(selection)
+}
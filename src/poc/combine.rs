@@ -0,0 +1,338 @@
+//! The combiner role of BIP-174, merging psbts of the same transaction.
+
+use core::fmt;
+
+use crate::prelude::*;
+use crate::psbt::{raw, Version};
+
+use super::diff::{field_name, ChangeKind};
+use super::input::Input;
+use super::modifiable::{HAS_SIGHASH_SINGLE, INPUTS_MODIFIABLE, OUTPUTS_MODIFIABLE};
+use super::output::Output;
+use super::poc::Psbt;
+use super::serialize::{MapLocation, PSBT_GLOBAL_TX_MODIFIABLE};
+
+/// How [`Psbt::combine_with`] resolves keys both psbts set to different
+/// values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Fail with [`CombineError::Conflicts`], leaving the psbt untouched.
+    #[default]
+    ErrorOnConflict,
+    /// Keep the values of this psbt.
+    PreferSelf,
+    /// Take the values of the other psbt.
+    PreferOther,
+}
+
+/// How [`Psbt::combine_with`] combines two psbts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CombineOptions {
+    /// How keys both psbts set to different values are resolved.
+    pub conflict_policy: ConflictPolicy,
+    /// Upgrades the PsbtV0 of a PsbtV0 and PsbtV2 pair to a PsbtV2 first,
+    /// otherwise such psbts fail to combine.
+    pub upgrade_version: bool,
+}
+
+impl CombineOptions {
+    /// Replaces the conflict policy of the options.
+    pub fn with_conflict_policy(mut self, conflict_policy: ConflictPolicy) -> Self {
+        self.conflict_policy = conflict_policy;
+        self
+    }
+
+    /// Makes psbts of different versions fail to combine.
+    pub fn without_version_upgrade(mut self) -> Self {
+        self.upgrade_version = false;
+        self
+    }
+}
+
+impl Default for CombineOptions {
+    fn default() -> Self {
+        CombineOptions {
+            conflict_policy: ConflictPolicy::default(),
+            upgrade_version: true,
+        }
+    }
+}
+
+/// A key both combined psbts set, to different values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CombineConflict {
+    /// The map holding the key.
+    pub map: MapLocation,
+    /// The key, with its type and key data.
+    pub key: raw::Key,
+    /// The serialized value of this psbt.
+    pub own: Vec<u8>,
+    /// The serialized value of the other psbt.
+    pub other: Vec<u8>,
+}
+
+impl CombineConflict {
+    /// The BIP-174 or BIP-370 name of the field the key belongs to.
+    pub fn field_name(&self) -> &'static str {
+        field_name(self.map, self.key.type_value)
+    }
+}
+
+impl fmt::Display for CombineConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: conflicting {}", self.map, self.field_name())
+    }
+}
+
+/// Errors encountered while combining two psbts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CombineError {
+    /// The psbts are of different versions, only returned without
+    /// [`CombineOptions::upgrade_version`].
+    DifferentVersions { own: Version, other: Version },
+    /// The psbts describe different transactions.
+    DifferentUnsignedTx,
+    /// The unsigned transaction of one of the psbts could not be built.
+    UnsignedTx(String),
+    /// The `sighash_type` of the input at this index in one psbt contradicts
+    /// the `sighash_type` or a signature of the same input in the other one,
+    /// whatever the conflict policy.
+    SighashTypeMismatch { index: usize },
+    /// The psbts set keys to different values, only returned with
+    /// [`ConflictPolicy::ErrorOnConflict`].
+    Conflicts(Vec<CombineConflict>),
+}
+
+impl fmt::Display for CombineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CombineError::DifferentVersions { own, other } => {
+                write!(f, "can't combine a {:?} psbt with a {:?} psbt", own, other)
+            }
+            CombineError::DifferentUnsignedTx => {
+                write!(f, "the psbts describe different transactions")
+            }
+            CombineError::UnsignedTx(err) => write!(f, "invalid unsigned transaction: {}", err),
+            CombineError::SighashTypeMismatch { index } => {
+                write!(f, "contradictory sighash types on input {}", index)
+            }
+            CombineError::Conflicts(conflicts) => {
+                write!(f, "the psbts conflict")?;
+                for (index, conflict) in conflicts.iter().enumerate() {
+                    let separator = if index == 0 { ": " } else { ", " };
+                    write!(f, "{}{}", separator, conflict)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Psbt {
+    /// Merges the data of `other`, a psbt of the same transaction, into this
+    /// psbt.
+    ///
+    /// A PsbtV0 combined with a PsbtV2 is upgraded to a PsbtV2 first. Fails
+    /// with [`CombineError::Conflicts`] if the psbts set any key to different
+    /// values, see [`Psbt::combine_with`] to resolve them instead.
+    pub fn combine(&mut self, other: Psbt) -> Result<(), CombineError> {
+        self.combine_with(other, CombineOptions::default())
+            .map(|_| ())
+    }
+
+    /// Merges the data of `other` like [`Psbt::combine`], as `options` ask
+    /// for. Returns the conflicts that were resolved.
+    ///
+    /// The `tx_modifiable` flags never conflict: inputs or outputs stay
+    /// modifiable only if both psbts allow it.
+    pub fn combine_with(
+        &mut self,
+        mut other: Psbt,
+        options: CombineOptions,
+    ) -> Result<Vec<CombineConflict>, CombineError> {
+        if options.upgrade_version && self.inner.version != other.inner.version {
+            if self.inner.version == Version::PsbtV0 {
+                // Restore the PsbtV0 if the upgraded psbts fail to combine
+                let original = self.inner.clone();
+                self.upgrade_to_v2().map_err(CombineError::UnsignedTx)?;
+                let result = self.combine_with(other, options);
+                if result.is_err() {
+                    self.inner = original;
+                }
+                return result;
+            }
+            other.upgrade_to_v2().map_err(CombineError::UnsignedTx)?;
+        }
+        if self.inner.version != other.inner.version {
+            return Err(CombineError::DifferentVersions {
+                own: self.inner.version,
+                other: other.inner.version,
+            });
+        }
+        let own_tx = self.unsigned_tx().map_err(CombineError::UnsignedTx)?;
+        let other_tx = other.unsigned_tx().map_err(CombineError::UnsignedTx)?;
+        if own_tx != other_tx {
+            return Err(CombineError::DifferentUnsignedTx);
+        }
+
+        let mismatch = self
+            .inner
+            .inputs
+            .iter()
+            .zip(&other.inner.inputs)
+            .position(|(input, other)| input.sighash_type_conflicts(other));
+        if let Some(index) = mismatch {
+            return Err(CombineError::SighashTypeMismatch { index });
+        }
+
+        let policy = options.conflict_policy;
+        let conflicts = self.conflicts(&other);
+        if policy == ConflictPolicy::ErrorOnConflict && !conflicts.is_empty() {
+            return Err(CombineError::Conflicts(conflicts));
+        }
+
+        let mut other = other.to_inner();
+        if policy == ConflictPolicy::PreferOther {
+            // Merging into the other psbt lets its values win
+            core::mem::swap(&mut self.inner, &mut other);
+        }
+        merge_map(&mut self.inner.xpub, other.xpub);
+        merge_map(&mut self.inner.proprietary, other.proprietary);
+        merge_map(&mut self.inner.unknown, other.unknown);
+        merge_map(&mut self.inner.sp_ecdh_shares, other.sp_ecdh_shares);
+        merge_map(&mut self.inner.sp_dleq_proofs, other.sp_dleq_proofs);
+        self.inner.tx_modifiable = match (self.inner.tx_modifiable, other.tx_modifiable) {
+            // Adding inputs or outputs must be allowed by both, a
+            // SIGHASH_SINGLE signature in either one counts
+            (Some(own), Some(other)) => {
+                let modifiable = own & other & (INPUTS_MODIFIABLE | OUTPUTS_MODIFIABLE);
+                Some(modifiable | ((own | other) & HAS_SIGHASH_SINGLE))
+            }
+            (own, other) => own.or(other),
+        };
+
+        for (input, other) in self.inner.inputs.iter_mut().zip(other.inputs) {
+            input.combine(other);
+        }
+        for (output, other) in self.inner.outputs.iter_mut().zip(other.outputs) {
+            output.combine(other);
+        }
+        Ok(conflicts)
+    }
+
+    /// The keys this psbt and `other` both set to different values.
+    fn conflicts(&self, other: &Psbt) -> Vec<CombineConflict> {
+        self.diff(other)
+            .changes
+            .into_iter()
+            .filter(|change| {
+                change.map != MapLocation::Global
+                    || change.key.type_value != PSBT_GLOBAL_TX_MODIFIABLE
+            })
+            .filter_map(|change| match change.kind {
+                ChangeKind::Modified { old, new } => Some(CombineConflict {
+                    map: change.map,
+                    key: change.key,
+                    own: old,
+                    other: new,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Input {
+    /// Merges the data of `other`, the same input of another psbt, into this
+    /// input, keeping the values of this input for fields set in both.
+    pub fn combine(&mut self, other: Input) {
+        merge_option(&mut self.non_witness_utxo, other.non_witness_utxo);
+        merge_option(&mut self.witness_utxo, other.witness_utxo);
+        merge_map(&mut self.partial_sigs, other.partial_sigs);
+        merge_option(&mut self.sighash_type, other.sighash_type);
+        merge_option(&mut self.redeem_script, other.redeem_script);
+        merge_option(&mut self.witness_script, other.witness_script);
+        merge_map(&mut self.bip32_derivation, other.bip32_derivation);
+        merge_option(&mut self.final_script_sig, other.final_script_sig);
+        merge_option(&mut self.final_script_witness, other.final_script_witness);
+        merge_option(&mut self.por_commitment, other.por_commitment);
+        merge_map(&mut self.ripemd160_preimages, other.ripemd160_preimages);
+        merge_map(&mut self.sha256_preimages, other.sha256_preimages);
+        merge_map(&mut self.hash160_preimages, other.hash160_preimages);
+        merge_map(&mut self.hash256_preimages, other.hash256_preimages);
+        merge_option(&mut self.tap_key_sig, other.tap_key_sig);
+        merge_map(&mut self.tap_script_sigs, other.tap_script_sigs);
+        merge_map(&mut self.tap_scripts, other.tap_scripts);
+        merge_map(&mut self.tap_key_origins, other.tap_key_origins);
+        merge_option(&mut self.tap_internal_key, other.tap_internal_key);
+        merge_option(&mut self.tap_merkle_root, other.tap_merkle_root);
+        merge_option(&mut self.tap_annex, other.tap_annex);
+        merge_map(
+            &mut self.musig2_participant_pubkeys,
+            other.musig2_participant_pubkeys,
+        );
+        merge_map(&mut self.musig2_pub_nonces, other.musig2_pub_nonces);
+        merge_map(&mut self.musig2_partial_sigs, other.musig2_partial_sigs);
+        merge_map(&mut self.sp_ecdh_shares, other.sp_ecdh_shares);
+        merge_map(&mut self.sp_dleq_proofs, other.sp_dleq_proofs);
+        merge_map(&mut self.proprietary, other.proprietary);
+        merge_map(&mut self.unknown, other.unknown);
+        merge_option(&mut self.previous_tx_id, other.previous_tx_id);
+        merge_option(&mut self.output_index, other.output_index);
+        merge_option(&mut self.sequence, other.sequence);
+        merge_option(
+            &mut self.required_time_locktime,
+            other.required_time_locktime,
+        );
+        merge_option(
+            &mut self.required_height_locktime,
+            other.required_height_locktime,
+        );
+        #[cfg(feature = "elements")]
+        self.elements.combine(other.elements);
+
+        // The partial data is no longer needed once either side finalized
+        if self.is_finalized() {
+            self.clear_finalized_data();
+        }
+    }
+}
+
+impl Output {
+    /// Merges the data of `other`, the same output of another psbt, into this
+    /// output, keeping the values of this output for fields set in both.
+    pub fn combine(&mut self, other: Output) {
+        merge_option(&mut self.redeem_script, other.redeem_script);
+        merge_option(&mut self.witness_script, other.witness_script);
+        merge_map(&mut self.bip32_derivation, other.bip32_derivation);
+        merge_option(&mut self.tap_internal_key, other.tap_internal_key);
+        merge_option(&mut self.tap_tree, other.tap_tree);
+        merge_map(&mut self.tap_key_origins, other.tap_key_origins);
+        merge_map(
+            &mut self.musig2_participant_pubkeys,
+            other.musig2_participant_pubkeys,
+        );
+        merge_option(&mut self.sp_v0_info, other.sp_v0_info);
+        merge_option(&mut self.sp_v0_label, other.sp_v0_label);
+        merge_map(&mut self.proprietary, other.proprietary);
+        merge_map(&mut self.unknown, other.unknown);
+        merge_option(&mut self.amount, other.amount);
+        merge_option(&mut self.script, other.script);
+        #[cfg(feature = "elements")]
+        self.elements.combine(other.elements);
+    }
+}
+
+/// Adds the entries of `other` whose keys `map` doesn't have yet.
+fn merge_map<K: Ord, V>(map: &mut BTreeMap<K, V>, other: BTreeMap<K, V>) {
+    for (key, value) in other {
+        map.entry(key).or_insert(value);
+    }
+}
+
+/// Sets `field` to `other` unless it already has a value.
+fn merge_option<T>(field: &mut Option<T>, other: Option<T>) {
+    if field.is_none() {
+        *field = other;
+    }
+}
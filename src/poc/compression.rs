@@ -0,0 +1,104 @@
+//! Deflate compression of serialized psbts, which shrinks psbts carrying
+//! full `non_witness_utxo` transactions considerably.
+//!
+//! Compressed psbts start with [`COMPRESSED_MAGIC`] followed by an algorithm
+//! byte, so readers can tell them apart from plain psbts.
+
+use core::fmt;
+
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+
+use crate::prelude::*;
+
+use super::poc::Psbt;
+use super::serialize::{DecodeError, ParseLimits, ParseOptions, PSBT_MAGIC};
+
+/// The magic bytes every compressed psbt starts with, `psbz` followed by
+/// 0xff.
+pub const COMPRESSED_MAGIC: [u8; 5] = [0x70, 0x73, 0x62, 0x7a, 0xff];
+
+/// The size [`Psbt::from_compressed_bytes`] lets compressed psbts inflate to
+/// at most, since a few bytes of deflate can otherwise inflate to gigabytes.
+/// Larger psbts are read with [`Psbt::from_compressed_bytes_with`] and a
+/// higher [`ParseLimits::max_size`].
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 4_000_000;
+
+/// The algorithm byte of deflate compressed psbts.
+const ALGORITHM_DEFLATE: u8 = 0x01;
+
+/// The compression level handed to deflate, the best ratio since psbts are
+/// small enough for speed not to matter.
+const LEVEL: u8 = 10;
+
+/// Errors encountered while decoding a possibly compressed psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressionError {
+    /// The bytes are neither a plain nor a compressed psbt.
+    UnknownFormat,
+    /// The psbt is compressed with an algorithm this version doesn't know.
+    UnsupportedAlgorithm(u8),
+    /// The compressed data is corrupt or inflates beyond the size limit.
+    Inflate,
+    /// The inflated bytes aren't a valid psbt.
+    Psbt(DecodeError),
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressionError::UnknownFormat => write!(f, "not a plain or compressed psbt"),
+            CompressionError::UnsupportedAlgorithm(algorithm) => {
+                write!(f, "unsupported compression algorithm {}", algorithm)
+            }
+            CompressionError::Inflate => write!(f, "corrupt or oversized compressed psbt"),
+            CompressionError::Psbt(err) => write!(f, "invalid psbt: {}", err),
+        }
+    }
+}
+
+impl Psbt {
+    /// Serializes and deflate compresses the psbt behind a
+    /// [`COMPRESSED_MAGIC`] header.
+    pub fn to_compressed_bytes(&self) -> Vec<u8> {
+        let mut bytes = COMPRESSED_MAGIC.to_vec();
+        bytes.push(ALGORITHM_DEFLATE);
+        bytes.extend(compress_to_vec(&self.serialize(), LEVEL));
+        bytes
+    }
+
+    /// Decodes and validates a psbt from `bytes`, inflating it first if it's
+    /// compressed. Plain psbts are accepted as they are.
+    ///
+    /// Psbts larger than [`DEFAULT_MAX_DECOMPRESSED_SIZE`] are rejected.
+    pub fn from_compressed_bytes(bytes: &[u8]) -> Result<Psbt, CompressionError> {
+        let limits = ParseLimits {
+            max_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+            ..ParseLimits::none()
+        };
+        Psbt::from_compressed_bytes_with(bytes, ParseOptions::default().with_limits(limits))
+    }
+
+    /// Decodes a possibly compressed psbt like
+    /// [`Psbt::from_compressed_bytes`], parsing as strictly as `options` ask
+    /// for. The inflated size is capped by the size limit of `options`, which
+    /// callers accepting compressed psbts from untrusted parties must set.
+    pub fn from_compressed_bytes_with(
+        bytes: &[u8],
+        options: ParseOptions,
+    ) -> Result<Psbt, CompressionError> {
+        if bytes.starts_with(&PSBT_MAGIC) {
+            return Psbt::deserialize_with(bytes, options).map_err(CompressionError::Psbt);
+        }
+        let rest = bytes
+            .strip_prefix(&COMPRESSED_MAGIC[..])
+            .ok_or(CompressionError::UnknownFormat)?;
+        let (&algorithm, compressed) = rest.split_first().ok_or(CompressionError::UnknownFormat)?;
+        if algorithm != ALGORITHM_DEFLATE {
+            return Err(CompressionError::UnsupportedAlgorithm(algorithm));
+        }
+        let psbt = decompress_to_vec_with_limit(compressed, options.limits.max_size)
+            .map_err(|_| CompressionError::Inflate)?;
+        Psbt::deserialize_with(&psbt, options).map_err(CompressionError::Psbt)
+    }
+}
@@ -0,0 +1,158 @@
+//! Child-pays-for-parent: a child psbt spending an output of an unconfirmed
+//! parent psbt, paying enough fee for the parent and child to be mined
+//! together at a target fee rate.
+
+use core::fmt;
+
+use crate::blockdata::locktime::absolute;
+use crate::blockdata::script::ScriptBuf;
+use crate::blockdata::transaction::{self, OutPoint, Sequence, Transaction, TxIn, TxOut};
+use crate::blockdata::witness::Witness;
+use crate::prelude::*;
+use crate::psbt::Version;
+use crate::{Amount, FeeRate};
+
+use super::fee::FeeError;
+use super::input::Input;
+use super::output::Output;
+use super::poc::{PartiallySignedTransactionInner, Psbt};
+use super::validation::ValidationReport;
+
+/// Errors encountered while building a CPFP child.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpfpError {
+    /// The parent has no output at this index.
+    NoSuchOutput { index: usize },
+    /// The parent input at this index doesn't spend a segwit output, so
+    /// signing it changes the parent's txid the child refers to.
+    NonSegwitParentInput { index: usize },
+    /// The fee or size of the parent or the child could not be determined.
+    Fee(FeeError),
+    /// The spent output can't cover the fee the child has to pay.
+    InsufficientValue { needed: Amount, available: Amount },
+    /// The child failed validation.
+    Invalid(ValidationReport),
+}
+
+impl fmt::Display for CpfpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpfpError::NoSuchOutput { index } => write!(f, "the parent has no output {}", index),
+            CpfpError::NonSegwitParentInput { index } => {
+                write!(f, "parent input {} doesn't spend a segwit output", index)
+            }
+            CpfpError::Fee(err) => write!(f, "can't determine the fee: {:?}", err),
+            CpfpError::InsufficientValue { needed, available } => write!(
+                f,
+                "output of {} can't cover the child fee of {}",
+                available, needed
+            ),
+            CpfpError::Invalid(report) => write!(f, "invalid child: {}", report),
+        }
+    }
+}
+
+impl Psbt {
+    /// Builds a PsbtV0 spending the output at `output_index` of this psbt to
+    /// `destination`, paying enough fee for parent and child together to
+    /// reach `fee_rate`.
+    ///
+    /// The child input gets the spent output as `witness_utxo` along with the
+    /// scripts and key origins of the parent's output map, so it can be
+    /// signed right away. The child refers to the parent by the txid of its
+    /// unsigned transaction, which only stays the parent's txid once signed
+    /// if every parent input is segwit, so parents with other inputs are
+    /// refused.
+    pub fn create_cpfp_child(
+        &self,
+        output_index: usize,
+        fee_rate: FeeRate,
+        destination: ScriptBuf,
+    ) -> Result<Psbt, CpfpError> {
+        let parent_tx = self
+            .unsigned_tx()
+            .map_err(|err| CpfpError::Fee(FeeError::UnsignedTx(err)))?;
+        let spent = parent_tx
+            .output
+            .get(output_index)
+            .cloned()
+            .ok_or(CpfpError::NoSuchOutput {
+                index: output_index,
+            })?;
+        let parent_output = &self.inner.outputs[output_index];
+        let parent_fee = self.fee().map_err(CpfpError::Fee)?;
+        for (index, (txin, input)) in parent_tx.input.iter().zip(&self.inner.inputs).enumerate() {
+            let segwit = input
+                .spent_utxo(&txin.previous_output)
+                .map_or(false, |utxo| input.spends_segwit(&utxo.script_pubkey));
+            if !segwit {
+                return Err(CpfpError::NonSegwitParentInput { index });
+            }
+        }
+        let parent_vsize = self.estimate_vsize().map_err(CpfpError::Fee)?;
+
+        let input = Input {
+            witness_utxo: Some(spent.clone()),
+            redeem_script: parent_output.redeem_script.clone(),
+            witness_script: parent_output.witness_script.clone(),
+            bip32_derivation: parent_output.bip32_derivation.clone(),
+            tap_internal_key: parent_output.tap_internal_key,
+            tap_key_origins: parent_output.tap_key_origins.clone(),
+            ..Default::default()
+        };
+        let unsigned_tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(parent_tx.txid(), output_index as u32),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::default(),
+            }],
+            output: vec![TxOut {
+                value: spent.value,
+                script_pubkey: destination,
+            }],
+        };
+        let mut child = Psbt {
+            inner: PartiallySignedTransactionInner {
+                unsigned_tx: Some(unsigned_tx),
+                version: Version::PsbtV0,
+                xpub: self.inner.xpub.clone(),
+                proprietary: Default::default(),
+                unknown: Default::default(),
+                inputs: vec![input],
+                outputs: vec![Output::default()],
+                tx_version: None,
+                fallback_locktime: None,
+                tx_modifiable: None,
+                sp_ecdh_shares: Default::default(),
+                sp_dleq_proofs: Default::default(),
+            },
+            fee_policy: None,
+            network: self.network,
+        };
+
+        // The package of parent and child must pay the fee rate as a whole
+        let child_vsize = child.estimate_vsize().map_err(CpfpError::Fee)?;
+        let package_fee = fee_rate
+            .fee_vb(parent_vsize + child_vsize)
+            .unwrap_or(Amount::MAX);
+        let needed = package_fee
+            .checked_sub(parent_fee)
+            .unwrap_or(Amount::ZERO)
+            .max(fee_rate.fee_vb(child_vsize).unwrap_or(Amount::MAX));
+        let amount = spent
+            .value
+            .checked_sub(needed)
+            .ok_or(CpfpError::InsufficientValue {
+                needed,
+                available: spent.value,
+            })?;
+        child.set_output_amount(0, amount);
+
+        let mut child = Psbt::from_inner(child.inner).map_err(CpfpError::Invalid)?;
+        child.network = self.network;
+        Ok(child)
+    }
+}
@@ -0,0 +1,195 @@
+//! The Updater role for descriptor wallets: filling the scripts and key
+//! origins of the inputs and outputs a descriptor derives, so signers can
+//! recognize and sign for them.
+//!
+//! Descriptor libraries such as miniscript depend on this crate, so this
+//! crate can't take their descriptors. They derive a descriptor into
+//! [`DerivedScript`]s instead, which carry everything an updater fills in.
+
+use core::fmt;
+
+use secp256k1::{Secp256k1, XOnlyPublicKey};
+
+use crate::bip32::KeySource;
+use crate::blockdata::script::ScriptBuf;
+use crate::prelude::*;
+use crate::psbt::Version;
+use crate::taproot::{LeafVersion, TapLeafHash, TaprootBuilder, TaprootSpendInfo};
+
+use super::input::Input;
+use super::output::Output;
+use super::poc::Psbt;
+use super::taproot::TaprootError;
+
+/// Errors encountered while updating a psbt from a descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DescriptorError {
+    /// The tapscript leaves of the derived script at this index can't be
+    /// placed in a tree at their depths.
+    InvalidTapTree { index: usize, error: TaprootError },
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+}
+
+impl fmt::Display for DescriptorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DescriptorError::InvalidTapTree { index, error } => {
+                write!(f, "derived script {}: {}", index, error)
+            }
+            DescriptorError::UnsignedTx(err) => write!(f, "invalid unsigned transaction: {}", err),
+        }
+    }
+}
+
+/// A script a descriptor derives at one index, along with the scripts and
+/// key origins spending it takes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DerivedScript {
+    /// The script paid to.
+    pub script_pubkey: ScriptBuf,
+    /// The redeem script of a P2SH descriptor.
+    pub redeem_script: Option<ScriptBuf>,
+    /// The witness script of a P2WSH descriptor, possibly nested in P2SH.
+    pub witness_script: Option<ScriptBuf>,
+    /// The origins of the keys of a non-taproot descriptor.
+    pub bip32_derivation: BTreeMap<secp256k1::PublicKey, KeySource>,
+    /// The internal key of a taproot descriptor.
+    pub tap_internal_key: Option<XOnlyPublicKey>,
+    /// The tapscript leaves of a taproot descriptor with their depth in the
+    /// tree.
+    pub tap_leaves: Vec<(u8, ScriptBuf)>,
+    /// The origins of the keys of a taproot descriptor, along with the
+    /// hashes of the leaves using them.
+    pub tap_key_origins: BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+}
+
+/// The inputs and outputs [`Psbt::update_with_descriptor`] updated.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DescriptorMatches {
+    /// Indices of the inputs spending a script of the descriptor.
+    pub inputs: Vec<usize>,
+    /// Indices of the outputs paying to a script of the descriptor.
+    pub outputs: Vec<usize>,
+}
+
+impl Psbt {
+    /// Fills the inputs spending, and the outputs paying to, one of the
+    /// `derived` scripts, a descriptor derived at a range of indices.
+    ///
+    /// Inputs and outputs get their redeem and witness scripts and the
+    /// origins of their keys. Taproot ones get their internal key and the
+    /// origins of their keys along with the leaves using them, inputs their
+    /// leaf scripts and merkle root, outputs their tap tree. Inputs are
+    /// matched on their trusted utxo, so ones without one are left alone.
+    pub fn update_with_descriptor(
+        &mut self,
+        derived: &[DerivedScript],
+    ) -> Result<DescriptorMatches, DescriptorError> {
+        let scripts: BTreeMap<&ScriptBuf, usize> = derived
+            .iter()
+            .enumerate()
+            .map(|(index, script)| (&script.script_pubkey, index))
+            .collect();
+        let invalid_tree =
+            |index: usize| move |error| DescriptorError::InvalidTapTree { index, error };
+
+        let tx = self.unsigned_tx().map_err(DescriptorError::UnsignedTx)?;
+        let mut matches = DescriptorMatches::default();
+        for (index, (txin, input)) in tx.input.iter().zip(&mut self.inner.inputs).enumerate() {
+            let position = input
+                .spent_utxo(&txin.previous_output)
+                .and_then(|utxo| scripts.get(&utxo.script_pubkey).copied());
+            if let Some(position) = position {
+                input
+                    .update_with_descriptor(&derived[position])
+                    .map_err(invalid_tree(position))?;
+                matches.inputs.push(index);
+            }
+        }
+        for index in 0..self.inner.outputs.len() {
+            let position = self
+                .output_script_pubkey(index)
+                .and_then(|script| scripts.get(script).copied());
+            if let Some(position) = position {
+                self.inner.outputs[index]
+                    .update_with_descriptor(self.inner.version, &derived[position])
+                    .map_err(invalid_tree(position))?;
+                matches.outputs.push(index);
+            }
+        }
+        Ok(matches)
+    }
+}
+
+impl Input {
+    /// Fills the scripts and key origins of an input spending the script of
+    /// `derived`.
+    pub fn update_with_descriptor(&mut self, derived: &DerivedScript) -> Result<(), TaprootError> {
+        if let Some(internal_key) = derived.tap_internal_key {
+            let spend_info = spend_info(internal_key, &derived.tap_leaves)?;
+            self.tap_internal_key = Some(internal_key);
+            self.tap_merkle_root = spend_info.merkle_root();
+            self.tap_key_origins.extend(derived.tap_key_origins.clone());
+            for (_, script) in &derived.tap_leaves {
+                let leaf = (script.clone(), LeafVersion::TapScript);
+                if let Some(control_block) = spend_info.control_block(&leaf) {
+                    self.tap_scripts.insert(control_block, leaf);
+                }
+            }
+            return Ok(());
+        }
+        self.redeem_script = derived.redeem_script.clone().or(self.redeem_script.take());
+        self.witness_script = derived
+            .witness_script
+            .clone()
+            .or(self.witness_script.take());
+        self.bip32_derivation
+            .extend(derived.bip32_derivation.clone());
+        Ok(())
+    }
+}
+
+impl Output {
+    /// Fills the scripts and key origins of an output of a psbt of `version`
+    /// paying to the script of `derived`.
+    pub fn update_with_descriptor(
+        &mut self,
+        version: Version,
+        derived: &DerivedScript,
+    ) -> Result<(), TaprootError> {
+        if let Some(internal_key) = derived.tap_internal_key {
+            let key_origins = derived
+                .tap_key_origins
+                .iter()
+                .map(|(key, (_, origin))| (*key, origin.clone()))
+                .collect();
+            self.set_taproot(version, internal_key, &derived.tap_leaves, &key_origins)?;
+            return Ok(());
+        }
+        self.redeem_script = derived.redeem_script.clone().or(self.redeem_script.take());
+        self.witness_script = derived
+            .witness_script
+            .clone()
+            .or(self.witness_script.take());
+        self.bip32_derivation
+            .extend(derived.bip32_derivation.clone());
+        Ok(())
+    }
+}
+
+/// Builds the spend info of `internal_key` tweaked with a tree of the
+/// tapscript `leaves`, given with their depth in the tree.
+fn spend_info(
+    internal_key: XOnlyPublicKey,
+    leaves: &[(u8, ScriptBuf)],
+) -> Result<TaprootSpendInfo, TaprootError> {
+    leaves
+        .iter()
+        .try_fold(TaprootBuilder::new(), |builder, (depth, script)| {
+            builder.add_leaf(*depth, script.clone())
+        })
+        .map_err(|err| TaprootError::InvalidTree(err.to_string()))?
+        .finalize(&Secp256k1::verification_only(), internal_key)
+        .map_err(|_| TaprootError::InvalidTree(String::from("incomplete tree")))
+}
@@ -0,0 +1,243 @@
+//! Comparison of two versions of the same psbt, so a coordinator can show
+//! what another party changed before accepting its psbt.
+//!
+//! Psbts are compared pair by pair as they serialize, inputs and outputs
+//! matched by index.
+
+use core::fmt;
+
+use crate::hashes::hex::DisplayHex;
+use crate::prelude::*;
+use crate::psbt::raw;
+
+use super::poc::Psbt;
+use super::serialize::*;
+
+/// How the value of a key changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The key was added with this value.
+    Added(Vec<u8>),
+    /// The key was removed, it had this value.
+    Removed(Vec<u8>),
+    /// The value of the key changed.
+    Modified { old: Vec<u8>, new: Vec<u8> },
+}
+
+/// A key of a map whose value differs between two psbts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    /// The map holding the key.
+    pub map: MapLocation,
+    /// The key, with its type and key data.
+    pub key: raw::Key,
+    /// How its value changed.
+    pub kind: ChangeKind,
+}
+
+impl FieldChange {
+    /// The BIP-174 or BIP-370 name of the field the key belongs to.
+    pub fn field_name(&self) -> &'static str {
+        field_name(self.map, self.key.type_value)
+    }
+
+    /// Returns `true` if the change adds a signature to an input: an ECDSA
+    /// or taproot signature, or a MuSig2 partial signature.
+    pub fn is_new_signature(&self) -> bool {
+        matches!(self.map, MapLocation::Input(_))
+            && matches!(self.kind, ChangeKind::Added(_))
+            && matches!(
+                self.key.type_value,
+                PSBT_IN_PARTIAL_SIG
+                    | PSBT_IN_TAP_KEY_SIG
+                    | PSBT_IN_TAP_SCRIPT_SIG
+                    | PSBT_IN_MUSIG2_PARTIAL_SIG
+            )
+    }
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.kind {
+            ChangeKind::Added(_) => "added",
+            ChangeKind::Removed(_) => "removed",
+            ChangeKind::Modified { .. } => "modified",
+        };
+        write!(f, "{}: {} {}", self.map, action, self.field_name())?;
+        if !self.key.key.is_empty() {
+            write!(f, " {:x}", self.key.key.as_hex())?;
+        }
+        Ok(())
+    }
+}
+
+/// The differences between two versions of a psbt, see [`Psbt::diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PsbtDiff {
+    /// Keys added, removed or modified in the global map and in the maps of
+    /// inputs and outputs present in both psbts.
+    pub changes: Vec<FieldChange>,
+    /// Indices of the inputs only the other psbt has.
+    pub added_inputs: Vec<usize>,
+    /// Indices of the inputs only this psbt has.
+    pub removed_inputs: Vec<usize>,
+    /// Indices of the outputs only the other psbt has.
+    pub added_outputs: Vec<usize>,
+    /// Indices of the outputs only this psbt has.
+    pub removed_outputs: Vec<usize>,
+}
+
+impl PsbtDiff {
+    /// Returns `true` if the psbts serialize to the same pairs.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+            && self.added_inputs.is_empty()
+            && self.removed_inputs.is_empty()
+            && self.added_outputs.is_empty()
+            && self.removed_outputs.is_empty()
+    }
+
+    /// The signatures the other psbt adds to inputs.
+    pub fn new_signatures(&self) -> impl Iterator<Item = &FieldChange> {
+        self.changes
+            .iter()
+            .filter(|change| change.is_new_signature())
+    }
+
+    /// The changes to the map at `map`.
+    pub fn changes_in(&self, map: MapLocation) -> impl Iterator<Item = &FieldChange> {
+        self.changes.iter().filter(move |change| change.map == map)
+    }
+}
+
+impl Psbt {
+    /// Compares this psbt with `other`, a later version of it, reporting
+    /// every key whose value differs as well as added and removed inputs and
+    /// outputs.
+    pub fn diff(&self, other: &Psbt) -> PsbtDiff {
+        let own = &self.inner;
+        let theirs = &other.inner;
+        let mut diff = PsbtDiff::default();
+
+        diff_map(
+            &mut diff.changes,
+            MapLocation::Global,
+            own.pairs(),
+            theirs.pairs(),
+        );
+        for (index, (input, other_input)) in own.inputs.iter().zip(&theirs.inputs).enumerate() {
+            let map = MapLocation::Input(index);
+            diff_map(&mut diff.changes, map, input.pairs(), other_input.pairs());
+        }
+        for (index, (output, other_output)) in own.outputs.iter().zip(&theirs.outputs).enumerate() {
+            let map = MapLocation::Output(index);
+            diff_map(&mut diff.changes, map, output.pairs(), other_output.pairs());
+        }
+
+        diff.added_inputs = (own.inputs.len()..theirs.inputs.len()).collect();
+        diff.removed_inputs = (theirs.inputs.len()..own.inputs.len()).collect();
+        diff.added_outputs = (own.outputs.len()..theirs.outputs.len()).collect();
+        diff.removed_outputs = (theirs.outputs.len()..own.outputs.len()).collect();
+        diff
+    }
+}
+
+/// Pushes the changes between the pairs `own` and `other` of the map at
+/// `map`, ordered by key.
+fn diff_map(
+    changes: &mut Vec<FieldChange>,
+    map: MapLocation,
+    own: Vec<raw::Pair>,
+    other: Vec<raw::Pair>,
+) {
+    let own: BTreeMap<_, _> = own.into_iter().map(|pair| (pair.key, pair.value)).collect();
+    let mut other: BTreeMap<_, _> = other
+        .into_iter()
+        .map(|pair| (pair.key, pair.value))
+        .collect();
+
+    let mut map_changes = Vec::new();
+    for (key, old) in own {
+        let kind = match other.remove(&key) {
+            Some(new) if new == old => continue,
+            Some(new) => ChangeKind::Modified { old, new },
+            None => ChangeKind::Removed(old),
+        };
+        map_changes.push(FieldChange { map, key, kind });
+    }
+    for (key, new) in other {
+        let kind = ChangeKind::Added(new);
+        map_changes.push(FieldChange { map, key, kind });
+    }
+    map_changes.sort_by(|a, b| a.key.cmp(&b.key));
+    changes.extend(map_changes);
+}
+
+/// The name of the field of type `type_value` in a map at `map`.
+pub(crate) fn field_name(map: MapLocation, type_value: u8) -> &'static str {
+    match map {
+        MapLocation::Global => match type_value {
+            PSBT_GLOBAL_UNSIGNED_TX => "unsigned transaction",
+            PSBT_GLOBAL_XPUB => "extended public key",
+            PSBT_GLOBAL_TX_VERSION => "transaction version",
+            PSBT_GLOBAL_FALLBACK_LOCKTIME => "fallback locktime",
+            PSBT_GLOBAL_INPUT_COUNT => "input count",
+            PSBT_GLOBAL_OUTPUT_COUNT => "output count",
+            PSBT_GLOBAL_TX_MODIFIABLE => "modifiable flags",
+            PSBT_GLOBAL_SP_ECDH_SHARE => "silent payment ECDH share",
+            PSBT_GLOBAL_SP_DLEQ => "silent payment DLEQ proof",
+            PSBT_GLOBAL_VERSION => "psbt version",
+            PSBT_GLOBAL_PROPRIETARY => "proprietary field",
+            _ => "unknown field",
+        },
+        MapLocation::Input(_) => match type_value {
+            PSBT_IN_NON_WITNESS_UTXO => "non-witness utxo",
+            PSBT_IN_WITNESS_UTXO => "witness utxo",
+            PSBT_IN_PARTIAL_SIG => "partial signature",
+            PSBT_IN_SIGHASH_TYPE => "sighash type",
+            PSBT_IN_REDEEM_SCRIPT => "redeem script",
+            PSBT_IN_WITNESS_SCRIPT => "witness script",
+            PSBT_IN_BIP32_DERIVATION => "bip32 derivation",
+            PSBT_IN_FINAL_SCRIPTSIG => "final scriptSig",
+            PSBT_IN_FINAL_SCRIPTWITNESS => "final script witness",
+            PSBT_IN_POR_COMMITMENT => "proof of reserves commitment",
+            PSBT_IN_RIPEMD160 => "ripemd160 preimage",
+            PSBT_IN_SHA256 => "sha256 preimage",
+            PSBT_IN_HASH160 => "hash160 preimage",
+            PSBT_IN_HASH256 => "hash256 preimage",
+            PSBT_IN_PREVIOUS_TXID => "previous txid",
+            PSBT_IN_OUTPUT_INDEX => "output index",
+            PSBT_IN_SEQUENCE => "sequence",
+            PSBT_IN_REQUIRED_TIME_LOCKTIME => "required time locktime",
+            PSBT_IN_REQUIRED_HEIGHT_LOCKTIME => "required height locktime",
+            PSBT_IN_TAP_KEY_SIG => "taproot key signature",
+            PSBT_IN_TAP_SCRIPT_SIG => "taproot script signature",
+            PSBT_IN_TAP_LEAF_SCRIPT => "taproot leaf script",
+            PSBT_IN_TAP_BIP32_DERIVATION => "taproot bip32 derivation",
+            PSBT_IN_TAP_INTERNAL_KEY => "taproot internal key",
+            PSBT_IN_TAP_MERKLE_ROOT => "taproot merkle root",
+            PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS => "MuSig2 participant keys",
+            PSBT_IN_MUSIG2_PUB_NONCE => "MuSig2 public nonce",
+            PSBT_IN_MUSIG2_PARTIAL_SIG => "MuSig2 partial signature",
+            PSBT_IN_SP_ECDH_SHARE => "silent payment ECDH share",
+            PSBT_IN_SP_DLEQ => "silent payment DLEQ proof",
+            PSBT_IN_PROPRIETARY => "proprietary field",
+            _ => "unknown field",
+        },
+        MapLocation::Output(_) => match type_value {
+            PSBT_OUT_REDEEM_SCRIPT => "redeem script",
+            PSBT_OUT_WITNESS_SCRIPT => "witness script",
+            PSBT_OUT_BIP32_DERIVATION => "bip32 derivation",
+            PSBT_OUT_AMOUNT => "amount",
+            PSBT_OUT_SCRIPT => "script",
+            PSBT_OUT_TAP_INTERNAL_KEY => "taproot internal key",
+            PSBT_OUT_TAP_TREE => "taproot tree",
+            PSBT_OUT_TAP_BIP32_DERIVATION => "taproot bip32 derivation",
+            PSBT_OUT_MUSIG2_PARTICIPANT_PUBKEYS => "MuSig2 participant keys",
+            PSBT_OUT_SP_V0_INFO => "silent payment address",
+            PSBT_OUT_SP_V0_LABEL => "silent payment label",
+            PSBT_OUT_PROPRIETARY => "proprietary field",
+            _ => "unknown field",
+        },
+    }
+}
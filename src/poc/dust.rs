@@ -0,0 +1,70 @@
+//! Detection of dust outputs, outputs worth less than the fee of spending
+//! them, which nodes refuse to relay.
+
+use crate::blockdata::script::Script;
+use crate::consensus::encode::VarInt;
+use crate::prelude::*;
+use crate::psbt::Version;
+use crate::{Amount, FeeRate};
+
+use super::poc::Psbt;
+
+/// The dust relay fee of Bitcoin Core, 3 sat/vB.
+pub const DEFAULT_DUST_RELAY_FEE: FeeRate = FeeRate::from_sat_per_kwu(750);
+
+/// Returns the smallest amount an output paying to `script_pubkey` can have
+/// without being dust at `dust_relay_fee`, following Bitcoin Core: the fee of
+/// the output plus the input spending it. Unspendable outputs are never dust.
+pub fn dust_threshold(script_pubkey: &Script, dust_relay_fee: FeeRate) -> Amount {
+    // Outpoint, scriptSig length and sequence, with a 107 byte scriptSig or
+    // witness for a single signature
+    const SPEND_SIZE: u64 = 32 + 4 + 1 + 107 + 4;
+    const WITNESS_SPEND_SIZE: u64 = 32 + 4 + 1 + 107 / 4 + 4;
+
+    if script_pubkey.is_op_return() {
+        return Amount::ZERO;
+    }
+    let script_len = script_pubkey.len() as u64;
+    let output_size = 8 + VarInt(script_len).size() as u64 + script_len;
+    let spend_size = if script_pubkey.is_witness_program() {
+        WITNESS_SPEND_SIZE
+    } else {
+        SPEND_SIZE
+    };
+    dust_relay_fee
+        .fee_vb(output_size + spend_size)
+        .unwrap_or(Amount::MAX)
+}
+
+impl Psbt {
+    /// Returns the indices of the outputs whose amount is below the
+    /// [`dust_threshold`] of their script at `dust_relay_fee`.
+    ///
+    /// Silent payment outputs whose script isn't derived yet are skipped.
+    pub fn dust_outputs(&self, dust_relay_fee: FeeRate) -> Vec<usize> {
+        (0..self.inner.outputs.len())
+            .filter(|&index| self.is_dust_output(index, dust_relay_fee))
+            .collect()
+    }
+
+    /// Returns `true` if the output at `index` is dust at `dust_relay_fee`.
+    fn is_dust_output(&self, index: usize, dust_relay_fee: FeeRate) -> bool {
+        let amount = match self.inner.version {
+            Version::PsbtV0 => self
+                .inner
+                .unsigned_tx
+                .as_ref()
+                .and_then(|tx| tx.output.get(index))
+                .map(|txout| txout.value),
+            Version::PsbtV2 => self
+                .inner
+                .outputs
+                .get(index)
+                .and_then(|output| output.amount),
+        };
+        match (amount, self.output_script_pubkey(index)) {
+            (Some(amount), Some(script)) => amount < dust_threshold(script, dust_relay_fee),
+            _ => false,
+        }
+    }
+}
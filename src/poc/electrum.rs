@@ -0,0 +1,671 @@
+//! Conversion from and to the partial transaction format of Electrum
+//! versions before 4.0, so wallets migrating to psbts can keep working with
+//! cosigners running those versions.
+//!
+//! Electrum serializes a partial transaction like a network transaction
+//! whose unsigned inputs carry placeholders: `ff` for missing signatures and
+//! "extended" public keys, an xpub followed by the change and address index
+//! to derive the key at. Segwit inputs prefix their witness with the value
+//! they spend. The format knows nothing about the origin of xpubs, so the
+//! caller gives the master fingerprint and path of each, and keys are
+//! recorded with their full path from the master key as signers expect.
+//!
+//! P2PKH, P2WPKH and P2WPKH-in-P2SH inputs and `OP_CHECKMULTISIG` inputs
+//! behind P2SH, P2WSH or P2WSH-in-P2SH convert, complete inputs convert to
+//! finalized ones. Legacy inputs carry no utxo in either direction.
+//!
+//! Fields the format can't carry, like proprietary ones, aren't written. A
+//! wallet sending its psbt to a legacy cosigner keeps them by merging the
+//! transaction the cosigner returns back into its psbt with
+//! [`Psbt::merge_electrum`].
+
+use core::fmt;
+
+use secp256k1::Secp256k1;
+
+use crate::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, KeySource};
+use crate::blockdata::locktime::absolute;
+use crate::blockdata::opcodes::all::OP_CHECKMULTISIG;
+use crate::blockdata::script::{Builder, Instruction, PushBytes, Script, ScriptBuf};
+use crate::blockdata::transaction::{self, OutPoint, Sequence, Transaction, TxIn, TxOut};
+use crate::blockdata::witness::Witness;
+use crate::consensus::encode::{self, Decodable, VarInt};
+use crate::crypto::ecdsa;
+use crate::crypto::key::PublicKey;
+use crate::prelude::*;
+use crate::psbt::Version;
+use crate::Amount;
+
+use super::combine::{CombineError, CombineOptions, ConflictPolicy};
+use super::finalizer::{parse_multisig, pays_to_key};
+use super::input::Input;
+use super::output::Output;
+use super::poc::{PartiallySignedTransactionInner, Psbt};
+use super::validation::ValidationReport;
+
+/// The magic bytes Electrum 3.3 and later put in front of partial
+/// transactions, `EPTF` followed by 0xff.
+pub const ELECTRUM_MAGIC: [u8; 5] = [0x45, 0x50, 0x54, 0x46, 0xff];
+
+/// The partial format version following [`ELECTRUM_MAGIC`].
+const FORMAT_VERSION: u8 = 0x00;
+
+/// The placeholder of a missing signature.
+const NO_SIGNATURE: u8 = 0xff;
+
+/// The first byte of an extended public key placeholder.
+const XPUB_PREFIX: u8 = 0xff;
+
+/// The witness item count marking a partial segwit input, followed by the
+/// value it spends.
+const PARTIAL_WITNESS_MARKER: u64 = 0xffff_ffff;
+
+/// Errors encountered while converting from or to the Electrum format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElectrumError {
+    /// The bytes aren't a valid Electrum partial transaction.
+    Encoding(String),
+    /// The input at this index is of a type the Electrum format can't carry
+    /// or this version doesn't know.
+    UnsupportedInput { index: usize },
+    /// A public key of the input at this index is of a kind this version
+    /// doesn't know, such as an old Electrum master public key.
+    UnsupportedKey { index: usize },
+    /// The segwit input at this index doesn't know the value it spends.
+    MissingValue { index: usize },
+    /// A key of the input at this index is derived from this xpub, which
+    /// isn't a master key and whose origin wasn't given.
+    MissingXpubOrigin { index: usize, xpub: ExtendedPubKey },
+    /// The converted psbt couldn't be merged into the psbt.
+    Combine(CombineError),
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+    /// The converted psbt isn't valid.
+    Invalid(ValidationReport),
+}
+
+impl fmt::Display for ElectrumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElectrumError::Encoding(err) => write!(f, "invalid Electrum transaction: {}", err),
+            ElectrumError::UnsupportedInput { index } => {
+                write!(f, "input {} has no Electrum representation", index)
+            }
+            ElectrumError::UnsupportedKey { index } => {
+                write!(f, "input {} has an unsupported public key", index)
+            }
+            ElectrumError::MissingValue { index } => {
+                write!(f, "segwit input {} is missing its value", index)
+            }
+            ElectrumError::MissingXpubOrigin { index, xpub } => {
+                write!(
+                    f,
+                    "input {} derives from xpub {} of unknown origin",
+                    index, xpub
+                )
+            }
+            ElectrumError::Combine(err) => write!(f, "can't merge the transaction: {}", err),
+            ElectrumError::UnsignedTx(err) => write!(f, "invalid unsigned transaction: {}", err),
+            ElectrumError::Invalid(report) => write!(f, "invalid psbt: {:?}", report),
+        }
+    }
+}
+
+/// A public key as Electrum writes it in partial transactions.
+enum ElectrumKey {
+    /// A key without known derivation.
+    Plain(PublicKey),
+    /// The key at `change/index` below `xpub`.
+    Derived {
+        xpub: ExtendedPubKey,
+        change: u16,
+        index: u16,
+    },
+}
+
+impl ElectrumKey {
+    fn from_slice(bytes: &[u8]) -> Option<ElectrumKey> {
+        match *bytes.first()? {
+            XPUB_PREFIX if bytes.len() == 83 => {
+                let xpub = ExtendedPubKey::decode(&bytes[1..79]).ok()?;
+                let change = u16::from_le_bytes([bytes[79], bytes[80]]);
+                let index = u16::from_le_bytes([bytes[81], bytes[82]]);
+                Some(ElectrumKey::Derived {
+                    xpub,
+                    change,
+                    index,
+                })
+            }
+            0x02 | 0x03 | 0x04 => PublicKey::from_slice(bytes).ok().map(ElectrumKey::Plain),
+            _ => None,
+        }
+    }
+
+    /// Derives the public key, along with the xpub and the path below it it
+    /// was derived at.
+    fn resolve(&self) -> Option<(PublicKey, Option<(ExtendedPubKey, DerivationPath)>)> {
+        match *self {
+            ElectrumKey::Plain(key) => Some((key, None)),
+            ElectrumKey::Derived {
+                xpub,
+                change,
+                index,
+            } => {
+                let path: DerivationPath = vec![
+                    ChildNumber::from_normal_idx(change.into()).ok()?,
+                    ChildNumber::from_normal_idx(index.into()).ok()?,
+                ]
+                .into();
+                let child = xpub
+                    .derive_pub(&Secp256k1::verification_only(), &path)
+                    .ok()?;
+                Some((child.to_pub(), Some((xpub, path))))
+            }
+        }
+    }
+}
+
+impl Psbt {
+    /// Converts an Electrum partial transaction, with or without the
+    /// [`ELECTRUM_MAGIC`] header, into a PsbtV0.
+    ///
+    /// `xpub_origins` maps the xpubs the keys of the transaction are derived
+    /// from to their master fingerprint and path, and become the global
+    /// xpubs of the psbt. Master xpubs don't need an origin, any other xpub
+    /// missing from `xpub_origins` fails the conversion.
+    pub fn from_electrum(
+        bytes: &[u8],
+        xpub_origins: &BTreeMap<ExtendedPubKey, KeySource>,
+    ) -> Result<Psbt, ElectrumError> {
+        let bytes = match bytes.strip_prefix(&ELECTRUM_MAGIC[..]) {
+            Some([FORMAT_VERSION, rest @ ..]) => rest,
+            Some(_) => {
+                return Err(ElectrumError::Encoding(String::from(
+                    "unknown format version",
+                )))
+            }
+            None => bytes,
+        };
+        let mut reader = TxReader { bytes };
+
+        let version: transaction::Version = reader.read()?;
+        let segwit = reader.bytes.starts_with(&[0x00, 0x01]);
+        if segwit {
+            reader.bytes = &reader.bytes[2..];
+        }
+        let input_count = reader.read::<VarInt>()?.0;
+        let mut txins = Vec::new();
+        for _ in 0..input_count {
+            let previous_output: OutPoint = reader.read()?;
+            let script_sig: ScriptBuf = reader.read()?;
+            let sequence: Sequence = reader.read()?;
+            txins.push((previous_output, script_sig, sequence));
+        }
+        let output: Vec<TxOut> = reader.read()?;
+        let mut witnesses = Vec::new();
+        for _ in 0..txins.len() {
+            witnesses.push(if segwit {
+                reader.read_witness()?
+            } else {
+                (None, Vec::new())
+            });
+        }
+        let lock_time: absolute::LockTime = reader.read()?;
+        if !reader.bytes.is_empty() {
+            return Err(ElectrumError::Encoding(String::from("trailing data")));
+        }
+
+        let mut xpub = BTreeMap::new();
+        let mut inputs = Vec::with_capacity(txins.len());
+        for (index, ((_, script_sig, _), (value, witness))) in
+            txins.iter().zip(witnesses).enumerate()
+        {
+            inputs.push(psbt_input(
+                index,
+                script_sig,
+                value,
+                witness,
+                xpub_origins,
+                &mut xpub,
+            )?);
+        }
+
+        let unsigned_tx = Transaction {
+            version,
+            lock_time,
+            input: txins
+                .into_iter()
+                .map(|(previous_output, _, sequence)| TxIn {
+                    previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence,
+                    witness: Witness::default(),
+                })
+                .collect(),
+            output,
+        };
+        let outputs = vec![Output::default(); unsigned_tx.output.len()];
+        Psbt::from_inner(PartiallySignedTransactionInner {
+            unsigned_tx: Some(unsigned_tx),
+            version: Version::PsbtV0,
+            xpub,
+            proprietary: Default::default(),
+            unknown: Default::default(),
+            inputs,
+            outputs,
+            tx_version: None,
+            fallback_locktime: None,
+            tx_modifiable: None,
+            sp_ecdh_shares: Default::default(),
+            sp_dleq_proofs: Default::default(),
+        })
+        .map_err(ElectrumError::Invalid)
+    }
+
+    /// Converts the psbt into an Electrum partial transaction, with the
+    /// [`ELECTRUM_MAGIC`] header.
+    ///
+    /// Keys whose origin is a global xpub followed by two unhardened steps
+    /// are written as extended public keys, so Electrum cosigners recognize
+    /// theirs. Other keys are written as they are. Fields the format can't
+    /// carry, like proprietary ones, aren't written, see
+    /// [`Psbt::merge_electrum`] to keep them.
+    pub fn to_electrum(&self) -> Result<Vec<u8>, ElectrumError> {
+        let tx = self.unsigned_tx().map_err(ElectrumError::UnsignedTx)?;
+
+        let mut script_sigs = Vec::with_capacity(tx.input.len());
+        let mut witnesses = Vec::with_capacity(tx.input.len());
+        for (index, (txin, input)) in tx.input.iter().zip(&self.inner.inputs).enumerate() {
+            let (script_sig, witness) = self.electrum_input(index, txin, input)?;
+            script_sigs.push(script_sig);
+            witnesses.push(witness);
+        }
+        let segwit = witnesses.iter().any(Option::is_some);
+
+        let mut bytes = ELECTRUM_MAGIC.to_vec();
+        bytes.push(FORMAT_VERSION);
+        bytes.extend(encode::serialize(&tx.version));
+        if segwit {
+            bytes.extend([0x00, 0x01]);
+        }
+        bytes.extend(encode::serialize(&VarInt(tx.input.len() as u64)));
+        for (txin, script_sig) in tx.input.iter().zip(&script_sigs) {
+            bytes.extend(encode::serialize(&txin.previous_output));
+            bytes.extend(encode::serialize(script_sig));
+            bytes.extend(encode::serialize(&txin.sequence));
+        }
+        bytes.extend(encode::serialize(&tx.output));
+        if segwit {
+            for witness in witnesses {
+                bytes.extend(witness.unwrap_or_else(|| vec![0x00]));
+            }
+        }
+        bytes.extend(encode::serialize(&tx.lock_time));
+        Ok(bytes)
+    }
+
+    /// Merges the signatures and finalized inputs of `bytes`, an Electrum
+    /// partial transaction of the same transaction as the psbt, into it.
+    ///
+    /// The keys of the transaction get their origins from the global xpubs of
+    /// the psbt. Everything the psbt already holds, including the fields the
+    /// Electrum format can't carry, is kept, so a psbt sent out with
+    /// [`Psbt::to_electrum`] comes back without losing any of its data.
+    pub fn merge_electrum(&mut self, bytes: &[u8]) -> Result<(), ElectrumError> {
+        let other = Psbt::from_electrum(bytes, &self.inner.xpub)?;
+        let options = CombineOptions::default().with_conflict_policy(ConflictPolicy::PreferSelf);
+        self.combine_with(other, options)
+            .map_err(ElectrumError::Combine)?;
+        Ok(())
+    }
+
+    /// Returns the scriptSig and the serialized witness, if any, of an input
+    /// in the Electrum format.
+    fn electrum_input(
+        &self,
+        index: usize,
+        txin: &TxIn,
+        input: &Input,
+    ) -> Result<(ScriptBuf, Option<Vec<u8>>), ElectrumError> {
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            let witness = input
+                .final_script_witness
+                .as_ref()
+                .filter(|witness| !witness.is_empty())
+                .map(encode::serialize);
+            return Ok((input.final_script_sig.clone().unwrap_or_default(), witness));
+        }
+
+        let unsupported = ElectrumError::UnsupportedInput { index };
+        let utxo = input.spent_utxo(&txin.previous_output);
+        let value = utxo.map(|utxo| utxo.value);
+
+        let (stack, segwit) = if let Some(ref witness_script) = input.witness_script {
+            (self.multisig_stack(index, input, witness_script)?, true)
+        } else if let Some(ref redeem_script) = input.redeem_script {
+            if redeem_script.is_p2wpkh() {
+                (self.single_key_stack(index, input, redeem_script)?, true)
+            } else {
+                (self.multisig_stack(index, input, redeem_script)?, false)
+            }
+        } else {
+            let script_pubkey = &utxo.ok_or(unsupported.clone())?.script_pubkey;
+            (
+                self.single_key_stack(index, input, script_pubkey)?,
+                script_pubkey.is_p2wpkh(),
+            )
+        };
+
+        if !segwit {
+            let mut builder = Builder::new();
+            for item in &stack {
+                builder = builder.push_slice(push_bytes(item, index)?);
+            }
+            return Ok((builder.into_script(), None));
+        }
+
+        let value = value.ok_or(ElectrumError::MissingValue { index })?;
+        let mut witness = encode::serialize(&VarInt(PARTIAL_WITNESS_MARKER));
+        witness.extend(encode::serialize(&value.to_sat()));
+        witness.extend(encode::serialize(&0u16));
+        witness.extend(encode::serialize(&VarInt(stack.len() as u64)));
+        for item in &stack {
+            witness.extend(encode::serialize(item));
+        }
+        let script_sig = match input.redeem_script {
+            Some(ref redeem_script) => Builder::new()
+                .push_slice(push_bytes(redeem_script.as_bytes(), index)?)
+                .into_script(),
+            None => ScriptBuf::new(),
+        };
+        Ok((script_sig, Some(witness)))
+    }
+
+    /// The stack of a single key input paying to `script_pubkey`:
+    /// a signature or its placeholder and the key.
+    fn single_key_stack(
+        &self,
+        index: usize,
+        input: &Input,
+        script_pubkey: &Script,
+    ) -> Result<Vec<Vec<u8>>, ElectrumError> {
+        let key = input
+            .partial_sigs
+            .keys()
+            .copied()
+            .chain(
+                input
+                    .bip32_derivation
+                    .keys()
+                    .map(|key| PublicKey::new(*key)),
+            )
+            .find(|key| pays_to_key(script_pubkey, key))
+            .ok_or(ElectrumError::UnsupportedInput { index })?;
+        Ok(vec![signature(input, &key), self.electrum_key(input, &key)])
+    }
+
+    /// The stack of an `OP_CHECKMULTISIG` input: the dummy element, a
+    /// signature or its placeholder per key and the script with the keys in
+    /// their Electrum form.
+    fn multisig_stack(
+        &self,
+        index: usize,
+        input: &Input,
+        script: &Script,
+    ) -> Result<Vec<Vec<u8>>, ElectrumError> {
+        let (threshold, keys) =
+            parse_multisig(script).ok_or(ElectrumError::UnsupportedInput { index })?;
+
+        let mut stack = vec![Vec::new()];
+        stack.extend(keys.iter().map(|key| signature(input, key)));
+        let mut builder = Builder::new().push_int(threshold as i64);
+        for key in &keys {
+            builder = builder.push_slice(push_bytes(&self.electrum_key(input, key), index)?);
+        }
+        let script = builder
+            .push_int(keys.len() as i64)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        stack.push(script.into_bytes());
+        Ok(stack)
+    }
+
+    /// Writes `key` as an extended public key if its origin is a global xpub
+    /// followed by a change and an address index, as it is otherwise.
+    fn electrum_key(&self, input: &Input, key: &PublicKey) -> Vec<u8> {
+        let origin = input.bip32_derivation.get(&key.inner);
+        for (xpub, (fingerprint, xpub_path)) in &self.inner.xpub {
+            let rest = origin
+                .filter(|(key_fingerprint, _)| key_fingerprint == fingerprint)
+                .and_then(|(_, path)| path.as_ref().strip_prefix(xpub_path.as_ref()));
+            if let Some(&[ChildNumber::Normal { index: change }, ChildNumber::Normal { index }]) =
+                rest
+            {
+                if let (Ok(change), Ok(index)) = (u16::try_from(change), u16::try_from(index)) {
+                    let mut bytes = vec![XPUB_PREFIX];
+                    bytes.extend(xpub.encode());
+                    bytes.extend(change.to_le_bytes());
+                    bytes.extend(index.to_le_bytes());
+                    return bytes;
+                }
+            }
+        }
+        key.to_bytes()
+    }
+}
+
+/// Builds the psbt input of an Electrum input from its scriptSig and, for
+/// partial segwit inputs, the value it spends and its witness items.
+///
+/// The xpubs its keys derive from are added to `xpubs` with their origin in
+/// `xpub_origins`.
+fn psbt_input(
+    index: usize,
+    script_sig: &Script,
+    value: Option<Amount>,
+    witness: Vec<Vec<u8>>,
+    xpub_origins: &BTreeMap<ExtendedPubKey, KeySource>,
+    xpubs: &mut BTreeMap<ExtendedPubKey, KeySource>,
+) -> Result<Input, ElectrumError> {
+    let unsupported = ElectrumError::UnsupportedInput { index };
+    let pushes = script_sig
+        .instructions()
+        .map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => Ok(bytes.as_bytes().to_vec()),
+            _ => Err(unsupported.clone()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if value.is_none() && !has_placeholder(&pushes) {
+        // Complete inputs are written like in a network transaction
+        return Ok(Input {
+            final_script_sig: (!script_sig.is_empty()).then(|| script_sig.to_owned()),
+            final_script_witness: (!witness.is_empty()).then(|| Witness::from_slice(&witness)),
+            ..Default::default()
+        });
+    }
+
+    let mut input = Input::default();
+    let stack = if value.is_some() { &witness } else { &pushes };
+    let (sigs, keys, threshold) = match stack.as_slice() {
+        [sig, key] => (vec![sig.clone()], vec![key.clone()], None),
+        [dummy, rest @ .., script] if dummy.is_empty() => {
+            let (threshold, keys) = parse_electrum_multisig(script).ok_or(unsupported.clone())?;
+            if rest.len() != keys.len() {
+                return Err(unsupported);
+            }
+            (rest.to_vec(), keys, Some(threshold))
+        }
+        _ => return Err(unsupported),
+    };
+
+    let mut public_keys = Vec::with_capacity(keys.len());
+    for (sig, key) in sigs.iter().zip(&keys) {
+        let (public_key, origin) = ElectrumKey::from_slice(key)
+            .and_then(|key| key.resolve())
+            .ok_or(ElectrumError::UnsupportedKey { index })?;
+        if let Some((xpub, path)) = origin {
+            let (fingerprint, xpub_path) = xpub_origin(&xpub, xpub_origins)
+                .ok_or(ElectrumError::MissingXpubOrigin { index, xpub })?;
+            let key_source = (fingerprint, xpub_path.extend(&path));
+            xpubs.insert(xpub, (fingerprint, xpub_path));
+            input.bip32_derivation.insert(public_key.inner, key_source);
+        }
+        if sig.as_slice() != [NO_SIGNATURE] {
+            let sig = ecdsa::Signature::from_slice(sig).map_err(|_| unsupported.clone())?;
+            input.partial_sigs.insert(public_key, sig);
+        }
+        public_keys.push(public_key);
+    }
+
+    let script = threshold.map(|threshold| multisig_script(threshold, &public_keys));
+    let value = match value {
+        Some(value) => value,
+        None => {
+            input.redeem_script = script;
+            return Ok(input);
+        }
+    };
+    let witness_program = match script {
+        Some(script) => {
+            let witness_program = ScriptBuf::new_p2wsh(&script.wscript_hash());
+            input.witness_script = Some(script);
+            witness_program
+        }
+        None => {
+            let hash = public_keys[0]
+                .wpubkey_hash()
+                .ok_or(ElectrumError::UnsupportedKey { index })?;
+            ScriptBuf::new_p2wpkh(&hash)
+        }
+    };
+    let script_pubkey = if pushes.is_empty() {
+        witness_program
+    } else {
+        let script_pubkey = ScriptBuf::new_p2sh(&witness_program.script_hash());
+        input.redeem_script = Some(witness_program);
+        script_pubkey
+    };
+    input.witness_utxo = Some(TxOut {
+        value,
+        script_pubkey,
+    });
+    Ok(input)
+}
+
+/// Returns the origin of `xpub` given in `xpub_origins`, or the xpub itself
+/// if it's a master key.
+fn xpub_origin(
+    xpub: &ExtendedPubKey,
+    xpub_origins: &BTreeMap<ExtendedPubKey, KeySource>,
+) -> Option<KeySource> {
+    match xpub_origins.get(xpub) {
+        Some(origin) => Some(origin.clone()),
+        None if xpub.depth == 0 => Some((xpub.fingerprint(), DerivationPath::master())),
+        None => None,
+    }
+}
+
+/// Builds the `OP_CHECKMULTISIG` script of `threshold` of `keys`.
+fn multisig_script(threshold: usize, keys: &[PublicKey]) -> ScriptBuf {
+    let mut builder = Builder::new().push_int(threshold as i64);
+    for key in keys {
+        builder = builder.push_key(key);
+    }
+    builder
+        .push_int(keys.len() as i64)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_script()
+}
+
+/// Returns `true` if `stack` holds a missing signature or an extended
+/// public key, directly or in its multisig script.
+fn has_placeholder(stack: &[Vec<u8>]) -> bool {
+    let is_placeholder = |item: &[u8]| item == [NO_SIGNATURE] || item.first() == Some(&XPUB_PREFIX);
+    stack.iter().any(|item| is_placeholder(item))
+        || stack.last().map_or(false, |script| {
+            Script::from_bytes(script)
+                .instructions()
+                .any(|instruction| match instruction {
+                    Ok(Instruction::PushBytes(bytes)) => is_placeholder(bytes.as_bytes()),
+                    _ => false,
+                })
+        })
+}
+
+/// Parses an `OP_CHECKMULTISIG` script whose keys are in their Electrum
+/// form, returning the threshold and the keys.
+fn parse_electrum_multisig(script: &[u8]) -> Option<(usize, Vec<Vec<u8>>)> {
+    let instructions = Script::from_bytes(script)
+        .instructions()
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let (threshold, rest) = instructions.split_first()?;
+    let (checkmultisig, rest) = rest.split_last()?;
+    let (key_count, keys) = rest.split_last()?;
+    if *checkmultisig != Instruction::Op(OP_CHECKMULTISIG) {
+        return None;
+    }
+    let small_int = |instruction: &Instruction| match instruction {
+        Instruction::Op(op) if (0x51..=0x60).contains(&op.to_u8()) => {
+            Some((op.to_u8() - 0x50) as usize)
+        }
+        _ => None,
+    };
+    let threshold = small_int(threshold)?;
+    let keys = keys
+        .iter()
+        .map(|instruction| match instruction {
+            Instruction::PushBytes(bytes) => Some(bytes.as_bytes().to_vec()),
+            Instruction::Op(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if keys.len() != small_int(key_count)? || threshold > keys.len() {
+        return None;
+    }
+    Some((threshold, keys))
+}
+
+/// The signature of `key` with its sighash byte, or the placeholder of a
+/// missing one.
+fn signature(input: &Input, key: &PublicKey) -> Vec<u8> {
+    input
+        .partial_sigs
+        .get(key)
+        .map_or_else(|| vec![NO_SIGNATURE], |sig| sig.to_vec())
+}
+
+fn push_bytes(bytes: &[u8], index: usize) -> Result<&PushBytes, ElectrumError> {
+    <&PushBytes>::try_from(bytes).map_err(|_| ElectrumError::UnsupportedInput { index })
+}
+
+/// Reads the consensus encoded parts of an Electrum transaction.
+struct TxReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl TxReader<'_> {
+    fn read<T: Decodable>(&mut self) -> Result<T, ElectrumError> {
+        let (value, len) = encode::deserialize_partial(self.bytes)
+            .map_err(|err| ElectrumError::Encoding(err.to_string()))?;
+        self.bytes = &self.bytes[len..];
+        Ok(value)
+    }
+
+    /// Reads the witness items of an input, along with the value it spends
+    /// if it's partial.
+    fn read_witness(&mut self) -> Result<(Option<Amount>, Vec<Vec<u8>>), ElectrumError> {
+        let mut count = self.read::<VarInt>()?.0;
+        let mut value = None;
+        if count == PARTIAL_WITNESS_MARKER {
+            value = Some(Amount::from_sat(self.read()?));
+            let _witness_version: u16 = self.read()?;
+            count = self.read::<VarInt>()?.0;
+        }
+        let mut items = Vec::new();
+        for _ in 0..count {
+            items.push(self.read()?);
+        }
+        Ok((value, items))
+    }
+}
@@ -0,0 +1,336 @@
+//! Support for PSETs, the Partially Signed Elements Transactions of Liquid
+//! and other Elements sidechains.
+//!
+//! A PSET is a PsbtV2 starting with [`PSET_MAGIC`] whose Elements fields are
+//! proprietary pairs with the [`PSET_PREFIX`] prefix. The confidential value
+//! and asset fields are parsed into [`ElementsInput`] and [`ElementsOutput`]
+//! while the others, such as issuances and peg-ins, stay in the proprietary
+//! maps. [`Psbt::deserialize_pset`] and [`Psbt::serialize_pset`] read and
+//! write PSETs with the serialization of psbts.
+//!
+//! Elements transactions have their own encoding, sighash and txid, which
+//! this crate doesn't implement, so PSETs can be decoded, inspected, updated
+//! and combined but not signed, finalized or extracted. Amounts and fees
+//! computed by the rest of the crate ignore blinded values, and validation
+//! requires every output to keep its explicit amount.
+
+use crate::prelude::*;
+use crate::psbt::raw;
+use crate::Amount;
+
+use super::poc::Psbt;
+use super::serialize::{DecodeError, PSBT_MAGIC};
+
+/// The magic bytes every serialized PSET starts with, `pset` followed by
+/// 0xff.
+pub const PSET_MAGIC: [u8; 5] = [0x70, 0x73, 0x65, 0x74, 0xff];
+/// The prefix of the proprietary keys holding the Elements fields.
+pub const PSET_PREFIX: &[u8] = b"pset";
+
+const PSBT_ELEMENTS_IN_UTXO_RANGEPROOF: u8 = 0x0e;
+const PSBT_ELEMENTS_IN_EXPLICIT_VALUE: u8 = 0x11;
+const PSBT_ELEMENTS_IN_VALUE_PROOF: u8 = 0x12;
+const PSBT_ELEMENTS_IN_EXPLICIT_ASSET: u8 = 0x13;
+const PSBT_ELEMENTS_IN_ASSET_PROOF: u8 = 0x14;
+
+const PSBT_ELEMENTS_OUT_VALUE_COMMITMENT: u8 = 0x01;
+const PSBT_ELEMENTS_OUT_ASSET: u8 = 0x02;
+const PSBT_ELEMENTS_OUT_ASSET_COMMITMENT: u8 = 0x03;
+const PSBT_ELEMENTS_OUT_VALUE_RANGEPROOF: u8 = 0x04;
+const PSBT_ELEMENTS_OUT_ASSET_SURJECTION_PROOF: u8 = 0x05;
+const PSBT_ELEMENTS_OUT_BLINDING_PUBKEY: u8 = 0x06;
+const PSBT_ELEMENTS_OUT_ECDH_PUBKEY: u8 = 0x07;
+const PSBT_ELEMENTS_OUT_BLINDER_INDEX: u8 = 0x08;
+const PSBT_ELEMENTS_OUT_BLIND_VALUE_PROOF: u8 = 0x09;
+const PSBT_ELEMENTS_OUT_BLIND_ASSET_PROOF: u8 = 0x0a;
+
+/// The size of a Pedersen commitment to a value or an asset.
+const COMMITMENT_SIZE: usize = 33;
+
+/// The confidential value and asset fields of a PSET input.
+#[derive(Clone, Default, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(crate = "actual_serde"))]
+pub struct ElementsInput {
+    /// The rangeproof of the value of the spent output.
+    pub utxo_rangeproof: Option<Vec<u8>>,
+    /// The unblinded value of the spent output.
+    pub explicit_value: Option<Amount>,
+    /// The proof that `explicit_value` is the value committed to by the
+    /// spent output.
+    pub value_proof: Option<Vec<u8>>,
+    /// The unblinded asset of the spent output.
+    pub explicit_asset: Option<[u8; 32]>,
+    /// The proof that `explicit_asset` is the asset committed to by the
+    /// spent output.
+    pub asset_proof: Option<Vec<u8>>,
+}
+
+/// The confidential value and asset fields of a PSET output.
+#[derive(Clone, Default, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(crate = "actual_serde"))]
+pub struct ElementsOutput {
+    /// The 33 byte commitment to the value of the output.
+    pub value_commitment: Option<Vec<u8>>,
+    /// The explicit asset of the output.
+    pub asset: Option<[u8; 32]>,
+    /// The 33 byte commitment to the asset of the output.
+    pub asset_commitment: Option<Vec<u8>>,
+    /// The rangeproof of the value commitment.
+    pub value_rangeproof: Option<Vec<u8>>,
+    /// The surjection proof of the asset commitment.
+    pub asset_surjection_proof: Option<Vec<u8>>,
+    /// The key the output is blinded to.
+    pub blinding_pubkey: Option<secp256k1::PublicKey>,
+    /// The ephemeral key used to blind the output.
+    pub ecdh_pubkey: Option<secp256k1::PublicKey>,
+    /// The index of the input whose owner blinds the output.
+    pub blinder_index: Option<u32>,
+    /// The proof that the value commitment commits to the explicit value.
+    pub blind_value_proof: Option<Vec<u8>>,
+    /// The proof that the asset commitment commits to the explicit asset.
+    pub blind_asset_proof: Option<Vec<u8>>,
+}
+
+impl ElementsInput {
+    pub(crate) fn push_pairs(&self, pairs: &mut Vec<raw::Pair>) {
+        let explicit_value = self
+            .explicit_value
+            .map(|value| value.to_sat().to_le_bytes().to_vec());
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_IN_UTXO_RANGEPROOF,
+            self.utxo_rangeproof.clone(),
+        );
+        push_pair(pairs, PSBT_ELEMENTS_IN_EXPLICIT_VALUE, explicit_value);
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_IN_VALUE_PROOF,
+            self.value_proof.clone(),
+        );
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_IN_EXPLICIT_ASSET,
+            self.explicit_asset.map(|asset| asset.to_vec()),
+        );
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_IN_ASSET_PROOF,
+            self.asset_proof.clone(),
+        );
+    }
+
+    /// Parses `key` into its field if it's one of the Elements fields,
+    /// returning whether it was.
+    pub(crate) fn insert_pair(
+        &mut self,
+        key: &raw::Key,
+        value: &[u8],
+    ) -> Result<bool, DecodeError> {
+        let subtype = match pset_subtype(key) {
+            Some(subtype) => subtype,
+            None => return Ok(false),
+        };
+        match subtype {
+            PSBT_ELEMENTS_IN_UTXO_RANGEPROOF => self.utxo_rangeproof = Some(value.to_vec()),
+            PSBT_ELEMENTS_IN_EXPLICIT_VALUE => {
+                let value = u64::from_le_bytes(decode_array(key, value)?);
+                self.explicit_value = Some(Amount::from_sat(value));
+            }
+            PSBT_ELEMENTS_IN_VALUE_PROOF => self.value_proof = Some(value.to_vec()),
+            PSBT_ELEMENTS_IN_EXPLICIT_ASSET => {
+                self.explicit_asset = Some(decode_array(key, value)?);
+            }
+            PSBT_ELEMENTS_IN_ASSET_PROOF => self.asset_proof = Some(value.to_vec()),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Fills the fields `self` lacks from `other`.
+    pub fn combine(&mut self, other: ElementsInput) {
+        self.utxo_rangeproof = self.utxo_rangeproof.take().or(other.utxo_rangeproof);
+        self.explicit_value = self.explicit_value.or(other.explicit_value);
+        self.value_proof = self.value_proof.take().or(other.value_proof);
+        self.explicit_asset = self.explicit_asset.or(other.explicit_asset);
+        self.asset_proof = self.asset_proof.take().or(other.asset_proof);
+    }
+}
+
+impl ElementsOutput {
+    pub(crate) fn push_pairs(&self, pairs: &mut Vec<raw::Pair>) {
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_OUT_VALUE_COMMITMENT,
+            self.value_commitment.clone(),
+        );
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_OUT_ASSET,
+            self.asset.map(|asset| asset.to_vec()),
+        );
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_OUT_ASSET_COMMITMENT,
+            self.asset_commitment.clone(),
+        );
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_OUT_VALUE_RANGEPROOF,
+            self.value_rangeproof.clone(),
+        );
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_OUT_ASSET_SURJECTION_PROOF,
+            self.asset_surjection_proof.clone(),
+        );
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_OUT_BLINDING_PUBKEY,
+            self.blinding_pubkey.map(|key| key.serialize().to_vec()),
+        );
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_OUT_ECDH_PUBKEY,
+            self.ecdh_pubkey.map(|key| key.serialize().to_vec()),
+        );
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_OUT_BLINDER_INDEX,
+            self.blinder_index.map(|index| index.to_le_bytes().to_vec()),
+        );
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_OUT_BLIND_VALUE_PROOF,
+            self.blind_value_proof.clone(),
+        );
+        push_pair(
+            pairs,
+            PSBT_ELEMENTS_OUT_BLIND_ASSET_PROOF,
+            self.blind_asset_proof.clone(),
+        );
+    }
+
+    /// Parses `key` into its field if it's one of the Elements fields,
+    /// returning whether it was.
+    pub(crate) fn insert_pair(
+        &mut self,
+        key: &raw::Key,
+        value: &[u8],
+    ) -> Result<bool, DecodeError> {
+        let subtype = match pset_subtype(key) {
+            Some(subtype) => subtype,
+            None => return Ok(false),
+        };
+        match subtype {
+            PSBT_ELEMENTS_OUT_VALUE_COMMITMENT => {
+                self.value_commitment = Some(decode_commitment(key, value)?);
+            }
+            PSBT_ELEMENTS_OUT_ASSET => self.asset = Some(decode_array(key, value)?),
+            PSBT_ELEMENTS_OUT_ASSET_COMMITMENT => {
+                self.asset_commitment = Some(decode_commitment(key, value)?);
+            }
+            PSBT_ELEMENTS_OUT_VALUE_RANGEPROOF => self.value_rangeproof = Some(value.to_vec()),
+            PSBT_ELEMENTS_OUT_ASSET_SURJECTION_PROOF => {
+                self.asset_surjection_proof = Some(value.to_vec());
+            }
+            PSBT_ELEMENTS_OUT_BLINDING_PUBKEY => {
+                self.blinding_pubkey = Some(decode_public_key(key, value)?);
+            }
+            PSBT_ELEMENTS_OUT_ECDH_PUBKEY => {
+                self.ecdh_pubkey = Some(decode_public_key(key, value)?);
+            }
+            PSBT_ELEMENTS_OUT_BLINDER_INDEX => {
+                self.blinder_index = Some(u32::from_le_bytes(decode_array(key, value)?));
+            }
+            PSBT_ELEMENTS_OUT_BLIND_VALUE_PROOF => self.blind_value_proof = Some(value.to_vec()),
+            PSBT_ELEMENTS_OUT_BLIND_ASSET_PROOF => self.blind_asset_proof = Some(value.to_vec()),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Fills the fields `self` lacks from `other`.
+    pub fn combine(&mut self, other: ElementsOutput) {
+        self.value_commitment = self.value_commitment.take().or(other.value_commitment);
+        self.asset = self.asset.or(other.asset);
+        self.asset_commitment = self.asset_commitment.take().or(other.asset_commitment);
+        self.value_rangeproof = self.value_rangeproof.take().or(other.value_rangeproof);
+        self.asset_surjection_proof = self
+            .asset_surjection_proof
+            .take()
+            .or(other.asset_surjection_proof);
+        self.blinding_pubkey = self.blinding_pubkey.or(other.blinding_pubkey);
+        self.ecdh_pubkey = self.ecdh_pubkey.or(other.ecdh_pubkey);
+        self.blinder_index = self.blinder_index.or(other.blinder_index);
+        self.blind_value_proof = self.blind_value_proof.take().or(other.blind_value_proof);
+        self.blind_asset_proof = self.blind_asset_proof.take().or(other.blind_asset_proof);
+    }
+
+    /// Returns `true` if the output is blinded, its value and asset only
+    /// known through their commitments.
+    pub fn is_blinded(&self) -> bool {
+        self.value_commitment.is_some() && self.asset_commitment.is_some()
+    }
+}
+
+impl Psbt {
+    /// Serializes the PsbtV2 as a PSET, see [`Psbt::serialize`].
+    pub fn serialize_pset(&self) -> Vec<u8> {
+        let mut bytes = self.serialize();
+        bytes[..PSET_MAGIC.len()].copy_from_slice(&PSET_MAGIC);
+        bytes
+    }
+
+    /// Deserializes and validates a PSET, see [`Psbt::deserialize`]. Fails
+    /// with [`DecodeError::InvalidMagic`] if `bytes` don't start with
+    /// [`PSET_MAGIC`].
+    pub fn deserialize_pset(bytes: &[u8]) -> Result<Psbt, DecodeError> {
+        let maps = bytes
+            .strip_prefix(&PSET_MAGIC[..])
+            .ok_or(DecodeError::InvalidMagic)?;
+        let mut psbt = PSBT_MAGIC.to_vec();
+        psbt.extend_from_slice(maps);
+        Psbt::deserialize(&psbt)
+    }
+}
+
+/// The subtype of a proprietary key holding an Elements field, which has
+/// the PSET prefix and no key data.
+fn pset_subtype(key: &raw::Key) -> Option<u8> {
+    let key = raw::ProprietaryKey::try_from(key.clone()).ok()?;
+    (key.prefix == PSET_PREFIX && key.key.is_empty()).then_some(key.subtype)
+}
+
+fn push_pair(pairs: &mut Vec<raw::Pair>, subtype: u8, value: Option<Vec<u8>>) {
+    if let Some(value) = value {
+        let key = raw::ProprietaryKey {
+            prefix: PSET_PREFIX.to_vec(),
+            subtype,
+            key: vec![],
+        };
+        pairs.push(raw::Pair {
+            key: key.to_key(),
+            value,
+        });
+    }
+}
+
+fn decode_array<const N: usize>(key: &raw::Key, value: &[u8]) -> Result<[u8; N], DecodeError> {
+    value.try_into().map_err(|_| DecodeError::InvalidValue {
+        key: key.clone(),
+        reason: format!("expected {} bytes, got {}", N, value.len()),
+    })
+}
+
+fn decode_commitment(key: &raw::Key, value: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    decode_array::<COMMITMENT_SIZE>(key, value).map(|commitment| commitment.to_vec())
+}
+
+fn decode_public_key(key: &raw::Key, value: &[u8]) -> Result<secp256k1::PublicKey, DecodeError> {
+    secp256k1::PublicKey::from_slice(value).map_err(|err| DecodeError::InvalidValue {
+        key: key.clone(),
+        reason: err.to_string(),
+    })
+}
@@ -0,0 +1,131 @@
+//! Text encodings of serialized psbts: hex, and base64 with the `base64`
+//! feature.
+
+use core::fmt;
+#[cfg(feature = "base64")]
+use core::str::FromStr;
+
+#[cfg(feature = "base64")]
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+
+use crate::hashes::hex::DisplayHex;
+use crate::prelude::*;
+
+use super::poc::Psbt;
+use super::serialize::DecodeError;
+
+/// Errors encountered while parsing a psbt from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PsbtParseError {
+    /// The string isn't valid base64.
+    #[cfg(feature = "base64")]
+    Base64Encoding(base64::DecodeError),
+    /// The string isn't valid hex.
+    HexEncoding(HexError),
+    /// The decoded bytes aren't a valid psbt.
+    PsbtEncoding(DecodeError),
+}
+
+impl fmt::Display for PsbtParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "base64")]
+            PsbtParseError::Base64Encoding(err) => write!(f, "invalid base64: {}", err),
+            PsbtParseError::HexEncoding(err) => write!(f, "invalid hex: {}", err),
+            PsbtParseError::PsbtEncoding(err) => write!(f, "invalid psbt: {}", err),
+        }
+    }
+}
+
+/// Errors encountered while decoding hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HexError {
+    /// The character at this byte position isn't a hex digit.
+    InvalidChar { position: usize, character: char },
+    /// The string has an odd number of hex digits.
+    OddLength { len: usize },
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HexError::InvalidChar {
+                position,
+                character,
+            } => write!(
+                f,
+                "invalid character {:?} at position {}",
+                character, position
+            ),
+            HexError::OddLength { len } => write!(f, "odd number of hex digits {}", len),
+        }
+    }
+}
+
+impl Psbt {
+    /// Encodes the serialized psbt as lowercase hex, as some RPC interfaces
+    /// expect.
+    pub fn to_hex(&self) -> String {
+        self.serialize().to_lower_hex_string()
+    }
+
+    /// Decodes and validates a hex encoded psbt, upper or lowercase.
+    pub fn from_hex(s: &str) -> Result<Psbt, PsbtParseError> {
+        let bytes = decode_hex(s).map_err(PsbtParseError::HexEncoding)?;
+        Psbt::deserialize(&bytes).map_err(PsbtParseError::PsbtEncoding)
+    }
+}
+
+#[cfg(feature = "base64")]
+impl Psbt {
+    /// Encodes the serialized psbt as base64, the format BIP-174 defines for
+    /// passing psbts around as text.
+    pub fn to_base64(&self) -> String {
+        BASE64_STANDARD.encode(self.serialize())
+    }
+
+    /// Decodes and validates a base64 encoded psbt.
+    pub fn from_base64(s: &str) -> Result<Psbt, PsbtParseError> {
+        let bytes = BASE64_STANDARD
+            .decode(s)
+            .map_err(PsbtParseError::Base64Encoding)?;
+        Psbt::deserialize(&bytes).map_err(PsbtParseError::PsbtEncoding)
+    }
+}
+
+#[cfg(feature = "base64")]
+impl fmt::Display for Psbt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_base64())
+    }
+}
+
+#[cfg(feature = "base64")]
+impl FromStr for Psbt {
+    type Err = PsbtParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Psbt::from_base64(s)
+    }
+}
+
+/// Decodes `s` as hex, reporting the position of the first character that
+/// isn't a hex digit.
+fn decode_hex(s: &str) -> Result<Vec<u8>, HexError> {
+    let mut bytes = Vec::with_capacity(s.len() / 2);
+    let mut high = None;
+    for (position, character) in s.char_indices() {
+        let digit = character.to_digit(16).ok_or(HexError::InvalidChar {
+            position,
+            character,
+        })? as u8;
+        match high.take() {
+            Some(high) => bytes.push(high << 4 | digit),
+            None => high = Some(digit),
+        }
+    }
+    if high.is_some() {
+        return Err(HexError::OddLength { len: s.len() });
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,85 @@
+use crate::blockdata::transaction::Transaction;
+use crate::prelude::*;
+use crate::{Amount, FeeRate};
+
+use super::fee::{FeeError, FeePolicy};
+use super::poc::Psbt;
+
+/// The default maximum fee rate used by [`Psbt::extract_tx_fee_checked`]
+/// callers that don't have a policy of their own, 25 000 sat/vB.
+pub const DEFAULT_MAX_FEE_RATE: FeeRate = FeeRate::from_sat_per_vb_unchecked(25_000);
+
+/// Errors encountered while extracting the final transaction from a psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtractTxError {
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+    /// The input at this index has neither `final_script_sig` nor
+    /// `final_script_witness` set.
+    NotFinalized { index: usize },
+    /// The fee of the transaction could not be calculated.
+    Fee(FeeError),
+    /// The fee of the extracted transaction exceeds the allowed maximum.
+    AbsurdFee { fee: Amount, tx: Transaction },
+    /// The fee rate of the extracted transaction exceeds the allowed maximum.
+    AbsurdFeeRate { fee_rate: FeeRate, tx: Transaction },
+}
+
+impl Psbt {
+    /// Extracts the fully signed, broadcastable transaction.
+    ///
+    /// Every input must have been finalized, the `final_script_sig` and
+    /// `final_script_witness` of each input are moved into the transaction.
+    /// If a [`FeePolicy`] is set on the psbt, the fee of the transaction is
+    /// checked against it.
+    pub fn extract_tx(&self) -> Result<Transaction, ExtractTxError> {
+        let mut tx = self.unsigned_tx().map_err(ExtractTxError::UnsignedTx)?;
+
+        for (index, (txin, input)) in tx.input.iter_mut().zip(&self.inner.inputs).enumerate() {
+            if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+                return Err(ExtractTxError::NotFinalized { index });
+            }
+            txin.script_sig = input.final_script_sig.clone().unwrap_or_default();
+            txin.witness = input.final_script_witness.clone().unwrap_or_default();
+        }
+
+        if let Some(policy) = self.fee_policy {
+            self.check_extracted_fee(tx, &policy)
+        } else {
+            Ok(tx)
+        }
+    }
+
+    /// Same as [`Psbt::extract_tx`], but refuses to extract the transaction if
+    /// its fee rate is above `max_fee_rate`.
+    ///
+    /// This guards against broadcasting a transaction whose fee was inflated
+    /// by a buggy or malicious party during the psbt workflow.
+    pub fn extract_tx_fee_checked(
+        &self,
+        max_fee_rate: FeeRate,
+    ) -> Result<Transaction, ExtractTxError> {
+        let tx = self.extract_tx()?;
+        let policy = FeePolicy {
+            max_fee: None,
+            max_fee_rate: Some(max_fee_rate),
+        };
+        self.check_extracted_fee(tx, &policy)
+    }
+
+    fn check_extracted_fee(
+        &self,
+        tx: Transaction,
+        policy: &FeePolicy,
+    ) -> Result<Transaction, ExtractTxError> {
+        let fee = self.fee().map_err(ExtractTxError::Fee)?;
+        match policy.check(fee, tx.weight()) {
+            Ok(()) => Ok(tx),
+            Err(FeeError::AbsurdFee { fee, .. }) => Err(ExtractTxError::AbsurdFee { fee, tx }),
+            Err(FeeError::AbsurdFeeRate { fee_rate, .. }) => {
+                Err(ExtractTxError::AbsurdFeeRate { fee_rate, tx })
+            }
+            Err(err) => Err(ExtractTxError::Fee(err)),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::prelude::*;
+use crate::{Amount, FeeRate, Weight};
+
+use super::poc::Psbt;
+
+/// Errors encountered while calculating the fee of a psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeeError {
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+    /// The input at this index has no trusted utxo, see
+    /// [`Input::spent_utxo`](super::input::Input::spent_utxo), so its value
+    /// is unknown.
+    MissingUtxo { index: usize },
+    /// The outputs spend more than the inputs provide.
+    NegativeFee,
+    /// The input or output amounts add up to more than
+    /// [`Amount::MAX_MONEY`].
+    AmountOverflow,
+    /// The size of the final scriptSig and witness of the input at this index
+    /// can't be estimated.
+    UnknownSatisfactionSize { index: usize },
+    /// The fee exceeds the maximum of the [`FeePolicy`].
+    AbsurdFee { fee: Amount, max_fee: Amount },
+    /// The fee rate exceeds the maximum of the [`FeePolicy`].
+    AbsurdFeeRate {
+        fee_rate: FeeRate,
+        max_fee_rate: FeeRate,
+    },
+}
+
+/// Upper bounds on the fee a psbt may pay.
+///
+/// Set on a [`Psbt`] with [`Psbt::set_fee_policy`], the policy is enforced by
+/// [`Psbt::extract_tx`] and by the signing methods, so a compromised
+/// coordinator can't trick a signer into paying an absurd fee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FeePolicy {
+    /// The maximum absolute fee, unlimited if `None`.
+    pub max_fee: Option<Amount>,
+    /// The maximum fee rate, unlimited if `None`.
+    pub max_fee_rate: Option<FeeRate>,
+}
+
+impl FeePolicy {
+    /// Checks `fee`, paid by a transaction of `weight`, against the policy.
+    pub fn check(&self, fee: Amount, weight: Weight) -> Result<(), FeeError> {
+        self.check_fee(fee)?;
+        if let Some(max_fee_rate) = self.max_fee_rate {
+            let fee_rate = FeeRate::from_sat_per_kwu(fee.to_sat() * 1000 / weight.to_wu());
+            if fee_rate > max_fee_rate {
+                return Err(FeeError::AbsurdFeeRate {
+                    fee_rate,
+                    max_fee_rate,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks `fee` against the maximum absolute fee of the policy only.
+    fn check_fee(&self, fee: Amount) -> Result<(), FeeError> {
+        match self.max_fee {
+            Some(max_fee) if fee > max_fee => Err(FeeError::AbsurdFee { fee, max_fee }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Psbt {
+    /// Calculates the fee paid by the transaction.
+    ///
+    /// Input values are taken from the trusted utxo of each input, see
+    /// [`Input::spent_utxo`](super::input::Input::spent_utxo),
+    /// output values from the unsigned transaction for a PsbtV0 and from the
+    /// `amount` fields for a PsbtV2.
+    pub fn fee(&self) -> Result<Amount, FeeError> {
+        let tx = self.unsigned_tx().map_err(FeeError::UnsignedTx)?;
+
+        let mut input_values = Vec::with_capacity(tx.input.len());
+        for context in self.iter_inputs() {
+            let utxo = context.spent_utxo.ok_or(FeeError::MissingUtxo {
+                index: context.index,
+            })?;
+            input_values.push(utxo.value);
+        }
+        let input_value = checked_sum(input_values)?;
+        let output_value = checked_sum(tx.output.iter().map(|txout| txout.value))?;
+
+        input_value
+            .checked_sub(output_value)
+            .ok_or(FeeError::NegativeFee)
+    }
+
+    /// Returns the fee policy enforced on this psbt, if any.
+    pub fn fee_policy(&self) -> Option<&FeePolicy> {
+        self.fee_policy.as_ref()
+    }
+
+    /// Sets the fee policy enforced on this psbt. The policy is not part of
+    /// the serialized psbt.
+    pub fn set_fee_policy(&mut self, fee_policy: Option<FeePolicy>) {
+        self.fee_policy = fee_policy;
+    }
+
+    /// Checks the fee against the psbt's [`FeePolicy`], using the estimated
+    /// final size of the transaction. Always succeeds if no policy is set.
+    ///
+    /// If an input spends a script whose satisfaction size can't be
+    /// estimated, only the maximum absolute fee is checked, so such inputs
+    /// can still be signed. [`Psbt::extract_tx`] checks the fee rate of the
+    /// final transaction.
+    pub fn check_fee_policy(&self) -> Result<(), FeeError> {
+        let policy = match self.fee_policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        let fee = self.fee()?;
+        match self.estimate_vsize() {
+            Ok(vsize) => policy.check(fee, Weight::from_vb_unchecked(vsize)),
+            Err(FeeError::UnknownSatisfactionSize { .. }) => policy.check_fee(fee),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Estimates the virtual size of the final transaction, see
+    /// [`Psbt::estimate_weight`].
+    pub fn estimate_vsize(&self) -> Result<u64, FeeError> {
+        Ok(self.estimate_weight()?.to_vbytes_ceil())
+    }
+}
+
+/// Sums `amounts`, failing with [`FeeError::AmountOverflow`] if the total
+/// exceeds [`Amount::MAX_MONEY`]. The amounts come from the psbt, so a
+/// malicious one must not make the sum wrap.
+pub(crate) fn checked_sum<I: IntoIterator<Item = Amount>>(amounts: I) -> Result<Amount, FeeError> {
+    amounts
+        .into_iter()
+        .try_fold(Amount::ZERO, |total, amount| total.checked_add(amount))
+        .filter(|total| *total <= Amount::MAX_MONEY)
+        .ok_or(FeeError::AmountOverflow)
+}
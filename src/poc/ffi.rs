@@ -0,0 +1,236 @@
+//! C bindings, so mobile wallet SDKs can drive psbts through Swift or
+//! Kotlin wrappers.
+//!
+//! Psbts are handed out as opaque pointers the caller releases with
+//! [`psbt_free`], byte buffers returned by the library are released with
+//! [`psbt_bytes_free`]. Every fallible function reports its outcome as a
+//! [`PsbtErrorCode`]. Panics never unwind into the caller, they are reported
+//! as [`PsbtErrorCode::Panic`].
+
+use core::{ptr, slice};
+use std::panic::{self, AssertUnwindSafe};
+
+use secp256k1::SecretKey;
+
+use crate::consensus::encode;
+use crate::prelude::*;
+
+use super::extractor::ExtractTxError;
+use super::poc::Psbt;
+use super::secret::Wiped;
+use super::serialize::DecodeError;
+use super::signer::{PsbtSigner, SignOptions};
+
+/// The outcome of an FFI call.
+///
+/// The values are part of the C ABI: existing codes are never renumbered or
+/// reused, new ones are only appended.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsbtErrorCode {
+    /// The call succeeded.
+    Ok = 0,
+    /// A required pointer argument is null.
+    NullPointer = 1,
+    /// The bytes aren't a well formed psbt.
+    Decode = 2,
+    /// The psbt is well formed but breaks a rule of BIP-174 or BIP-370.
+    Invalid = 3,
+    /// The psbts can't be combined.
+    Combine = 4,
+    /// Signing failed.
+    Sign = 5,
+    /// The secret key isn't valid.
+    InvalidKey = 6,
+    /// An input isn't finalized yet.
+    NotFinalized = 7,
+    /// The transaction couldn't be extracted.
+    Extract = 8,
+    /// The library panicked, the arguments are left in an unspecified but
+    /// valid state.
+    Panic = 9,
+}
+
+/// A byte buffer owned by the library, released with [`psbt_bytes_free`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct PsbtBytes {
+    /// The bytes, null if the buffer is empty.
+    pub data: *mut u8,
+    /// The number of bytes at `data`.
+    pub len: usize,
+}
+
+impl PsbtBytes {
+    fn new(bytes: Vec<u8>) -> Self {
+        if bytes.is_empty() {
+            return PsbtBytes {
+                data: ptr::null_mut(),
+                len: 0,
+            };
+        }
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        PsbtBytes { data, len }
+    }
+}
+
+/// Runs the body of an FFI call, reporting a panic instead of unwinding
+/// across the C ABI, which is undefined behavior.
+fn catch_panic<F: FnOnce() -> PsbtErrorCode>(body: F) -> PsbtErrorCode {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(PsbtErrorCode::Panic)
+}
+
+/// Parses and validates a serialized psbt, storing it in `out` on success.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` must be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn psbt_parse(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut Psbt,
+) -> PsbtErrorCode {
+    catch_panic(|| {
+        if data.is_null() || out.is_null() {
+            return PsbtErrorCode::NullPointer;
+        }
+        let bytes = slice::from_raw_parts(data, len);
+        match Psbt::deserialize(bytes) {
+            Ok(psbt) => {
+                *out = Box::into_raw(Box::new(psbt));
+                PsbtErrorCode::Ok
+            }
+            Err(DecodeError::Invalid(_)) => PsbtErrorCode::Invalid,
+            Err(_) => PsbtErrorCode::Decode,
+        }
+    })
+}
+
+/// Serializes `psbt` into `out`.
+///
+/// # Safety
+///
+/// `psbt` must come from [`psbt_parse`] and not be freed yet, `out` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn psbt_serialize(psbt: *const Psbt, out: *mut PsbtBytes) -> PsbtErrorCode {
+    catch_panic(|| {
+        if psbt.is_null() || out.is_null() {
+            return PsbtErrorCode::NullPointer;
+        }
+        *out = PsbtBytes::new((*psbt).serialize());
+        PsbtErrorCode::Ok
+    })
+}
+
+/// Merges `other` into `psbt`, `other` is left untouched.
+///
+/// # Safety
+///
+/// Both psbts must come from [`psbt_parse`] and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn psbt_combine(psbt: *mut Psbt, other: *const Psbt) -> PsbtErrorCode {
+    catch_panic(|| {
+        if psbt.is_null() || other.is_null() {
+            return PsbtErrorCode::NullPointer;
+        }
+        let other = Psbt {
+            inner: (*other).inner.clone(),
+            fee_policy: None,
+            network: None,
+        };
+        match (*psbt).combine(other) {
+            Ok(()) => PsbtErrorCode::Ok,
+            Err(_) => PsbtErrorCode::Combine,
+        }
+    })
+}
+
+/// Signs every input of `psbt` spending to the 32 byte secret key at
+/// `secret_key` and stores the number of signed inputs in `signed`.
+///
+/// # Safety
+///
+/// `psbt` must come from [`psbt_parse`] and not be freed yet, `secret_key`
+/// must point to 32 readable bytes and `signed` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn psbt_sign_with_key(
+    psbt: *mut Psbt,
+    secret_key: *const u8,
+    signed: *mut usize,
+) -> PsbtErrorCode {
+    catch_panic(|| {
+        if psbt.is_null() || secret_key.is_null() || signed.is_null() {
+            return PsbtErrorCode::NullPointer;
+        }
+        let secret_key = match SecretKey::from_slice(slice::from_raw_parts(secret_key, 32)) {
+            Ok(secret_key) => Wiped(secret_key),
+            Err(_) => return PsbtErrorCode::InvalidKey,
+        };
+        match secret_key.sign(&mut *psbt, SignOptions::default()) {
+            Ok(report) => {
+                *signed = report.signed.len();
+                PsbtErrorCode::Ok
+            }
+            Err(_) => PsbtErrorCode::Sign,
+        }
+    })
+}
+
+/// Extracts the finalized transaction of `psbt` into `out`, consensus
+/// encoded.
+///
+/// # Safety
+///
+/// `psbt` must come from [`psbt_parse`] and not be freed yet, `out` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn psbt_extract(psbt: *const Psbt, out: *mut PsbtBytes) -> PsbtErrorCode {
+    catch_panic(|| {
+        if psbt.is_null() || out.is_null() {
+            return PsbtErrorCode::NullPointer;
+        }
+        match (*psbt).extract_tx() {
+            Ok(tx) => {
+                *out = PsbtBytes::new(encode::serialize(&tx));
+                PsbtErrorCode::Ok
+            }
+            Err(ExtractTxError::NotFinalized { .. }) => PsbtErrorCode::NotFinalized,
+            Err(_) => PsbtErrorCode::Extract,
+        }
+    })
+}
+
+/// Releases a psbt returned by [`psbt_parse`]. Null is ignored.
+///
+/// # Safety
+///
+/// `psbt` must come from [`psbt_parse`] and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn psbt_free(psbt: *mut Psbt) {
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+        if !psbt.is_null() {
+            drop(Box::from_raw(psbt));
+        }
+    }));
+}
+
+/// Releases a buffer returned by the library. Buffers with a null `data`,
+/// which is what empty buffers have, are ignored.
+///
+/// # Safety
+///
+/// `bytes` must come from the library and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn psbt_bytes_free(bytes: PsbtBytes) {
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+        if !bytes.data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                bytes.data, bytes.len,
+            )));
+        }
+    }));
+}
@@ -0,0 +1,618 @@
+use core::fmt;
+
+use crate::blockdata::locktime::absolute;
+use crate::blockdata::opcodes::all::{
+    OP_CHECKMULTISIG, OP_CHECKSIG, OP_CHECKSIGADD, OP_ELSE, OP_ENDIF, OP_EQUALVERIFY, OP_HASH160,
+    OP_IF, OP_NUMEQUAL, OP_PUSHBYTES_0,
+};
+use crate::blockdata::opcodes::{Class, ClassifyContext};
+use crate::blockdata::script::{
+    read_scriptint, Builder, Instruction, PushBytes, Script, ScriptBuf,
+};
+use crate::blockdata::transaction::TxIn;
+use crate::blockdata::witness::Witness;
+use crate::crypto::ecdsa;
+use crate::crypto::key::{PublicKey, XOnlyPublicKey};
+use crate::hashes::{hash160, Hash};
+use crate::prelude::*;
+use crate::taproot::{self, TapLeafHash};
+
+use super::index::InputIndex;
+use super::input::Input;
+use super::poc::Psbt;
+
+/// Errors encountered while finalizing a psbt input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinalizeError {
+    /// The input index is out of range.
+    IndexOutOfBounds { index: usize, length: usize },
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+    /// Neither `witness_utxo` nor `non_witness_utxo` is set for the input.
+    MissingUtxo { index: usize },
+    /// The spent output is P2SH but the input has no `redeem_script`.
+    MissingRedeemScript { index: usize },
+    /// The spent output is P2WSH but the input has no `witness_script`.
+    MissingWitnessScript { index: usize },
+    /// The input spends a script the finalizer doesn't know how to satisfy.
+    UnsupportedScript { index: usize },
+    /// `partial_sigs` has no signature for the key the script requires.
+    MissingSignature { index: usize },
+    /// The input spends a hashlock whose preimage isn't in
+    /// `hash160_preimages`.
+    MissingPreimage { index: usize },
+    /// `partial_sigs` has fewer signatures from the multisig keys than the
+    /// script's threshold.
+    InsufficientSignatures {
+        index: usize,
+        required: usize,
+        present: usize,
+    },
+    /// The script couldn't be parsed or satisfied as miniscript.
+    #[cfg(feature = "miniscript")]
+    Miniscript(String),
+}
+
+impl fmt::Display for FinalizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FinalizeError::IndexOutOfBounds { index, length } => {
+                write!(
+                    f,
+                    "input index {} out of bounds for {} inputs",
+                    index, length
+                )
+            }
+            FinalizeError::UnsignedTx(err) => write!(f, "invalid unsigned transaction: {}", err),
+            FinalizeError::MissingUtxo { index } => write!(f, "input {} has no utxo", index),
+            FinalizeError::MissingRedeemScript { index } => {
+                write!(f, "input {} has no redeem script", index)
+            }
+            FinalizeError::MissingWitnessScript { index } => {
+                write!(f, "input {} has no witness script", index)
+            }
+            FinalizeError::UnsupportedScript { index } => {
+                write!(f, "input {} spends an unsupported script", index)
+            }
+            FinalizeError::MissingSignature { index } => {
+                write!(f, "input {} is missing a signature", index)
+            }
+            FinalizeError::MissingPreimage { index } => {
+                write!(f, "input {} is missing a hashlock preimage", index)
+            }
+            FinalizeError::InsufficientSignatures {
+                index,
+                required,
+                present,
+            } => write!(
+                f,
+                "input {} has {} of the {} required signatures",
+                index, present, required
+            ),
+            #[cfg(feature = "miniscript")]
+            FinalizeError::Miniscript(err) => write!(f, "miniscript: {}", err),
+        }
+    }
+}
+
+impl Psbt {
+    /// Finalizes every input of the psbt, see [`Psbt::finalize_input`].
+    pub fn finalize(&mut self) -> Result<(), FinalizeError> {
+        for index in 0..self.inner.inputs.len() {
+            self.finalize_input(InputIndex::new(index))?;
+        }
+        Ok(())
+    }
+
+    /// Finalizes the input at `index`.
+    ///
+    /// Builds `final_script_sig` and `final_script_witness` from the collected
+    /// partial data and then clears every field that is no longer needed, as
+    /// required by BIP-174. Inputs that are already finalized are left as is.
+    ///
+    /// With the `miniscript` feature, scripts the hand-rolled satisfiers of
+    /// this module don't support or can't find the signatures of are lifted
+    /// into miniscript and satisfied from the signatures, preimages and
+    /// timelocks of the input.
+    pub fn finalize_input(&mut self, index: InputIndex) -> Result<(), FinalizeError> {
+        let index = index.get();
+        let tx = self.unsigned_tx().map_err(FinalizeError::UnsignedTx)?;
+        let length = self.inner.inputs.len();
+        let input = self
+            .inner
+            .inputs
+            .get_mut(index)
+            .ok_or(FinalizeError::IndexOutOfBounds { index, length })?;
+        if input.is_finalized() {
+            return Ok(());
+        }
+
+        let (script_sig, witness) = satisfy_input(input, &tx.input[index], tx.lock_time, index)?;
+
+        input.final_script_sig = script_sig;
+        input.final_script_witness = witness;
+        input.clear_finalized_data();
+        Ok(())
+    }
+}
+
+impl Input {
+    /// Returns `true` if the input has a `final_script_sig` or a
+    /// `final_script_witness`.
+    pub fn is_finalized(&self) -> bool {
+        self.final_script_sig.is_some() || self.final_script_witness.is_some()
+    }
+
+    /// Clears every field a finalized input must not carry any more.
+    ///
+    /// Only the UTXO fields, the final scriptSig and witness, the proof of
+    /// reserves commitment, and the proprietary and unknown key-value pairs
+    /// are kept.
+    pub fn clear_finalized_data(&mut self) {
+        self.partial_sigs.clear();
+        self.sighash_type = None;
+        self.redeem_script = None;
+        self.witness_script = None;
+        self.bip32_derivation.clear();
+        self.ripemd160_preimages.clear();
+        self.sha256_preimages.clear();
+        self.hash160_preimages.clear();
+        self.hash256_preimages.clear();
+        self.tap_key_sig = None;
+        self.tap_script_sigs.clear();
+        self.tap_scripts.clear();
+        self.tap_key_origins.clear();
+        self.tap_internal_key = None;
+        self.tap_merkle_root = None;
+        self.tap_annex = None;
+        self.musig2_participant_pubkeys.clear();
+        self.musig2_pub_nonces.clear();
+        self.musig2_partial_sigs.clear();
+        self.sp_ecdh_shares.clear();
+        self.sp_dleq_proofs.clear();
+    }
+}
+
+/// Builds the final scriptSig and witness of the input at `index`, spent by
+/// `txin` of a transaction locked at `lock_time`, without setting them.
+///
+/// Falls back to the miniscript satisfier with the `miniscript` feature, see
+/// [`Psbt::finalize_input`].
+#[cfg_attr(
+    not(feature = "miniscript"),
+    allow(unused_variables, clippy::let_and_return)
+)]
+pub(crate) fn satisfy_input(
+    input: &Input,
+    txin: &TxIn,
+    lock_time: absolute::LockTime,
+    index: usize,
+) -> Result<(Option<ScriptBuf>, Option<Witness>), FinalizeError> {
+    let script_pubkey = &input
+        .spent_utxo(&txin.previous_output)
+        .ok_or(FinalizeError::MissingUtxo { index })?
+        .script_pubkey;
+
+    let satisfaction = satisfy(input, script_pubkey, index);
+    #[cfg(feature = "miniscript")]
+    let satisfaction = match satisfaction {
+        Err(FinalizeError::UnsupportedScript { .. })
+        | Err(FinalizeError::MissingSignature { .. }) => {
+            super::satisfier::satisfy(input, script_pubkey, txin.sequence, lock_time, index)
+        }
+        satisfaction => satisfaction,
+    };
+    satisfaction
+}
+
+/// Builds the final scriptSig and witness of an input spending
+/// `script_pubkey` with the hand-rolled satisfiers of this module.
+fn satisfy(
+    input: &Input,
+    script_pubkey: &Script,
+    index: usize,
+) -> Result<(Option<ScriptBuf>, Option<Witness>), FinalizeError> {
+    if script_pubkey.is_p2pkh() {
+        let (public_key, signature) = single_key_signature(input, script_pubkey, index)?;
+        let script_sig = Builder::new()
+            .push_slice(signature.serialize())
+            .push_key(&public_key)
+            .into_script();
+        Ok((Some(script_sig), None))
+    } else if script_pubkey.is_p2wpkh() {
+        let (public_key, signature) = single_key_signature(input, script_pubkey, index)?;
+        Ok((None, Some(Witness::p2wpkh(&signature, &public_key.inner))))
+    } else if script_pubkey.is_p2tr() {
+        Ok((None, Some(taproot_witness(input, index)?)))
+    } else if script_pubkey.is_p2wsh() {
+        let witness_script = input
+            .witness_script
+            .as_ref()
+            .ok_or(FinalizeError::MissingWitnessScript { index })?;
+        Ok((None, Some(wsh_witness(input, witness_script, index)?)))
+    } else if script_pubkey.is_p2sh() {
+        let redeem_script = input
+            .redeem_script
+            .as_ref()
+            .ok_or(FinalizeError::MissingRedeemScript { index })?;
+        if redeem_script.is_p2wpkh() {
+            let (public_key, signature) = single_key_signature(input, redeem_script, index)?;
+            Ok((
+                Some(push_redeem_script(redeem_script)),
+                Some(Witness::p2wpkh(&signature, &public_key.inner)),
+            ))
+        } else if redeem_script.is_p2wsh() {
+            let witness_script = input
+                .witness_script
+                .as_ref()
+                .ok_or(FinalizeError::MissingWitnessScript { index })?;
+            Ok((
+                Some(push_redeem_script(redeem_script)),
+                Some(wsh_witness(input, witness_script, index)?),
+            ))
+        } else {
+            let signatures = multisig_signatures(input, redeem_script, index)?;
+            let mut builder = Builder::new().push_opcode(OP_PUSHBYTES_0);
+            for signature in signatures {
+                builder = builder.push_slice(signature.serialize());
+            }
+            let bytes = <&PushBytes>::try_from(redeem_script.as_bytes())
+                .expect("redeem scripts are limited to 520 bytes");
+            Ok((Some(builder.push_slice(bytes).into_script()), None))
+        }
+    } else {
+        Err(FinalizeError::UnsupportedScript { index })
+    }
+}
+
+/// Finds the signature in `partial_sigs` made by the single key that
+/// `script_pubkey` (P2PKH or P2WPKH) pays to.
+fn single_key_signature(
+    input: &Input,
+    script_pubkey: &Script,
+    index: usize,
+) -> Result<(PublicKey, ecdsa::Signature), FinalizeError> {
+    input
+        .partial_sigs
+        .iter()
+        .find(|(public_key, _)| pays_to_key(script_pubkey, public_key))
+        .map(|(public_key, signature)| (*public_key, *signature))
+        .ok_or(FinalizeError::MissingSignature { index })
+}
+
+/// Builds the witness spending a taproot input.
+///
+/// A key path spend is used whenever `tap_key_sig` is present. Otherwise the
+/// leaf of `tap_scripts` with the smallest satisfying witness is picked among
+/// the single key `<xonly_pubkey> OP_CHECKSIG` leaves and the `OP_CHECKSIGADD`
+/// multisig leaves signed for in `tap_script_sigs`. The `tap_annex` of the input, if any, ends the
+/// witness.
+fn taproot_witness(input: &Input, index: usize) -> Result<Witness, FinalizeError> {
+    let mut witness = taproot_spend_witness(input, index)?;
+    if let Some(ref annex) = input.tap_annex {
+        witness.push(annex);
+    }
+    Ok(witness)
+}
+
+/// Builds the witness of a taproot spend, without the annex.
+fn taproot_spend_witness(input: &Input, index: usize) -> Result<Witness, FinalizeError> {
+    if let Some(signature) = input.tap_key_sig {
+        let mut witness = Witness::new();
+        witness.push(signature.to_vec());
+        return Ok(witness);
+    }
+
+    let mut best: Option<Witness> = None;
+    for (control_block, (script, leaf_version)) in &input.tap_scripts {
+        let leaf_hash = TapLeafHash::from_script(script, *leaf_version);
+        let signatures = match tapscript_signatures(input, script, leaf_hash) {
+            Some(signatures) => signatures,
+            None => continue,
+        };
+
+        let mut witness = Witness::new();
+        for signature in signatures {
+            witness.push(signature);
+        }
+        witness.push(script.as_bytes());
+        witness.push(control_block.serialize());
+        if best
+            .as_ref()
+            .map_or(true, |best| witness.size() < best.size())
+        {
+            best = Some(witness);
+        }
+    }
+
+    best.ok_or(FinalizeError::MissingSignature { index })
+}
+
+/// Returns `true` if a taproot input has a key path signature, or enough
+/// `tap_script_sigs` for one of its leaves to be satisfied the way the
+/// finalizer satisfies them.
+pub(crate) fn taproot_satisfied(input: &Input) -> bool {
+    input.tap_key_sig.is_some()
+        || input.tap_scripts.values().any(|(script, leaf_version)| {
+            let leaf_hash = TapLeafHash::from_script(script, *leaf_version);
+            tapscript_signatures(input, script, leaf_hash).is_some()
+        })
+}
+
+/// Returns the witness elements satisfying a single key or `OP_CHECKSIGADD`
+/// multisig leaf with the signatures of `tap_script_sigs`, `None` if there
+/// aren't enough of them.
+///
+/// A multisig leaf takes the signatures of the first keys with one, up to
+/// its threshold, and an empty vector for every other key. The first key of
+/// the script checks the top of the stack, so the elements are in reverse
+/// script order.
+fn tapscript_signatures(
+    input: &Input,
+    script: &Script,
+    leaf_hash: TapLeafHash,
+) -> Option<Vec<Vec<u8>>> {
+    if let Some(key) = parse_single_key_tapscript(script) {
+        let signature: &taproot::Signature = input.tap_script_sigs.get(&(key, leaf_hash))?;
+        return Some(vec![signature.to_vec()]);
+    }
+
+    let (threshold, keys) = parse_checksigadd_multisig(script)?;
+    let mut missing = threshold;
+    let mut elements: Vec<Vec<u8>> = keys
+        .iter()
+        .map(|key| match input.tap_script_sigs.get(&(*key, leaf_hash)) {
+            Some(signature) if missing > 0 => {
+                missing -= 1;
+                signature.to_vec()
+            }
+            _ => Vec::new(),
+        })
+        .collect();
+    if missing > 0 {
+        return None;
+    }
+    elements.reverse();
+    Some(elements)
+}
+
+/// Parses a tapscript multisig leaf of the form
+/// `<xonly_pubkey> OP_CHECKSIG (<xonly_pubkey> OP_CHECKSIGADD)... <k>
+/// OP_NUMEQUAL`, returning the threshold and the keys in script order.
+fn parse_checksigadd_multisig(script: &Script) -> Option<(usize, Vec<XOnlyPublicKey>)> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let (numequal, rest) = instructions.split_last()?;
+    let (threshold, checks) = rest.split_last()?;
+    if *numequal != Instruction::Op(OP_NUMEQUAL) || checks.len() % 2 != 0 {
+        return None;
+    }
+
+    let threshold = match threshold {
+        Instruction::PushBytes(bytes) => {
+            usize::try_from(read_scriptint(bytes.as_bytes()).ok()?).ok()?
+        }
+        Instruction::Op(_) => small_int(threshold)?,
+    };
+    let keys = checks
+        .chunks(2)
+        .enumerate()
+        .map(|(position, check)| {
+            let opcode = if position == 0 {
+                OP_CHECKSIG
+            } else {
+                OP_CHECKSIGADD
+            };
+            match check {
+                [Instruction::PushBytes(key), Instruction::Op(op)] if *op == opcode => {
+                    XOnlyPublicKey::from_slice(key.as_bytes()).ok()
+                }
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if threshold == 0 || threshold > keys.len() {
+        return None;
+    }
+
+    Some((threshold, keys))
+}
+
+/// Parses a tapscript leaf of the form `<xonly_pubkey> OP_CHECKSIG`.
+fn parse_single_key_tapscript(script: &Script) -> Option<XOnlyPublicKey> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    match instructions.as_slice() {
+        [Instruction::PushBytes(bytes), Instruction::Op(op)] if *op == OP_CHECKSIG => {
+            XOnlyPublicKey::from_slice(bytes.as_bytes()).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Returns `true` if `script_pubkey` is a P2PKH or P2WPKH script paying to
+/// `public_key`.
+pub(crate) fn pays_to_key(script_pubkey: &Script, public_key: &PublicKey) -> bool {
+    if script_pubkey.is_p2pkh() {
+        ScriptBuf::new_p2pkh(&public_key.pubkey_hash()) == *script_pubkey
+    } else {
+        public_key
+            .wpubkey_hash()
+            .map_or(false, |hash| ScriptBuf::new_p2wpkh(&hash) == *script_pubkey)
+    }
+}
+
+/// Parses a bare `OP_CHECKMULTISIG` script of the form
+/// `<m> <pubkey>... <n> OP_CHECKMULTISIG`, returning the threshold and the
+/// public keys in script order.
+pub(crate) fn parse_multisig(script: &Script) -> Option<(usize, Vec<PublicKey>)> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let (threshold, rest) = instructions.split_first()?;
+    let (checkmultisig, rest) = rest.split_last()?;
+    let (key_count, keys) = rest.split_last()?;
+    if *checkmultisig != Instruction::Op(OP_CHECKMULTISIG) {
+        return None;
+    }
+
+    let threshold = small_int(threshold)?;
+    let key_count = small_int(key_count)?;
+    let keys = keys
+        .iter()
+        .map(|instruction| match instruction {
+            Instruction::PushBytes(bytes) => PublicKey::from_slice(bytes.as_bytes()).ok(),
+            Instruction::Op(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if keys.len() != key_count || threshold > key_count {
+        return None;
+    }
+
+    Some((threshold, keys))
+}
+
+/// Decodes the `OP_1`..`OP_16` opcodes used for multisig thresholds and key
+/// counts.
+fn small_int(instruction: &Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::Op(op) => match op.classify(ClassifyContext::Legacy) {
+            Class::PushNum(n) if n > 0 => Some(n as usize),
+            _ => None,
+        },
+        Instruction::PushBytes(_) => None,
+    }
+}
+
+/// Picks `threshold` signatures for the multisig `script` from
+/// `partial_sigs`, in the order their keys appear in the script as
+/// `OP_CHECKMULTISIG` requires.
+fn multisig_signatures(
+    input: &Input,
+    script: &Script,
+    index: usize,
+) -> Result<Vec<ecdsa::Signature>, FinalizeError> {
+    let (threshold, keys) =
+        parse_multisig(script).ok_or(FinalizeError::UnsupportedScript { index })?;
+    let signatures: Vec<ecdsa::Signature> = keys
+        .iter()
+        .filter_map(|key| input.partial_sigs.get(key).copied())
+        .take(threshold)
+        .collect();
+    if signatures.len() < threshold {
+        return Err(FinalizeError::InsufficientSignatures {
+            index,
+            required: threshold,
+            present: signatures.len(),
+        });
+    }
+    Ok(signatures)
+}
+
+/// Builds the witness spending a P2WSH `witness_script`, either a hashlock
+/// or a multisig.
+fn wsh_witness(
+    input: &Input,
+    witness_script: &Script,
+    index: usize,
+) -> Result<Witness, FinalizeError> {
+    match parse_hashlock(witness_script) {
+        Some(hashlock) => hashlock_witness(input, witness_script, &hashlock, index),
+        None => multisig_witness(input, witness_script, index),
+    }
+}
+
+/// A script locked to a key and the preimage of a HASH160.
+struct Hashlock {
+    hash: hash160::Hash,
+    public_key: PublicKey,
+    /// Whether the hashlock is the `OP_IF` branch of an HTLC, so the witness
+    /// must select it.
+    in_branch: bool,
+}
+
+/// Parses a hashlock of the form
+/// `OP_HASH160 <hash> OP_EQUALVERIFY <pubkey> OP_CHECKSIG`, or an HTLC whose
+/// `OP_IF` branch is `OP_HASH160 <hash> OP_EQUALVERIFY <pubkey>` and whose
+/// `OP_ELSE` branch leaves the key of the timeout path for the final
+/// `OP_CHECKSIG`.
+///
+/// This is not the HTLC template of BIP-199, which hashes with `OP_SHA256`
+/// and checks the preimage with `OP_EQUAL`, scripts of that form are left to
+/// the miniscript satisfier of the `miniscript` feature.
+fn parse_hashlock(script: &Script) -> Option<Hashlock> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let (lock, in_branch) = match instructions.as_slice() {
+        [lock @ .., Instruction::Op(checksig)] if *checksig == OP_CHECKSIG && lock.len() == 4 => {
+            (lock, false)
+        }
+        [Instruction::Op(op_if), rest @ ..] if *op_if == OP_IF => {
+            let (lock, rest) = rest.split_at(rest.len().min(4));
+            let ends = rest.first() == Some(&Instruction::Op(OP_ELSE))
+                && rest.ends_with(&[Instruction::Op(OP_ENDIF), Instruction::Op(OP_CHECKSIG)]);
+            if !ends {
+                return None;
+            }
+            (lock, true)
+        }
+        _ => return None,
+    };
+    match lock {
+        [Instruction::Op(hash160), Instruction::PushBytes(hash), Instruction::Op(equalverify), Instruction::PushBytes(public_key)]
+            if *hash160 == OP_HASH160 && *equalverify == OP_EQUALVERIFY =>
+        {
+            Some(Hashlock {
+                hash: hash160::Hash::from_slice(hash.as_bytes()).ok()?,
+                public_key: PublicKey::from_slice(public_key.as_bytes()).ok()?,
+                in_branch,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Builds the witness spending `hashlock` with the signature of its key and
+/// the preimage of its hash, selecting the `OP_IF` branch of an HTLC.
+fn hashlock_witness(
+    input: &Input,
+    witness_script: &Script,
+    hashlock: &Hashlock,
+    index: usize,
+) -> Result<Witness, FinalizeError> {
+    let signature = input
+        .partial_sigs
+        .get(&hashlock.public_key)
+        .ok_or(FinalizeError::MissingSignature { index })?;
+    let preimage = input
+        .hash160_preimages
+        .get(&hashlock.hash)
+        .ok_or(FinalizeError::MissingPreimage { index })?;
+    let mut witness = Witness::new();
+    witness.push(signature.to_vec());
+    witness.push(preimage);
+    if hashlock.in_branch {
+        witness.push([1]);
+    }
+    witness.push(witness_script.as_bytes());
+    Ok(witness)
+}
+
+/// Builds the witness spending a P2WSH multisig `witness_script`, including
+/// the dummy element consumed by `OP_CHECKMULTISIG`.
+fn multisig_witness(
+    input: &Input,
+    witness_script: &Script,
+    index: usize,
+) -> Result<Witness, FinalizeError> {
+    let signatures = multisig_signatures(input, witness_script, index)?;
+    let mut witness = Witness::new();
+    witness.push([]);
+    for signature in signatures {
+        witness.push(signature.to_vec());
+    }
+    witness.push(witness_script.as_bytes());
+    Ok(witness)
+}
+
+/// Builds a scriptSig pushing only the serialized `redeem_script`.
+pub(crate) fn push_redeem_script(redeem_script: &Script) -> ScriptBuf {
+    let bytes = <&PushBytes>::try_from(redeem_script.as_bytes())
+        .expect("redeem scripts are limited to 520 bytes");
+    Builder::new().push_slice(bytes).into_script()
+}
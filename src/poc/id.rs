@@ -0,0 +1,24 @@
+//! The unique identifier of BIP-370, identifying the transaction a psbt
+//! describes regardless of its signatures and sequence numbers.
+
+use crate::blockdata::transaction::Sequence;
+use crate::hash_types::Txid;
+use crate::prelude::*;
+
+use super::poc::Psbt;
+
+impl Psbt {
+    /// Computes the unique identifier of the psbt: the txid of its unsigned
+    /// transaction with the sequence numbers of all inputs set to 0.
+    ///
+    /// Psbts of the same transaction have the same id whatever signatures
+    /// they carry and even if a PsbtV2 input changed its sequence number, so
+    /// coordinators can key psbts by it.
+    pub fn unique_id(&self) -> Result<Txid, String> {
+        let mut tx = self.unsigned_tx()?;
+        for input in &mut tx.input {
+            input.sequence = Sequence::ZERO;
+        }
+        Ok(tx.txid())
+    }
+}
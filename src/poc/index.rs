@@ -0,0 +1,133 @@
+//! Typed indices of psbt inputs and outputs.
+//!
+//! The methods signing, finalizing, computing the sighash of or verifying the
+//! signatures of a single input take an [`InputIndex`], so an output index,
+//! or an index into some other list, can't be passed where an input is meant.
+//!
+//! Indices the crate hands back, in errors, [`SignReport`], [`InputContext`]
+//! and the like, as well as the input selection of [`SignOptions`], remain
+//! plain `usize` positions, which [`InputIndex::new`] wraps.
+//!
+//! [`SignReport`]: super::signer::SignReport
+//! [`SignOptions`]: super::signer::SignOptions
+//! [`InputContext`]: super::iter::InputContext
+
+use core::fmt;
+
+use crate::prelude::*;
+
+use super::input::Input;
+use super::output::Output;
+use super::poc::Psbt;
+
+/// The index of an input of a psbt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct InputIndex(usize);
+
+/// The index of an output of a psbt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct OutputIndex(usize);
+
+impl InputIndex {
+    /// Wraps the position of an input.
+    pub const fn new(index: usize) -> Self {
+        InputIndex(index)
+    }
+
+    /// Returns the position of the input.
+    pub const fn get(self) -> usize {
+        self.0
+    }
+}
+
+impl OutputIndex {
+    /// Wraps the position of an output.
+    pub const fn new(index: usize) -> Self {
+        OutputIndex(index)
+    }
+
+    /// Returns the position of the output.
+    pub const fn get(self) -> usize {
+        self.0
+    }
+}
+
+impl From<usize> for InputIndex {
+    fn from(index: usize) -> Self {
+        InputIndex(index)
+    }
+}
+
+impl From<InputIndex> for usize {
+    fn from(index: InputIndex) -> Self {
+        index.0
+    }
+}
+
+impl fmt::Display for InputIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl From<usize> for OutputIndex {
+    fn from(index: usize) -> Self {
+        OutputIndex(index)
+    }
+}
+
+impl From<OutputIndex> for usize {
+    fn from(index: OutputIndex) -> Self {
+        index.0
+    }
+}
+
+impl fmt::Display for OutputIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// An index past the inputs or outputs of a psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexError {
+    /// There's no input at this index.
+    Input { index: InputIndex, length: usize },
+    /// There's no output at this index.
+    Output { index: OutputIndex, length: usize },
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexError::Input { index, length } => write!(
+                f,
+                "input index {} out of bounds for {} inputs",
+                index, length
+            ),
+            IndexError::Output { index, length } => write!(
+                f,
+                "output index {} out of bounds for {} outputs",
+                index, length
+            ),
+        }
+    }
+}
+
+impl Psbt {
+    /// Returns the input at `index`.
+    pub fn input(&self, index: InputIndex) -> Result<&Input, IndexError> {
+        self.inner.inputs.get(index.0).ok_or(IndexError::Input {
+            index,
+            length: self.inner.inputs.len(),
+        })
+    }
+
+    /// Returns the output at `index`.
+    pub fn output(&self, index: OutputIndex) -> Result<&Output, IndexError> {
+        self.inner.outputs.get(index.0).ok_or(IndexError::Output {
+            index,
+            length: self.inner.outputs.len(),
+        })
+    }
+}
@@ -1,10 +1,77 @@
+use crate::prelude::sync::Arc;
+use crate::prelude::*;
+
+/// The id of the transaction an input spends from, stored in the internal
+/// byte order of a [`Txid`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PreviousTxId([u8; 32]);
 
+impl PreviousTxId {
+    /// Returns the txid of the transaction this input spends from.
+    pub fn to_txid(&self) -> Txid {
+        Txid::from_byte_array(self.0)
+    }
+}
+
+impl From<Txid> for PreviousTxId {
+    fn from(txid: Txid) -> Self {
+        PreviousTxId(txid.to_byte_array())
+    }
+}
+
+impl From<PreviousTxId> for Txid {
+    fn from(previous_tx_id: PreviousTxId) -> Self {
+        previous_tx_id.to_txid()
+    }
+}
+
+impl AsRef<[u8]> for PreviousTxId {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Displays the id as hex in reversed byte order, like a [`Txid`].
+impl fmt::Display for PreviousTxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.to_txid(), f)
+    }
+}
+
+/// Parses the id from hex in reversed byte order, like a [`Txid`].
+impl FromStr for PreviousTxId {
+    type Err = <Txid as FromStr>::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Txid::from_str(s).map(PreviousTxId::from)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl actual_serde::Serialize for PreviousTxId {
+    fn serialize<S: actual_serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        actual_serde::Serialize::serialize(&self.to_txid(), serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> actual_serde::Deserialize<'de> for PreviousTxId {
+    fn deserialize<D: actual_serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <Txid as actual_serde::Deserialize>::deserialize(deserializer).map(PreviousTxId::from)
+    }
+}
+
+/// A key-value map for an input of the corresponding index in the unsigned
+/// transaction.
+#[derive(Clone, Default, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(crate = "actual_serde"))]
 pub struct Input {
     /// The non-witness transaction this input spends from. Should only be
     /// [std::option::Option::Some] for inputs which spend non-segwit outputs or
-    /// if it is unknown whether an input spends a segwit output.
-    pub non_witness_utxo: Option<Transaction>,
+    /// if it is unknown whether an input spends a segwit output. Shared, so
+    /// copies of the psbt don't copy the transaction.
+    pub non_witness_utxo: Option<Arc<Transaction>>,
     /// The transaction output this input spends from. Should only be
     /// [std::option::Option::Some] for inputs which spend segwit outputs,
     /// including P2SH embedded ones.
@@ -16,42 +83,47 @@ pub struct Input {
     /// must use the sighash type.
     pub sighash_type: Option<PsbtSighashType>,
     /// The redeem script for this input.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_hr::opt_script"))]
     pub redeem_script: Option<ScriptBuf>,
     /// The witness script for this input.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_hr::opt_script"))]
     pub witness_script: Option<ScriptBuf>,
     /// A map from public keys needed to sign this input to their corresponding
     /// master key fingerprints and derivation paths.
-    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::btreemap_as_seq"))]
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_hr::key_sources"))]
     pub bip32_derivation: BTreeMap<secp256k1::PublicKey, KeySource>,
     /// The finalized, fully-constructed scriptSig with signatures and any other
     /// scripts necessary for this input to pass validation.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_hr::opt_script"))]
     pub final_script_sig: Option<ScriptBuf>,
     /// The finalized, fully-constructed scriptWitness with signatures and any
     /// other scripts necessary for this input to pass validation.
     pub final_script_witness: Option<Witness>,
-    /// TODO: Proof of reserves commitment
+    /// The UTF-8 encoded commitment message of a proof of reserves, as
+    /// defined by BIP 127.
+    pub por_commitment: Option<String>,
     /// RIPEMD160 hash to preimage map.
     #[cfg_attr(
         feature = "serde",
-        serde(with = "crate::serde_utils::btreemap_byte_values")
+        serde(with = "super::serde_hr::btreemap_hex_values")
     )]
     pub ripemd160_preimages: BTreeMap<ripemd160::Hash, Vec<u8>>,
     /// SHA256 hash to preimage map.
     #[cfg_attr(
         feature = "serde",
-        serde(with = "crate::serde_utils::btreemap_byte_values")
+        serde(with = "super::serde_hr::btreemap_hex_values")
     )]
     pub sha256_preimages: BTreeMap<sha256::Hash, Vec<u8>>,
     /// HSAH160 hash to preimage map.
     #[cfg_attr(
         feature = "serde",
-        serde(with = "crate::serde_utils::btreemap_byte_values")
+        serde(with = "super::serde_hr::btreemap_hex_values")
     )]
     pub hash160_preimages: BTreeMap<hash160::Hash, Vec<u8>>,
     /// HAS256 hash to preimage map.
     #[cfg_attr(
         feature = "serde",
-        serde(with = "crate::serde_utils::btreemap_byte_values")
+        serde(with = "super::serde_hr::btreemap_hex_values")
     )]
     pub hash256_preimages: BTreeMap<sha256d::Hash, Vec<u8>>,
     /// Serialized taproot signature with sighash type for key spend.
@@ -69,23 +141,84 @@ pub struct Input {
     pub tap_internal_key: Option<XOnlyPublicKey>,
     /// Taproot Merkle root.
     pub tap_merkle_root: Option<TapNodeHash>,
+    /// The annex appended to the witness of a taproot spend, starting with
+    /// [`TAPROOT_ANNEX_PREFIX`](crate::taproot::TAPROOT_ANNEX_PREFIX).
+    pub tap_annex: Option<Vec<u8>>,
+    /// Map of MuSig2 aggregate keys to the keys of their participants.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::btreemap_as_seq"))]
+    pub musig2_participant_pubkeys: BTreeMap<secp256k1::PublicKey, Vec<secp256k1::PublicKey>>,
+    /// Map of `<participant>|<aggregate>|<leafhash>` to MuSig2 public nonce.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::btreemap_as_seq"))]
+    pub musig2_pub_nonces: BTreeMap<MusigSessionKey, PubNonce>,
+    /// Map of `<participant>|<aggregate>|<leafhash>` to MuSig2 partial signature.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::btreemap_as_seq"))]
+    pub musig2_partial_sigs: BTreeMap<MusigSessionKey, PartialSig>,
+    /// Map of silent payment scan keys to the ECDH share of this input.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::btreemap_as_seq"))]
+    pub sp_ecdh_shares: BTreeMap<secp256k1::PublicKey, secp256k1::PublicKey>,
+    /// Map of silent payment scan keys to the DLEQ proof of this input's ECDH share.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::btreemap_as_seq"))]
+    pub sp_dleq_proofs: BTreeMap<secp256k1::PublicKey, DleqProof>,
     /// Proprietary key-value pairs for this input.
     #[cfg_attr(
         feature = "serde",
-        serde(with = "crate::serde_utils::btreemap_as_seq_byte_values")
+        serde(with = "super::serde_hr::btreemap_as_seq_hex_values")
     )]
     pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
     /// Unknown key-value pairs for this input.
     #[cfg_attr(
         feature = "serde",
-        serde(with = "crate::serde_utils::btreemap_as_seq_byte_values")
+        serde(with = "super::serde_hr::btreemap_as_seq_hex_values")
     )]
     pub unknown: BTreeMap<raw::Key, Vec<u8>>,
 
     // Optional Psbtv2 fields
-    pub previous_tx_id: Optional<PreviousTxId>,
-    pub output_index: Optional<u32>,
-    pub sequence: Optional<u32>,
-    pub required_time_locktime: Optional<u32>,
-    pub required_height_locktime: Optional<u32>,
+    pub previous_tx_id: Option<PreviousTxId>,
+    pub output_index: Option<u32>,
+    pub sequence: Option<Sequence>,
+    pub required_time_locktime: Option<u32>,
+    pub required_height_locktime: Option<u32>,
+
+    /// The Elements fields of a PSET input.
+    #[cfg(feature = "elements")]
+    pub elements: super::elements::ElementsInput,
+}
+
+impl Input {
+    /// Returns the output this input spends, or `None` if no trusted utxo
+    /// is set.
+    ///
+    /// The matching output of `non_witness_utxo` is preferred, but only if
+    /// the transaction's txid is the one `previous_output` spends from.
+    /// Otherwise `witness_utxo` is used, but only for a segwit spend: a
+    /// legacy sighash doesn't commit to the spent amount, so an updater
+    /// could lie about it unchecked.
+    pub fn spent_utxo(&self, previous_output: &OutPoint) -> Option<&TxOut> {
+        let verified = self
+            .non_witness_utxo
+            .as_ref()
+            .filter(|tx| tx.txid() == previous_output.txid)
+            .and_then(|tx| tx.output.get(previous_output.vout as usize));
+        if verified.is_some() {
+            return verified;
+        }
+        self.witness_utxo
+            .as_ref()
+            .filter(|utxo| self.spends_segwit(&utxo.script_pubkey))
+    }
+
+    /// Returns `true` if `script_pubkey` is spent with a witness: a native
+    /// witness program, or P2SH wrapping one as the redeem script or final
+    /// witness show.
+    pub(crate) fn spends_segwit(&self, script_pubkey: &Script) -> bool {
+        if script_pubkey.is_witness_program() {
+            return true;
+        }
+        script_pubkey.is_p2sh()
+            && (self
+                .redeem_script
+                .as_ref()
+                .map_or(false, |script| script.is_witness_program())
+                || self.final_script_witness.is_some())
+    }
 }
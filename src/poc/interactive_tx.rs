@@ -0,0 +1,483 @@
+//! Conversions between a PsbtV2 and the messages of the interactive
+//! transaction construction protocol of Lightning (BOLT 2), used to build
+//! dual-funded channel and splice transactions.
+//!
+//! Peers build the transaction with `tx_add_input`, `tx_add_output`,
+//! `tx_remove_input` and `tx_remove_output` messages, each input and output
+//! identified by a serial id, and the transaction orders them by serial id.
+//! Converting the messages into a psbt lets implementations validate,
+//! serialize and sign the transaction with this crate. Serial ids are kept
+//! in proprietary keys so the psbt converts back to the same messages.
+
+use core::fmt;
+
+use crate::blockdata::script::ScriptBuf;
+use crate::blockdata::transaction::{Sequence, Transaction};
+use crate::consensus::encode;
+use crate::prelude::sync::Arc;
+use crate::prelude::*;
+use crate::psbt::{raw, Version};
+use crate::Amount;
+
+use super::input::Input;
+use super::output::Output;
+use super::poc::{PartiallySignedTransactionInner, Psbt};
+use super::validation::ValidationReport;
+
+/// The prefix of the proprietary keys holding the serial id of an input or
+/// output.
+pub const INTERACTIVE_TX_PREFIX: &[u8] = b"LNTX";
+/// The subtype of the proprietary key holding the serial id, a big endian
+/// `u64`.
+pub const SERIAL_ID_SUBTYPE: u8 = 0x00;
+
+/// The type of the `tx_add_input` message.
+pub const TX_ADD_INPUT: u16 = 66;
+/// The type of the `tx_add_output` message.
+pub const TX_ADD_OUTPUT: u16 = 67;
+/// The type of the `tx_remove_input` message.
+pub const TX_REMOVE_INPUT: u16 = 68;
+/// The type of the `tx_remove_output` message.
+pub const TX_REMOVE_OUTPUT: u16 = 69;
+/// The type of the `tx_complete` message.
+pub const TX_COMPLETE: u16 = 70;
+
+/// The id of the channel the transaction is built for.
+pub type ChannelId = [u8; 32];
+
+/// Errors encountered while converting between a psbt and interactive
+/// transaction messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InteractiveTxError {
+    /// A message couldn't be decoded.
+    Malformed(String),
+    /// The message type isn't one of the interactive transaction messages.
+    UnknownMessage(u16),
+    /// A message is for another channel than the previous ones.
+    ChannelMismatch,
+    /// An input or output is added with a serial id already in use.
+    DuplicateSerialId { serial_id: u64 },
+    /// An input or output is removed with a serial id not in use.
+    UnknownSerialId { serial_id: u64 },
+    /// The input with this serial id spends an output its previous
+    /// transaction doesn't have.
+    MissingPrevOutput { serial_id: u64 },
+    /// The input with this serial id spends the same output as another one.
+    DuplicateInput { serial_id: u64 },
+    /// The input at this index lacks its `non_witness_utxo`, which
+    /// `tx_add_input` carries.
+    MissingPrevTx { index: usize },
+    /// The previous transaction of the input at this index is longer than
+    /// the `u16` length `tx_add_input` gives it.
+    PrevTxTooLong { index: usize, len: usize },
+    /// The script of the output at this index is longer than the `u16`
+    /// length `tx_add_output` gives it.
+    ScriptTooLong { index: usize, len: usize },
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+    /// The converted psbt isn't valid.
+    Invalid(ValidationReport),
+}
+
+impl fmt::Display for InteractiveTxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InteractiveTxError::Malformed(err) => write!(f, "malformed message: {}", err),
+            InteractiveTxError::UnknownMessage(message_type) => {
+                write!(f, "unknown message type {}", message_type)
+            }
+            InteractiveTxError::ChannelMismatch => write!(f, "messages are for different channels"),
+            InteractiveTxError::DuplicateSerialId { serial_id } => {
+                write!(f, "serial id {} is already in use", serial_id)
+            }
+            InteractiveTxError::UnknownSerialId { serial_id } => {
+                write!(f, "serial id {} isn't in use", serial_id)
+            }
+            InteractiveTxError::MissingPrevOutput { serial_id } => {
+                write!(f, "input {} spends a missing output", serial_id)
+            }
+            InteractiveTxError::DuplicateInput { serial_id } => {
+                write!(f, "input {} spends an output already spent", serial_id)
+            }
+            InteractiveTxError::MissingPrevTx { index } => {
+                write!(f, "input {} lacks its previous transaction", index)
+            }
+            InteractiveTxError::PrevTxTooLong { index, len } => {
+                write!(
+                    f,
+                    "previous transaction of input {} is {} bytes long",
+                    index, len
+                )
+            }
+            InteractiveTxError::ScriptTooLong { index, len } => {
+                write!(f, "script of output {} is {} bytes long", index, len)
+            }
+            InteractiveTxError::UnsignedTx(err) => {
+                write!(f, "invalid unsigned transaction: {}", err)
+            }
+            InteractiveTxError::Invalid(report) => write!(f, "invalid psbt: {:?}", report),
+        }
+    }
+}
+
+/// A `tx_add_input` message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxAddInput {
+    pub channel_id: ChannelId,
+    pub serial_id: u64,
+    /// The transaction whose output the input spends.
+    pub prevtx: Transaction,
+    /// The index of the spent output in `prevtx`.
+    pub prevtx_vout: u32,
+    pub sequence: Sequence,
+}
+
+/// A `tx_add_output` message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxAddOutput {
+    pub channel_id: ChannelId,
+    pub serial_id: u64,
+    pub amount: Amount,
+    pub script: ScriptBuf,
+}
+
+/// A message of the interactive transaction construction protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InteractiveTxMessage {
+    /// `tx_add_input`, adding an input.
+    AddInput(TxAddInput),
+    /// `tx_add_output`, adding an output.
+    AddOutput(TxAddOutput),
+    /// `tx_remove_input`, removing the input with this serial id.
+    RemoveInput {
+        channel_id: ChannelId,
+        serial_id: u64,
+    },
+    /// `tx_remove_output`, removing the output with this serial id.
+    RemoveOutput {
+        channel_id: ChannelId,
+        serial_id: u64,
+    },
+    /// `tx_complete`, the sender has nothing more to add or remove.
+    Complete { channel_id: ChannelId },
+}
+
+impl InteractiveTxMessage {
+    /// The id of the channel the message is for.
+    pub fn channel_id(&self) -> &ChannelId {
+        match self {
+            InteractiveTxMessage::AddInput(add) => &add.channel_id,
+            InteractiveTxMessage::AddOutput(add) => &add.channel_id,
+            InteractiveTxMessage::RemoveInput { channel_id, .. }
+            | InteractiveTxMessage::RemoveOutput { channel_id, .. }
+            | InteractiveTxMessage::Complete { channel_id } => channel_id,
+        }
+    }
+
+    /// Serializes the message as sent on the wire, starting with its type.
+    ///
+    /// The previous transaction of a `tx_add_input` and the script of a
+    /// `tx_add_output` must be at most `u16::MAX` bytes long, as they are in
+    /// messages built by [`Psbt::to_interactive_tx`].
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            InteractiveTxMessage::AddInput(add) => {
+                let prevtx = encode::serialize(&add.prevtx);
+                bytes.extend(TX_ADD_INPUT.to_be_bytes());
+                bytes.extend(add.channel_id);
+                bytes.extend(add.serial_id.to_be_bytes());
+                bytes.extend((prevtx.len() as u16).to_be_bytes());
+                bytes.extend(prevtx);
+                bytes.extend(add.prevtx_vout.to_be_bytes());
+                bytes.extend(add.sequence.0.to_be_bytes());
+            }
+            InteractiveTxMessage::AddOutput(add) => {
+                bytes.extend(TX_ADD_OUTPUT.to_be_bytes());
+                bytes.extend(add.channel_id);
+                bytes.extend(add.serial_id.to_be_bytes());
+                bytes.extend(add.amount.to_sat().to_be_bytes());
+                bytes.extend((add.script.len() as u16).to_be_bytes());
+                bytes.extend(add.script.as_bytes());
+            }
+            InteractiveTxMessage::RemoveInput {
+                channel_id,
+                serial_id,
+            } => {
+                bytes.extend(TX_REMOVE_INPUT.to_be_bytes());
+                bytes.extend(channel_id);
+                bytes.extend(serial_id.to_be_bytes());
+            }
+            InteractiveTxMessage::RemoveOutput {
+                channel_id,
+                serial_id,
+            } => {
+                bytes.extend(TX_REMOVE_OUTPUT.to_be_bytes());
+                bytes.extend(channel_id);
+                bytes.extend(serial_id.to_be_bytes());
+            }
+            InteractiveTxMessage::Complete { channel_id } => {
+                bytes.extend(TX_COMPLETE.to_be_bytes());
+                bytes.extend(channel_id);
+            }
+        }
+        bytes
+    }
+
+    /// Parses a message as received from the wire. Trailing TLV records are
+    /// ignored.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, InteractiveTxError> {
+        let mut reader = MessageReader(bytes);
+        let message_type = u16::from_be_bytes(reader.read_array()?);
+        let channel_id = reader.read_array()?;
+        let message = match message_type {
+            TX_ADD_INPUT => {
+                let serial_id = u64::from_be_bytes(reader.read_array()?);
+                let len = u16::from_be_bytes(reader.read_array()?);
+                let prevtx = encode::deserialize(reader.read(len as usize)?)
+                    .map_err(|err| InteractiveTxError::Malformed(err.to_string()))?;
+                InteractiveTxMessage::AddInput(TxAddInput {
+                    channel_id,
+                    serial_id,
+                    prevtx,
+                    prevtx_vout: u32::from_be_bytes(reader.read_array()?),
+                    sequence: Sequence(u32::from_be_bytes(reader.read_array()?)),
+                })
+            }
+            TX_ADD_OUTPUT => {
+                let serial_id = u64::from_be_bytes(reader.read_array()?);
+                let amount = Amount::from_sat(u64::from_be_bytes(reader.read_array()?));
+                let len = u16::from_be_bytes(reader.read_array()?);
+                InteractiveTxMessage::AddOutput(TxAddOutput {
+                    channel_id,
+                    serial_id,
+                    amount,
+                    script: ScriptBuf::from(reader.read(len as usize)?.to_vec()),
+                })
+            }
+            TX_REMOVE_INPUT => InteractiveTxMessage::RemoveInput {
+                channel_id,
+                serial_id: u64::from_be_bytes(reader.read_array()?),
+            },
+            TX_REMOVE_OUTPUT => InteractiveTxMessage::RemoveOutput {
+                channel_id,
+                serial_id: u64::from_be_bytes(reader.read_array()?),
+            },
+            TX_COMPLETE => InteractiveTxMessage::Complete { channel_id },
+            message_type => return Err(InteractiveTxError::UnknownMessage(message_type)),
+        };
+        Ok(message)
+    }
+}
+
+impl Psbt {
+    /// Builds a PsbtV2 from the messages both peers sent, in the order they
+    /// were exchanged. Inputs and outputs are ordered by serial id and
+    /// inputs get their `non_witness_utxo`, along with their `witness_utxo`
+    /// when they spend a witness program. `tx_complete` messages are
+    /// ignored.
+    pub fn from_interactive_tx(
+        messages: &[InteractiveTxMessage],
+        tx_version: i32,
+        locktime: u32,
+    ) -> Result<Psbt, InteractiveTxError> {
+        let mut inputs: BTreeMap<u64, &TxAddInput> = BTreeMap::new();
+        let mut outputs: BTreeMap<u64, &TxAddOutput> = BTreeMap::new();
+        let channel_id = messages.first().map(InteractiveTxMessage::channel_id);
+        for message in messages {
+            if Some(message.channel_id()) != channel_id {
+                return Err(InteractiveTxError::ChannelMismatch);
+            }
+            match message {
+                InteractiveTxMessage::AddInput(add) => {
+                    let serial_id = add.serial_id;
+                    if inputs.contains_key(&serial_id) || outputs.contains_key(&serial_id) {
+                        return Err(InteractiveTxError::DuplicateSerialId { serial_id });
+                    }
+                    if add.prevtx.output.len() <= add.prevtx_vout as usize {
+                        return Err(InteractiveTxError::MissingPrevOutput { serial_id });
+                    }
+                    let spent = inputs.values().any(|other| {
+                        other.prevtx_vout == add.prevtx_vout
+                            && other.prevtx.txid() == add.prevtx.txid()
+                    });
+                    if spent {
+                        return Err(InteractiveTxError::DuplicateInput { serial_id });
+                    }
+                    inputs.insert(serial_id, add);
+                }
+                InteractiveTxMessage::AddOutput(add) => {
+                    let serial_id = add.serial_id;
+                    if inputs.contains_key(&serial_id) || outputs.contains_key(&serial_id) {
+                        return Err(InteractiveTxError::DuplicateSerialId { serial_id });
+                    }
+                    outputs.insert(serial_id, add);
+                }
+                InteractiveTxMessage::RemoveInput { serial_id, .. } => {
+                    inputs
+                        .remove(serial_id)
+                        .ok_or(InteractiveTxError::UnknownSerialId {
+                            serial_id: *serial_id,
+                        })?;
+                }
+                InteractiveTxMessage::RemoveOutput { serial_id, .. } => {
+                    outputs
+                        .remove(serial_id)
+                        .ok_or(InteractiveTxError::UnknownSerialId {
+                            serial_id: *serial_id,
+                        })?;
+                }
+                InteractiveTxMessage::Complete { .. } => {}
+            }
+        }
+
+        let inputs = inputs
+            .into_iter()
+            .map(|(serial_id, add)| {
+                let utxo = &add.prevtx.output[add.prevtx_vout as usize];
+                let mut input = Input {
+                    previous_tx_id: Some(add.prevtx.txid().into()),
+                    output_index: Some(add.prevtx_vout),
+                    sequence: Some(add.sequence),
+                    witness_utxo: utxo
+                        .script_pubkey
+                        .is_witness_program()
+                        .then(|| utxo.clone()),
+                    non_witness_utxo: Some(Arc::new(add.prevtx.clone())),
+                    ..Default::default()
+                };
+                input
+                    .proprietary
+                    .insert(serial_id_key(), serial_id.to_be_bytes().to_vec());
+                input
+            })
+            .collect();
+        let outputs = outputs
+            .into_iter()
+            .map(|(serial_id, add)| {
+                let mut output = Output {
+                    amount: Some(add.amount),
+                    script: Some(add.script.clone()),
+                    ..Default::default()
+                };
+                output
+                    .proprietary
+                    .insert(serial_id_key(), serial_id.to_be_bytes().to_vec());
+                output
+            })
+            .collect();
+
+        Psbt::from_inner(PartiallySignedTransactionInner {
+            unsigned_tx: None,
+            version: Version::PsbtV2,
+            xpub: Default::default(),
+            proprietary: Default::default(),
+            unknown: Default::default(),
+            inputs,
+            outputs,
+            tx_version: Some(tx_version),
+            fallback_locktime: Some(locktime),
+            tx_modifiable: None,
+            sp_ecdh_shares: Default::default(),
+            sp_dleq_proofs: Default::default(),
+        })
+        .map_err(InteractiveTxError::Invalid)
+    }
+
+    /// Returns the messages adding the inputs and outputs of the psbt to the
+    /// transaction of `channel_id`, followed by `tx_complete`.
+    ///
+    /// Inputs and outputs keep the serial id stored in their proprietary
+    /// keys. The others get one derived from their index, even for the
+    /// `initiator` of the construction and odd for the other peer as BOLT 2
+    /// requires. Every input needs its `non_witness_utxo`, and previous
+    /// transactions and output scripts must fit the `u16` lengths of the
+    /// messages.
+    pub fn to_interactive_tx(
+        &self,
+        channel_id: ChannelId,
+        initiator: bool,
+    ) -> Result<Vec<InteractiveTxMessage>, InteractiveTxError> {
+        let tx = self.unsigned_tx().map_err(InteractiveTxError::UnsignedTx)?;
+        let parity = u64::from(!initiator);
+        let mut serial_ids = BTreeSet::new();
+        let mut messages = Vec::new();
+
+        for (index, (txin, input)) in tx.input.iter().zip(&self.inner.inputs).enumerate() {
+            let serial_id = serial_id(&input.proprietary).unwrap_or(2 * index as u64 + parity);
+            if !serial_ids.insert(serial_id) {
+                return Err(InteractiveTxError::DuplicateSerialId { serial_id });
+            }
+            let prevtx = input
+                .non_witness_utxo
+                .as_deref()
+                .cloned()
+                .ok_or(InteractiveTxError::MissingPrevTx { index })?;
+            let len = encode::serialize(&prevtx).len();
+            if len > usize::from(u16::MAX) {
+                return Err(InteractiveTxError::PrevTxTooLong { index, len });
+            }
+            messages.push(InteractiveTxMessage::AddInput(TxAddInput {
+                channel_id,
+                serial_id,
+                prevtx,
+                prevtx_vout: txin.previous_output.vout,
+                sequence: txin.sequence,
+            }));
+        }
+        let first_output = 2 * tx.input.len() as u64;
+        for (index, (txout, output)) in tx.output.iter().zip(&self.inner.outputs).enumerate() {
+            let serial_id =
+                serial_id(&output.proprietary).unwrap_or(first_output + 2 * index as u64 + parity);
+            if !serial_ids.insert(serial_id) {
+                return Err(InteractiveTxError::DuplicateSerialId { serial_id });
+            }
+            let len = txout.script_pubkey.len();
+            if len > usize::from(u16::MAX) {
+                return Err(InteractiveTxError::ScriptTooLong { index, len });
+            }
+            messages.push(InteractiveTxMessage::AddOutput(TxAddOutput {
+                channel_id,
+                serial_id,
+                amount: txout.value,
+                script: txout.script_pubkey.clone(),
+            }));
+        }
+        messages.push(InteractiveTxMessage::Complete { channel_id });
+        Ok(messages)
+    }
+}
+
+fn serial_id_key() -> raw::ProprietaryKey {
+    raw::ProprietaryKey {
+        prefix: INTERACTIVE_TX_PREFIX.to_vec(),
+        subtype: SERIAL_ID_SUBTYPE,
+        key: vec![],
+    }
+}
+
+/// The serial id stored in the proprietary map of an input or output.
+fn serial_id(proprietary: &BTreeMap<raw::ProprietaryKey, Vec<u8>>) -> Option<u64> {
+    let bytes = proprietary.get(&serial_id_key())?;
+    Some(u64::from_be_bytes(bytes.as_slice().try_into().ok()?))
+}
+
+/// Reads the fields of a message off the front of its bytes.
+struct MessageReader<'a>(&'a [u8]);
+
+impl<'a> MessageReader<'a> {
+    fn read(&mut self, len: usize) -> Result<&'a [u8], InteractiveTxError> {
+        if self.0.len() < len {
+            return Err(InteractiveTxError::Malformed(String::from(
+                "message too short",
+            )));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], InteractiveTxError> {
+        Ok(self.read(N)?.try_into().expect("read N bytes"))
+    }
+}
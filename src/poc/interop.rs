@@ -0,0 +1,111 @@
+//! Conversions from and to the [`Psbt`](crate::psbt::Psbt) of rust-bitcoin,
+//! so code built on it can adopt these types piecemeal.
+//!
+//! Psbts are converted through their serialization, which keeps every field
+//! both types know about along with proprietary and unknown pairs.
+//! rust-bitcoin only knows PsbtV0, so PsbtV2 psbts are converted back to
+//! PsbtV0 on the way out.
+
+use core::fmt;
+
+use crate::prelude::*;
+use crate::psbt::{self, Version};
+
+use super::poc::{PartiallySignedTransactionInner, Psbt};
+use super::serialize::{DecodeError, ParseOptions};
+
+/// Errors encountered while converting a psbt into a rust-bitcoin psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntoBitcoinError {
+    /// The PsbtV2 has modifiable flags, which a PsbtV0 can't express.
+    Modifiable,
+    /// The input at this index of the PsbtV2 requires a locktime, which a
+    /// PsbtV0 can't express.
+    RequiredLockTime { index: usize },
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+    /// rust-bitcoin rejected the psbt.
+    Bitcoin(String),
+}
+
+impl fmt::Display for IntoBitcoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntoBitcoinError::Modifiable => write!(f, "PsbtV0 can't express modifiable flags"),
+            IntoBitcoinError::RequiredLockTime { index } => {
+                write!(
+                    f,
+                    "PsbtV0 can't express the required locktime of input {}",
+                    index
+                )
+            }
+            IntoBitcoinError::UnsignedTx(err) => {
+                write!(f, "invalid unsigned transaction: {}", err)
+            }
+            IntoBitcoinError::Bitcoin(err) => write!(f, "rejected by rust-bitcoin: {}", err),
+        }
+    }
+}
+
+/// Parses rust-bitcoin psbts leniently, so fields only this crate gives
+/// meaning to come out typed and anything else ends up in `unknown`.
+///
+/// rust-bitcoin keeps pairs of key types it doesn't know as unknown, even
+/// ones this crate decodes, such as the MuSig2 or silent payment fields. The
+/// conversion fails if such a pair doesn't decode, or if the psbt fails
+/// [`Psbt::from_inner`] validation.
+impl TryFrom<psbt::Psbt> for Psbt {
+    type Error = DecodeError;
+
+    fn try_from(psbt: psbt::Psbt) -> Result<Self, Self::Error> {
+        let inner = PartiallySignedTransactionInner::deserialize(
+            &psbt.serialize(),
+            ParseOptions::lenient(),
+        )?;
+        Psbt::from_inner(inner).map_err(DecodeError::Invalid)
+    }
+}
+
+impl TryFrom<Psbt> for psbt::Psbt {
+    type Error = IntoBitcoinError;
+
+    fn try_from(psbt: Psbt) -> Result<Self, Self::Error> {
+        let inner = psbt.downgraded()?;
+        psbt::Psbt::deserialize(&inner.serialize())
+            .map_err(|err| IntoBitcoinError::Bitcoin(err.to_string()))
+    }
+}
+
+impl Psbt {
+    /// Returns the inner psbt as a PsbtV0, converting a PsbtV2 by assembling
+    /// its unsigned transaction.
+    pub(crate) fn downgraded(&self) -> Result<PartiallySignedTransactionInner, IntoBitcoinError> {
+        let mut inner = self.inner.clone();
+        if inner.version == Version::PsbtV0 {
+            return Ok(inner);
+        }
+        if inner.tx_modifiable.is_some() {
+            return Err(IntoBitcoinError::Modifiable);
+        }
+        if let Some(index) = inner.inputs.iter().position(|input| {
+            input.required_time_locktime.is_some() || input.required_height_locktime.is_some()
+        }) {
+            return Err(IntoBitcoinError::RequiredLockTime { index });
+        }
+
+        inner.unsigned_tx = Some(self.unsigned_tx().map_err(IntoBitcoinError::UnsignedTx)?);
+        for input in &mut inner.inputs {
+            input.previous_tx_id = None;
+            input.output_index = None;
+            input.sequence = None;
+        }
+        for output in &mut inner.outputs {
+            output.amount = None;
+            output.script = None;
+        }
+        inner.tx_version = None;
+        inner.fallback_locktime = None;
+        inner.version = Version::PsbtV0;
+        Ok(inner)
+    }
+}
@@ -0,0 +1,45 @@
+//! Iteration over the inputs of a psbt along with the transaction context
+//! consumers keep resolving by hand: the outpoint each input spends and the
+//! output it spends.
+
+use crate::blockdata::transaction::{OutPoint, TxOut};
+
+use super::input::Input;
+use super::poc::Psbt;
+
+/// An input of a psbt with what the transaction says about it, yielded by
+/// [`Psbt::iter_inputs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputContext<'a> {
+    /// The index of the input.
+    pub index: usize,
+    /// The input map.
+    pub input: &'a Input,
+    /// The outpoint the input spends, from the unsigned transaction of a
+    /// PsbtV0 or the `previous_tx_id` and `output_index` of a PsbtV2 input.
+    pub previous_output: OutPoint,
+    /// The output the input spends, `None` if it has no trusted utxo, see
+    /// [`Input::spent_utxo`].
+    pub spent_utxo: Option<&'a TxOut>,
+}
+
+impl Psbt {
+    /// Iterates over the inputs in order, each with its index, the outpoint
+    /// it spends and the output it spends if known.
+    pub fn iter_inputs(&self) -> impl Iterator<Item = InputContext<'_>> + '_ {
+        self.inner
+            .inputs
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, input)| {
+                // Validation guarantees every input of a psbt has an outpoint
+                let previous_output = self.inner.previous_output(index)?;
+                Some(InputContext {
+                    index,
+                    input,
+                    previous_output,
+                    spent_utxo: input.spent_utxo(&previous_output),
+                })
+            })
+    }
+}
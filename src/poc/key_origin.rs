@@ -0,0 +1,141 @@
+use secp256k1::XOnlyPublicKey;
+
+use crate::bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource};
+use crate::prelude::*;
+use crate::taproot::TapLeafHash;
+
+use super::input::Input;
+use super::output::Output;
+use super::poc::Psbt;
+
+/// A key of an input or output together with its derivation path.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OriginKey {
+    /// A key from `bip32_derivation`.
+    Ecdsa(secp256k1::PublicKey, DerivationPath),
+    /// A key from `tap_key_origins`.
+    Taproot(XOnlyPublicKey, DerivationPath),
+}
+
+impl OriginKey {
+    /// The derivation path of the key.
+    pub fn path(&self) -> &DerivationPath {
+        match self {
+            OriginKey::Ecdsa(_, path) | OriginKey::Taproot(_, path) => path,
+        }
+    }
+
+    /// Returns `true` if the key is on a change branch: its path ends in an
+    /// unhardened 1 followed by an address index, as in `m/84'/0'/0'/1/5`.
+    pub fn is_change(&self) -> bool {
+        let path: &[ChildNumber] = self.path().as_ref();
+        matches!(
+            path,
+            [
+                ..,
+                ChildNumber::Normal { index: 1 },
+                ChildNumber::Normal { .. }
+            ]
+        )
+    }
+}
+
+impl Input {
+    /// Returns the keys of `bip32_derivation` and `tap_key_origins` derived
+    /// from the master key with fingerprint `fingerprint`.
+    pub fn keys_for_fingerprint(&self, fingerprint: Fingerprint) -> Vec<OriginKey> {
+        keys_for_fingerprint(&self.bip32_derivation, &self.tap_key_origins, fingerprint)
+    }
+}
+
+impl Output {
+    /// Returns the keys of `bip32_derivation` and `tap_key_origins` derived
+    /// from the master key with fingerprint `fingerprint`.
+    pub fn keys_for_fingerprint(&self, fingerprint: Fingerprint) -> Vec<OriginKey> {
+        keys_for_fingerprint(&self.bip32_derivation, &self.tap_key_origins, fingerprint)
+    }
+
+    /// Returns `true` if one of the keys of `bip32_derivation` or
+    /// `tap_key_origins` is on a change branch, see [`OriginKey::is_change`].
+    pub fn is_change(&self) -> bool {
+        let ecdsa_keys = self
+            .bip32_derivation
+            .iter()
+            .map(|(key, (_, path))| OriginKey::Ecdsa(*key, path.clone()));
+        let taproot_keys = self
+            .tap_key_origins
+            .iter()
+            .map(|(key, (_, (_, path)))| OriginKey::Taproot(*key, path.clone()));
+        ecdsa_keys.chain(taproot_keys).any(|key| key.is_change())
+    }
+}
+
+impl Psbt {
+    /// Returns the indices of the inputs holding a key derived from the master
+    /// key with fingerprint `fingerprint`.
+    ///
+    /// Besides the input key origins, the global `xpub` map is searched: keys
+    /// whose origin is an xpub derived from `fingerprint` are matched as well,
+    /// so signers can quickly tell whether the psbt concerns them at all.
+    pub fn inputs_signable_by(&self, fingerprint: Fingerprint) -> Vec<usize> {
+        let mut fingerprints = vec![fingerprint];
+        fingerprints.extend(
+            self.inner
+                .xpub
+                .iter()
+                .filter(|(_, (master_fingerprint, _))| *master_fingerprint == fingerprint)
+                .map(|(xpub, _)| xpub.fingerprint()),
+        );
+
+        self.inner
+            .inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| {
+                fingerprints
+                    .iter()
+                    .any(|fingerprint| !input.keys_for_fingerprint(*fingerprint).is_empty())
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns the indices of the outputs paying back to the wallet with
+    /// master fingerprint `master_fingerprint`: outputs with a key derived
+    /// from it on a change branch, see [`OriginKey::is_change`].
+    ///
+    /// Signers subtract these outputs to display the amount actually sent.
+    pub fn detect_change(&self, master_fingerprint: Fingerprint) -> Vec<usize> {
+        self.inner
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| {
+                output
+                    .keys_for_fingerprint(master_fingerprint)
+                    .iter()
+                    .any(OriginKey::is_change)
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// Returns the keys of the `bip32_derivation` and `tap_key_origins` maps of
+/// an input or output derived from the master key with fingerprint
+/// `fingerprint`.
+fn keys_for_fingerprint(
+    bip32_derivation: &BTreeMap<secp256k1::PublicKey, KeySource>,
+    tap_key_origins: &BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+    fingerprint: Fingerprint,
+) -> Vec<OriginKey> {
+    let ecdsa_keys = bip32_derivation
+        .iter()
+        .filter(|(_, (key_fingerprint, _))| *key_fingerprint == fingerprint)
+        .map(|(public_key, (_, path))| OriginKey::Ecdsa(*public_key, path.clone()));
+    let taproot_keys = tap_key_origins
+        .iter()
+        .filter(|(_, (_, (key_fingerprint, _)))| *key_fingerprint == fingerprint)
+        .map(|(public_key, (_, (_, path)))| OriginKey::Taproot(*public_key, path.clone()));
+    ecdsa_keys.chain(taproot_keys).collect()
+}
@@ -0,0 +1,164 @@
+//! Resolution of the PsbtV2 transaction locktime as defined by BIP 370.
+
+use core::fmt;
+
+use crate::blockdata::locktime::absolute::{self, LOCK_TIME_THRESHOLD};
+use crate::prelude::*;
+use crate::psbt::Version;
+
+use super::input::Input;
+use super::poc::Psbt;
+
+/// Errors encountered while determining the locktime of a PsbtV2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockTimeError {
+    /// Some inputs only accept a height based locktime and others only a
+    /// time based one, listed by index.
+    Incompatible {
+        height_only: Vec<usize>,
+        time_only: Vec<usize>,
+    },
+    /// The `required_height_locktime` of the input at this index is not a
+    /// block height.
+    InvalidHeight { index: usize, value: u32 },
+    /// The `required_time_locktime` of the input at this index is not a unix
+    /// timestamp.
+    InvalidTime { index: usize, value: u32 },
+    /// The `fallback_locktime` is only used by a PsbtV2.
+    NotPsbtV2,
+}
+
+impl fmt::Display for LockTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockTimeError::Incompatible {
+                height_only,
+                time_only,
+            } => write!(
+                f,
+                "inputs {:?} require a height locktime but inputs {:?} a time locktime",
+                height_only, time_only
+            ),
+            LockTimeError::InvalidHeight { index, value } => {
+                write!(
+                    f,
+                    "input {} requires an invalid height locktime {}",
+                    index, value
+                )
+            }
+            LockTimeError::InvalidTime { index, value } => {
+                write!(
+                    f,
+                    "input {} requires an invalid time locktime {}",
+                    index, value
+                )
+            }
+            LockTimeError::NotPsbtV2 => write!(f, "locktime resolution requires a PsbtV2"),
+        }
+    }
+}
+
+/// The locktime satisfying every input of a PsbtV2, see
+/// [`Psbt::locktime_requirements`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockTimeRequirements {
+    /// The locktime of the transaction.
+    pub lock_time: absolute::LockTime,
+    /// The inputs requiring exactly this locktime, the ones delaying the
+    /// broadcast. Empty if no input requires a locktime and the fallback is
+    /// used.
+    pub binding_inputs: Vec<usize>,
+}
+
+impl LockTimeRequirements {
+    /// Returns `true` if the locktime is reached in a block of `height`
+    /// whose median time past is `time`.
+    pub fn is_satisfied_by(&self, height: absolute::Height, time: absolute::Time) -> bool {
+        self.lock_time.is_satisfied_by(height, time)
+    }
+}
+
+impl Psbt {
+    /// Computes the locktime of the transaction described by a PsbtV2.
+    ///
+    /// Without any input requiring a locktime, `fallback_locktime` (or 0) is
+    /// used. Otherwise a height based locktime is chosen if every input with
+    /// a requirement accepts one, else a time based locktime if they all
+    /// accept that, in both cases the largest required value.
+    pub fn compute_lock_time(&self) -> Result<absolute::LockTime, LockTimeError> {
+        self.locktime_requirements()
+            .map(|requirements| requirements.lock_time)
+    }
+
+    /// Aggregates the locktimes the inputs of a PsbtV2 require into the
+    /// locktime of the transaction, like [`Psbt::compute_lock_time`], and
+    /// reports the inputs that determine it, so a wallet can tell when the
+    /// transaction becomes broadcastable and why.
+    ///
+    /// Fails with [`LockTimeError::Incompatible`] listing every input that
+    /// only accepts a height and every input that only accepts a time if
+    /// both exist.
+    pub fn locktime_requirements(&self) -> Result<LockTimeRequirements, LockTimeError> {
+        if self.inner.version != Version::PsbtV2 {
+            return Err(LockTimeError::NotPsbtV2);
+        }
+
+        let mut height: Option<u32> = None;
+        let mut time: Option<u32> = None;
+        let mut height_only = Vec::new();
+        let mut time_only = Vec::new();
+        for (index, input) in self.inner.inputs.iter().enumerate() {
+            if let Some(value) = input.required_height_locktime {
+                if value == 0 || value >= LOCK_TIME_THRESHOLD {
+                    return Err(LockTimeError::InvalidHeight { index, value });
+                }
+            }
+            if let Some(value) = input.required_time_locktime {
+                if value < LOCK_TIME_THRESHOLD {
+                    return Err(LockTimeError::InvalidTime { index, value });
+                }
+            }
+
+            match (input.required_height_locktime, input.required_time_locktime) {
+                (Some(_), None) => height_only.push(index),
+                (None, Some(_)) => time_only.push(index),
+                _ => {}
+            }
+            height = height.max(input.required_height_locktime);
+            time = time.max(input.required_time_locktime);
+        }
+
+        if !height_only.is_empty() && !time_only.is_empty() {
+            return Err(LockTimeError::Incompatible {
+                height_only,
+                time_only,
+            });
+        }
+        let (lock_time, binding_inputs) = match (height, time) {
+            (Some(height), _) if time_only.is_empty() => {
+                let inputs = self.inputs_requiring(|input| input.required_height_locktime, height);
+                (height, inputs)
+            }
+            (_, Some(time)) => {
+                let inputs = self.inputs_requiring(|input| input.required_time_locktime, time);
+                (time, inputs)
+            }
+            _ => (self.inner.fallback_locktime.unwrap_or(0), Vec::new()),
+        };
+        Ok(LockTimeRequirements {
+            lock_time: absolute::LockTime::from_consensus(lock_time),
+            binding_inputs,
+        })
+    }
+
+    /// The indices of the inputs whose `required` locktime is `value`.
+    fn inputs_requiring(&self, required: impl Fn(&Input) -> Option<u32>, value: u32) -> Vec<usize> {
+        self.inner
+            .inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| required(input) == Some(value))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
@@ -1,3 +1,73 @@
+pub mod address;
+pub mod analyzer;
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+#[cfg(feature = "async")]
+pub mod async_signer;
+pub mod builder;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod coin_selection;
+pub mod combine;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod cpfp;
+pub mod descriptor;
+pub mod diff;
+pub mod dust;
+pub mod electrum;
+#[cfg(feature = "elements")]
+pub mod elements;
+pub mod encoding;
+pub mod extractor;
+pub mod fee;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod finalizer;
+pub mod id;
+pub mod index;
 pub mod input;
+pub mod interactive_tx;
+pub mod interop;
+pub mod iter;
+pub mod key_origin;
+pub mod locktime;
+pub mod modifiable;
+pub mod musig;
+pub mod network;
+pub mod ordering;
 pub mod output;
+pub mod ownership;
+pub mod partial_sig;
+pub mod payjoin;
 pub mod poc;
+pub mod pretty;
+pub mod proprietary;
+pub mod replacement;
+pub mod reserves;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "miniscript")]
+mod satisfier;
+mod secret;
+pub mod sequence;
+#[cfg(feature = "serde")]
+mod serde_hr;
+pub mod serialize;
+pub mod session;
+pub mod sighash;
+pub mod signer;
+pub mod silent_payments;
+pub mod taproot;
+pub mod unknown;
+pub mod upgrade;
+#[cfg(feature = "ur")]
+pub mod ur;
+pub mod utxo;
+pub mod validation;
+pub mod verify;
+pub mod view;
+pub mod wallet_policy;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod weight;
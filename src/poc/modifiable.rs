@@ -0,0 +1,130 @@
+//! The PsbtV2 `tx_modifiable` flags of BIP 370.
+//!
+//! Constructors may only add inputs or outputs while the corresponding flag
+//! is set, and signers clear the flags their signatures don't allow.
+
+use crate::psbt::Version;
+
+use super::input::Input;
+use super::poc::Psbt;
+
+/// Inputs may be added to the psbt.
+pub const INPUTS_MODIFIABLE: u8 = 0x01;
+/// Outputs may be added to the psbt.
+pub const OUTPUTS_MODIFIABLE: u8 = 0x02;
+/// At least one signature uses `SIGHASH_SINGLE`, so the input-output pairs
+/// it commits to must keep their indices.
+pub const HAS_SIGHASH_SINGLE: u8 = 0x04;
+
+impl Psbt {
+    /// Returns the `tx_modifiable` flags, with the flags forbidden by the
+    /// signatures already present cleared. Always 0 for a PsbtV0.
+    pub fn tx_modifiable(&self) -> u8 {
+        if self.inner.version != Version::PsbtV2 {
+            return 0;
+        }
+        let mut flags = self.inner.tx_modifiable.unwrap_or(0);
+        for sighash_type in self.signature_sighash_types() {
+            flags = apply_signature(flags, sighash_type);
+        }
+        flags
+    }
+
+    /// Returns `true` if inputs may be added to the psbt.
+    pub fn inputs_modifiable(&self) -> bool {
+        self.tx_modifiable() & INPUTS_MODIFIABLE != 0
+    }
+
+    /// Returns `true` if outputs may be added to the psbt.
+    pub fn outputs_modifiable(&self) -> bool {
+        self.tx_modifiable() & OUTPUTS_MODIFIABLE != 0
+    }
+
+    /// Returns `true` if a signature uses `SIGHASH_SINGLE`.
+    pub fn has_sighash_single(&self) -> bool {
+        self.tx_modifiable() & HAS_SIGHASH_SINGLE != 0
+    }
+
+    /// Stores the flags returned by [`Psbt::tx_modifiable`], so the serialized
+    /// psbt reflects the signatures it carries.
+    pub(crate) fn refresh_tx_modifiable(&mut self) {
+        if self.inner.tx_modifiable.is_some() {
+            self.inner.tx_modifiable = Some(self.tx_modifiable());
+        }
+    }
+
+    /// The raw sighash type of every ECDSA and taproot signature in the psbt.
+    fn signature_sighash_types(&self) -> impl Iterator<Item = u32> + '_ {
+        self.inner
+            .inputs
+            .iter()
+            .flat_map(Input::signature_sighash_types)
+    }
+}
+
+impl Input {
+    /// Returns `true` if the input carries a signature or is finalized, so
+    /// changes to the transaction may invalidate it.
+    pub fn is_signed(&self) -> bool {
+        !self.partial_sigs.is_empty()
+            || self.tap_key_sig.is_some()
+            || !self.tap_script_sigs.is_empty()
+            || !self.musig2_partial_sigs.is_empty()
+            || self.is_finalized()
+    }
+
+    /// Returns `true` if a signature of the input uses `SIGHASH_SINGLE`.
+    pub fn has_sighash_single_signature(&self) -> bool {
+        self.signature_sighash_types().any(is_sighash_single)
+    }
+
+    /// Returns `true` if the input's sighash type or one of its signatures
+    /// uses `SIGHASH_SINGLE`, pairing the input with the output at its index.
+    pub fn uses_sighash_single(&self) -> bool {
+        self.sighash_type.map_or(false, |sighash_type| {
+            is_sighash_single(sighash_type.to_u32())
+        }) || self.has_sighash_single_signature()
+    }
+
+    /// The raw sighash type of every ECDSA and taproot signature of the
+    /// input.
+    fn signature_sighash_types(&self) -> impl Iterator<Item = u32> + '_ {
+        let ecdsa = self
+            .partial_sigs
+            .values()
+            .map(|signature| signature.hash_ty.to_u32());
+        let taproot = self
+            .tap_key_sig
+            .iter()
+            .chain(self.tap_script_sigs.values())
+            .map(|signature| signature.hash_ty as u32);
+        ecdsa.chain(taproot)
+    }
+}
+
+const SIGHASH_NONE: u32 = 0x02;
+const SIGHASH_SINGLE: u32 = 0x03;
+const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+/// Returns `true` if `sighash_type` is `SIGHASH_SINGLE`, with or without
+/// `ANYONECANPAY`.
+fn is_sighash_single(sighash_type: u32) -> bool {
+    sighash_type & !SIGHASH_ANYONECANPAY == SIGHASH_SINGLE
+}
+
+/// Updates `flags` for a signature of `sighash_type`: without
+/// `ANYONECANPAY` no inputs may be added, unless the signature is
+/// `SIGHASH_NONE` no outputs may be added.
+fn apply_signature(mut flags: u8, sighash_type: u32) -> u8 {
+    if sighash_type & SIGHASH_ANYONECANPAY == 0 {
+        flags &= !INPUTS_MODIFIABLE;
+    }
+    let base_type = sighash_type & !SIGHASH_ANYONECANPAY;
+    if base_type != SIGHASH_NONE {
+        flags &= !OUTPUTS_MODIFIABLE;
+    }
+    if is_sighash_single(sighash_type) {
+        flags |= HAS_SIGHASH_SINGLE;
+    }
+    flags
+}
@@ -0,0 +1,204 @@
+//! MuSig2 fields of psbt inputs and outputs as defined by BIP 373.
+//!
+//! The fields let the participants of a MuSig2 signing session exchange
+//! their public nonces and partial signatures through the psbt. Nonce
+//! generation and signature aggregation are left to a MuSig2 implementation.
+
+use core::fmt;
+
+use secp256k1::PublicKey;
+
+use crate::hashes::Hash;
+use crate::prelude::*;
+use crate::taproot::TapLeafHash;
+
+use super::input::Input;
+use super::output::Output;
+
+/// Identifies a participant's nonce or partial signature: the participant's
+/// key, the aggregate key it signs for and, for script path spends, the leaf
+/// the aggregate key appears in.
+pub type MusigSessionKey = (PublicKey, PublicKey, Option<TapLeafHash>);
+
+/// A 66 byte MuSig2 public nonce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PubNonce(pub [u8; 66]);
+
+/// A 32 byte MuSig2 partial signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PartialSig(pub [u8; 32]);
+
+/// Errors encountered while decoding a MuSig2 field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MusigError {
+    /// A participant or aggregate key isn't a valid compressed public key.
+    InvalidKey,
+    /// The leaf hash in a key isn't 32 bytes.
+    InvalidLeafHash,
+    /// A value has the wrong length.
+    InvalidLength { expected: usize, actual: usize },
+}
+
+impl fmt::Display for MusigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MusigError::InvalidKey => write!(f, "invalid MuSig2 public key"),
+            MusigError::InvalidLeafHash => write!(f, "invalid MuSig2 leaf hash"),
+            MusigError::InvalidLength { expected, actual } => {
+                write!(f, "expected {} bytes, got {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl Input {
+    /// Returns the participants of the MuSig2 aggregate key `aggregate`.
+    pub fn musig2_participants(&self, aggregate: &PublicKey) -> Option<&[PublicKey]> {
+        self.musig2_participant_pubkeys
+            .get(aggregate)
+            .map(|participants| participants.as_slice())
+    }
+
+    /// Records `nonce` as the public nonce of `participant` for the session
+    /// identified by `aggregate` and `leaf_hash`.
+    pub fn add_musig2_pub_nonce(
+        &mut self,
+        participant: PublicKey,
+        aggregate: PublicKey,
+        leaf_hash: Option<TapLeafHash>,
+        nonce: PubNonce,
+    ) {
+        self.musig2_pub_nonces
+            .insert((participant, aggregate, leaf_hash), nonce);
+    }
+
+    /// Records `signature` as the partial signature of `participant` for the
+    /// session identified by `aggregate` and `leaf_hash`.
+    pub fn add_musig2_partial_sig(
+        &mut self,
+        participant: PublicKey,
+        aggregate: PublicKey,
+        leaf_hash: Option<TapLeafHash>,
+        signature: PartialSig,
+    ) {
+        self.musig2_partial_sigs
+            .insert((participant, aggregate, leaf_hash), signature);
+    }
+
+    /// Returns the public nonces of the session in participant order, or
+    /// `None` if the participants are unknown or a nonce is still missing.
+    pub fn musig2_session_nonces(
+        &self,
+        aggregate: &PublicKey,
+        leaf_hash: Option<TapLeafHash>,
+    ) -> Option<Vec<PubNonce>> {
+        self.musig2_participants(aggregate)?
+            .iter()
+            .map(|participant| {
+                self.musig2_pub_nonces
+                    .get(&(*participant, *aggregate, leaf_hash))
+                    .copied()
+            })
+            .collect()
+    }
+
+    /// Returns the partial signatures of the session in participant order, or
+    /// `None` if the participants are unknown or a signature is still missing.
+    pub fn musig2_session_partial_sigs(
+        &self,
+        aggregate: &PublicKey,
+        leaf_hash: Option<TapLeafHash>,
+    ) -> Option<Vec<PartialSig>> {
+        self.musig2_participants(aggregate)?
+            .iter()
+            .map(|participant| {
+                self.musig2_partial_sigs
+                    .get(&(*participant, *aggregate, leaf_hash))
+                    .copied()
+            })
+            .collect()
+    }
+}
+
+impl Output {
+    /// Returns the participants of the MuSig2 aggregate key `aggregate`.
+    pub fn musig2_participants(&self, aggregate: &PublicKey) -> Option<&[PublicKey]> {
+        self.musig2_participant_pubkeys
+            .get(aggregate)
+            .map(|participants| participants.as_slice())
+    }
+}
+
+/// Serializes a list of participant keys, each as a 33 byte compressed key.
+pub(crate) fn serialize_participants(participants: &[PublicKey]) -> Vec<u8> {
+    participants
+        .iter()
+        .flat_map(|participant| participant.serialize())
+        .collect()
+}
+
+/// Deserializes a list of participant keys.
+pub(crate) fn deserialize_participants(bytes: &[u8]) -> Result<Vec<PublicKey>, MusigError> {
+    if bytes.len() % 33 != 0 {
+        return Err(MusigError::InvalidLength {
+            expected: bytes.len() / 33 * 33,
+            actual: bytes.len(),
+        });
+    }
+    bytes
+        .chunks(33)
+        .map(|chunk| PublicKey::from_slice(chunk).map_err(|_| MusigError::InvalidKey))
+        .collect()
+}
+
+/// Serializes the key data of a nonce or partial signature.
+pub(crate) fn serialize_session_key(key: &MusigSessionKey) -> Vec<u8> {
+    let (participant, aggregate, leaf_hash) = key;
+    let mut bytes = participant.serialize().to_vec();
+    bytes.extend(aggregate.serialize());
+    if let Some(leaf_hash) = leaf_hash {
+        bytes.extend(leaf_hash.to_byte_array());
+    }
+    bytes
+}
+
+/// Deserializes the key data of a nonce or partial signature.
+pub(crate) fn deserialize_session_key(bytes: &[u8]) -> Result<MusigSessionKey, MusigError> {
+    if bytes.len() < 66 {
+        return Err(MusigError::InvalidKey);
+    }
+    let participant = PublicKey::from_slice(&bytes[..33]).map_err(|_| MusigError::InvalidKey)?;
+    let aggregate = PublicKey::from_slice(&bytes[33..66]).map_err(|_| MusigError::InvalidKey)?;
+    let leaf_hash = match bytes.len() {
+        66 => None,
+        98 => Some(TapLeafHash::from_slice(&bytes[66..]).map_err(|_| MusigError::InvalidLeafHash)?),
+        _ => return Err(MusigError::InvalidLeafHash),
+    };
+    Ok((participant, aggregate, leaf_hash))
+}
+
+impl PubNonce {
+    /// Decodes a public nonce from its 66 bytes.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, MusigError> {
+        bytes
+            .try_into()
+            .map(PubNonce)
+            .map_err(|_| MusigError::InvalidLength {
+                expected: 66,
+                actual: bytes.len(),
+            })
+    }
+}
+
+impl PartialSig {
+    /// Decodes a partial signature from its 32 bytes.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, MusigError> {
+        bytes
+            .try_into()
+            .map(PartialSig)
+            .map_err(|_| MusigError::InvalidLength {
+                expected: 32,
+                actual: bytes.len(),
+            })
+    }
+}
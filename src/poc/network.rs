@@ -0,0 +1,154 @@
+//! The network a psbt is meant for, kept alongside the psbt but not
+//! serialized, as BIP 174 has no field for it.
+
+use core::fmt;
+
+use crate::address::Address;
+use crate::bip32::{ChildNumber, DerivationPath, ExtendedPubKey};
+use crate::network::Network;
+use crate::prelude::*;
+use crate::FeeRate;
+
+use super::address::AddressError;
+use super::dust::DEFAULT_DUST_RELAY_FEE;
+use super::output::Output;
+use super::poc::{AddOutputOptions, Psbt};
+
+/// The BIP 44 coin type of mainnet keys.
+const MAINNET_COIN_TYPE: u32 = 0;
+/// The BIP 44 coin type of keys of every test network.
+const TESTNET_COIN_TYPE: u32 = 1;
+
+/// A part of a psbt encoded for another network than expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkMismatch {
+    /// A global xpub of the other kind of network: a `tpub` where mainnet is
+    /// expected or an `xpub` where a test network is.
+    Xpub(ExtendedPubKey),
+    /// The output at this index pays to an address derived with the BIP 44
+    /// coin type of the other kind of network.
+    OutputAddress { index: usize, coin_type: u32 },
+}
+
+impl fmt::Display for NetworkMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkMismatch::Xpub(xpub) => {
+                write!(f, "xpub {} is for {}", xpub, xpub.network)
+            }
+            NetworkMismatch::OutputAddress { index, coin_type } => write!(
+                f,
+                "address of output {} is derived with coin type {}",
+                index, coin_type
+            ),
+        }
+    }
+}
+
+impl Psbt {
+    /// Returns the network the psbt is meant for, if set.
+    pub fn network(&self) -> Option<Network> {
+        self.network
+    }
+
+    /// Sets the network the psbt is meant for, used to render its output
+    /// addresses and pick the dust threshold [`Psbt::add_output`] enforces.
+    /// The network is not part of the serialized psbt.
+    pub fn set_network(&mut self, network: Option<Network>) {
+        self.network = network;
+    }
+
+    /// Checks the global xpubs and the output addresses against `network`,
+    /// reporting every one meant for another kind of network.
+    ///
+    /// Output scripts carry no network, so an output address is checked
+    /// through the key origins of the output: a BIP 44, 48, 49, 84 or 86
+    /// path with the coin type of the other kind of network is reported.
+    /// Outputs without such a path, like payments to others, can't be
+    /// checked.
+    pub fn check_network(&self, network: Network) -> Result<(), Vec<NetworkMismatch>> {
+        let mut mismatches: Vec<NetworkMismatch> = self
+            .inner
+            .xpub
+            .keys()
+            .filter(|xpub| is_mainnet(xpub.network) != is_mainnet(network))
+            .map(|xpub| NetworkMismatch::Xpub(*xpub))
+            .collect();
+        for (index, output) in self.inner.outputs.iter().enumerate() {
+            if let Some(coin_type) = foreign_coin_type(output, network) {
+                mismatches.push(NetworkMismatch::OutputAddress { index, coin_type });
+            }
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(mismatches)
+        }
+    }
+
+    /// Decodes the output addresses on the psbt's network like
+    /// [`Psbt::output_addresses`], `None` if no network is set.
+    pub fn addresses(&self) -> Option<Vec<Result<Address, AddressError>>> {
+        self.network.map(|network| self.output_addresses(network))
+    }
+}
+
+/// Returns the dust relay fee outputs are checked against on `network`,
+/// `None` on testnet and regtest, whose nodes relay non-standard
+/// transactions, dust outputs included, by default.
+pub fn dust_relay_fee(network: Network) -> Option<FeeRate> {
+    match network {
+        Network::Testnet | Network::Regtest => None,
+        _ => Some(DEFAULT_DUST_RELAY_FEE),
+    }
+}
+
+impl AddOutputOptions {
+    /// Options rejecting the outputs nodes of `network` don't relay, see
+    /// [`dust_relay_fee`].
+    pub fn for_network(network: Network) -> Self {
+        AddOutputOptions {
+            reject_dust: dust_relay_fee(network),
+        }
+    }
+}
+
+/// Returns `true` for mainnet, the only network extended keys are encoded
+/// differently for.
+fn is_mainnet(network: Network) -> bool {
+    network == Network::Bitcoin
+}
+
+/// Returns the coin type of a key origin of `output` meant for the other
+/// kind of network than `network`, if any.
+fn foreign_coin_type(output: &Output, network: Network) -> Option<u32> {
+    let expected = if is_mainnet(network) {
+        MAINNET_COIN_TYPE
+    } else {
+        TESTNET_COIN_TYPE
+    };
+    let bip32_paths = output.bip32_derivation.values().map(|(_, path)| path);
+    let taproot_paths = output.tap_key_origins.values().map(|(_, (_, path))| path);
+    bip32_paths
+        .chain(taproot_paths)
+        .filter_map(coin_type)
+        .find(|coin_type| {
+            (*coin_type == MAINNET_COIN_TYPE || *coin_type == TESTNET_COIN_TYPE)
+                && *coin_type != expected
+        })
+}
+
+/// Returns the coin type of a BIP 44, 48, 49, 84 or 86 derivation path.
+fn coin_type(path: &DerivationPath) -> Option<u32> {
+    const PURPOSES: [u32; 5] = [44, 48, 49, 84, 86];
+
+    let path: &[ChildNumber] = path.as_ref();
+    match path {
+        [ChildNumber::Hardened { index: purpose }, ChildNumber::Hardened { index: coin_type }, ..]
+            if PURPOSES.contains(purpose) =>
+        {
+            Some(*coin_type)
+        }
+        _ => None,
+    }
+}
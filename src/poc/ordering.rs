@@ -0,0 +1,165 @@
+//! Reordering of the inputs and outputs of a psbt, keeping the input and
+//! output maps in sync with the unsigned transaction.
+
+use core::fmt;
+
+#[cfg(feature = "rand")]
+use rand::seq::SliceRandom;
+#[cfg(feature = "rand")]
+use rand::Rng;
+
+use crate::hashes::Hash;
+use crate::prelude::*;
+use crate::psbt::Version;
+
+use super::input::Input;
+use super::poc::Psbt;
+
+/// Errors encountered while reordering the inputs or outputs of a psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderingError {
+    /// The input at this index is signed, its signatures commit to the
+    /// current order.
+    SignedInput { index: usize },
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+    /// The psbt isn't a PsbtV2 whose `tx_modifiable` flags allow adding
+    /// inputs.
+    InputsNotModifiable,
+}
+
+impl fmt::Display for OrderingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderingError::SignedInput { index } => write!(
+                f,
+                "input {} is signed, its signatures commit to the order",
+                index
+            ),
+            OrderingError::UnsignedTx(err) => write!(f, "invalid unsigned transaction: {}", err),
+            OrderingError::InputsNotModifiable => write!(f, "the inputs aren't modifiable"),
+        }
+    }
+}
+
+impl Psbt {
+    /// Sorts the inputs and outputs as defined by BIP 69: inputs by previous
+    /// txid, in the reversed byte order txids are displayed in, then output
+    /// index, and outputs by amount, then script.
+    ///
+    /// Fails without changing anything if any input is signed.
+    pub fn sort_bip69(&mut self) -> Result<(), OrderingError> {
+        self.check_unsigned()?;
+        let tx = self.unsigned_tx().map_err(OrderingError::UnsignedTx)?;
+
+        let mut input_order: Vec<usize> = (0..tx.input.len()).collect();
+        input_order.sort_by(|&a, &b| {
+            let (a, b) = (tx.input[a].previous_output, tx.input[b].previous_output);
+            let mut a_txid = a.txid.to_byte_array();
+            let mut b_txid = b.txid.to_byte_array();
+            a_txid.reverse();
+            b_txid.reverse();
+            (a_txid, a.vout).cmp(&(b_txid, b.vout))
+        });
+        let mut output_order: Vec<usize> = (0..tx.output.len()).collect();
+        output_order.sort_by(|&a, &b| {
+            let (a, b) = (&tx.output[a], &tx.output[b]);
+            (a.value, a.script_pubkey.as_bytes()).cmp(&(b.value, b.script_pubkey.as_bytes()))
+        });
+
+        self.reorder(&input_order, &output_order);
+        Ok(())
+    }
+
+    /// Fails if any input is signed.
+    pub(crate) fn check_unsigned(&self) -> Result<(), OrderingError> {
+        match self.inner.inputs.iter().position(Input::is_signed) {
+            Some(index) => Err(OrderingError::SignedInput { index }),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the input at `input_order[i]` and the output at
+    /// `output_order[i]` to index `i`, in the maps and in the unsigned
+    /// transaction of a PsbtV0.
+    pub(crate) fn reorder(&mut self, input_order: &[usize], output_order: &[usize]) {
+        permute(&mut self.inner.inputs, input_order);
+        permute(&mut self.inner.outputs, output_order);
+        if self.inner.version == Version::PsbtV0 {
+            if let Some(ref mut tx) = self.inner.unsigned_tx {
+                permute(&mut tx.input, input_order);
+                permute(&mut tx.output, output_order);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rand")]
+impl Psbt {
+    /// Shuffles the outputs, so the position of the change output doesn't
+    /// reveal it.
+    ///
+    /// Outputs paired with an input using `SIGHASH_SINGLE` keep their index.
+    /// Fails without changing anything if any input is signed.
+    pub fn shuffle_outputs<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Result<(), OrderingError> {
+        self.check_unsigned()?;
+        let pinned = self.sighash_single_pairs();
+        let input_order: Vec<usize> = (0..self.inner.inputs.len()).collect();
+        let output_order = shuffled_order(self.inner.outputs.len(), &pinned, rng);
+        self.reorder(&input_order, &output_order);
+        Ok(())
+    }
+
+    /// Shuffles the inputs of a PsbtV2 whose `tx_modifiable` flags allow
+    /// adding inputs.
+    ///
+    /// Inputs using `SIGHASH_SINGLE` keep the index of their output. Fails
+    /// without changing anything if any input is signed.
+    pub fn shuffle_inputs<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Result<(), OrderingError> {
+        if self.inner.version != Version::PsbtV2 || !self.inputs_modifiable() {
+            return Err(OrderingError::InputsNotModifiable);
+        }
+        self.check_unsigned()?;
+        let pinned = self.sighash_single_pairs();
+        let input_order = shuffled_order(self.inner.inputs.len(), &pinned, rng);
+        let output_order: Vec<usize> = (0..self.inner.outputs.len()).collect();
+        self.reorder(&input_order, &output_order);
+        Ok(())
+    }
+
+    /// The indices of the inputs using `SIGHASH_SINGLE` that have an output
+    /// at their index, the pairs that must keep their position.
+    fn sighash_single_pairs(&self) -> Vec<usize> {
+        self.inner
+            .inputs
+            .iter()
+            .enumerate()
+            .filter(|(index, input)| {
+                *index < self.inner.outputs.len() && input.uses_sighash_single()
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// Returns a random permutation of `0..len` leaving the indices in `pinned`
+/// in place.
+#[cfg(feature = "rand")]
+fn shuffled_order<R: Rng + ?Sized>(len: usize, pinned: &[usize], rng: &mut R) -> Vec<usize> {
+    let free: Vec<usize> = (0..len).filter(|index| !pinned.contains(index)).collect();
+    let mut moved = free.clone();
+    moved.shuffle(rng);
+
+    let mut order: Vec<usize> = (0..len).collect();
+    for (position, index) in free.into_iter().zip(moved) {
+        order[position] = index;
+    }
+    order
+}
+
+/// Moves the item at `order[i]` to index `i`, `order` being a permutation
+/// of the indices of `items`.
+fn permute<T>(items: &mut Vec<T>, order: &[usize]) {
+    let mut old: Vec<Option<T>> = items.drain(..).map(Some).collect();
+    items.extend(order.iter().filter_map(|&index| old[index].take()));
+}
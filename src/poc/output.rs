@@ -1,3 +1,7 @@
+use crate::prelude::*;
+
+use super::fee::checked_sum;
+
 /// A key-value map for an output of the corresponding index in the unsigned
 /// transaction.
 #[derive(Clone, Default, Debug, PartialEq, Eq, Hash)]
@@ -5,12 +9,14 @@
 #[cfg_attr(feature = "serde", serde(crate = "actual_serde"))]
 pub struct Output {
     /// The redeem script for this output.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_hr::opt_script"))]
     pub redeem_script: Option<ScriptBuf>,
     /// The witness script for this output.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_hr::opt_script"))]
     pub witness_script: Option<ScriptBuf>,
     /// A map from public keys needed to spend this output to their
     /// corresponding master key fingerprints and derivation paths.
-    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::btreemap_as_seq"))]
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_hr::key_sources"))]
     pub bip32_derivation: BTreeMap<secp256k1::PublicKey, KeySource>,
     /// The internal pubkey.
     pub tap_internal_key: Option<XOnlyPublicKey>,
@@ -19,20 +25,128 @@ pub struct Output {
     /// Map of tap root x only keys to origin info and leaf hashes contained in it.
     #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::btreemap_as_seq"))]
     pub tap_key_origins: BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+    /// Map of MuSig2 aggregate keys to the keys of their participants.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_utils::btreemap_as_seq"))]
+    pub musig2_participant_pubkeys: BTreeMap<secp256k1::PublicKey, Vec<secp256k1::PublicKey>>,
+    /// The silent payment address this output pays to. The script is left
+    /// unset until a signer derives it from the inputs' ECDH shares.
+    pub sp_v0_info: Option<SilentPaymentInfo>,
+    /// The label applied to the silent payment address, if any.
+    pub sp_v0_label: Option<u32>,
     /// Proprietary key-value pairs for this output.
     #[cfg_attr(
         feature = "serde",
-        serde(with = "crate::serde_utils::btreemap_as_seq_byte_values")
+        serde(with = "super::serde_hr::btreemap_as_seq_hex_values")
     )]
     pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
     /// Unknown key-value pairs for this output.
     #[cfg_attr(
         feature = "serde",
-        serde(with = "crate::serde_utils::btreemap_as_seq_byte_values")
+        serde(with = "super::serde_hr::btreemap_as_seq_hex_values")
     )]
     pub unknown: BTreeMap<raw::Key, Vec<u8>>,
 
     // Optional Psbtv2 output fields
-    pub amount: Optional<i64>,
-    pub script: Optional<Vec<u8>>,
+    /// The amount paid to this output, never more than [`Amount::MAX_MONEY`].
+    pub amount: Option<Amount>,
+    /// The script of this output, at most [`MAX_SCRIPT_SIZE`] bytes.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_hr::opt_script"))]
+    pub script: Option<ScriptBuf>,
+
+    /// The Elements fields of a PSET output.
+    #[cfg(feature = "elements")]
+    pub elements: super::elements::ElementsOutput,
+}
+
+/// The maximum size of an output script that can ever be spent.
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// Errors encountered while setting the script of an output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputScriptError {
+    /// The script is larger than [`MAX_SCRIPT_SIZE`].
+    TooLarge { size: usize },
+}
+
+impl fmt::Display for OutputScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputScriptError::TooLarge { size } => write!(
+                f,
+                "script of {} bytes exceeds the maximum of {}",
+                size, MAX_SCRIPT_SIZE
+            ),
+        }
+    }
+}
+
+impl Output {
+    /// Returns the script of the output at `index`: the `script` field of a
+    /// PsbtV2 output, or the script of the matching output of `unsigned_tx`
+    /// for a PsbtV0.
+    pub fn script_pubkey<'a>(
+        &'a self,
+        unsigned_tx: Option<&'a Transaction>,
+        index: usize,
+    ) -> Option<&'a Script> {
+        match self.script {
+            Some(ref script) => Some(script),
+            None => unsigned_tx?
+                .output
+                .get(index)
+                .map(|txout| txout.script_pubkey.as_script()),
+        }
+    }
+
+    /// Sets the PsbtV2 `script` field, rejecting scripts larger than
+    /// [`MAX_SCRIPT_SIZE`].
+    pub fn set_script(&mut self, script: ScriptBuf) -> Result<(), OutputScriptError> {
+        if script.len() > MAX_SCRIPT_SIZE {
+            return Err(OutputScriptError::TooLarge { size: script.len() });
+        }
+        self.script = Some(script);
+        Ok(())
+    }
+}
+
+impl Psbt {
+    /// Returns the script of the output at `index`, see
+    /// [`Output::script_pubkey`].
+    pub fn output_script_pubkey(&self, index: usize) -> Option<&Script> {
+        self.inner
+            .outputs
+            .get(index)?
+            .script_pubkey(self.inner.unsigned_tx.as_ref(), index)
+    }
+
+    /// Sets the amount of the output at `index`, in the unsigned transaction
+    /// of a PsbtV0 or the `amount` field of a PsbtV2.
+    pub(crate) fn set_output_amount(&mut self, index: usize, amount: Amount) {
+        match self.inner.version {
+            Version::PsbtV0 => {
+                if let Some(txout) = self
+                    .inner
+                    .unsigned_tx
+                    .as_mut()
+                    .and_then(|tx| tx.output.get_mut(index))
+                {
+                    txout.value = amount;
+                }
+            }
+            Version::PsbtV2 => {
+                if let Some(output) = self.inner.outputs.get_mut(index) {
+                    output.amount = Some(amount);
+                }
+            }
+        }
+    }
+
+    /// Sums the amounts of all outputs with checked arithmetic.
+    ///
+    /// Returns `None` if an amount is missing, or if the total overflows or
+    /// exceeds [`Amount::MAX_MONEY`].
+    pub fn total_output_amount(&self) -> Option<Amount> {
+        let tx = self.unsigned_tx().ok()?;
+        checked_sum(tx.output.iter().map(|txout| txout.value)).ok()
+    }
 }
@@ -0,0 +1,308 @@
+//! SLIP-0019 proofs of ownership carried in psbt inputs.
+//!
+//! A proof lets a coordinator, such as a CoinJoin server, check that the
+//! participant registering an input controls its key without the input being
+//! signed. The proof is stored under a proprietary key of the input so it
+//! travels with the psbt.
+
+use secp256k1::{Keypair, Message, Secp256k1, XOnlyPublicKey};
+
+use crate::blockdata::script::{Script, ScriptBuf};
+use crate::blockdata::witness::Witness;
+use crate::consensus::encode::{self, VarInt};
+use crate::crypto::ecdsa;
+use crate::crypto::key::{PublicKey, TapTweak};
+use crate::hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use crate::prelude::*;
+use crate::psbt::raw;
+use crate::sighash::{EcdsaSighashType, TapSighashType};
+use crate::taproot;
+
+use super::finalizer::pays_to_key;
+use super::input::Input;
+use super::poc::Psbt;
+use super::secret::Wiped;
+use super::sighash::SighashError;
+
+/// The prefix of the proprietary key holding the proof of ownership.
+pub const OWNERSHIP_PROOF_PREFIX: &[u8] = b"SLIP-0019";
+/// The subtype of the proprietary key holding the proof of ownership.
+pub const OWNERSHIP_PROOF_SUBTYPE: u8 = 0x00;
+
+/// The magic bytes every proof of ownership starts with.
+const VERSION_MAGIC: [u8; 4] = [0x53, 0x4c, 0x00, 0x19];
+/// The flag set when the user confirmed the proof on their device.
+const USER_CONFIRMATION_FLAG: u8 = 0x01;
+
+/// Errors encountered while creating or verifying a proof of ownership.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OwnershipProofError {
+    /// The spent output could not be looked up.
+    Sighash(SighashError),
+    /// The input has no proof of ownership.
+    MissingProof { index: usize },
+    /// The stored proof couldn't be decoded.
+    Malformed(String),
+    /// Proofs can only be made for P2WPKH and P2TR outputs.
+    UnsupportedScript { index: usize },
+    /// The key doesn't control the spent output.
+    KeyMismatch { index: usize },
+    /// The proof's signature doesn't verify.
+    InvalidSignature { index: usize },
+}
+
+impl From<SighashError> for OwnershipProofError {
+    fn from(err: SighashError) -> Self {
+        OwnershipProofError::Sighash(err)
+    }
+}
+
+/// A SLIP-0019 proof of ownership.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OwnershipProof {
+    /// Whether the user confirmed the proof on their signing device.
+    pub user_confirmed: bool,
+    /// The ownership identifiers of the proof, see
+    /// [`OwnershipProof::ownership_id`].
+    pub ownership_ids: Vec<[u8; 32]>,
+    /// The scriptSig proving control of the output, empty for native segwit.
+    pub script_sig: ScriptBuf,
+    /// The witness proving control of the output.
+    pub witness: Witness,
+}
+
+impl OwnershipProof {
+    /// Computes the ownership identifier of `script_pubkey`, an HMAC-SHA256
+    /// of the script keyed with the wallet's ownership identification key.
+    pub fn ownership_id(ownership_key: &[u8; 32], script_pubkey: &Script) -> [u8; 32] {
+        let mut engine = HmacEngine::<sha256::Hash>::new(ownership_key);
+        engine.input(script_pubkey.as_bytes());
+        Hmac::from_engine(engine).to_byte_array()
+    }
+
+    /// Serializes the proof body followed by its scriptSig and witness.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = self.body();
+        bytes.extend(encode::serialize(&self.script_sig));
+        bytes.extend(encode::serialize(&self.witness));
+        bytes
+    }
+
+    /// Deserializes a proof produced by [`OwnershipProof::serialize`].
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, OwnershipProofError> {
+        let malformed = |reason: &str| OwnershipProofError::Malformed(String::from(reason));
+
+        if bytes.len() < VERSION_MAGIC.len() + 1 || bytes[..VERSION_MAGIC.len()] != VERSION_MAGIC {
+            return Err(malformed("invalid version magic"));
+        }
+        let flags = bytes[VERSION_MAGIC.len()];
+        if flags & !USER_CONFIRMATION_FLAG != 0 {
+            return Err(malformed("unknown flags"));
+        }
+
+        let mut position = VERSION_MAGIC.len() + 1;
+        let (VarInt(count), read) = encode::deserialize_partial(&bytes[position..])
+            .map_err(|err| OwnershipProofError::Malformed(err.to_string()))?;
+        position += read;
+
+        let mut ownership_ids = Vec::new();
+        for _ in 0..count {
+            let id = bytes
+                .get(position..position + 32)
+                .ok_or_else(|| malformed("truncated ownership ids"))?;
+            ownership_ids.push(id.try_into().expect("slice of 32 bytes"));
+            position += 32;
+        }
+
+        let (script_sig, read) = encode::deserialize_partial(&bytes[position..])
+            .map_err(|err| OwnershipProofError::Malformed(err.to_string()))?;
+        position += read;
+        let (witness, read) = encode::deserialize_partial(&bytes[position..])
+            .map_err(|err| OwnershipProofError::Malformed(err.to_string()))?;
+        position += read;
+        if position != bytes.len() {
+            return Err(malformed("trailing bytes"));
+        }
+
+        Ok(OwnershipProof {
+            user_confirmed: flags & USER_CONFIRMATION_FLAG != 0,
+            ownership_ids,
+            script_sig,
+            witness,
+        })
+    }
+
+    /// Computes the digest the proof signs, committing to the proof body, the
+    /// spent `script_pubkey` and the coordinator's `commitment_data`.
+    pub fn sighash(&self, script_pubkey: &Script, commitment_data: &[u8]) -> sha256::Hash {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.body());
+        engine.input(&encode::serialize(script_pubkey));
+        engine.input(&encode::serialize(&commitment_data.to_vec()));
+        sha256::Hash::from_engine(engine)
+    }
+
+    fn body(&self) -> Vec<u8> {
+        let mut bytes = VERSION_MAGIC.to_vec();
+        bytes.push(if self.user_confirmed {
+            USER_CONFIRMATION_FLAG
+        } else {
+            0
+        });
+        bytes.extend(encode::serialize(&VarInt(self.ownership_ids.len() as u64)));
+        for id in &self.ownership_ids {
+            bytes.extend(id);
+        }
+        bytes
+    }
+}
+
+impl Input {
+    /// Returns the proof of ownership stored in the input's proprietary map,
+    /// if any.
+    pub fn ownership_proof(&self) -> Result<Option<OwnershipProof>, OwnershipProofError> {
+        self.proprietary
+            .get(&ownership_proof_key())
+            .map(|bytes| OwnershipProof::deserialize(bytes))
+            .transpose()
+    }
+
+    /// Stores `proof` in the input's proprietary map, replacing any previous
+    /// proof.
+    pub fn set_ownership_proof(&mut self, proof: &OwnershipProof) {
+        self.proprietary
+            .insert(ownership_proof_key(), proof.serialize());
+    }
+}
+
+impl Psbt {
+    /// Creates a proof of ownership of the output spent by the input at
+    /// `index` and stores it in the input.
+    ///
+    /// P2WPKH outputs are proven with an ECDSA signature of `keypair`, P2TR
+    /// outputs with a key path signature of `keypair` tweaked with the
+    /// input's `tap_merkle_root`.
+    pub fn create_ownership_proof(
+        &mut self,
+        index: usize,
+        keypair: &Keypair,
+        ownership_ids: Vec<[u8; 32]>,
+        commitment_data: &[u8],
+        user_confirmed: bool,
+    ) -> Result<OwnershipProof, OwnershipProofError> {
+        let secp = Secp256k1::new();
+        let script_pubkey = self.spent_txout(index)?.script_pubkey;
+
+        let mut proof = OwnershipProof {
+            user_confirmed,
+            ownership_ids,
+            script_sig: ScriptBuf::new(),
+            witness: Witness::new(),
+        };
+        let message = Message::from_digest(
+            proof
+                .sighash(&script_pubkey, commitment_data)
+                .to_byte_array(),
+        );
+
+        if script_pubkey.is_p2wpkh() {
+            let public_key = PublicKey::new(keypair.public_key());
+            if !pays_to_key(&script_pubkey, &public_key) {
+                return Err(OwnershipProofError::KeyMismatch { index });
+            }
+            let signature = ecdsa::Signature {
+                sig: secp.sign_ecdsa(&message, &Wiped(keypair.secret_key())),
+                hash_ty: EcdsaSighashType::All,
+            };
+            proof.witness.push(signature.to_vec());
+            proof.witness.push(public_key.to_bytes());
+        } else if script_pubkey.is_p2tr() {
+            let tweaked = Wiped(
+                keypair
+                    .tap_tweak(&secp, self.inner.inputs[index].tap_merkle_root)
+                    .to_inner(),
+            );
+            let (output_key, _) = tweaked.x_only_public_key();
+            if script_pubkey.as_bytes()[2..] != output_key.serialize() {
+                return Err(OwnershipProofError::KeyMismatch { index });
+            }
+            let signature = taproot::Signature {
+                sig: secp.sign_schnorr_no_aux_rand(&message, &tweaked),
+                hash_ty: TapSighashType::Default,
+            };
+            proof.witness.push(signature.to_vec());
+        } else {
+            return Err(OwnershipProofError::UnsupportedScript { index });
+        }
+
+        self.inner.inputs[index].set_ownership_proof(&proof);
+        Ok(proof)
+    }
+
+    /// Verifies the proof of ownership stored in the input at `index` against
+    /// the spent output and the coordinator's `commitment_data`.
+    pub fn verify_ownership_proof(
+        &self,
+        index: usize,
+        commitment_data: &[u8],
+    ) -> Result<OwnershipProof, OwnershipProofError> {
+        let secp = Secp256k1::verification_only();
+        let proof = self
+            .input_at(index)?
+            .ownership_proof()?
+            .ok_or(OwnershipProofError::MissingProof { index })?;
+        let script_pubkey = self.spent_txout(index)?.script_pubkey;
+        let message = Message::from_digest(
+            proof
+                .sighash(&script_pubkey, commitment_data)
+                .to_byte_array(),
+        );
+        let invalid = OwnershipProofError::InvalidSignature { index };
+
+        if !proof.script_sig.is_empty() {
+            return Err(invalid);
+        }
+        if script_pubkey.is_p2wpkh() {
+            let (signature, public_key) = match proof.witness.to_vec().as_slice() {
+                [signature, public_key] => (
+                    ecdsa::Signature::from_slice(signature).map_err(|_| invalid.clone())?,
+                    PublicKey::from_slice(public_key).map_err(|_| invalid.clone())?,
+                ),
+                _ => return Err(invalid),
+            };
+            if signature.hash_ty != EcdsaSighashType::All
+                || !pays_to_key(&script_pubkey, &public_key)
+            {
+                return Err(invalid);
+            }
+            secp.verify_ecdsa(&message, &signature.sig, &public_key.inner)
+                .map_err(|_| invalid)?;
+        } else if script_pubkey.is_p2tr() {
+            let signature = match proof.witness.to_vec().as_slice() {
+                [signature] => {
+                    taproot::Signature::from_slice(signature).map_err(|_| invalid.clone())?
+                }
+                _ => return Err(invalid),
+            };
+            let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+                .map_err(|_| invalid.clone())?;
+            if signature.hash_ty != TapSighashType::Default {
+                return Err(invalid);
+            }
+            secp.verify_schnorr(&signature.sig, &message, &output_key)
+                .map_err(|_| invalid)?;
+        } else {
+            return Err(OwnershipProofError::UnsupportedScript { index });
+        }
+
+        Ok(proof)
+    }
+}
+
+fn ownership_proof_key() -> raw::ProprietaryKey {
+    raw::ProprietaryKey {
+        prefix: OWNERSHIP_PROOF_PREFIX.to_vec(),
+        subtype: OWNERSHIP_PROOF_SUBTYPE,
+        key: vec![],
+    }
+}
@@ -0,0 +1,194 @@
+//! Checked insertion of partial signatures.
+//!
+//! Standardness rules reject signatures whose S value is in the upper half
+//! of the curve order and signatures that aren't strict DER. Some signers
+//! still produce them, so these APIs catch them when they enter the psbt
+//! rather than when the transaction is broadcast. Signatures must also
+//! commit to the `sighash_type` of their input, which is set from the first
+//! signature if the input has none.
+
+use core::fmt;
+
+use secp256k1::XOnlyPublicKey;
+
+use crate::crypto::ecdsa;
+use crate::crypto::key::PublicKey;
+use crate::psbt::PsbtSighashType;
+use crate::taproot::{self, TapLeafHash};
+
+use super::index::InputIndex;
+use super::input::Input;
+use super::poc::Psbt;
+
+/// What to do with a signature whose S value is high.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum HighSPolicy {
+    /// Reject the signature.
+    #[default]
+    Reject,
+    /// Replace S by its negation, which keeps the signature valid.
+    Normalize,
+}
+
+/// Errors encountered while inserting a partial signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartialSigError {
+    /// The signature isn't strict DER followed by a sighash type.
+    Encoding(ecdsa::Error),
+    /// The S value of the signature is high and [`HighSPolicy::Reject`] was
+    /// asked for.
+    HighS,
+    /// The signature commits to a sighash type other than the `sighash_type`
+    /// of the input.
+    SighashTypeMismatch {
+        expected: PsbtSighashType,
+        found: PsbtSighashType,
+    },
+    /// There's no input at this index.
+    IndexOutOfBounds { index: usize, length: usize },
+}
+
+impl fmt::Display for PartialSigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartialSigError::Encoding(err) => write!(f, "invalid signature encoding: {}", err),
+            PartialSigError::HighS => write!(f, "signature has a high S value"),
+            PartialSigError::SighashTypeMismatch { expected, found } => write!(
+                f,
+                "signature uses sighash type {} but the input requires {}",
+                found, expected
+            ),
+            PartialSigError::IndexOutOfBounds { index, length } => write!(
+                f,
+                "input index {} out of bounds for {} inputs",
+                index, length
+            ),
+        }
+    }
+}
+
+impl Input {
+    /// Inserts the signature of `public_key` into `partial_sigs`, handling a
+    /// high S value according to `policy`.
+    ///
+    /// The signature must use the input's `sighash_type`, which is set to the
+    /// signature's sighash type if missing.
+    ///
+    /// Returns the signature previously stored for the key, if any.
+    pub fn insert_partial_sig(
+        &mut self,
+        public_key: PublicKey,
+        mut signature: ecdsa::Signature,
+        policy: HighSPolicy,
+    ) -> Result<Option<ecdsa::Signature>, PartialSigError> {
+        let mut normalized = signature.sig;
+        normalized.normalize_s();
+        if normalized != signature.sig {
+            match policy {
+                HighSPolicy::Reject => return Err(PartialSigError::HighS),
+                HighSPolicy::Normalize => signature.sig = normalized,
+            }
+        }
+        self.check_sighash_type(signature.hash_ty.into())?;
+        Ok(self.partial_sigs.insert(public_key, signature))
+    }
+
+    /// Inserts the script path signature of `public_key` for the leaf
+    /// `leaf_hash` into `tap_script_sigs`.
+    ///
+    /// The signature must use the input's `sighash_type`, which is set to the
+    /// signature's sighash type if missing. Returns the signature previously
+    /// stored for the key and leaf, if any.
+    pub fn insert_tap_script_sig(
+        &mut self,
+        public_key: XOnlyPublicKey,
+        leaf_hash: TapLeafHash,
+        signature: taproot::Signature,
+    ) -> Result<Option<taproot::Signature>, PartialSigError> {
+        self.check_sighash_type(signature.hash_ty.into())?;
+        Ok(self
+            .tap_script_sigs
+            .insert((public_key, leaf_hash), signature))
+    }
+
+    /// Checks a signature's sighash type against the input's `sighash_type`,
+    /// setting it if missing.
+    fn check_sighash_type(&mut self, found: PsbtSighashType) -> Result<(), PartialSigError> {
+        match self.sighash_type {
+            Some(expected) if expected != found => {
+                Err(PartialSigError::SighashTypeMismatch { expected, found })
+            }
+            Some(_) => Ok(()),
+            None => {
+                self.sighash_type = Some(found);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns `true` if the `sighash_type` of this input or `other`
+    /// contradicts the `sighash_type` or a signature of the other one, or,
+    /// without a `sighash_type`, if the signatures of both inputs use
+    /// different sighash types.
+    pub(crate) fn sighash_type_conflicts(&self, other: &Input) -> bool {
+        let contradicts = |input: &Input, other: &Input| match input.sighash_type {
+            Some(sighash_type) => other.sighash_types().any(|found| found != sighash_type),
+            None => false,
+        };
+        let signatures_disagree = match self.sighash_types().next() {
+            Some(first) => other.sighash_types().any(|found| found != first),
+            None => false,
+        };
+        contradicts(self, other) || contradicts(other, self) || signatures_disagree
+    }
+
+    /// The `sighash_type` of the input followed by the sighash types of its
+    /// signatures.
+    fn sighash_types(&self) -> impl Iterator<Item = PsbtSighashType> + '_ {
+        let ecdsa = self.partial_sigs.values().map(|sig| sig.hash_ty.into());
+        let key_path = self.tap_key_sig.iter().map(|sig| sig.hash_ty.into());
+        let script_path = self.tap_script_sigs.values().map(|sig| sig.hash_ty.into());
+        self.sighash_type
+            .into_iter()
+            .chain(ecdsa)
+            .chain(key_path)
+            .chain(script_path)
+    }
+
+    /// Parses `signature`, a strict DER signature followed by its sighash
+    /// type byte, and inserts it with [`Input::insert_partial_sig`].
+    pub fn insert_partial_sig_slice(
+        &mut self,
+        public_key: PublicKey,
+        signature: &[u8],
+        policy: HighSPolicy,
+    ) -> Result<Option<ecdsa::Signature>, PartialSigError> {
+        let signature =
+            ecdsa::Signature::from_slice(signature).map_err(PartialSigError::Encoding)?;
+        self.insert_partial_sig(public_key, signature, policy)
+    }
+}
+
+impl Psbt {
+    /// Inserts a partial signature into the input at `index`, see
+    /// [`Input::insert_partial_sig_slice`], and updates the modifiable flags
+    /// for its sighash type.
+    pub fn insert_partial_sig(
+        &mut self,
+        index: InputIndex,
+        public_key: PublicKey,
+        signature: &[u8],
+        policy: HighSPolicy,
+    ) -> Result<Option<ecdsa::Signature>, PartialSigError> {
+        let index = index.get();
+        let length = self.inner.inputs.len();
+        let previous = self
+            .inner
+            .inputs
+            .get_mut(index)
+            .ok_or(PartialSigError::IndexOutOfBounds { index, length })?
+            .insert_partial_sig_slice(public_key, signature, policy)?;
+        self.refresh_tx_modifiable();
+        Ok(previous)
+    }
+}
@@ -0,0 +1,485 @@
+//! Payjoin (BIP-78): the receiver of a payment adds inputs of its own to the
+//! sender's psbt, breaking the common-input-ownership heuristic.
+//!
+//! The receiver turns the signed original psbt into a proposal with
+//! [`Psbt::payjoin_proposal`], adds and signs its inputs and adjusts its
+//! output. The sender checks the proposal against its original with
+//! [`Psbt::check_payjoin_proposal`] before signing it again.
+
+use core::fmt;
+
+use crate::blockdata::script::{Script, ScriptBuf};
+use crate::blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn};
+use crate::blockdata::witness::Witness;
+use crate::consensus::encode::{self, VarInt};
+use crate::prelude::*;
+use crate::psbt::Version;
+use crate::{Amount, FeeRate, Weight};
+
+use super::fee::{checked_sum, FeeError};
+use super::input::Input;
+use super::poc::Psbt;
+
+/// The weight of an input before its scriptSig and witness: the outpoint
+/// and sequence.
+const INPUT_BASE_WEIGHT: u64 = 40 * 4;
+
+/// Errors encountered while building or checking a payjoin proposal, one per
+/// check of BIP-78.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayjoinError {
+    /// The unsigned transaction could not be built from a psbt.
+    UnsignedTx(String),
+    /// The fee of a psbt could not be calculated.
+    Fee(FeeError),
+    /// There is no input or output at this index.
+    NoSuchIndex { index: usize },
+    /// The proposal changed the transaction version or locktime.
+    TxChanged,
+    /// The proposal changed the sequence of the sender input at this index.
+    SequenceChanged { index: usize },
+    /// The inputs of the proposal don't all have the same sequence.
+    MixedSequences,
+    /// The inputs of the proposal spend different script types.
+    MixedInputTypes,
+    /// The input at this index of the proposal has key origins.
+    InputKeypaths { index: usize },
+    /// The input at this index of the proposal has partial signatures.
+    PartialSignatures { index: usize },
+    /// The sender input at this index of the proposal is finalized.
+    SenderInputFinalized { index: usize },
+    /// The sender input at this index of the proposal has utxo fields.
+    SenderInputUtxo { index: usize },
+    /// The receiver input at this index of the proposal isn't finalized.
+    ReceiverInputNotFinalized { index: usize },
+    /// The receiver input at this index of the proposal has no utxo fields.
+    ReceiverInputMissingUtxo { index: usize },
+    /// The input at this index of the original isn't in the proposal.
+    MissingSenderInput { index: usize },
+    /// The output at this index of the proposal has key origins.
+    OutputKeypaths { index: usize },
+    /// The output at this index of the original isn't in the proposal.
+    MissingSenderOutput { index: usize },
+    /// The value of the output at this index of the original decreased.
+    OutputDecreased { index: usize },
+    /// The proposal pays less fee than the original.
+    FeeDecreased,
+    /// The fee output was reduced by more than the sender allows.
+    ContributionTooHigh { contribution: Amount, max: Amount },
+    /// The fee output was reduced by more than the fee increased.
+    ContributionNotForFee { contribution: Amount },
+    /// The fee output was reduced by more than the added inputs cost at the
+    /// original fee rate.
+    ContributionNotForInputs { contribution: Amount },
+    /// The fee rate of the proposal is below the sender's minimum.
+    FeeRateTooLow { fee_rate: FeeRate },
+}
+
+impl fmt::Display for PayjoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayjoinError::UnsignedTx(err) => write!(f, "invalid unsigned transaction: {}", err),
+            PayjoinError::Fee(err) => write!(f, "fee error: {:?}", err),
+            PayjoinError::NoSuchIndex { index } => write!(f, "no input or output {}", index),
+            PayjoinError::TxChanged => write!(f, "transaction version or locktime changed"),
+            PayjoinError::SequenceChanged { index } => {
+                write!(f, "sequence of sender input {} changed", index)
+            }
+            PayjoinError::MixedSequences => write!(f, "inputs have different sequences"),
+            PayjoinError::MixedInputTypes => write!(f, "inputs spend different script types"),
+            PayjoinError::InputKeypaths { index } => write!(f, "input {} has keypaths", index),
+            PayjoinError::PartialSignatures { index } => {
+                write!(f, "input {} has partial signatures", index)
+            }
+            PayjoinError::SenderInputFinalized { index } => {
+                write!(f, "sender input {} is finalized", index)
+            }
+            PayjoinError::SenderInputUtxo { index } => {
+                write!(f, "sender input {} has utxo fields", index)
+            }
+            PayjoinError::ReceiverInputNotFinalized { index } => {
+                write!(f, "receiver input {} isn't finalized", index)
+            }
+            PayjoinError::ReceiverInputMissingUtxo { index } => {
+                write!(f, "receiver input {} has no utxo", index)
+            }
+            PayjoinError::MissingSenderInput { index } => {
+                write!(f, "original input {} is missing from the proposal", index)
+            }
+            PayjoinError::OutputKeypaths { index } => write!(f, "output {} has keypaths", index),
+            PayjoinError::MissingSenderOutput { index } => {
+                write!(f, "original output {} is missing from the proposal", index)
+            }
+            PayjoinError::OutputDecreased { index } => {
+                write!(f, "value of original output {} decreased", index)
+            }
+            PayjoinError::FeeDecreased => write!(f, "proposal pays less fee than the original"),
+            PayjoinError::ContributionTooHigh { contribution, max } => write!(
+                f,
+                "fee contribution {} exceeds the maximum of {}",
+                contribution, max
+            ),
+            PayjoinError::ContributionNotForFee { contribution } => {
+                write!(
+                    f,
+                    "fee contribution {} exceeds the fee increase",
+                    contribution
+                )
+            }
+            PayjoinError::ContributionNotForInputs { contribution } => write!(
+                f,
+                "fee contribution {} exceeds the cost of the added inputs",
+                contribution
+            ),
+            PayjoinError::FeeRateTooLow { fee_rate } => {
+                write!(f, "fee rate {} is below the minimum", fee_rate)
+            }
+        }
+    }
+}
+
+/// The parameters the sender passes the receiver, which its checks of the
+/// proposal enforce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PayjoinParams {
+    /// The index of the original output the receiver may reduce to pay for
+    /// its inputs, and the most it may reduce it by.
+    pub additional_fee_contribution: Option<(usize, Amount)>,
+    /// Forbids the receiver from substituting the payment output.
+    pub disable_output_substitution: bool,
+    /// The minimum fee rate of the proposal.
+    pub min_fee_rate: Option<FeeRate>,
+}
+
+impl Psbt {
+    /// Turns the signed original psbt of the sender into a payjoin proposal
+    /// the receiver can add its inputs to.
+    ///
+    /// The sender inputs lose their signatures, finalized scripts, utxos and
+    /// key origins, outputs and the global map their key origins, as BIP-78
+    /// requires of proposals.
+    pub fn payjoin_proposal(&self) -> Psbt {
+        let mut proposal = Psbt {
+            inner: self.inner.clone(),
+            fee_policy: None,
+            network: self.network,
+        };
+        for input in &mut proposal.inner.inputs {
+            input.clear_signatures();
+            input.non_witness_utxo = None;
+            input.witness_utxo = None;
+            input.bip32_derivation.clear();
+            input.tap_key_origins.clear();
+        }
+        for output in &mut proposal.inner.outputs {
+            output.bip32_derivation.clear();
+            output.tap_key_origins.clear();
+        }
+        proposal.inner.xpub.clear();
+        proposal
+    }
+
+    /// Inserts an input of the receiver spending `previous_output` at
+    /// `position` of a proposal, with the sequence of the sender inputs.
+    ///
+    /// `input` should carry its utxo, and be finalized before the proposal
+    /// is sent back.
+    pub fn add_receiver_input(
+        &mut self,
+        position: usize,
+        previous_output: OutPoint,
+        mut input: Input,
+    ) -> Result<(), PayjoinError> {
+        if position > self.inner.inputs.len() {
+            return Err(PayjoinError::NoSuchIndex { index: position });
+        }
+        let tx = self.unsigned_tx().map_err(PayjoinError::UnsignedTx)?;
+        let sequence = tx
+            .input
+            .first()
+            .map_or(Sequence::ENABLE_RBF_NO_LOCKTIME, |txin| txin.sequence);
+
+        match self.inner.version {
+            Version::PsbtV0 => {
+                let unsigned_tx = self.inner.unsigned_tx.as_mut().expect("unsigned tx built");
+                unsigned_tx.input.insert(
+                    position,
+                    TxIn {
+                        previous_output,
+                        script_sig: ScriptBuf::new(),
+                        sequence,
+                        witness: Witness::default(),
+                    },
+                );
+            }
+            Version::PsbtV2 => {
+                input.previous_tx_id = Some(previous_output.txid.into());
+                input.output_index = Some(previous_output.vout);
+                input.sequence = Some(sequence);
+            }
+        }
+        self.inner.inputs.insert(position, input);
+        Ok(())
+    }
+
+    /// Sets the amount of the receiver's output at `index` of a proposal,
+    /// typically raised by the value of the receiver's inputs.
+    pub fn adjust_receiver_output(
+        &mut self,
+        index: usize,
+        amount: Amount,
+    ) -> Result<(), PayjoinError> {
+        if index >= self.inner.outputs.len() {
+            return Err(PayjoinError::NoSuchIndex { index });
+        }
+        self.set_output_amount(index, amount);
+        Ok(())
+    }
+
+    /// Runs the checks BIP-78 requires of the sender on a payjoin
+    /// `proposal` for this original psbt, paying the receiver at
+    /// `payment_script`, before the sender signs it.
+    pub fn check_payjoin_proposal(
+        &self,
+        proposal: &Psbt,
+        payment_script: &Script,
+        params: &PayjoinParams,
+    ) -> Result<(), PayjoinError> {
+        let original_tx = self.unsigned_tx().map_err(PayjoinError::UnsignedTx)?;
+        let proposal_tx = proposal.unsigned_tx().map_err(PayjoinError::UnsignedTx)?;
+        if original_tx.version != proposal_tx.version
+            || original_tx.lock_time != proposal_tx.lock_time
+        {
+            return Err(PayjoinError::TxChanged);
+        }
+
+        self.check_proposal_inputs(proposal, &original_tx, &proposal_tx)?;
+
+        let original_fee = self.fee().map_err(PayjoinError::Fee)?;
+        let proposal_fee = proposal_fee(self, &original_tx, proposal, &proposal_tx)?;
+        let fee_increase = proposal_fee
+            .checked_sub(original_fee)
+            .ok_or(PayjoinError::FeeDecreased)?;
+        let added_inputs = proposal_tx
+            .input
+            .len()
+            .saturating_sub(original_tx.input.len()) as u64;
+        let original_weight = self.estimate_weight().map_err(PayjoinError::Fee)?;
+
+        for (index, txout) in original_tx.output.iter().enumerate() {
+            let new_value = proposal_tx
+                .output
+                .iter()
+                .find(|new_txout| new_txout.script_pubkey == txout.script_pubkey)
+                .map(|new_txout| new_txout.value);
+            if txout.script_pubkey == *payment_script && !params.disable_output_substitution {
+                continue;
+            }
+            let new_value = new_value.ok_or(PayjoinError::MissingSenderOutput { index })?;
+            let contribution = txout.value.checked_sub(new_value).unwrap_or(Amount::ZERO);
+            match params.additional_fee_contribution {
+                Some((fee_index, max)) if fee_index == index => {
+                    if contribution > max {
+                        return Err(PayjoinError::ContributionTooHigh { contribution, max });
+                    }
+                    if contribution > fee_increase {
+                        return Err(PayjoinError::ContributionNotForFee { contribution });
+                    }
+                    // The first input stands for the sender's script type
+                    let first_input = self
+                        .inner
+                        .inputs
+                        .first()
+                        .ok_or(PayjoinError::NoSuchIndex { index: 0 })?;
+                    let input_weight = input_weight(first_input);
+                    let original_fee_rate = FeeRate::from_sat_per_kwu(
+                        original_fee.to_sat() * 1000 / original_weight.to_wu(),
+                    );
+                    let inputs_cost = original_fee_rate
+                        .fee_wu(input_weight * added_inputs)
+                        .unwrap_or(Amount::MAX);
+                    if contribution > inputs_cost {
+                        return Err(PayjoinError::ContributionNotForInputs { contribution });
+                    }
+                }
+                _ if contribution > Amount::ZERO => {
+                    return Err(PayjoinError::OutputDecreased { index })
+                }
+                _ => {}
+            }
+        }
+        if let Some(index) = proposal.inner.outputs.iter().position(|output| {
+            !output.bip32_derivation.is_empty() || !output.tap_key_origins.is_empty()
+        }) {
+            return Err(PayjoinError::OutputKeypaths { index });
+        }
+
+        if let Some(min_fee_rate) = params.min_fee_rate {
+            // The sender inputs weigh what they did in the original
+            let proposal_weight = proposal
+                .inner
+                .inputs
+                .iter()
+                .filter(|input| {
+                    input.final_script_sig.is_some() || input.final_script_witness.is_some()
+                })
+                .map(input_weight)
+                .fold(original_weight, |weight, input_weight| {
+                    weight + input_weight
+                });
+            let fee_rate =
+                FeeRate::from_sat_per_kwu(proposal_fee.to_sat() * 1000 / proposal_weight.to_wu());
+            if fee_rate < min_fee_rate {
+                return Err(PayjoinError::FeeRateTooLow { fee_rate });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_proposal_inputs(
+        &self,
+        proposal: &Psbt,
+        original_tx: &Transaction,
+        proposal_tx: &Transaction,
+    ) -> Result<(), PayjoinError> {
+        let mut input_types = BTreeSet::new();
+        let mut sequences = BTreeSet::new();
+        for (index, (txin, input)) in proposal_tx
+            .input
+            .iter()
+            .zip(&proposal.inner.inputs)
+            .enumerate()
+        {
+            if !input.bip32_derivation.is_empty() || !input.tap_key_origins.is_empty() {
+                return Err(PayjoinError::InputKeypaths { index });
+            }
+            if !input.partial_sigs.is_empty() {
+                return Err(PayjoinError::PartialSignatures { index });
+            }
+            sequences.insert(txin.sequence);
+
+            let original = original_tx
+                .input
+                .iter()
+                .position(|original| original.previous_output == txin.previous_output);
+            let utxo = match original {
+                Some(original) => {
+                    if txin.sequence != original_tx.input[original].sequence {
+                        return Err(PayjoinError::SequenceChanged { index });
+                    }
+                    if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                        return Err(PayjoinError::SenderInputFinalized { index });
+                    }
+                    if input.non_witness_utxo.is_some() || input.witness_utxo.is_some() {
+                        return Err(PayjoinError::SenderInputUtxo { index });
+                    }
+                    self.inner.inputs[original].spent_utxo(&txin.previous_output)
+                }
+                None => {
+                    if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+                        return Err(PayjoinError::ReceiverInputNotFinalized { index });
+                    }
+                    let utxo = input.spent_utxo(&txin.previous_output);
+                    Some(utxo.ok_or(PayjoinError::ReceiverInputMissingUtxo { index })?)
+                }
+            };
+            if let Some(utxo) = utxo {
+                input_types.insert(input_type(&utxo.script_pubkey));
+            }
+        }
+        if sequences.len() > 1 {
+            return Err(PayjoinError::MixedSequences);
+        }
+        if input_types.len() > 1 {
+            return Err(PayjoinError::MixedInputTypes);
+        }
+
+        for (index, txin) in original_tx.input.iter().enumerate() {
+            if !proposal_tx
+                .input
+                .iter()
+                .any(|new_txin| new_txin.previous_output == txin.previous_output)
+            {
+                return Err(PayjoinError::MissingSenderInput { index });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The fee of a `proposal`, whose sender inputs have their utxos looked up
+/// in the `original`.
+fn proposal_fee(
+    original: &Psbt,
+    original_tx: &Transaction,
+    proposal: &Psbt,
+    proposal_tx: &Transaction,
+) -> Result<Amount, PayjoinError> {
+    let mut input_values = Vec::with_capacity(proposal_tx.input.len());
+    for (index, (txin, input)) in proposal_tx
+        .input
+        .iter()
+        .zip(&proposal.inner.inputs)
+        .enumerate()
+    {
+        let utxo = original_tx
+            .input
+            .iter()
+            .position(|original_txin| original_txin.previous_output == txin.previous_output)
+            .map_or_else(
+                || input.spent_utxo(&txin.previous_output),
+                |original_index| {
+                    original.inner.inputs[original_index].spent_utxo(&txin.previous_output)
+                },
+            )
+            .ok_or(PayjoinError::Fee(FeeError::MissingUtxo { index }))?;
+        input_values.push(utxo.value);
+    }
+    let input_value = checked_sum(input_values).map_err(PayjoinError::Fee)?;
+    let output_value = checked_sum(proposal_tx.output.iter().map(|txout| txout.value))
+        .map_err(PayjoinError::Fee)?;
+    input_value
+        .checked_sub(output_value)
+        .ok_or(PayjoinError::Fee(FeeError::NegativeFee))
+}
+
+/// The weight of a finalized input.
+fn input_weight(input: &Input) -> Weight {
+    let script_sig_len = input
+        .final_script_sig
+        .as_ref()
+        .map_or(0, |script| script.len());
+    let script_sig_size = VarInt(script_sig_len as u64).size() + script_sig_len;
+    let witness_size = input
+        .final_script_witness
+        .as_ref()
+        .map_or(0, |witness| encode::serialize(witness).len());
+    Weight::from_wu(INPUT_BASE_WEIGHT + script_sig_size as u64 * 4 + witness_size as u64)
+}
+
+/// The script types inputs of a payjoin may not mix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum InputType {
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    Other,
+}
+
+fn input_type(script_pubkey: &Script) -> InputType {
+    if script_pubkey.is_p2pkh() {
+        InputType::P2pkh
+    } else if script_pubkey.is_p2sh() {
+        InputType::P2sh
+    } else if script_pubkey.is_p2wpkh() {
+        InputType::P2wpkh
+    } else if script_pubkey.is_p2wsh() {
+        InputType::P2wsh
+    } else if script_pubkey.is_p2tr() {
+        InputType::P2tr
+    } else {
+        InputType::Other
+    }
+}
@@ -1,5 +1,12 @@
+use crate::network::Network;
+use crate::prelude::*;
+
+use super::dust::dust_threshold;
+use super::fee::FeePolicy;
 use super::input::Input;
-use super::output::Output;
+use super::output::{Output, MAX_SCRIPT_SIZE};
+use super::silent_payments::DleqProof;
+use super::validation::ValidationReport;
 
 /// A Partially Signed Transaction.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -13,17 +20,18 @@ pub struct PartiallySignedTransactionInner {
     pub version: Version,
     /// A global map from extended public keys to the used key fingerprint and
     /// derivation path as defined by BIP 32.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_hr::key_sources"))]
     pub xpub: BTreeMap<ExtendedPubKey, KeySource>,
     /// Global proprietary key-value pairs.
     #[cfg_attr(
         feature = "serde",
-        serde(with = "crate::serde_utils::btreemap_as_seq_byte_values")
+        serde(with = "super::serde_hr::btreemap_as_seq_hex_values")
     )]
     pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
     /// Unknown global key-value pairs.
     #[cfg_attr(
         feature = "serde",
-        serde(with = "crate::serde_utils::btreemap_as_seq_byte_values")
+        serde(with = "super::serde_hr::btreemap_as_seq_hex_values")
     )]
     pub unknown: BTreeMap<raw::Key, Vec<u8>>,
 
@@ -31,69 +39,225 @@ pub struct PartiallySignedTransactionInner {
     pub inputs: Vec<Input>,
     /// The corresponding key-value map for each output in the unsigned transaction.
     pub outputs: Vec<Output>,
+
+    // Psbtv2 global fields
+    /// 32-bit little endian signed integer representing the
+    /// version number of the transaction being created.
+    pub tx_version: Option<i32>,
+    /// 32-bit little endian unsigned integer representing the transaction locktime
+    /// to use if no inputs specify a required locktime.
+    pub fallback_locktime: Option<u32>,
+    /// 8 bit unsigned integer as a bitfield for various transaction
+    /// modification flags, see [`super::modifiable`].
+    pub tx_modifiable: Option<u8>,
+
+    // Silent payment global fields
+    /// Map of silent payment scan keys to the ECDH share of all inputs.
+    pub sp_ecdh_shares: BTreeMap<secp256k1::PublicKey, secp256k1::PublicKey>,
+    /// Map of silent payment scan keys to the DLEQ proof of the global ECDH share.
+    pub sp_dleq_proofs: BTreeMap<secp256k1::PublicKey, DleqProof>,
+}
+
+/// Checks [`Psbt::add_output_with`] runs on top of the structural ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AddOutputOptions {
+    /// Rejects outputs that are dust at this dust relay fee, see
+    /// [`super::dust::DEFAULT_DUST_RELAY_FEE`].
+    pub reject_dust: Option<FeeRate>,
 }
 
 pub struct Psbt {
-    inner: PartiallySignedTransactionInner,
+    pub(crate) inner: PartiallySignedTransactionInner,
+    /// Fee limits enforced when signing and extracting, not serialized.
+    pub(crate) fee_policy: Option<FeePolicy>,
+    /// The network the psbt is meant for, not serialized.
+    pub(crate) network: Option<Network>,
 }
 
 impl Psbt {
-    pub fn from_inner(psbt: PartiallySignedTransactionInner) -> Result<Psbt<Version>, String> {
-        match validate_psbt_inner(psbt) {
-            Ok(()) => Ok(Psbt { inner: psbt }),
-            Err(err) => Err(err),
-        }
+    /// Validates `psbt` and wraps it, see
+    /// [`PartiallySignedTransactionInner::validate`].
+    pub fn from_inner(psbt: PartiallySignedTransactionInner) -> Result<Psbt, ValidationReport> {
+        Self::validate_psbt_inner(&psbt)?;
+        Ok(Psbt {
+            inner: psbt,
+            fee_policy: None,
+            network: None,
+        })
     }
 
-    fn validate_psbt_inner(psbt: &PartiallySignedTransactionInner) -> Result<(), String> {
-        match psbt.version {
-            Version::PsbtV0 => {
-                // Some code to validate Psbt as a version 0 Psbt
-                // let valid = validate(psbt);
-                if !valid {
-                    Err(String::from("Error parsing psbtv0"))
-                }
-            }
-            Version::Psbtv2 => {
-                // Some code to validate Psbt as a version 2 Psbt
-                // let valid = validate(psbt);
-                if !valid {
-                    Err(String::from("Error parsing psbtv2"))
-                }
-            }
+    fn validate_psbt_inner(psbt: &PartiallySignedTransactionInner) -> Result<(), ValidationReport> {
+        let report = psbt.validate();
+        if report.is_valid() {
+            Ok(())
+        } else {
+            Err(report)
         }
-        Ok(())
     }
 
-    pub fn add_input(&self, input: Input) -> Result<(), String> {
+    /// Appends `input` to a PsbtV2. Fails if the input lacks its outpoint, if
+    /// it uses `SIGHASH_SINGLE` without an output at its index or if the
+    /// `tx_modifiable` flags don't allow adding inputs.
+    pub fn add_input(&mut self, input: Input) -> Result<(), String> {
         // Validate the input according to the version
-        if validate_input(input) {
-            Ok(())
-        } else {
-            Err("Error validating input!")
+        self.validate_input(&input)?;
+        if !self.inputs_modifiable() {
+            return Err(String::from("Inputs of this psbt can't be modified"));
         }
+        self.inner.inputs.push(input);
+        Ok(())
+    }
+
+    /// Appends `output` to a PsbtV2. Fails if the output lacks its amount or
+    /// script, if it would invalidate a `SIGHASH_SINGLE` signature or if the
+    /// `tx_modifiable` flags don't allow adding outputs.
+    ///
+    /// If the psbt's network is set, outputs its nodes wouldn't relay as dust
+    /// are rejected too, see [`AddOutputOptions::for_network`].
+    pub fn add_output(&mut self, output: Output) -> Result<(), String> {
+        let options = self
+            .network
+            .map_or_else(AddOutputOptions::default, AddOutputOptions::for_network);
+        self.add_output_with(output, options)
     }
 
-    pub fn add_output(&self, output: Output) -> Result<(), String> {
+    /// Appends `output` like [`Psbt::add_output`], additionally running the
+    /// checks `options` ask for.
+    pub fn add_output_with(
+        &mut self,
+        output: Output,
+        options: AddOutputOptions,
+    ) -> Result<(), String> {
         // Validate the output according to the version
-        if validate_output(output) {
-            Ok(())
-        } else {
-            Err("Error validating output!")
+        if !self.validate_output(&output) {
+            return Err(String::from("Error validating output!"));
         }
+        if let (Some(dust_relay_fee), Some(amount), Some(script)) =
+            (options.reject_dust, output.amount, output.script.as_ref())
+        {
+            let threshold = dust_threshold(script, dust_relay_fee);
+            if amount < threshold {
+                return Err(format!(
+                    "Output amount {} is below the dust threshold of {}",
+                    amount, threshold
+                ));
+            }
+        }
+        // A SIGHASH_SINGLE signature made without an output at its index
+        // commits to there being none
+        let index = self.inner.outputs.len();
+        if let Some(input) = self.inner.inputs.get(index) {
+            if input.has_sighash_single_signature() {
+                return Err(format!(
+                    "Output {} would invalidate the SIGHASH_SINGLE signature of input {}",
+                    index, index
+                ));
+            }
+        }
+        if !self.outputs_modifiable() {
+            return Err(String::from("Outputs of this psbt can't be modified"));
+        }
+        self.inner.outputs.push(output);
+        Ok(())
     }
 
-    fn validate_input(&self, input: &Input) -> bool {
-        // Code to validate input based on the psbt version
-        true
+    fn validate_input(&self, input: &Input) -> Result<(), String> {
+        // Only a PsbtV2 describes its inputs without an unsigned transaction
+        if self.inner.version != Version::PsbtV2 {
+            return Err(String::from("Inputs can only be added to a PsbtV2"));
+        }
+        let index = self.inner.inputs.len();
+        if let Some(err) = input.version_field_errors(Version::PsbtV2, index).first() {
+            return Err(err.to_string());
+        }
+        if index >= self.inner.outputs.len() && input.uses_sighash_single() {
+            return Err(format!(
+                "Input {} uses SIGHASH_SINGLE without an output at its index",
+                index
+            ));
+        }
+        Ok(())
     }
 
     fn validate_output(&self, output: &Output) -> bool {
-        // Code to validate output based on the psbt version
-        true
+        // Silent payment outputs get their script from the signer
+        self.inner.version == Version::PsbtV2
+            && output
+                .amount
+                .map_or(false, |amount| amount <= Amount::MAX_MONEY)
+            && output
+                .script
+                .as_ref()
+                .map_or(output.is_silent_payment(), |script| {
+                    script.len() <= MAX_SCRIPT_SIZE
+                })
+            && output
+                .validate_silent_payment(self.inner.outputs.len())
+                .is_ok()
     }
 
     pub fn to_inner(self) -> PartiallySignedTransactionInner {
         self.inner
     }
+
+    /// Returns the unsigned transaction described by this psbt.
+    ///
+    /// For a PsbtV0 this is a copy of the global `unsigned_tx`, for a PsbtV2
+    /// the transaction is assembled from the per-input and per-output fields,
+    /// with the locktime resolved by [`Psbt::compute_lock_time`].
+    pub fn unsigned_tx(&self) -> Result<Transaction, String> {
+        match self.inner.version {
+            Version::PsbtV0 => self
+                .inner
+                .unsigned_tx
+                .clone()
+                .ok_or_else(|| String::from("PsbtV0 is missing the unsigned transaction")),
+            Version::PsbtV2 => {
+                let mut input = Vec::with_capacity(self.inner.inputs.len());
+                for (index, psbt_input) in self.inner.inputs.iter().enumerate() {
+                    let txid = psbt_input
+                        .previous_tx_id
+                        .as_ref()
+                        .ok_or_else(|| format!("Input {} is missing the previous txid", index))?
+                        .to_txid();
+                    let vout = psbt_input
+                        .output_index
+                        .ok_or_else(|| format!("Input {} is missing the output index", index))?;
+                    input.push(TxIn {
+                        previous_output: OutPoint::new(txid, vout),
+                        script_sig: ScriptBuf::new(),
+                        sequence: psbt_input.sequence(),
+                        witness: Witness::default(),
+                    });
+                }
+
+                let mut output = Vec::with_capacity(self.inner.outputs.len());
+                for (index, psbt_output) in self.inner.outputs.iter().enumerate() {
+                    let amount = psbt_output
+                        .amount
+                        .ok_or_else(|| format!("Output {} is missing the amount", index))?;
+                    let script = psbt_output
+                        .script
+                        .as_ref()
+                        .ok_or_else(|| format!("Output {} is missing the script", index))?;
+                    output.push(TxOut {
+                        value: amount,
+                        script_pubkey: script.clone(),
+                    });
+                }
+
+                let tx_version = self
+                    .inner
+                    .tx_version
+                    .ok_or_else(|| String::from("PsbtV2 is missing the transaction version"))?;
+
+                Ok(Transaction {
+                    version: transaction::Version(tx_version),
+                    lock_time: self.compute_lock_time().map_err(|err| err.to_string())?,
+                    input,
+                    output,
+                })
+            }
+        }
+    }
 }
@@ -0,0 +1,199 @@
+//! A human readable report of a psbt, for debugging and for pasting into
+//! support tickets and reviews.
+
+use core::fmt;
+
+use crate::address::Address;
+use crate::blockdata::script::Script;
+use crate::prelude::*;
+use crate::psbt::Version;
+use crate::Amount;
+
+use super::analyzer::InputAnalysis;
+use super::fee::checked_sum;
+use super::input::Input;
+use super::iter::InputContext;
+use super::key_origin::OriginKey;
+use super::output::Output;
+use super::poc::Psbt;
+
+impl Psbt {
+    /// Renders a multi-line report of the psbt.
+    ///
+    /// Every input is listed with the outpoint it spends, its value, the
+    /// type of the spent script and how many of the signatures it needs are
+    /// present. Every output is listed with its address (or script without a
+    /// network set), its amount and whether it's change, that is whether one
+    /// of its key origins is on a change branch. Totals and the fee follow.
+    /// Values that can't be determined are shown as `unknown`.
+    pub fn to_pretty_string(&self) -> String {
+        PrettyPsbt(self).to_string()
+    }
+}
+
+struct PrettyPsbt<'a>(&'a Psbt);
+
+impl fmt::Display for PrettyPsbt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let psbt = self.0;
+        let tx = psbt.unsigned_tx().ok();
+        let analysis = psbt.analyze().ok();
+
+        let version = match psbt.inner.version {
+            Version::PsbtV0 => 0,
+            Version::PsbtV2 => 2,
+        };
+        write!(f, "PSBT v{}", version)?;
+        match tx {
+            Some(ref tx) => writeln!(
+                f,
+                ", txid {}, tx version {}, locktime {}",
+                tx.txid(),
+                tx.version.0,
+                tx.lock_time
+            )?,
+            None => writeln!(f)?,
+        }
+
+        writeln!(f, "Inputs ({}):", psbt.inner.inputs.len())?;
+        for context in psbt.iter_inputs() {
+            let input_analysis = analysis
+                .as_ref()
+                .and_then(|analysis| analysis.inputs.get(context.index));
+            write_input(f, &context, input_analysis)?;
+        }
+
+        writeln!(f, "Outputs ({}):", psbt.inner.outputs.len())?;
+        for (index, output) in psbt.inner.outputs.iter().enumerate() {
+            let script = psbt.output_script_pubkey(index);
+            let amount = tx
+                .as_ref()
+                .and_then(|tx| tx.output.get(index))
+                .map(|txout| txout.value);
+            writeln!(f, "  #{} {}", index, Destination(script, psbt))?;
+            writeln!(f, "     amount: {}", Unknown(amount))?;
+            writeln!(
+                f,
+                "     change: {}",
+                if is_change(output) { "yes" } else { "no" }
+            )?;
+        }
+
+        let input_total = psbt
+            .iter_inputs()
+            .map(|context| context.spent_utxo.map(|utxo| utxo.value))
+            .collect::<Option<Vec<Amount>>>()
+            .and_then(|values| checked_sum(values).ok());
+        writeln!(f, "Totals:")?;
+        writeln!(f, "  inputs:  {}", Unknown(input_total))?;
+        writeln!(f, "  outputs: {}", Unknown(psbt.total_output_amount()))?;
+        write!(f, "  fee:     {}", Unknown(psbt.fee().ok()))?;
+        match (psbt.fee(), psbt.estimate_vsize()) {
+            (Ok(fee), Ok(vsize)) if vsize > 0 => writeln!(
+                f,
+                " (~{} vB, {:.2} sat/vB)",
+                vsize,
+                fee.to_sat() as f64 / vsize as f64
+            ),
+            _ => writeln!(f),
+        }
+    }
+}
+
+fn write_input(
+    f: &mut fmt::Formatter<'_>,
+    context: &InputContext,
+    analysis: Option<&InputAnalysis>,
+) -> fmt::Result {
+    writeln!(f, "  #{} {}", context.index, context.previous_output)?;
+    writeln!(
+        f,
+        "     value:  {}",
+        Unknown(context.spent_utxo.map(|utxo| utxo.value))
+    )?;
+    let script_type = context.spent_utxo.map_or("unknown", |utxo| {
+        script_type(&utxo.script_pubkey, context.input)
+    });
+    writeln!(f, "     script: {}", script_type)?;
+    match analysis {
+        Some(analysis) if analysis.is_final => writeln!(f, "     sigs:   finalized"),
+        Some(analysis) => writeln!(
+            f,
+            "     sigs:   {}/{}",
+            analysis.signatures_present,
+            Unknown(analysis.signatures_needed)
+        ),
+        None => writeln!(f, "     sigs:   unknown"),
+    }
+}
+
+/// Names the type of `script_pubkey`, spent by `input`.
+fn script_type(script_pubkey: &Script, input: &Input) -> &'static str {
+    if script_pubkey.is_p2pkh() {
+        "p2pkh"
+    } else if script_pubkey.is_p2sh() {
+        match input.redeem_script {
+            Some(ref script) if script.is_p2wpkh() => "p2sh-p2wpkh",
+            Some(ref script) if script.is_p2wsh() => "p2sh-p2wsh",
+            _ => "p2sh",
+        }
+    } else if script_pubkey.is_p2wpkh() {
+        "p2wpkh"
+    } else if script_pubkey.is_p2wsh() {
+        "p2wsh"
+    } else if script_pubkey.is_p2tr() {
+        "p2tr"
+    } else if script_pubkey.is_op_return() {
+        "op_return"
+    } else if script_pubkey.is_witness_program() {
+        "witness program"
+    } else {
+        "nonstandard"
+    }
+}
+
+/// Returns `true` if one of the key origins of `output` is on a change
+/// branch, see [`OriginKey::is_change`].
+fn is_change(output: &Output) -> bool {
+    let ecdsa = output
+        .bip32_derivation
+        .iter()
+        .map(|(key, (_, path))| OriginKey::Ecdsa(*key, path.clone()));
+    let taproot = output
+        .tap_key_origins
+        .iter()
+        .map(|(key, (_, (_, path)))| OriginKey::Taproot(*key, path.clone()));
+    ecdsa.chain(taproot).any(|key| key.is_change())
+}
+
+/// Displays the address an output pays to on the psbt's network, or its
+/// script if there's no network or address.
+struct Destination<'a>(Option<&'a Script>, &'a Psbt);
+
+impl fmt::Display for Destination<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let script = match self.0 {
+            Some(script) => script,
+            None => return write!(f, "unknown script"),
+        };
+        match self.1.network {
+            Some(network) => match Address::from_script(script, network) {
+                Ok(address) => write!(f, "{}", address),
+                Err(_) => write!(f, "script {}", script),
+            },
+            None => write!(f, "script {}", script),
+        }
+    }
+}
+
+/// Displays a value, or `unknown` if it's `None`.
+struct Unknown<T>(Option<T>);
+
+impl<T: fmt::Display> fmt::Display for Unknown<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(ref value) => fmt::Display::fmt(value, f),
+            None => write!(f, "unknown"),
+        }
+    }
+}
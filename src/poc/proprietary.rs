@@ -0,0 +1,107 @@
+//! Typed access to proprietary key-value pairs.
+//!
+//! Vendors describe a structured proprietary field by implementing
+//! [`ProprietaryExtension`] for its type, then read and write it on the
+//! global map, inputs and outputs without building raw keys by hand.
+
+use crate::prelude::*;
+use crate::psbt::raw;
+
+use super::input::Input;
+use super::output::Output;
+use super::poc::Psbt;
+
+/// A structured field stored in a proprietary key-value pair.
+///
+/// The key is made of [`PREFIX`](Self::PREFIX) and
+/// [`SUBTYPE`](Self::SUBTYPE) without key data, so a map holds at most one
+/// value of each field.
+pub trait ProprietaryExtension: Sized {
+    /// The identifier prefix of the key, usually naming the vendor.
+    const PREFIX: &'static [u8];
+    /// The subtype of the key, telling apart the fields of a vendor.
+    const SUBTYPE: u8;
+    /// The error returned when a value can't be decoded.
+    type Error;
+
+    /// Encodes the field into the value of the pair.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Decodes the field from the value of the pair.
+    fn decode(value: &[u8]) -> Result<Self, Self::Error>;
+
+    /// The key the field is stored under.
+    fn key() -> raw::ProprietaryKey {
+        raw::ProprietaryKey {
+            prefix: Self::PREFIX.to_vec(),
+            subtype: Self::SUBTYPE,
+            key: vec![],
+        }
+    }
+}
+
+impl Psbt {
+    /// Returns the field `T` of the global proprietary map, if set.
+    pub fn get_proprietary<T: ProprietaryExtension>(&self) -> Result<Option<T>, T::Error> {
+        get(&self.inner.proprietary)
+    }
+
+    /// Stores `value` in the global proprietary map, replacing any previous
+    /// value of the field.
+    pub fn insert_proprietary<T: ProprietaryExtension>(&mut self, value: &T) {
+        self.inner.proprietary.insert(T::key(), value.encode());
+    }
+
+    /// Removes the field `T` from the global proprietary map, returning
+    /// whether it was set.
+    pub fn remove_proprietary<T: ProprietaryExtension>(&mut self) -> bool {
+        self.inner.proprietary.remove(&T::key()).is_some()
+    }
+}
+
+impl Input {
+    /// Returns the field `T` of the proprietary map of the input, if set.
+    pub fn get_proprietary<T: ProprietaryExtension>(&self) -> Result<Option<T>, T::Error> {
+        get(&self.proprietary)
+    }
+
+    /// Stores `value` in the proprietary map of the input, replacing any
+    /// previous value of the field.
+    pub fn insert_proprietary<T: ProprietaryExtension>(&mut self, value: &T) {
+        self.proprietary.insert(T::key(), value.encode());
+    }
+
+    /// Removes the field `T` from the proprietary map of the input,
+    /// returning whether it was set.
+    pub fn remove_proprietary<T: ProprietaryExtension>(&mut self) -> bool {
+        self.proprietary.remove(&T::key()).is_some()
+    }
+}
+
+impl Output {
+    /// Returns the field `T` of the proprietary map of the output, if set.
+    pub fn get_proprietary<T: ProprietaryExtension>(&self) -> Result<Option<T>, T::Error> {
+        get(&self.proprietary)
+    }
+
+    /// Stores `value` in the proprietary map of the output, replacing any
+    /// previous value of the field.
+    pub fn insert_proprietary<T: ProprietaryExtension>(&mut self, value: &T) {
+        self.proprietary.insert(T::key(), value.encode());
+    }
+
+    /// Removes the field `T` from the proprietary map of the output,
+    /// returning whether it was set.
+    pub fn remove_proprietary<T: ProprietaryExtension>(&mut self) -> bool {
+        self.proprietary.remove(&T::key()).is_some()
+    }
+}
+
+fn get<T: ProprietaryExtension>(
+    proprietary: &BTreeMap<raw::ProprietaryKey, Vec<u8>>,
+) -> Result<Option<T>, T::Error> {
+    proprietary
+        .get(&T::key())
+        .map(|value| T::decode(value))
+        .transpose()
+}
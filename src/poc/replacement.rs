@@ -0,0 +1,193 @@
+//! Fee bumping of psbts by replace-by-fee, the replacement paying for its
+//! higher fee out of the change output.
+
+use core::fmt;
+
+use crate::prelude::*;
+use crate::{Amount, FeeRate};
+
+use super::dust::{dust_threshold, DEFAULT_DUST_RELAY_FEE};
+use super::fee::FeeError;
+use super::input::Input;
+use super::poc::Psbt;
+use super::validation::ValidationReport;
+
+/// The incremental relay fee of Bitcoin Core, 1 sat/vB. BIP 125 requires a
+/// replacement to pay at least this rate on its own size on top of the fee
+/// of the transaction it replaces.
+pub const DEFAULT_INCREMENTAL_RELAY_FEE: FeeRate = FeeRate::from_sat_per_kwu(250);
+
+/// Errors encountered while building a fee-bumped replacement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BumpFeeError {
+    /// The fee or size of the transaction could not be determined.
+    Fee(FeeError),
+    /// No output has a key on a change branch, see
+    /// [`OriginKey::is_change`](super::key_origin::OriginKey::is_change).
+    NoChangeOutput,
+    /// Several outputs at these indices look like change.
+    AmbiguousChange { outputs: Vec<usize> },
+    /// The fee at the new rate doesn't exceed the current fee by the
+    /// incremental relay fee, as BIP 125 requires.
+    FeeTooLow { min_fee: Amount, new_fee: Amount },
+    /// The change output can't cover the fee increase.
+    InsufficientChange { needed: Amount, available: Amount },
+    /// The change output would be left with less than the dust threshold of
+    /// its script.
+    DustChange { change: Amount, threshold: Amount },
+    /// The replacement failed validation.
+    Invalid(ValidationReport),
+}
+
+impl fmt::Display for BumpFeeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BumpFeeError::Fee(err) => write!(f, "can't determine the fee: {:?}", err),
+            BumpFeeError::NoChangeOutput => write!(f, "no change output to take the fee from"),
+            BumpFeeError::AmbiguousChange { outputs } => {
+                write!(f, "outputs {:?} all look like change", outputs)
+            }
+            BumpFeeError::FeeTooLow { min_fee, new_fee } => write!(
+                f,
+                "fee of {} at the new rate is below the minimum replacement fee of {}",
+                new_fee, min_fee
+            ),
+            BumpFeeError::InsufficientChange { needed, available } => write!(
+                f,
+                "change of {} can't cover the fee increase of {}",
+                available, needed
+            ),
+            BumpFeeError::DustChange { change, threshold } => write!(
+                f,
+                "change of {} would be below the dust threshold of {}",
+                change, threshold
+            ),
+            BumpFeeError::Invalid(report) => write!(f, "invalid replacement: {}", report),
+        }
+    }
+}
+
+impl Psbt {
+    /// Builds a replacement of this psbt paying `new_fee_rate`, the psbt
+    /// itself is left untouched.
+    ///
+    /// All signatures and finalized data are stripped, as they commit to the
+    /// old output amounts, and the increase in fee is taken from the change
+    /// output: the single output with a key on a change branch. Inputs that
+    /// were finalized lose the data their finalization cleared, so they need
+    /// updating before being signed again.
+    ///
+    /// The new fee must exceed the current one by
+    /// [`DEFAULT_INCREMENTAL_RELAY_FEE`] on the size of the replacement, and
+    /// the change must stay above its dust threshold.
+    pub fn bump_fee(&self, new_fee_rate: FeeRate) -> Result<Psbt, BumpFeeError> {
+        let mut replacement = self.replacement();
+        let change_index = replacement.change_output()?;
+        replacement.take_fee_from(change_index, new_fee_rate)?;
+        self.validated(replacement)
+    }
+
+    /// Builds a replacement like [`Psbt::bump_fee`], taking the increase in
+    /// fee from the output at `change_index` instead of detecting the change
+    /// output.
+    pub fn bump_fee_from_output(
+        &self,
+        new_fee_rate: FeeRate,
+        change_index: usize,
+    ) -> Result<Psbt, BumpFeeError> {
+        let mut replacement = self.replacement();
+        replacement.take_fee_from(change_index, new_fee_rate)?;
+        self.validated(replacement)
+    }
+
+    /// A copy of the psbt without any signature.
+    fn replacement(&self) -> Psbt {
+        let mut replacement = Psbt {
+            inner: self.inner.clone(),
+            fee_policy: self.fee_policy,
+            network: self.network,
+        };
+        for input in &mut replacement.inner.inputs {
+            input.clear_signatures();
+        }
+        replacement
+    }
+
+    /// Lowers the output at `change_index` to raise the fee to
+    /// `new_fee_rate`.
+    fn take_fee_from(
+        &mut self,
+        change_index: usize,
+        new_fee_rate: FeeRate,
+    ) -> Result<(), BumpFeeError> {
+        let fee = self.fee().map_err(BumpFeeError::Fee)?;
+        let vsize = self.estimate_vsize().map_err(BumpFeeError::Fee)?;
+        let new_fee = new_fee_rate.fee_vb(vsize).unwrap_or(Amount::MAX);
+        let increment = DEFAULT_INCREMENTAL_RELAY_FEE
+            .fee_vb(vsize)
+            .unwrap_or(Amount::MAX);
+        let min_fee = fee.checked_add(increment).unwrap_or(Amount::MAX);
+        if new_fee < min_fee {
+            return Err(BumpFeeError::FeeTooLow { min_fee, new_fee });
+        }
+
+        let needed = new_fee - fee;
+        let tx = self
+            .unsigned_tx()
+            .map_err(|err| BumpFeeError::Fee(FeeError::UnsignedTx(err)))?;
+        let txout = match tx.output.get(change_index) {
+            Some(txout) => txout,
+            None => return Err(BumpFeeError::NoChangeOutput),
+        };
+        let available = txout.value;
+        let change = available
+            .checked_sub(needed)
+            .ok_or(BumpFeeError::InsufficientChange { needed, available })?;
+        let threshold = dust_threshold(&txout.script_pubkey, DEFAULT_DUST_RELAY_FEE);
+        if change < threshold {
+            return Err(BumpFeeError::DustChange { change, threshold });
+        }
+        self.set_output_amount(change_index, change);
+        Ok(())
+    }
+
+    /// Validates a replacement of the psbt, keeping the psbt's fee policy
+    /// and network.
+    fn validated(&self, replacement: Psbt) -> Result<Psbt, BumpFeeError> {
+        let mut replacement = Psbt::from_inner(replacement.inner).map_err(BumpFeeError::Invalid)?;
+        replacement.fee_policy = self.fee_policy;
+        replacement.network = self.network;
+        Ok(replacement)
+    }
+
+    /// The index of the only output with a key on a change branch.
+    fn change_output(&self) -> Result<usize, BumpFeeError> {
+        let outputs: Vec<usize> = self
+            .inner
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| output.is_change())
+            .map(|(index, _)| index)
+            .collect();
+        match outputs[..] {
+            [] => Err(BumpFeeError::NoChangeOutput),
+            [index] => Ok(index),
+            _ => Err(BumpFeeError::AmbiguousChange { outputs }),
+        }
+    }
+}
+
+impl Input {
+    /// Removes every signature of the input along with its final scriptSig
+    /// and witness.
+    pub fn clear_signatures(&mut self) {
+        self.partial_sigs.clear();
+        self.tap_key_sig = None;
+        self.tap_script_sigs.clear();
+        self.musig2_pub_nonces.clear();
+        self.musig2_partial_sigs.clear();
+        self.final_script_sig = None;
+        self.final_script_witness = None;
+    }
+}
@@ -0,0 +1,222 @@
+//! Proof of reserves psbts as defined by BIP 127.
+//!
+//! A proof spends every UTXO it claims together with a challenge input
+//! committing to a message. The challenge input spends an output that
+//! doesn't exist, so the signed transaction can never be broadcast.
+
+use crate::blockdata::locktime::absolute;
+use crate::blockdata::opcodes::OP_TRUE;
+use crate::blockdata::script::{Builder, ScriptBuf};
+use crate::blockdata::transaction::{self, OutPoint, Sequence, Transaction, TxIn, TxOut};
+use crate::blockdata::witness::Witness;
+use crate::hash_types::{ScriptHash, Txid};
+use crate::hashes::{sha256d, Hash};
+use crate::prelude::*;
+use crate::psbt::Version;
+use crate::Amount;
+
+use super::input::Input;
+use super::output::Output;
+use super::poc::{PartiallySignedTransactionInner, Psbt};
+
+/// The prefix hashed together with the message into the challenge txid.
+const CHALLENGE_PREFIX: &str = "Proof-of-Reserves: ";
+
+/// Errors encountered while creating or verifying a proof of reserves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofOfReservesError {
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+    /// Neither `witness_utxo` nor `non_witness_utxo` is set for the input at
+    /// this index, so its value is unknown.
+    MissingUtxo { index: usize },
+    /// The psbt doesn't spend any reserves.
+    NoReserves,
+    /// The first input has no `por_commitment`.
+    MissingCommitment,
+    /// The first input doesn't spend the challenge outpoint of its
+    /// `por_commitment`.
+    ChallengeMismatch,
+    /// A reserve input spends the challenge outpoint.
+    ChallengeSpent { index: usize },
+    /// The proof must have a single output paying to the unspendable script.
+    InvalidOutput,
+    /// The output value differs from the sum of the reserves.
+    AmountMismatch { reserves: Amount, output: Amount },
+    /// The sum of the reserves overflows.
+    AmountOverflow,
+}
+
+impl Psbt {
+    /// Creates a proof of reserves for the UTXOs spent by this psbt, committing
+    /// to `message`.
+    ///
+    /// The returned PsbtV0 spends the challenge input first, followed by every
+    /// input of this psbt, into a single unspendable output of their total
+    /// value. The challenge input is already finalized, the reserve inputs
+    /// keep their UTXO, script and key origin data but not their signatures,
+    /// which commit to a different transaction.
+    pub fn create_proof_of_reserves(&self, message: &str) -> Result<Psbt, ProofOfReservesError> {
+        let tx = self
+            .unsigned_tx()
+            .map_err(ProofOfReservesError::UnsignedTx)?;
+        if tx.input.is_empty() {
+            return Err(ProofOfReservesError::NoReserves);
+        }
+
+        let mut reserves = Amount::ZERO;
+        let mut input = vec![TxIn {
+            previous_output: challenge_outpoint(message),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::default(),
+        }];
+        let mut inputs = vec![challenge_input(message)];
+        for (index, (txin, psbt_input)) in tx.input.iter().zip(&self.inner.inputs).enumerate() {
+            let utxo = psbt_input
+                .spent_utxo(&txin.previous_output)
+                .ok_or(ProofOfReservesError::MissingUtxo { index })?;
+            reserves = reserves
+                .checked_add(utxo.value)
+                .ok_or(ProofOfReservesError::AmountOverflow)?;
+
+            input.push(TxIn {
+                previous_output: txin.previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::default(),
+            });
+            inputs.push(reserve_input(psbt_input));
+        }
+
+        let unsigned_tx = Transaction {
+            version: transaction::Version(1),
+            lock_time: absolute::LockTime::ZERO,
+            input,
+            output: vec![TxOut {
+                value: reserves,
+                script_pubkey: unspendable_script(),
+            }],
+        };
+
+        Ok(Psbt {
+            inner: PartiallySignedTransactionInner {
+                unsigned_tx: Some(unsigned_tx),
+                version: Version::PsbtV0,
+                xpub: self.inner.xpub.clone(),
+                proprietary: Default::default(),
+                unknown: Default::default(),
+                inputs,
+                outputs: vec![Output::default()],
+                tx_version: None,
+                fallback_locktime: None,
+                tx_modifiable: None,
+                sp_ecdh_shares: Default::default(),
+                sp_dleq_proofs: Default::default(),
+            },
+            fee_policy: None,
+            network: self.network,
+        })
+    }
+
+    /// Checks that this psbt is a well formed proof of reserves and returns
+    /// the amount it proves.
+    ///
+    /// Only the structure of the proof is checked: the challenge input must
+    /// spend the outpoint derived from its `por_commitment`, and the single
+    /// output must pay the sum of the reserves to the unspendable script.
+    /// Signatures are not checked, use [`Psbt::verify_partial_sigs`] or
+    /// verify the scripts of the finalized inputs for that.
+    pub fn verify_proof_of_reserves(&self) -> Result<Amount, ProofOfReservesError> {
+        let tx = self
+            .unsigned_tx()
+            .map_err(ProofOfReservesError::UnsignedTx)?;
+
+        let message = self
+            .inner
+            .inputs
+            .first()
+            .and_then(|input| input.por_commitment.as_ref())
+            .ok_or(ProofOfReservesError::MissingCommitment)?;
+        let challenge = challenge_outpoint(message);
+        if tx.input[0].previous_output != challenge {
+            return Err(ProofOfReservesError::ChallengeMismatch);
+        }
+        if tx.input.len() < 2 {
+            return Err(ProofOfReservesError::NoReserves);
+        }
+
+        let mut reserves = Amount::ZERO;
+        for (index, (txin, input)) in tx.input.iter().zip(&self.inner.inputs).enumerate().skip(1) {
+            if txin.previous_output == challenge {
+                return Err(ProofOfReservesError::ChallengeSpent { index });
+            }
+            let utxo = input
+                .spent_utxo(&txin.previous_output)
+                .ok_or(ProofOfReservesError::MissingUtxo { index })?;
+            reserves = reserves
+                .checked_add(utxo.value)
+                .ok_or(ProofOfReservesError::AmountOverflow)?;
+        }
+
+        match tx.output.as_slice() {
+            [txout] if txout.script_pubkey == unspendable_script() => {
+                if txout.value != reserves {
+                    return Err(ProofOfReservesError::AmountMismatch {
+                        reserves,
+                        output: txout.value,
+                    });
+                }
+            }
+            _ => return Err(ProofOfReservesError::InvalidOutput),
+        }
+
+        Ok(reserves)
+    }
+}
+
+/// Returns the outpoint spent by the challenge input, output 0 of the
+/// transaction whose txid is the double SHA256 of the prefixed message.
+fn challenge_outpoint(message: &str) -> OutPoint {
+    let mut preimage = CHALLENGE_PREFIX.as_bytes().to_vec();
+    preimage.extend(message.as_bytes());
+    let hash = sha256d::Hash::hash(&preimage);
+    OutPoint::new(Txid::from_raw_hash(hash), 0)
+}
+
+/// The challenge input, finalized with an empty scriptSig since it spends an
+/// `OP_TRUE` output.
+fn challenge_input(message: &str) -> Input {
+    Input {
+        witness_utxo: Some(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_TRUE).into_script(),
+        }),
+        final_script_sig: Some(ScriptBuf::new()),
+        por_commitment: Some(String::from(message)),
+        ..Default::default()
+    }
+}
+
+/// Copies `input` into a proof of reserves, dropping its signatures and final
+/// scripts.
+fn reserve_input(input: &Input) -> Input {
+    let mut input = input.clone();
+    input.partial_sigs.clear();
+    input.tap_key_sig = None;
+    input.tap_script_sigs.clear();
+    input.final_script_sig = None;
+    input.final_script_witness = None;
+    input.por_commitment = None;
+    input.previous_tx_id = None;
+    input.output_index = None;
+    input.sequence = None;
+    input.required_time_locktime = None;
+    input.required_height_locktime = None;
+    input
+}
+
+/// A P2SH output with an all-zero script hash, which nobody can spend.
+fn unspendable_script() -> ScriptBuf {
+    ScriptBuf::new_p2sh(&ScriptHash::all_zeros())
+}
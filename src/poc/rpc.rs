@@ -0,0 +1,145 @@
+//! Helpers talking to a Bitcoin Core node over JSON-RPC, for coordinators
+//! running next to a node: filling utxos from its wallet or transaction
+//! index, checking the final transaction with `testmempoolaccept` and
+//! broadcasting it.
+
+use core::fmt;
+
+use serde_json::Value;
+
+use crate::blockdata::transaction::Transaction;
+use crate::consensus::encode;
+use crate::hash_types::Txid;
+use crate::hashes::hex::FromHex;
+use crate::prelude::*;
+
+use super::extractor::ExtractTxError;
+use super::poc::Psbt;
+use super::utxo::UtxoProvider;
+
+/// Errors encountered while talking to the node.
+#[derive(Debug)]
+pub enum RpcError {
+    /// The request failed or the node answered with an error.
+    Rpc(jsonrpc::Error),
+    /// The node answered with something that isn't what was asked for.
+    InvalidResponse(String),
+    /// The final transaction could not be extracted from the psbt.
+    Extract(ExtractTxError),
+    /// The node wouldn't accept the transaction into its mempool, for this
+    /// reason.
+    Rejected(String),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Rpc(err) => write!(f, "rpc error: {}", err),
+            RpcError::InvalidResponse(err) => write!(f, "invalid rpc response: {}", err),
+            RpcError::Extract(err) => write!(f, "could not extract the transaction: {:?}", err),
+            RpcError::Rejected(reason) => write!(f, "transaction rejected: {}", reason),
+        }
+    }
+}
+
+impl From<jsonrpc::Error> for RpcError {
+    fn from(err: jsonrpc::Error) -> Self {
+        RpcError::Rpc(err)
+    }
+}
+
+/// A JSON-RPC connection to a Bitcoin Core node.
+pub struct NodeClient {
+    client: jsonrpc::Client,
+}
+
+impl NodeClient {
+    /// Connects to the node at `url`, such as `http://127.0.0.1:8332`,
+    /// authenticating with `user` and `pass` if given.
+    pub fn new(url: &str, user: Option<String>, pass: Option<String>) -> Result<Self, RpcError> {
+        let client = jsonrpc::Client::simple_http(url, user, pass)
+            .map_err(|err| RpcError::Rpc(err.into()))?;
+        Ok(NodeClient { client })
+    }
+
+    /// Fetches the transaction with id `txid` from the wallet of the node
+    /// with `gettransaction`, falling back to `getrawtransaction`, which
+    /// needs `-txindex` for confirmed transactions the wallet doesn't know.
+    pub fn get_transaction(&self, txid: &Txid) -> Result<Transaction, RpcError> {
+        let hex = match self.call("gettransaction", &[txid.to_string().into()]) {
+            Ok(wallet_tx) => wallet_tx.get("hex").cloned().unwrap_or_default(),
+            Err(_) => self.call(
+                "getrawtransaction",
+                &[txid.to_string().into(), false.into()],
+            )?,
+        };
+        let hex = hex
+            .as_str()
+            .ok_or_else(|| RpcError::InvalidResponse(String::from("missing transaction hex")))?;
+        decode_tx(hex)
+    }
+
+    /// Runs `testmempoolaccept` on `tx`, failing with
+    /// [`RpcError::Rejected`] if the node wouldn't accept it.
+    pub fn test_mempool_accept(&self, tx: &Transaction) -> Result<(), RpcError> {
+        let raw_tx = encode::serialize_hex(tx);
+        let results = self.call("testmempoolaccept", &[vec![raw_tx].into()])?;
+        let result = results
+            .get(0)
+            .ok_or_else(|| RpcError::InvalidResponse(String::from("no result")))?;
+        if result.get("allowed").and_then(Value::as_bool) == Some(true) {
+            return Ok(());
+        }
+        let reason = result
+            .get("reject-reason")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown reason");
+        Err(RpcError::Rejected(reason.to_string()))
+    }
+
+    /// Broadcasts `tx` with `sendrawtransaction`, returning its txid.
+    pub fn send_transaction(&self, tx: &Transaction) -> Result<Txid, RpcError> {
+        let raw_tx = encode::serialize_hex(tx);
+        let txid = self.call("sendrawtransaction", &[raw_tx.into()])?;
+        txid.as_str()
+            .and_then(|txid| txid.parse().ok())
+            .ok_or_else(|| RpcError::InvalidResponse(format!("invalid txid {}", txid)))
+    }
+
+    fn call(&self, method: &str, args: &[Value]) -> Result<Value, RpcError> {
+        let params = jsonrpc::arg(args);
+        let request = self.client.build_request(method, Some(&params));
+        let response = self.client.send_request(request)?;
+        Ok(response.result()?)
+    }
+}
+
+/// Looks up previous transactions on the node, see
+/// [`NodeClient::get_transaction`]. Rpc errors are reported as unknown
+/// transactions.
+impl UtxoProvider for NodeClient {
+    fn get_tx(&self, txid: &Txid) -> Option<Transaction> {
+        self.get_transaction(txid).ok()
+    }
+}
+
+impl Psbt {
+    /// Extracts the final transaction and checks with the node that it would
+    /// be accepted into its mempool, without broadcasting it.
+    pub fn test_mempool_accept(&self, node: &NodeClient) -> Result<(), RpcError> {
+        let tx = self.extract_tx().map_err(RpcError::Extract)?;
+        node.test_mempool_accept(&tx)
+    }
+
+    /// Extracts the final transaction and broadcasts it through the node,
+    /// returning its txid.
+    pub fn broadcast(&self, node: &NodeClient) -> Result<Txid, RpcError> {
+        let tx = self.extract_tx().map_err(RpcError::Extract)?;
+        node.send_transaction(&tx)
+    }
+}
+
+fn decode_tx(hex: &str) -> Result<Transaction, RpcError> {
+    let bytes = Vec::from_hex(hex).map_err(|err| RpcError::InvalidResponse(err.to_string()))?;
+    encode::deserialize(&bytes).map_err(|err| RpcError::InvalidResponse(err.to_string()))
+}
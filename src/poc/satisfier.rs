@@ -0,0 +1,240 @@
+//! Miniscript-backed satisfaction of psbt inputs.
+//!
+//! Used by the finalizer for scripts its hand-rolled satisfiers don't cover,
+//! such as timelocked or hashlocked policies.
+
+use miniscript::{hash256, Legacy, Miniscript, Preimage32, Satisfier, Segwitv0, Tap};
+
+use crate::blockdata::locktime::{absolute, relative};
+use crate::blockdata::script::{Builder, PushBytes, Script, ScriptBuf};
+use crate::blockdata::transaction::Sequence;
+use crate::blockdata::witness::Witness;
+use crate::crypto::ecdsa;
+use crate::crypto::key::{PublicKey, XOnlyPublicKey};
+use crate::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use crate::prelude::*;
+use crate::taproot::{self, LeafVersion, TapLeafHash};
+
+use super::finalizer::{push_redeem_script, FinalizeError};
+use super::input::Input;
+
+/// Looks up signatures, preimages and timelock data of a single input.
+struct InputSatisfier<'a> {
+    input: &'a Input,
+    sequence: Sequence,
+    lock_time: absolute::LockTime,
+}
+
+impl InputSatisfier<'_> {
+    fn preimage<H: Ord>(preimages: &BTreeMap<H, Vec<u8>>, hash: &H) -> Option<Preimage32> {
+        preimages
+            .get(hash)
+            .and_then(|preimage| preimage.as_slice().try_into().ok())
+    }
+
+    fn sha256(&self, hash: &sha256::Hash) -> Option<Preimage32> {
+        Self::preimage(&self.input.sha256_preimages, hash)
+    }
+
+    fn hash256(&self, hash: &hash256::Hash) -> Option<Preimage32> {
+        let hash = sha256d::Hash::from_byte_array(hash.to_byte_array());
+        Self::preimage(&self.input.hash256_preimages, &hash)
+    }
+
+    fn ripemd160(&self, hash: &ripemd160::Hash) -> Option<Preimage32> {
+        Self::preimage(&self.input.ripemd160_preimages, hash)
+    }
+
+    fn hash160(&self, hash: &hash160::Hash) -> Option<Preimage32> {
+        Self::preimage(&self.input.hash160_preimages, hash)
+    }
+
+    fn older(&self, lock_time: relative::LockTime) -> bool {
+        self.sequence
+            .to_relative_lock_time()
+            .map_or(false, |sequence_lock_time| {
+                lock_time.is_implied_by(sequence_lock_time)
+            })
+    }
+
+    fn after(&self, lock_time: absolute::LockTime) -> bool {
+        self.sequence.enables_absolute_lock_time() && lock_time.is_implied_by(self.lock_time)
+    }
+}
+
+impl Satisfier<PublicKey> for InputSatisfier<'_> {
+    fn lookup_ecdsa_sig(&self, public_key: &PublicKey) -> Option<ecdsa::Signature> {
+        self.input.partial_sigs.get(public_key).copied()
+    }
+
+    fn lookup_sha256(&self, hash: &sha256::Hash) -> Option<Preimage32> {
+        self.sha256(hash)
+    }
+
+    fn lookup_hash256(&self, hash: &hash256::Hash) -> Option<Preimage32> {
+        self.hash256(hash)
+    }
+
+    fn lookup_ripemd160(&self, hash: &ripemd160::Hash) -> Option<Preimage32> {
+        self.ripemd160(hash)
+    }
+
+    fn lookup_hash160(&self, hash: &hash160::Hash) -> Option<Preimage32> {
+        self.hash160(hash)
+    }
+
+    fn check_older(&self, lock_time: relative::LockTime) -> bool {
+        self.older(lock_time)
+    }
+
+    fn check_after(&self, lock_time: absolute::LockTime) -> bool {
+        self.after(lock_time)
+    }
+}
+
+impl Satisfier<XOnlyPublicKey> for InputSatisfier<'_> {
+    fn lookup_tap_leaf_script_sig(
+        &self,
+        public_key: &XOnlyPublicKey,
+        leaf_hash: &TapLeafHash,
+    ) -> Option<taproot::Signature> {
+        self.input
+            .tap_script_sigs
+            .get(&(*public_key, *leaf_hash))
+            .copied()
+    }
+
+    fn lookup_sha256(&self, hash: &sha256::Hash) -> Option<Preimage32> {
+        self.sha256(hash)
+    }
+
+    fn lookup_hash256(&self, hash: &hash256::Hash) -> Option<Preimage32> {
+        self.hash256(hash)
+    }
+
+    fn lookup_ripemd160(&self, hash: &ripemd160::Hash) -> Option<Preimage32> {
+        self.ripemd160(hash)
+    }
+
+    fn lookup_hash160(&self, hash: &hash160::Hash) -> Option<Preimage32> {
+        self.hash160(hash)
+    }
+
+    fn check_older(&self, lock_time: relative::LockTime) -> bool {
+        self.older(lock_time)
+    }
+
+    fn check_after(&self, lock_time: absolute::LockTime) -> bool {
+        self.after(lock_time)
+    }
+}
+
+/// Builds the final scriptSig and witness of an input spending
+/// `script_pubkey` by lifting its redeem, witness or tap leaf scripts into
+/// miniscript and producing a minimal satisfaction.
+pub(crate) fn satisfy(
+    input: &Input,
+    script_pubkey: &Script,
+    sequence: Sequence,
+    lock_time: absolute::LockTime,
+    index: usize,
+) -> Result<(Option<ScriptBuf>, Option<Witness>), FinalizeError> {
+    let satisfier = InputSatisfier {
+        input,
+        sequence,
+        lock_time,
+    };
+
+    if script_pubkey.is_p2tr() {
+        let mut witness = satisfy_taproot(&satisfier, index)?;
+        if let Some(ref annex) = input.tap_annex {
+            witness.push(annex);
+        }
+        return Ok((None, Some(witness)));
+    }
+
+    let redeem_script = if script_pubkey.is_p2sh() {
+        Some(
+            input
+                .redeem_script
+                .as_ref()
+                .ok_or(FinalizeError::MissingRedeemScript { index })?,
+        )
+    } else {
+        None
+    };
+
+    let is_p2wsh = redeem_script
+        .map_or(script_pubkey, |script| script.as_script())
+        .is_p2wsh();
+    if is_p2wsh {
+        let witness_script = input
+            .witness_script
+            .as_ref()
+            .ok_or(FinalizeError::MissingWitnessScript { index })?;
+        let miniscript = Miniscript::<PublicKey, Segwitv0>::parse(witness_script)
+            .map_err(|err| FinalizeError::Miniscript(err.to_string()))?;
+        let mut stack = miniscript
+            .satisfy(&satisfier)
+            .map_err(|err| FinalizeError::Miniscript(err.to_string()))?;
+        stack.push(witness_script.to_bytes());
+
+        let script_sig = redeem_script.map(|script| push_redeem_script(script));
+        return Ok((script_sig, Some(Witness::from_slice(&stack))));
+    }
+
+    let redeem_script = redeem_script.ok_or(FinalizeError::UnsupportedScript { index })?;
+    let miniscript = Miniscript::<PublicKey, Legacy>::parse(redeem_script)
+        .map_err(|err| FinalizeError::Miniscript(err.to_string()))?;
+    let stack = miniscript
+        .satisfy(&satisfier)
+        .map_err(|err| FinalizeError::Miniscript(err.to_string()))?;
+
+    let mut builder = Builder::new();
+    for element in stack {
+        let element = <&PushBytes>::try_from(element.as_slice())
+            .map_err(|err| FinalizeError::Miniscript(err.to_string()))?;
+        builder = builder.push_slice(element);
+    }
+    let redeem_script = <&PushBytes>::try_from(redeem_script.as_bytes())
+        .expect("redeem scripts are limited to 520 bytes");
+    let script_sig = builder.push_slice(redeem_script).into_script();
+    Ok((Some(script_sig), None))
+}
+
+/// Picks the tap leaf with the smallest satisfying witness, preferring a key
+/// path spend when `tap_key_sig` is present.
+fn satisfy_taproot(satisfier: &InputSatisfier, index: usize) -> Result<Witness, FinalizeError> {
+    if let Some(signature) = satisfier.input.tap_key_sig {
+        let mut witness = Witness::new();
+        witness.push(signature.to_vec());
+        return Ok(witness);
+    }
+
+    let mut best: Option<Witness> = None;
+    for (control_block, (script, leaf_version)) in &satisfier.input.tap_scripts {
+        if *leaf_version != LeafVersion::TapScript {
+            continue;
+        }
+        let miniscript = match Miniscript::<XOnlyPublicKey, Tap>::parse(script) {
+            Ok(miniscript) => miniscript,
+            Err(_) => continue,
+        };
+        let mut stack = match miniscript.satisfy(satisfier) {
+            Ok(stack) => stack,
+            Err(_) => continue,
+        };
+        stack.push(script.to_bytes());
+        stack.push(control_block.serialize());
+
+        let witness = Witness::from_slice(&stack);
+        if best
+            .as_ref()
+            .map_or(true, |best| witness.size() < best.size())
+        {
+            best = Some(witness);
+        }
+    }
+
+    best.ok_or(FinalizeError::MissingSignature { index })
+}
@@ -0,0 +1,89 @@
+//! Wiping of the secret keys signing derives along the way.
+//!
+//! Child keys derived from an xpriv, tweaked taproot keys and keys copied
+//! out of caller buffers are held in a [`Wiped`] while they're used. With
+//! the `zeroize` feature their bytes are overwritten when it's dropped, so
+//! no copy made by this crate outlives the signing call. The keys the caller
+//! passes in stay the caller's to wipe.
+
+use core::ops::Deref;
+
+use secp256k1::{Keypair, Secp256k1, SecretKey, Signing};
+#[cfg(feature = "zeroize")]
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::bip32::{self, DerivationPath, Xpriv};
+
+/// Secret material that can be overwritten in place.
+pub(crate) trait Erase {
+    /// Overwrites the secret with a fixed value.
+    #[cfg_attr(not(feature = "zeroize"), allow(dead_code))]
+    fn erase(&mut self);
+}
+
+// secp256k1 keeps the bytes of its keys private, its own erasure is the only
+// way to overwrite them
+impl Erase for SecretKey {
+    fn erase(&mut self) {
+        self.non_secure_erase();
+    }
+}
+
+impl Erase for Keypair {
+    fn erase(&mut self) {
+        self.non_secure_erase();
+    }
+}
+
+impl Erase for Xpriv {
+    fn erase(&mut self) {
+        self.private_key.non_secure_erase();
+        #[cfg(feature = "zeroize")]
+        {
+            let chain_code: &mut [u8] = self.chain_code.as_mut();
+            chain_code.zeroize();
+        }
+    }
+}
+
+/// A secret wiped when dropped, with the `zeroize` feature.
+pub(crate) struct Wiped<T: Erase>(pub(crate) T);
+
+impl<T: Erase> Deref for Wiped<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<T: Erase> Zeroize for Wiped<T> {
+    fn zeroize(&mut self) {
+        self.0.erase();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<T: Erase> ZeroizeOnDrop for Wiped<T> {}
+
+impl<T: Erase> Drop for Wiped<T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "zeroize")]
+        self.zeroize();
+    }
+}
+
+/// Derives the child of `xpriv` at `path` one step at a time, so every
+/// intermediate key is wiped as soon as the next one is derived, unlike
+/// with [`Xpriv::derive_priv`].
+pub(crate) fn derive_wiped<C: Signing>(
+    xpriv: &Xpriv,
+    secp: &Secp256k1<C>,
+    path: &DerivationPath,
+) -> Result<Wiped<Xpriv>, bip32::Error> {
+    path.into_iter()
+        .try_fold(Wiped(*xpriv), |parent, child_number| {
+            parent.ckd_priv(secp, *child_number).map(Wiped)
+        })
+}
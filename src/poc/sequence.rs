@@ -0,0 +1,181 @@
+//! Sequence number helpers of psbt inputs.
+
+use core::fmt;
+
+use crate::blockdata::locktime::relative;
+use crate::blockdata::opcodes::all::OP_CSV;
+use crate::blockdata::script::{read_scriptint, Instruction, Script};
+use crate::blockdata::transaction::Sequence;
+use crate::prelude::*;
+use crate::psbt::Version;
+
+use super::input::Input;
+use super::poc::Psbt;
+
+/// Errors encountered while checking the sequence of an input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceError {
+    /// The sequence doesn't enable relative locktimes, but the input
+    /// requires one.
+    RelativeLockTimeDisabled { required: relative::LockTime },
+    /// The relative locktime of the sequence doesn't satisfy the requirement,
+    /// it's either too short or of the wrong unit.
+    RelativeLockTimeNotSatisfied {
+        required: relative::LockTime,
+        sequence: Sequence,
+    },
+}
+
+/// Errors encountered while signaling replaceability.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RbfError {
+    /// The input at this index is signed, its signatures commit to the
+    /// sequence that would have to change.
+    SignedInput { index: usize },
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+}
+
+impl fmt::Display for RbfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RbfError::SignedInput { index } => write!(
+                f,
+                "input {} is signed, its signatures commit to the sequences",
+                index
+            ),
+            RbfError::UnsignedTx(err) => write!(f, "invalid unsigned transaction: {}", err),
+        }
+    }
+}
+
+impl Input {
+    /// Returns the sequence of the input, `0xffffffff` if none is set.
+    pub fn sequence(&self) -> Sequence {
+        self.sequence.unwrap_or(Sequence::MAX)
+    }
+
+    /// Returns `true` if the input signals replaceability as defined by
+    /// BIP 125.
+    pub fn is_rbf(&self) -> bool {
+        self.sequence().is_rbf()
+    }
+
+    /// Returns `true` if the sequence of the input is interpreted as a
+    /// BIP 68 relative locktime.
+    pub fn enables_relative_locktime(&self) -> bool {
+        self.sequence().is_relative_lock_time()
+    }
+
+    /// Sets the sequence to a relative locktime of `height` blocks.
+    pub fn set_relative_height(&mut self, height: u16) {
+        self.sequence = Some(Sequence::from_height(height));
+    }
+
+    /// Sets the sequence to a relative locktime of `intervals` times 512
+    /// seconds.
+    pub fn set_relative_512_second_intervals(&mut self, intervals: u16) {
+        self.sequence = Some(Sequence::from_512_second_intervals(intervals));
+    }
+
+    /// Checks that the sequence of the input satisfies the relative locktime
+    /// `required`, as an `OP_CHECKSEQUENCEVERIFY` of that value would.
+    pub fn check_relative_lock_time(
+        &self,
+        required: relative::LockTime,
+    ) -> Result<(), SequenceError> {
+        let sequence = self.sequence();
+        match sequence.to_relative_lock_time() {
+            Some(lock_time) if required.is_implied_by(lock_time) => Ok(()),
+            Some(_) => Err(SequenceError::RelativeLockTimeNotSatisfied { required, sequence }),
+            None => Err(SequenceError::RelativeLockTimeDisabled { required }),
+        }
+    }
+
+    /// Returns the relative locktimes checked by `OP_CHECKSEQUENCEVERIFY` in
+    /// the input's redeem, witness and tap leaf scripts.
+    ///
+    /// Scripts with several spending paths may only require some of them, so
+    /// callers pick the ones of the path they intend to satisfy.
+    pub fn script_relative_lock_times(&self) -> Vec<relative::LockTime> {
+        let leaf_scripts = self.tap_scripts.values().map(|(script, _)| script);
+        self.redeem_script
+            .iter()
+            .chain(self.witness_script.iter())
+            .chain(leaf_scripts)
+            .flat_map(|script| csv_lock_times(script))
+            .collect()
+    }
+}
+
+/// Returns the values pushed right before each `OP_CHECKSEQUENCEVERIFY` of
+/// `script` that encode a relative locktime.
+fn csv_lock_times(script: &Script) -> Vec<relative::LockTime> {
+    let instructions = match script.instructions().collect::<Result<Vec<_>, _>>() {
+        Ok(instructions) => instructions,
+        Err(_) => return Vec::new(),
+    };
+
+    instructions
+        .windows(2)
+        .filter_map(|window| match window {
+            [push, Instruction::Op(OP_CSV)] => {
+                let value = match push {
+                    Instruction::PushBytes(bytes) => read_scriptint(bytes.as_bytes()).ok()?,
+                    Instruction::Op(op) => op.decode_pushnum()? as i64,
+                };
+                let sequence = Sequence::from_consensus(u32::try_from(value).ok()?);
+                sequence.to_relative_lock_time()
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+impl Psbt {
+    /// Returns `true` if the transaction signals replaceability as defined by
+    /// BIP 125, that is if any input does.
+    pub fn is_rbf_signaled(&self) -> bool {
+        self.unsigned_tx().map_or(false, |tx| {
+            tx.input.iter().any(|txin| txin.sequence.is_rbf())
+        })
+    }
+
+    /// Makes every input signal replaceability, in the unsigned transaction
+    /// of a PsbtV0 or the `sequence` fields of a PsbtV2.
+    ///
+    /// Inputs already signaling keep their sequence, so relative locktimes
+    /// are preserved, the others get `0xfffffffd`. Fails without changing
+    /// anything if a sequence has to change while any input is signed, as
+    /// signatures commit to the sequences.
+    pub fn signal_rbf(&mut self) -> Result<(), RbfError> {
+        let tx = self.unsigned_tx().map_err(RbfError::UnsignedTx)?;
+        let to_signal: Vec<usize> = tx
+            .input
+            .iter()
+            .enumerate()
+            .filter(|(_, txin)| !txin.sequence.is_rbf())
+            .map(|(index, _)| index)
+            .collect();
+        if to_signal.is_empty() {
+            return Ok(());
+        }
+        if let Some(index) = self.inner.inputs.iter().position(Input::is_signed) {
+            return Err(RbfError::SignedInput { index });
+        }
+
+        for index in to_signal {
+            match self.inner.version {
+                Version::PsbtV0 => {
+                    if let Some(ref mut tx) = self.inner.unsigned_tx {
+                        tx.input[index].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+                    }
+                }
+                Version::PsbtV2 => {
+                    self.inner.inputs[index].sequence = Some(Sequence::ENABLE_RBF_NO_LOCKTIME);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+//! Human-readable serde representations of psbt fields, used through
+//! `#[serde(with = "...")]`.
+//!
+//! With a human-readable serializer such as JSON, scripts and byte values are
+//! written as hex, extended public keys in base58 and key origins as a
+//! fingerprint with a derivation path like `m/84'/0'/0'`. Txids already
+//! serialize as strings. Binary serializers keep the compact representations
+//! of [`crate::serde_utils`].
+
+use core::fmt;
+use core::str::FromStr;
+
+use actual_serde::de::Error as _;
+use actual_serde::ser::{SerializeMap, SerializeSeq};
+use actual_serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::bip32::{DerivationPath, Fingerprint, KeySource};
+use crate::blockdata::script::ScriptBuf;
+use crate::hashes::hex::{DisplayHex, FromHex};
+use crate::prelude::*;
+
+/// A key origin as written by human-readable serializers.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "actual_serde")]
+struct ReadableKeySource {
+    fingerprint: String,
+    path: String,
+}
+
+impl ReadableKeySource {
+    fn new((fingerprint, path): &KeySource) -> Self {
+        ReadableKeySource {
+            fingerprint: fingerprint.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    fn into_key_source<E: actual_serde::de::Error>(self) -> Result<KeySource, E> {
+        let fingerprint = Fingerprint::from_str(&self.fingerprint).map_err(E::custom)?;
+        let path = DerivationPath::from_str(&self.path).map_err(E::custom)?;
+        Ok((fingerprint, path))
+    }
+}
+
+/// An optional script, as hex when human-readable.
+pub mod opt_script {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        script: &Option<ScriptBuf>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return script.serialize(serializer);
+        }
+        script
+            .as_ref()
+            .map(|script| script.as_bytes().to_lower_hex_string())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<ScriptBuf>, D::Error> {
+        if !deserializer.is_human_readable() {
+            return Option::<ScriptBuf>::deserialize(deserializer);
+        }
+        Option::<String>::deserialize(deserializer)?
+            .map(|hex| ScriptBuf::from_hex(&hex).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+/// Keys mapped to their origin, written as a map from the key's string form
+/// (base58 for extended keys, hex for public keys) to its origin when
+/// human-readable.
+pub mod key_sources {
+    use super::*;
+
+    pub fn serialize<S, K>(map: &BTreeMap<K, KeySource>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        K: Serialize + fmt::Display + Ord,
+    {
+        if !serializer.is_human_readable() {
+            return crate::serde_utils::btreemap_as_seq::serialize(map, serializer);
+        }
+        let mut entries = serializer.serialize_map(Some(map.len()))?;
+        for (key, key_source) in map {
+            entries.serialize_entry(&key.to_string(), &ReadableKeySource::new(key_source))?;
+        }
+        entries.end()
+    }
+
+    pub fn deserialize<'de, D, K>(deserializer: D) -> Result<BTreeMap<K, KeySource>, D::Error>
+    where
+        D: Deserializer<'de>,
+        K: Deserialize<'de> + FromStr + Ord,
+        K::Err: fmt::Display,
+    {
+        if !deserializer.is_human_readable() {
+            return crate::serde_utils::btreemap_as_seq::deserialize(deserializer);
+        }
+        BTreeMap::<String, ReadableKeySource>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, key_source)| {
+                let key = K::from_str(&key).map_err(D::Error::custom)?;
+                Ok((key, key_source.into_key_source()?))
+            })
+            .collect()
+    }
+}
+
+/// A map with byte values, written with hex values when human-readable.
+pub mod btreemap_hex_values {
+    use super::*;
+
+    pub fn serialize<S, K>(map: &BTreeMap<K, Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        K: Serialize + Ord,
+    {
+        if !serializer.is_human_readable() {
+            return crate::serde_utils::btreemap_byte_values::serialize(map, serializer);
+        }
+        let mut entries = serializer.serialize_map(Some(map.len()))?;
+        for (key, value) in map {
+            entries.serialize_entry(key, &value.to_lower_hex_string())?;
+        }
+        entries.end()
+    }
+
+    pub fn deserialize<'de, D, K>(deserializer: D) -> Result<BTreeMap<K, Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+        K: Deserialize<'de> + Ord,
+    {
+        if !deserializer.is_human_readable() {
+            return crate::serde_utils::btreemap_byte_values::deserialize(deserializer);
+        }
+        BTreeMap::<K, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, hex)| Ok((key, Vec::from_hex(&hex).map_err(D::Error::custom)?)))
+            .collect()
+    }
+}
+
+/// A map with byte values whose keys can't be map keys, written as a
+/// sequence of key and hex value pairs when human-readable.
+pub mod btreemap_as_seq_hex_values {
+    use super::*;
+
+    pub fn serialize<S, K>(map: &BTreeMap<K, Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        K: Serialize + Ord,
+    {
+        if !serializer.is_human_readable() {
+            return crate::serde_utils::btreemap_as_seq_byte_values::serialize(map, serializer);
+        }
+        let mut pairs = serializer.serialize_seq(Some(map.len()))?;
+        for (key, value) in map {
+            pairs.serialize_element(&(key, value.to_lower_hex_string()))?;
+        }
+        pairs.end()
+    }
+
+    pub fn deserialize<'de, D, K>(deserializer: D) -> Result<BTreeMap<K, Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+        K: Deserialize<'de> + Ord,
+    {
+        if !deserializer.is_human_readable() {
+            return crate::serde_utils::btreemap_as_seq_byte_values::deserialize(deserializer);
+        }
+        Vec::<(K, String)>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, hex)| Ok((key, Vec::from_hex(&hex).map_err(D::Error::custom)?)))
+            .collect()
+    }
+}
@@ -0,0 +1,1535 @@
+//! Serialization of psbts into the BIP-174 key-value map format, including
+//! the PsbtV2 fields of BIP-370.
+
+use core::fmt;
+
+use crate::bip32::ExtendedPubKey;
+use crate::blockdata::script::ScriptBuf;
+use crate::blockdata::transaction::Sequence;
+use crate::consensus::encode::{self, VarInt};
+use crate::hash_types::Txid;
+use crate::hashes::hex::DisplayHex;
+#[cfg(feature = "std")]
+use crate::io::{self, Read, Write};
+use crate::prelude::sync::Arc;
+use crate::prelude::*;
+use crate::psbt::raw;
+use crate::psbt::serialize::{Deserialize, Serialize};
+use crate::psbt::PsbtSighashType;
+use crate::psbt::Version;
+use crate::taproot::TAPROOT_ANNEX_PREFIX;
+use crate::Amount;
+
+use super::input::{Input, PreviousTxId};
+use super::musig::{self, PartialSig, PubNonce};
+use super::output::Output;
+use super::poc::{PartiallySignedTransactionInner, Psbt};
+use super::silent_payments::{DleqProof, SilentPaymentInfo};
+use super::validation::ValidationReport;
+
+/// The magic bytes every serialized psbt starts with, `psbt` followed by 0xff.
+pub const PSBT_MAGIC: [u8; 5] = [0x70, 0x73, 0x62, 0x74, 0xff];
+
+pub(crate) const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+pub(crate) const PSBT_GLOBAL_XPUB: u8 = 0x01;
+pub(crate) const PSBT_GLOBAL_TX_VERSION: u8 = 0x02;
+pub(crate) const PSBT_GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+pub(crate) const PSBT_GLOBAL_INPUT_COUNT: u8 = 0x04;
+pub(crate) const PSBT_GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+pub(crate) const PSBT_GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+pub(crate) const PSBT_GLOBAL_SP_ECDH_SHARE: u8 = 0x07;
+pub(crate) const PSBT_GLOBAL_SP_DLEQ: u8 = 0x08;
+pub(crate) const PSBT_GLOBAL_VERSION: u8 = 0xfb;
+pub(crate) const PSBT_GLOBAL_PROPRIETARY: u8 = 0xfc;
+
+pub(crate) const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+pub(crate) const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+pub(crate) const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+pub(crate) const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+pub(crate) const PSBT_IN_REDEEM_SCRIPT: u8 = 0x04;
+pub(crate) const PSBT_IN_WITNESS_SCRIPT: u8 = 0x05;
+pub(crate) const PSBT_IN_BIP32_DERIVATION: u8 = 0x06;
+pub(crate) const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+pub(crate) const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+pub(crate) const PSBT_IN_POR_COMMITMENT: u8 = 0x09;
+pub(crate) const PSBT_IN_RIPEMD160: u8 = 0x0a;
+pub(crate) const PSBT_IN_SHA256: u8 = 0x0b;
+pub(crate) const PSBT_IN_HASH160: u8 = 0x0c;
+pub(crate) const PSBT_IN_HASH256: u8 = 0x0d;
+pub(crate) const PSBT_IN_PREVIOUS_TXID: u8 = 0x0e;
+pub(crate) const PSBT_IN_OUTPUT_INDEX: u8 = 0x0f;
+pub(crate) const PSBT_IN_SEQUENCE: u8 = 0x10;
+pub(crate) const PSBT_IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+pub(crate) const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+pub(crate) const PSBT_IN_TAP_KEY_SIG: u8 = 0x13;
+pub(crate) const PSBT_IN_TAP_SCRIPT_SIG: u8 = 0x14;
+pub(crate) const PSBT_IN_TAP_LEAF_SCRIPT: u8 = 0x15;
+pub(crate) const PSBT_IN_TAP_BIP32_DERIVATION: u8 = 0x16;
+pub(crate) const PSBT_IN_TAP_INTERNAL_KEY: u8 = 0x17;
+pub(crate) const PSBT_IN_TAP_MERKLE_ROOT: u8 = 0x18;
+/// The key type of the draft proposal adding the annex to psbts.
+pub(crate) const PSBT_IN_TAP_ANNEX: u8 = 0x19;
+pub(crate) const PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS: u8 = 0x1a;
+pub(crate) const PSBT_IN_MUSIG2_PUB_NONCE: u8 = 0x1b;
+pub(crate) const PSBT_IN_MUSIG2_PARTIAL_SIG: u8 = 0x1c;
+pub(crate) const PSBT_IN_SP_ECDH_SHARE: u8 = 0x1d;
+pub(crate) const PSBT_IN_SP_DLEQ: u8 = 0x1e;
+pub(crate) const PSBT_IN_PROPRIETARY: u8 = 0xfc;
+
+pub(crate) const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
+pub(crate) const PSBT_OUT_WITNESS_SCRIPT: u8 = 0x01;
+pub(crate) const PSBT_OUT_BIP32_DERIVATION: u8 = 0x02;
+pub(crate) const PSBT_OUT_AMOUNT: u8 = 0x03;
+pub(crate) const PSBT_OUT_SCRIPT: u8 = 0x04;
+pub(crate) const PSBT_OUT_TAP_INTERNAL_KEY: u8 = 0x05;
+pub(crate) const PSBT_OUT_TAP_TREE: u8 = 0x06;
+pub(crate) const PSBT_OUT_TAP_BIP32_DERIVATION: u8 = 0x07;
+pub(crate) const PSBT_OUT_MUSIG2_PARTICIPANT_PUBKEYS: u8 = 0x08;
+pub(crate) const PSBT_OUT_SP_V0_INFO: u8 = 0x09;
+pub(crate) const PSBT_OUT_SP_V0_LABEL: u8 = 0x0a;
+pub(crate) const PSBT_OUT_PROPRIETARY: u8 = 0xfc;
+
+/// Errors encountered while deserializing a psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes don't start with [`PSBT_MAGIC`].
+    InvalidMagic,
+    /// The bytes ended before the psbt was complete.
+    UnexpectedEof,
+    /// The bytes go on after the last map of the psbt.
+    TrailingBytes,
+    /// Reading the psbt from a stream failed.
+    #[cfg(feature = "std")]
+    Io(io::ErrorKind),
+    /// A key has key data although its type doesn't allow any, or lacks key
+    /// data its type requires.
+    InvalidKey(raw::Key),
+    /// The value of a key couldn't be decoded.
+    InvalidValue { key: raw::Key, reason: String },
+    /// A field required to know the number of inputs and outputs is missing.
+    MissingField(&'static str),
+    /// The psbt declares `inputs` inputs and `outputs` outputs, but is
+    /// followed by `maps` input and output maps.
+    CountMismatch {
+        inputs: usize,
+        outputs: usize,
+        maps: usize,
+    },
+    /// A compact size isn't encoded in the fewest bytes possible, only
+    /// rejected by strict parsing.
+    NonMinimalCompactSize,
+    /// The psbt goes beyond one of the [`ParseLimits`] it was parsed with.
+    LimitExceeded {
+        limit: &'static str,
+        max: usize,
+        actual: usize,
+    },
+    /// The key, given with its type byte, appears more than once in `map`.
+    DuplicateKey { map: MapLocation, key: Vec<u8> },
+    /// The bytes are a valid psbt but differ from its canonical
+    /// serialization, only checked by [`Psbt::deserialize_canonical`].
+    NotCanonical,
+    /// The psbt is well formed but failed validation.
+    Invalid(ValidationReport),
+    /// The unsigned transaction can't be built from the fields of a
+    /// [`PsbtView`](super::view::PsbtView).
+    UnsignedTx(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidMagic => write!(f, "invalid psbt magic bytes"),
+            DecodeError::UnexpectedEof => write!(f, "unexpected end of psbt"),
+            DecodeError::TrailingBytes => write!(f, "bytes after the end of the psbt"),
+            #[cfg(feature = "std")]
+            DecodeError::Io(kind) => write!(f, "failed to read the psbt: {:?}", kind),
+            DecodeError::InvalidKey(key) => write!(f, "invalid key {}", key),
+            DecodeError::InvalidValue { key, reason } => {
+                write!(f, "invalid value of key {}: {}", key, reason)
+            }
+            DecodeError::MissingField(field) => write!(f, "missing {}", field),
+            DecodeError::CountMismatch {
+                inputs,
+                outputs,
+                maps,
+            } => write!(
+                f,
+                "expected {} input and {} output maps, found {} maps",
+                inputs, outputs, maps
+            ),
+            DecodeError::NonMinimalCompactSize => write!(f, "non-minimal compact size"),
+            DecodeError::LimitExceeded { limit, max, actual } => {
+                write!(f, "{} {} exceeds the limit of {}", limit, actual, max)
+            }
+            DecodeError::DuplicateKey { map, key } => {
+                write!(f, "duplicate key {:x} in {}", key.as_hex(), map)
+            }
+            DecodeError::NotCanonical => write!(f, "psbt isn't canonically serialized"),
+            DecodeError::Invalid(report) => write!(f, "invalid psbt: {}", report),
+            DecodeError::UnsignedTx(reason) => {
+                write!(f, "can't build the unsigned transaction: {}", reason)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for DecodeError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => DecodeError::UnexpectedEof,
+            kind => DecodeError::Io(kind),
+        }
+    }
+}
+
+/// How strictly [`Psbt::deserialize_with`] treats data a well-formed psbt
+/// doesn't contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Rejects unknown psbt versions, sighash types no signature can use and
+    /// non-minimally encoded compact sizes. Lenient parsing keeps such version
+    /// and sighash pairs in `unknown` and accepts the compact sizes.
+    pub strict: bool,
+    /// The caps on what the psbt may make the parser allocate.
+    pub limits: ParseLimits,
+}
+
+impl ParseOptions {
+    /// Options rejecting anything a well-formed psbt doesn't contain, for
+    /// coordinators acting on the psbt.
+    pub fn strict() -> Self {
+        ParseOptions {
+            strict: true,
+            limits: ParseLimits::default(),
+        }
+    }
+
+    /// Options preserving whatever can be preserved, for tools archiving or
+    /// inspecting psbts.
+    pub fn lenient() -> Self {
+        ParseOptions {
+            strict: false,
+            limits: ParseLimits::default(),
+        }
+    }
+
+    /// Replaces the limits of the options.
+    pub fn with_limits(mut self, limits: ParseLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions::strict()
+    }
+}
+
+/// Caps enforced while deserializing, so services accepting psbts from
+/// untrusted parties can bound the memory a crafted psbt makes them use.
+///
+/// No limit is enforced by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// The maximum number of inputs.
+    pub max_inputs: usize,
+    /// The maximum number of outputs.
+    pub max_outputs: usize,
+    /// The maximum number of key and value bytes kept in the `unknown` maps
+    /// of the psbt, its inputs and its outputs together.
+    pub max_unknown_bytes: usize,
+    /// The maximum size of the serialized psbt.
+    pub max_size: usize,
+}
+
+impl ParseLimits {
+    /// Limits that don't restrict anything.
+    pub fn none() -> Self {
+        ParseLimits {
+            max_inputs: usize::MAX,
+            max_outputs: usize::MAX,
+            max_unknown_bytes: usize::MAX,
+            max_size: usize::MAX,
+        }
+    }
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits::none()
+    }
+}
+
+/// A key-value map of a psbt, such as the one a decoding error occurred in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapLocation {
+    /// The global map.
+    Global,
+    /// The map of the input at this index.
+    Input(usize),
+    /// The map of the output at this index.
+    Output(usize),
+}
+
+impl fmt::Display for MapLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapLocation::Global => write!(f, "global map"),
+            MapLocation::Input(index) => write!(f, "input {}", index),
+            MapLocation::Output(index) => write!(f, "output {}", index),
+        }
+    }
+}
+
+impl Psbt {
+    /// Serializes the psbt into the BIP-174 binary format.
+    ///
+    /// The input and output counts of a PsbtV2 are always derived from its
+    /// input and output maps.
+    pub fn serialize(&self) -> Vec<u8> {
+        self.inner.serialize()
+    }
+
+    /// Serializes the psbt like [`Psbt::serialize`], with the pairs of every
+    /// map sorted by their key bytes.
+    ///
+    /// Compact sizes are always minimally encoded, so equal psbts serialize
+    /// to the same bytes regardless of the order their fields were parsed or
+    /// set in, which makes the result suitable for hashing and deduplication.
+    pub fn serialize_canonical(&self) -> Vec<u8> {
+        self.inner.serialize_canonical()
+    }
+
+    /// Serializes the psbt like [`Psbt::serialize`], writing one map at a
+    /// time to `writer`. Returns the number of bytes written.
+    #[cfg(feature = "std")]
+    pub fn consensus_encode<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        self.inner.consensus_encode(writer)
+    }
+
+    /// Deserializes a psbt from the BIP-174 binary format and validates it.
+    ///
+    /// The input and output counts, declared by the unsigned transaction of a
+    /// PsbtV0 or by the global count fields of a PsbtV2, must match the number
+    /// of maps that follow the global map, with nothing after the last one.
+    pub fn deserialize(bytes: &[u8]) -> Result<Psbt, DecodeError> {
+        Psbt::deserialize_with(bytes, ParseOptions::default())
+    }
+
+    /// Deserializes and validates a psbt like [`Psbt::deserialize`], parsing
+    /// as strictly as `options` ask for.
+    pub fn deserialize_with(bytes: &[u8], options: ParseOptions) -> Result<Psbt, DecodeError> {
+        let inner = PartiallySignedTransactionInner::deserialize(bytes, options)?;
+        Psbt::from_inner(inner).map_err(DecodeError::Invalid)
+    }
+
+    /// Deserializes and validates a psbt like [`Psbt::deserialize`], and
+    /// fails unless re-serializing it with [`Psbt::serialize_canonical`]
+    /// gives back exactly `bytes`.
+    pub fn deserialize_canonical(bytes: &[u8]) -> Result<Psbt, DecodeError> {
+        let psbt = Psbt::deserialize(bytes)?;
+        if psbt.serialize_canonical() != bytes {
+            return Err(DecodeError::NotCanonical);
+        }
+        Ok(psbt)
+    }
+
+    /// Deserializes and validates a psbt like [`Psbt::deserialize`], reading
+    /// it from `reader` up to the end of its last map.
+    ///
+    /// Exactly as many input and output maps as the global map declares are
+    /// read, anything after them is left in `reader`, so the psbt can be
+    /// followed by other data or read from a stream that stays open.
+    #[cfg(feature = "std")]
+    pub fn consensus_decode<R: Read + ?Sized>(reader: &mut R) -> Result<Psbt, DecodeError> {
+        Psbt::consensus_decode_with(reader, ParseOptions::default())
+    }
+
+    /// Reads a psbt from `reader` like [`Psbt::consensus_decode`], parsing as
+    /// strictly as `options` ask for.
+    #[cfg(feature = "std")]
+    pub fn consensus_decode_with<R: Read + ?Sized>(
+        reader: &mut R,
+        options: ParseOptions,
+    ) -> Result<Psbt, DecodeError> {
+        let inner = PartiallySignedTransactionInner::decode_from(IoSource(reader), options)?;
+        Psbt::from_inner(inner).map_err(DecodeError::Invalid)
+    }
+}
+
+impl PartiallySignedTransactionInner {
+    /// Serializes the psbt without validating it first, see
+    /// [`Psbt::serialize`].
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut bytes = PSBT_MAGIC.to_vec();
+        for pairs in self.maps() {
+            bytes.extend(encode_map(&pairs));
+        }
+        bytes
+    }
+
+    /// Serializes the psbt with sorted maps, see
+    /// [`Psbt::serialize_canonical`].
+    pub(crate) fn serialize_canonical(&self) -> Vec<u8> {
+        let mut bytes = PSBT_MAGIC.to_vec();
+        for mut pairs in self.maps() {
+            pairs.sort_by(|a, b| a.key.cmp(&b.key));
+            bytes.extend(encode_map(&pairs));
+        }
+        bytes
+    }
+
+    /// Writes the psbt to `writer` without validating it first, see
+    /// [`Psbt::consensus_encode`].
+    #[cfg(feature = "std")]
+    pub(crate) fn consensus_encode<W: Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, io::Error> {
+        writer.write_all(&PSBT_MAGIC)?;
+        let mut len = PSBT_MAGIC.len();
+
+        for pairs in self.maps() {
+            let map = encode_map(&pairs);
+            writer.write_all(&map)?;
+            len += map.len();
+        }
+
+        Ok(len)
+    }
+
+    /// Deserializes a psbt without validating it, see [`Psbt::deserialize`].
+    pub(crate) fn deserialize(
+        mut bytes: &[u8],
+        options: ParseOptions,
+    ) -> Result<Self, DecodeError> {
+        let psbt = Self::decode_from(&mut bytes, options)?;
+        if !bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(psbt)
+    }
+
+    /// Reads a psbt from `source` without validating it, stopping after the
+    /// last of the maps its global map declares.
+    fn decode_from<S: Source>(source: S, options: ParseOptions) -> Result<Self, DecodeError> {
+        let limits = options.limits;
+        let mut reader = Reader::new(source, options);
+        if reader.read_bytes(PSBT_MAGIC.len())? != PSBT_MAGIC {
+            return Err(DecodeError::InvalidMagic);
+        }
+
+        let mut psbt = PartiallySignedTransactionInner {
+            unsigned_tx: None,
+            version: Version::PsbtV0,
+            xpub: Default::default(),
+            proprietary: Default::default(),
+            unknown: Default::default(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            tx_version: None,
+            fallback_locktime: None,
+            tx_modifiable: None,
+            sp_ecdh_shares: Default::default(),
+            sp_dleq_proofs: Default::default(),
+        };
+        let mut input_count = None;
+        let mut output_count = None;
+        for pair in reader.read_map(MapLocation::Global)? {
+            match pair.key.type_value {
+                PSBT_GLOBAL_INPUT_COUNT => {
+                    input_count = Some((decode_count(&pair)?, pair));
+                }
+                PSBT_GLOBAL_OUTPUT_COUNT => {
+                    output_count = Some((decode_count(&pair)?, pair));
+                }
+                _ => psbt.insert_pair(pair, options)?,
+            }
+        }
+        let (input_count, output_count) = match psbt.unsigned_tx {
+            Some(ref tx) => {
+                check_count(input_count.as_ref(), tx.input.len())?;
+                check_count(output_count.as_ref(), tx.output.len())?;
+                // PsbtV0 doesn't define the counts, carry them untouched
+                for (_, pair) in input_count.into_iter().chain(output_count) {
+                    psbt.unknown.insert(pair.key, pair.value);
+                }
+                (tx.input.len(), tx.output.len())
+            }
+            None => (
+                input_count
+                    .ok_or(DecodeError::MissingField("input count"))?
+                    .0,
+                output_count
+                    .ok_or(DecodeError::MissingField("output count"))?
+                    .0,
+            ),
+        };
+        let mut unknown_bytes = unknown_size(&psbt.unknown);
+        check_limit("unknown bytes", limits.max_unknown_bytes, unknown_bytes)?;
+        check_limit("inputs", limits.max_inputs, input_count)?;
+        check_limit("outputs", limits.max_outputs, output_count)?;
+
+        for index in 0..input_count {
+            let mut input = Input::default();
+            for pair in reader.read_map(MapLocation::Input(index))? {
+                input.insert_pair(pair, options)?;
+            }
+            unknown_bytes = unknown_bytes.saturating_add(unknown_size(&input.unknown));
+            check_limit("unknown bytes", limits.max_unknown_bytes, unknown_bytes)?;
+            psbt.inputs.push(input);
+        }
+        for index in 0..output_count {
+            let mut output = Output::default();
+            for pair in reader.read_map(MapLocation::Output(index))? {
+                output.insert_pair(pair)?;
+            }
+            unknown_bytes = unknown_bytes.saturating_add(unknown_size(&output.unknown));
+            check_limit("unknown bytes", limits.max_unknown_bytes, unknown_bytes)?;
+            psbt.outputs.push(output);
+        }
+
+        Ok(psbt)
+    }
+
+    /// The pairs of the global map, followed by those of every input and
+    /// output map.
+    fn maps(&self) -> impl Iterator<Item = Vec<raw::Pair>> + '_ {
+        core::iter::once(self.pairs())
+            .chain(self.inputs.iter().map(Input::pairs))
+            .chain(self.outputs.iter().map(Output::pairs))
+    }
+
+    pub(crate) fn pairs(&self) -> Vec<raw::Pair> {
+        let mut pairs = Vec::new();
+
+        if let Some(ref tx) = self.unsigned_tx {
+            push_pair(
+                &mut pairs,
+                PSBT_GLOBAL_UNSIGNED_TX,
+                vec![],
+                encode::serialize(tx),
+            );
+        }
+        for (xpub, key_source) in &self.xpub {
+            push_pair(
+                &mut pairs,
+                PSBT_GLOBAL_XPUB,
+                xpub.encode().to_vec(),
+                key_source.serialize(),
+            );
+        }
+        if let Some(tx_version) = self.tx_version {
+            push_pair(
+                &mut pairs,
+                PSBT_GLOBAL_TX_VERSION,
+                vec![],
+                tx_version.to_le_bytes().to_vec(),
+            );
+        }
+        if let Some(locktime) = self.fallback_locktime {
+            let value = locktime.to_le_bytes().to_vec();
+            push_pair(&mut pairs, PSBT_GLOBAL_FALLBACK_LOCKTIME, vec![], value);
+        }
+        if self.version == Version::PsbtV2 {
+            let input_count = encode::serialize(&VarInt(self.inputs.len() as u64));
+            push_pair(&mut pairs, PSBT_GLOBAL_INPUT_COUNT, vec![], input_count);
+            let output_count = encode::serialize(&VarInt(self.outputs.len() as u64));
+            push_pair(&mut pairs, PSBT_GLOBAL_OUTPUT_COUNT, vec![], output_count);
+        }
+        if let Some(flags) = self.tx_modifiable {
+            push_pair(&mut pairs, PSBT_GLOBAL_TX_MODIFIABLE, vec![], vec![flags]);
+        }
+        push_ecdh_shares(
+            &mut pairs,
+            PSBT_GLOBAL_SP_ECDH_SHARE,
+            PSBT_GLOBAL_SP_DLEQ,
+            &self.sp_ecdh_shares,
+            &self.sp_dleq_proofs,
+        );
+        if self.version == Version::PsbtV2 {
+            push_pair(
+                &mut pairs,
+                PSBT_GLOBAL_VERSION,
+                vec![],
+                2u32.to_le_bytes().to_vec(),
+            );
+        }
+        push_proprietary_and_unknown(&mut pairs, &self.proprietary, &self.unknown);
+
+        pairs
+    }
+
+    pub(crate) fn insert_pair(
+        &mut self,
+        pair: raw::Pair,
+        options: ParseOptions,
+    ) -> Result<(), DecodeError> {
+        let raw::Pair { key, value } = pair;
+        match key.type_value {
+            PSBT_GLOBAL_UNSIGNED_TX => {
+                expect_no_key_data(&key)?;
+                let tx = encode::deserialize(&value).map_err(|err| invalid_value(&key, err))?;
+                self.unsigned_tx = Some(tx);
+            }
+            PSBT_GLOBAL_XPUB => {
+                let xpub =
+                    ExtendedPubKey::decode(&key.key).map_err(|err| invalid_value(&key, err))?;
+                self.xpub.insert(xpub, decode(&key, &value)?);
+            }
+            PSBT_GLOBAL_TX_VERSION => {
+                expect_no_key_data(&key)?;
+                self.tx_version = Some(i32::from_le_bytes(decode_array(&key, &value)?));
+            }
+            PSBT_GLOBAL_FALLBACK_LOCKTIME => {
+                expect_no_key_data(&key)?;
+                self.fallback_locktime = Some(u32::from_le_bytes(decode_array(&key, &value)?));
+            }
+            PSBT_GLOBAL_TX_MODIFIABLE => {
+                expect_no_key_data(&key)?;
+                let [flags] = decode_array(&key, &value)?;
+                self.tx_modifiable = Some(flags);
+            }
+            PSBT_GLOBAL_SP_ECDH_SHARE => {
+                self.sp_ecdh_shares
+                    .insert(decode_sp_scan_key(&key)?, decode_sp_share(&key, &value)?);
+            }
+            PSBT_GLOBAL_SP_DLEQ => {
+                self.sp_dleq_proofs
+                    .insert(decode_sp_scan_key(&key)?, decode_dleq_proof(&key, &value)?);
+            }
+            PSBT_GLOBAL_VERSION => {
+                expect_no_key_data(&key)?;
+                self.version = match u32::from_le_bytes(decode_array(&key, &value)?) {
+                    0 => Version::PsbtV0,
+                    2 => Version::PsbtV2,
+                    version if options.strict => {
+                        let reason = format!("unsupported psbt version {}", version);
+                        return Err(DecodeError::InvalidValue { key, reason });
+                    }
+                    _ => {
+                        self.unknown.insert(key, value);
+                        return Ok(());
+                    }
+                };
+            }
+            PSBT_GLOBAL_PROPRIETARY => insert_proprietary(&mut self.proprietary, key, value)?,
+            _ => {
+                self.unknown.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Input {
+    pub(crate) fn pairs(&self) -> Vec<raw::Pair> {
+        let mut pairs = Vec::new();
+
+        if let Some(ref tx) = self.non_witness_utxo {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_NON_WITNESS_UTXO,
+                vec![],
+                encode::serialize(tx.as_ref()),
+            );
+        }
+        if let Some(ref utxo) = self.witness_utxo {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_WITNESS_UTXO,
+                vec![],
+                encode::serialize(utxo),
+            );
+        }
+        for (public_key, signature) in &self.partial_sigs {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_PARTIAL_SIG,
+                public_key.to_bytes(),
+                signature.serialize(),
+            );
+        }
+        if let Some(sighash_type) = self.sighash_type {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_SIGHASH_TYPE,
+                vec![],
+                sighash_type.serialize(),
+            );
+        }
+        if let Some(ref script) = self.redeem_script {
+            push_pair(&mut pairs, PSBT_IN_REDEEM_SCRIPT, vec![], script.to_bytes());
+        }
+        if let Some(ref script) = self.witness_script {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_WITNESS_SCRIPT,
+                vec![],
+                script.to_bytes(),
+            );
+        }
+        for (public_key, key_source) in &self.bip32_derivation {
+            let key = public_key.serialize().to_vec();
+            push_pair(
+                &mut pairs,
+                PSBT_IN_BIP32_DERIVATION,
+                key,
+                key_source.serialize(),
+            );
+        }
+        if let Some(ref script) = self.final_script_sig {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_FINAL_SCRIPTSIG,
+                vec![],
+                script.to_bytes(),
+            );
+        }
+        if let Some(ref witness) = self.final_script_witness {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_FINAL_SCRIPTWITNESS,
+                vec![],
+                witness.serialize(),
+            );
+        }
+        if let Some(ref commitment) = self.por_commitment {
+            let value = commitment.as_bytes().to_vec();
+            push_pair(&mut pairs, PSBT_IN_POR_COMMITMENT, vec![], value);
+        }
+        for (hash, preimage) in &self.ripemd160_preimages {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_RIPEMD160,
+                hash.serialize(),
+                preimage.clone(),
+            );
+        }
+        for (hash, preimage) in &self.sha256_preimages {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_SHA256,
+                hash.serialize(),
+                preimage.clone(),
+            );
+        }
+        for (hash, preimage) in &self.hash160_preimages {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_HASH160,
+                hash.serialize(),
+                preimage.clone(),
+            );
+        }
+        for (hash, preimage) in &self.hash256_preimages {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_HASH256,
+                hash.serialize(),
+                preimage.clone(),
+            );
+        }
+        if let Some(ref previous_tx_id) = self.previous_tx_id {
+            let value = previous_tx_id.to_txid().serialize();
+            push_pair(&mut pairs, PSBT_IN_PREVIOUS_TXID, vec![], value);
+        }
+        if let Some(output_index) = self.output_index {
+            let value = output_index.to_le_bytes().to_vec();
+            push_pair(&mut pairs, PSBT_IN_OUTPUT_INDEX, vec![], value);
+        }
+        if let Some(sequence) = self.sequence {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_SEQUENCE,
+                vec![],
+                sequence.to_consensus_u32().to_le_bytes().to_vec(),
+            );
+        }
+        if let Some(locktime) = self.required_time_locktime {
+            let value = locktime.to_le_bytes().to_vec();
+            push_pair(&mut pairs, PSBT_IN_REQUIRED_TIME_LOCKTIME, vec![], value);
+        }
+        if let Some(locktime) = self.required_height_locktime {
+            let value = locktime.to_le_bytes().to_vec();
+            push_pair(&mut pairs, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, vec![], value);
+        }
+        if let Some(ref signature) = self.tap_key_sig {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_TAP_KEY_SIG,
+                vec![],
+                signature.serialize(),
+            );
+        }
+        for ((public_key, leaf_hash), signature) in &self.tap_script_sigs {
+            let key = (*public_key, *leaf_hash).serialize();
+            push_pair(
+                &mut pairs,
+                PSBT_IN_TAP_SCRIPT_SIG,
+                key,
+                signature.serialize(),
+            );
+        }
+        for (control_block, script_leaf) in &self.tap_scripts {
+            let key = control_block.serialize();
+            push_pair(
+                &mut pairs,
+                PSBT_IN_TAP_LEAF_SCRIPT,
+                key,
+                script_leaf.serialize(),
+            );
+        }
+        for (public_key, origin) in &self.tap_key_origins {
+            let key = public_key.serialize().to_vec();
+            push_pair(
+                &mut pairs,
+                PSBT_IN_TAP_BIP32_DERIVATION,
+                key,
+                origin.serialize(),
+            );
+        }
+        if let Some(ref internal_key) = self.tap_internal_key {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_TAP_INTERNAL_KEY,
+                vec![],
+                internal_key.serialize().to_vec(),
+            );
+        }
+        if let Some(ref merkle_root) = self.tap_merkle_root {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_TAP_MERKLE_ROOT,
+                vec![],
+                merkle_root.serialize(),
+            );
+        }
+        if let Some(ref annex) = self.tap_annex {
+            push_pair(&mut pairs, PSBT_IN_TAP_ANNEX, vec![], annex.clone());
+        }
+        for (aggregate, participants) in &self.musig2_participant_pubkeys {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS,
+                aggregate.serialize().to_vec(),
+                musig::serialize_participants(participants),
+            );
+        }
+        for (session_key, nonce) in &self.musig2_pub_nonces {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_MUSIG2_PUB_NONCE,
+                musig::serialize_session_key(session_key),
+                nonce.0.to_vec(),
+            );
+        }
+        for (session_key, signature) in &self.musig2_partial_sigs {
+            push_pair(
+                &mut pairs,
+                PSBT_IN_MUSIG2_PARTIAL_SIG,
+                musig::serialize_session_key(session_key),
+                signature.0.to_vec(),
+            );
+        }
+        push_ecdh_shares(
+            &mut pairs,
+            PSBT_IN_SP_ECDH_SHARE,
+            PSBT_IN_SP_DLEQ,
+            &self.sp_ecdh_shares,
+            &self.sp_dleq_proofs,
+        );
+        #[cfg(feature = "elements")]
+        self.elements.push_pairs(&mut pairs);
+        push_proprietary_and_unknown(&mut pairs, &self.proprietary, &self.unknown);
+
+        pairs
+    }
+
+    pub(crate) fn insert_pair(
+        &mut self,
+        pair: raw::Pair,
+        options: ParseOptions,
+    ) -> Result<(), DecodeError> {
+        let raw::Pair { key, value } = pair;
+        match key.type_value {
+            PSBT_IN_NON_WITNESS_UTXO => {
+                expect_no_key_data(&key)?;
+                let tx = encode::deserialize(&value).map_err(|err| invalid_value(&key, err))?;
+                self.non_witness_utxo = Some(Arc::new(tx));
+            }
+            PSBT_IN_WITNESS_UTXO => {
+                expect_no_key_data(&key)?;
+                let utxo = encode::deserialize(&value).map_err(|err| invalid_value(&key, err))?;
+                self.witness_utxo = Some(utxo);
+            }
+            PSBT_IN_PARTIAL_SIG => {
+                self.partial_sigs
+                    .insert(decode_key_data(&key)?, decode(&key, &value)?);
+            }
+            PSBT_IN_SIGHASH_TYPE => {
+                expect_no_key_data(&key)?;
+                let sighash_type: PsbtSighashType = decode(&key, &value)?;
+                let in_range =
+                    sighash_type.ecdsa_hash_ty().is_ok() || sighash_type.taproot_hash_ty().is_ok();
+                if in_range {
+                    self.sighash_type = Some(sighash_type);
+                } else if options.strict {
+                    let reason = format!("sighash type {} is out of range", sighash_type);
+                    return Err(DecodeError::InvalidValue { key, reason });
+                } else {
+                    self.unknown.insert(key, value);
+                }
+            }
+            PSBT_IN_REDEEM_SCRIPT => {
+                expect_no_key_data(&key)?;
+                self.redeem_script = Some(decode(&key, &value)?);
+            }
+            PSBT_IN_WITNESS_SCRIPT => {
+                expect_no_key_data(&key)?;
+                self.witness_script = Some(decode(&key, &value)?);
+            }
+            PSBT_IN_BIP32_DERIVATION => {
+                self.bip32_derivation
+                    .insert(decode_key_data(&key)?, decode(&key, &value)?);
+            }
+            PSBT_IN_FINAL_SCRIPTSIG => {
+                expect_no_key_data(&key)?;
+                self.final_script_sig = Some(decode(&key, &value)?);
+            }
+            PSBT_IN_FINAL_SCRIPTWITNESS => {
+                expect_no_key_data(&key)?;
+                self.final_script_witness = Some(decode(&key, &value)?);
+            }
+            PSBT_IN_POR_COMMITMENT => {
+                expect_no_key_data(&key)?;
+                let commitment =
+                    String::from_utf8(value).map_err(|err| invalid_value(&key, err))?;
+                self.por_commitment = Some(commitment);
+            }
+            PSBT_IN_RIPEMD160 => {
+                self.ripemd160_preimages
+                    .insert(decode_key_data(&key)?, value);
+            }
+            PSBT_IN_SHA256 => {
+                self.sha256_preimages.insert(decode_key_data(&key)?, value);
+            }
+            PSBT_IN_HASH160 => {
+                self.hash160_preimages.insert(decode_key_data(&key)?, value);
+            }
+            PSBT_IN_HASH256 => {
+                self.hash256_preimages.insert(decode_key_data(&key)?, value);
+            }
+            PSBT_IN_PREVIOUS_TXID => {
+                expect_no_key_data(&key)?;
+                let txid: Txid = decode(&key, &value)?;
+                self.previous_tx_id = Some(PreviousTxId::from(txid));
+            }
+            PSBT_IN_OUTPUT_INDEX => {
+                expect_no_key_data(&key)?;
+                self.output_index = Some(u32::from_le_bytes(decode_array(&key, &value)?));
+            }
+            PSBT_IN_SEQUENCE => {
+                expect_no_key_data(&key)?;
+                let sequence = u32::from_le_bytes(decode_array(&key, &value)?);
+                self.sequence = Some(Sequence::from_consensus(sequence));
+            }
+            PSBT_IN_REQUIRED_TIME_LOCKTIME => {
+                expect_no_key_data(&key)?;
+                let locktime = u32::from_le_bytes(decode_array(&key, &value)?);
+                self.required_time_locktime = Some(locktime);
+            }
+            PSBT_IN_REQUIRED_HEIGHT_LOCKTIME => {
+                expect_no_key_data(&key)?;
+                let locktime = u32::from_le_bytes(decode_array(&key, &value)?);
+                self.required_height_locktime = Some(locktime);
+            }
+            PSBT_IN_TAP_KEY_SIG => {
+                expect_no_key_data(&key)?;
+                self.tap_key_sig = Some(decode(&key, &value)?);
+            }
+            PSBT_IN_TAP_SCRIPT_SIG => {
+                self.tap_script_sigs
+                    .insert(decode_key_data(&key)?, decode(&key, &value)?);
+            }
+            PSBT_IN_TAP_LEAF_SCRIPT => {
+                self.tap_scripts
+                    .insert(decode_key_data(&key)?, decode(&key, &value)?);
+            }
+            PSBT_IN_TAP_BIP32_DERIVATION => {
+                self.tap_key_origins
+                    .insert(decode_key_data(&key)?, decode(&key, &value)?);
+            }
+            PSBT_IN_TAP_INTERNAL_KEY => {
+                expect_no_key_data(&key)?;
+                self.tap_internal_key = Some(decode(&key, &value)?);
+            }
+            PSBT_IN_TAP_MERKLE_ROOT => {
+                expect_no_key_data(&key)?;
+                self.tap_merkle_root = Some(decode(&key, &value)?);
+            }
+            PSBT_IN_TAP_ANNEX => {
+                expect_no_key_data(&key)?;
+                if value.first() != Some(&TAPROOT_ANNEX_PREFIX) {
+                    let reason = String::from("annex doesn't start with 0x50");
+                    return Err(DecodeError::InvalidValue { key, reason });
+                }
+                self.tap_annex = Some(value);
+            }
+            PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS => {
+                let aggregate = decode_key_data(&key)?;
+                let participants = musig::deserialize_participants(&value)
+                    .map_err(|err| invalid_value(&key, err))?;
+                self.musig2_participant_pubkeys
+                    .insert(aggregate, participants);
+            }
+            PSBT_IN_MUSIG2_PUB_NONCE => {
+                let session_key = musig::deserialize_session_key(&key.key)
+                    .map_err(|_| DecodeError::InvalidKey(key.clone()))?;
+                let nonce = PubNonce::from_slice(&value).map_err(|err| invalid_value(&key, err))?;
+                self.musig2_pub_nonces.insert(session_key, nonce);
+            }
+            PSBT_IN_MUSIG2_PARTIAL_SIG => {
+                let session_key = musig::deserialize_session_key(&key.key)
+                    .map_err(|_| DecodeError::InvalidKey(key.clone()))?;
+                let signature =
+                    PartialSig::from_slice(&value).map_err(|err| invalid_value(&key, err))?;
+                self.musig2_partial_sigs.insert(session_key, signature);
+            }
+            PSBT_IN_SP_ECDH_SHARE => {
+                self.sp_ecdh_shares
+                    .insert(decode_sp_scan_key(&key)?, decode_sp_share(&key, &value)?);
+            }
+            PSBT_IN_SP_DLEQ => {
+                self.sp_dleq_proofs
+                    .insert(decode_sp_scan_key(&key)?, decode_dleq_proof(&key, &value)?);
+            }
+            PSBT_IN_PROPRIETARY => {
+                #[cfg(feature = "elements")]
+                if self.elements.insert_pair(&key, &value)? {
+                    return Ok(());
+                }
+                insert_proprietary(&mut self.proprietary, key, value)?
+            }
+            _ => {
+                self.unknown.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Output {
+    pub(crate) fn pairs(&self) -> Vec<raw::Pair> {
+        let mut pairs = Vec::new();
+
+        if let Some(ref script) = self.redeem_script {
+            push_pair(
+                &mut pairs,
+                PSBT_OUT_REDEEM_SCRIPT,
+                vec![],
+                script.to_bytes(),
+            );
+        }
+        if let Some(ref script) = self.witness_script {
+            push_pair(
+                &mut pairs,
+                PSBT_OUT_WITNESS_SCRIPT,
+                vec![],
+                script.to_bytes(),
+            );
+        }
+        for (public_key, key_source) in &self.bip32_derivation {
+            let key = public_key.serialize().to_vec();
+            push_pair(
+                &mut pairs,
+                PSBT_OUT_BIP32_DERIVATION,
+                key,
+                key_source.serialize(),
+            );
+        }
+        if let Some(amount) = self.amount {
+            push_pair(
+                &mut pairs,
+                PSBT_OUT_AMOUNT,
+                vec![],
+                amount.to_sat().to_le_bytes().to_vec(),
+            );
+        }
+        if let Some(ref script) = self.script {
+            push_pair(&mut pairs, PSBT_OUT_SCRIPT, vec![], script.to_bytes());
+        }
+        if let Some(ref internal_key) = self.tap_internal_key {
+            let value = internal_key.serialize().to_vec();
+            push_pair(&mut pairs, PSBT_OUT_TAP_INTERNAL_KEY, vec![], value);
+        }
+        if let Some(ref tap_tree) = self.tap_tree {
+            push_pair(&mut pairs, PSBT_OUT_TAP_TREE, vec![], tap_tree.serialize());
+        }
+        for (public_key, origin) in &self.tap_key_origins {
+            let key = public_key.serialize().to_vec();
+            push_pair(
+                &mut pairs,
+                PSBT_OUT_TAP_BIP32_DERIVATION,
+                key,
+                origin.serialize(),
+            );
+        }
+        for (aggregate, participants) in &self.musig2_participant_pubkeys {
+            push_pair(
+                &mut pairs,
+                PSBT_OUT_MUSIG2_PARTICIPANT_PUBKEYS,
+                aggregate.serialize().to_vec(),
+                musig::serialize_participants(participants),
+            );
+        }
+        if let Some(ref info) = self.sp_v0_info {
+            push_pair(&mut pairs, PSBT_OUT_SP_V0_INFO, vec![], info.serialize());
+        }
+        if let Some(label) = self.sp_v0_label {
+            let value = label.to_le_bytes().to_vec();
+            push_pair(&mut pairs, PSBT_OUT_SP_V0_LABEL, vec![], value);
+        }
+        #[cfg(feature = "elements")]
+        self.elements.push_pairs(&mut pairs);
+        push_proprietary_and_unknown(&mut pairs, &self.proprietary, &self.unknown);
+
+        pairs
+    }
+
+    pub(crate) fn insert_pair(&mut self, pair: raw::Pair) -> Result<(), DecodeError> {
+        let raw::Pair { key, value } = pair;
+        match key.type_value {
+            PSBT_OUT_REDEEM_SCRIPT => {
+                expect_no_key_data(&key)?;
+                self.redeem_script = Some(decode(&key, &value)?);
+            }
+            PSBT_OUT_WITNESS_SCRIPT => {
+                expect_no_key_data(&key)?;
+                self.witness_script = Some(decode(&key, &value)?);
+            }
+            PSBT_OUT_BIP32_DERIVATION => {
+                self.bip32_derivation
+                    .insert(decode_key_data(&key)?, decode(&key, &value)?);
+            }
+            PSBT_OUT_AMOUNT => {
+                expect_no_key_data(&key)?;
+                let amount = i64::from_le_bytes(decode_array(&key, &value)?);
+                if amount < 0 || amount as u64 > Amount::MAX_MONEY.to_sat() {
+                    let reason = format!("amount {} is out of range", amount);
+                    return Err(DecodeError::InvalidValue { key, reason });
+                }
+                self.amount = Some(Amount::from_sat(amount as u64));
+            }
+            PSBT_OUT_SCRIPT => {
+                expect_no_key_data(&key)?;
+                self.set_script(ScriptBuf::from(value))
+                    .map_err(|err| invalid_value(&key, err))?;
+            }
+            PSBT_OUT_TAP_INTERNAL_KEY => {
+                expect_no_key_data(&key)?;
+                self.tap_internal_key = Some(decode(&key, &value)?);
+            }
+            PSBT_OUT_TAP_TREE => {
+                expect_no_key_data(&key)?;
+                self.tap_tree = Some(decode(&key, &value)?);
+            }
+            PSBT_OUT_TAP_BIP32_DERIVATION => {
+                self.tap_key_origins
+                    .insert(decode_key_data(&key)?, decode(&key, &value)?);
+            }
+            PSBT_OUT_MUSIG2_PARTICIPANT_PUBKEYS => {
+                let aggregate = decode_key_data(&key)?;
+                let participants = musig::deserialize_participants(&value)
+                    .map_err(|err| invalid_value(&key, err))?;
+                self.musig2_participant_pubkeys
+                    .insert(aggregate, participants);
+            }
+            PSBT_OUT_PROPRIETARY => {
+                #[cfg(feature = "elements")]
+                if self.elements.insert_pair(&key, &value)? {
+                    return Ok(());
+                }
+                insert_proprietary(&mut self.proprietary, key, value)?
+            }
+            _ => {
+                self.unknown.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a [`Reader`] gets the bytes of a psbt from.
+pub(crate) trait Source {
+    /// Reads up to `buf.len()` bytes into `buf`, returning how many were
+    /// read, 0 only at the end of the psbt.
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<usize, DecodeError>;
+}
+
+impl<S: Source + ?Sized> Source for &mut S {
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        (**self).read_into(buf)
+    }
+}
+
+impl Source for &[u8] {
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        let len = buf.len().min(self.len());
+        let (head, tail) = self.split_at(len);
+        buf[..len].copy_from_slice(head);
+        *self = tail;
+        Ok(len)
+    }
+}
+
+/// A [`Source`] reading from an [`io::Read`].
+#[cfg(feature = "std")]
+struct IoSource<R>(R);
+
+#[cfg(feature = "std")]
+impl<R: Read> Source for IoSource<R> {
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        loop {
+            match self.0.read(buf) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result.map_err(DecodeError::from),
+            }
+        }
+    }
+}
+
+/// Reads compact-size prefixed key-value maps from a [`Source`].
+pub(crate) struct Reader<S> {
+    source: S,
+    /// The number of bytes read so far.
+    position: usize,
+    options: ParseOptions,
+}
+
+impl<S: Source> Reader<S> {
+    pub(crate) fn new(source: S, options: ParseOptions) -> Self {
+        Reader {
+            source,
+            position: 0,
+            options,
+        }
+    }
+
+    /// Accounts for `len` more bytes read, failing before they are read if
+    /// the psbt would exceed its size limit.
+    fn advance(&mut self, len: usize) -> Result<(), DecodeError> {
+        self.position = self.position.saturating_add(len);
+        check_limit("size", self.options.limits.max_size, self.position)
+    }
+
+    /// Reads the next byte, or returns `None` at the end of the stream.
+    fn next_byte(&mut self) -> Result<Option<u8>, DecodeError> {
+        let mut byte = [0u8; 1];
+        if self.source.read_into(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        self.advance(1)?;
+        Ok(Some(byte[0]))
+    }
+
+    pub(crate) fn read_byte(&mut self) -> Result<u8, DecodeError> {
+        self.next_byte()?.ok_or(DecodeError::UnexpectedEof)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, DecodeError> {
+        self.advance(len)?;
+        // Growing the buffer as bytes arrive keeps a bogus length from
+        // allocating more than the stream actually holds
+        let mut bytes = Vec::new();
+        let mut chunk = [0u8; 4096];
+        while bytes.len() < len {
+            let want = (len - bytes.len()).min(chunk.len());
+            let read = self.source.read_into(&mut chunk[..want])?;
+            if read == 0 {
+                return Err(DecodeError::UnexpectedEof);
+            }
+            bytes.extend_from_slice(&chunk[..read]);
+        }
+        Ok(bytes)
+    }
+
+    /// Reads a whole compact size.
+    pub(crate) fn read_size(&mut self) -> Result<u64, DecodeError> {
+        let prefix = self.read_byte()?;
+        self.read_compact_size(prefix)
+    }
+
+    /// Reads the rest of a compact size starting with `prefix`.
+    fn read_compact_size(&mut self, prefix: u8) -> Result<u64, DecodeError> {
+        let (size, min) = match prefix {
+            0xfd => (u16::from_le_bytes(self.read_array()?) as u64, 0xfd),
+            0xfe => (u32::from_le_bytes(self.read_array()?) as u64, 0x1_0000),
+            0xff => (u64::from_le_bytes(self.read_array()?), 0x1_0000_0000),
+            size => (size as u64, 0),
+        };
+        if self.options.strict && size < min {
+            return Err(DecodeError::NonMinimalCompactSize);
+        }
+        Ok(size)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(&self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    /// Reads the pairs of a map up to and including its 0x00 separator,
+    /// rejecting any key that appears twice as BIP-174 requires.
+    fn read_map(&mut self, location: MapLocation) -> Result<Vec<raw::Pair>, DecodeError> {
+        let prefix = self.read_byte()?;
+        self.read_map_from(prefix, location)
+    }
+
+    /// Reads the rest of a map whose first key length starts with `prefix`.
+    fn read_map_from(
+        &mut self,
+        prefix: u8,
+        location: MapLocation,
+    ) -> Result<Vec<raw::Pair>, DecodeError> {
+        let pairs = self.read_pairs_from(prefix, location, Self::read_bytes)?;
+        Ok(pairs
+            .into_iter()
+            .map(|(key, value)| raw::Pair {
+                key: raw::Key {
+                    type_value: key[0],
+                    key: key[1..].to_vec(),
+                },
+                value,
+            })
+            .collect())
+    }
+
+    /// Reads the rest of a map whose first key length starts with `prefix`,
+    /// taking the keys and values off the source with `read`. Keys are
+    /// returned with their type and are never empty.
+    fn read_pairs_from<B>(
+        &mut self,
+        mut prefix: u8,
+        location: MapLocation,
+        mut read: impl FnMut(&mut Self, usize) -> Result<B, DecodeError>,
+    ) -> Result<Vec<(B, B)>, DecodeError>
+    where
+        B: AsRef<[u8]> + Clone + Ord,
+    {
+        let mut pairs = Vec::new();
+        let mut seen = BTreeSet::new();
+        loop {
+            let key_len = self.read_compact_size(prefix)?;
+            if key_len == 0 {
+                return Ok(pairs);
+            }
+            let key = read(self, key_len as usize)?;
+            if !seen.insert(key.clone()) {
+                return Err(DecodeError::DuplicateKey {
+                    map: location,
+                    key: key.as_ref().to_vec(),
+                });
+            }
+            let value_prefix = self.read_byte()?;
+            let value_len = self.read_compact_size(value_prefix)?;
+            let value = read(self, value_len as usize)?;
+            pairs.push((key, value));
+            prefix = self.read_byte()?;
+        }
+    }
+}
+
+impl<'a> Reader<&'a [u8]> {
+    /// Returns `true` once every byte has been read.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.source.is_empty()
+    }
+
+    /// Reads `len` bytes as a slice of the source, without copying them.
+    pub(crate) fn read_slice(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        self.advance(len)?;
+        if len > self.source.len() {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        let (head, tail) = self.source.split_at(len);
+        self.source = tail;
+        Ok(head)
+    }
+
+    /// Reads the pairs of a map like [`Reader::read_map`], borrowing the keys
+    /// and values from the source.
+    pub(crate) fn read_borrowed_map(
+        &mut self,
+        location: MapLocation,
+    ) -> Result<Vec<(&'a [u8], &'a [u8])>, DecodeError> {
+        let prefix = self.read_byte()?;
+        self.read_pairs_from(prefix, location, Self::read_slice)
+    }
+}
+
+fn write_compact_size(bytes: &mut Vec<u8>, size: u64) {
+    bytes.extend(encode::serialize(&VarInt(size)));
+}
+
+/// Encodes the pairs of a map followed by its 0x00 separator.
+fn encode_map(pairs: &[raw::Pair]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for pair in pairs {
+        write_compact_size(&mut bytes, pair.key.key.len() as u64 + 1);
+        bytes.push(pair.key.type_value);
+        bytes.extend(&pair.key.key);
+        write_compact_size(&mut bytes, pair.value.len() as u64);
+        bytes.extend(&pair.value);
+    }
+    bytes.push(0x00);
+    bytes
+}
+
+fn push_pair(pairs: &mut Vec<raw::Pair>, type_value: u8, key: Vec<u8>, value: Vec<u8>) {
+    pairs.push(raw::Pair {
+        key: raw::Key { type_value, key },
+        value,
+    });
+}
+
+fn push_proprietary_and_unknown(
+    pairs: &mut Vec<raw::Pair>,
+    proprietary: &BTreeMap<raw::ProprietaryKey, Vec<u8>>,
+    unknown: &BTreeMap<raw::Key, Vec<u8>>,
+) {
+    let known: BTreeSet<raw::Key> = pairs.iter().map(|pair| pair.key.clone()).collect();
+    for (key, value) in proprietary {
+        pairs.push(raw::Pair {
+            key: key.to_key(),
+            value: value.clone(),
+        });
+    }
+    for (key, value) in unknown {
+        // A field of the map always wins over an unknown pair with its key,
+        // such as the counts a PsbtV0 carried once it's upgraded
+        if known.contains(key) {
+            continue;
+        }
+        pairs.push(raw::Pair {
+            key: key.clone(),
+            value: value.clone(),
+        });
+    }
+}
+
+fn push_ecdh_shares(
+    pairs: &mut Vec<raw::Pair>,
+    share_type: u8,
+    proof_type: u8,
+    shares: &BTreeMap<secp256k1::PublicKey, secp256k1::PublicKey>,
+    proofs: &BTreeMap<secp256k1::PublicKey, DleqProof>,
+) {
+    for (scan_key, share) in shares {
+        let key = scan_key.serialize().to_vec();
+        push_pair(pairs, share_type, key, share.serialize().to_vec());
+    }
+    for (scan_key, proof) in proofs {
+        let key = scan_key.serialize().to_vec();
+        push_pair(pairs, proof_type, key, proof.0.to_vec());
+    }
+}
+
+fn insert_proprietary(
+    proprietary: &mut BTreeMap<raw::ProprietaryKey, Vec<u8>>,
+    key: raw::Key,
+    value: Vec<u8>,
+) -> Result<(), DecodeError> {
+    let proprietary_key =
+        raw::ProprietaryKey::try_from(key.clone()).map_err(|_| DecodeError::InvalidKey(key))?;
+    proprietary.insert(proprietary_key, value);
+    Ok(())
+}
+
+fn expect_no_key_data(key: &raw::Key) -> Result<(), DecodeError> {
+    if key.key.is_empty() {
+        Ok(())
+    } else {
+        Err(DecodeError::InvalidKey(key.clone()))
+    }
+}
+
+fn decode<T: Deserialize>(key: &raw::Key, value: &[u8]) -> Result<T, DecodeError> {
+    T::deserialize(value).map_err(|err| invalid_value(key, err))
+}
+
+fn decode_key_data<T: Deserialize>(key: &raw::Key) -> Result<T, DecodeError> {
+    T::deserialize(&key.key).map_err(|_| DecodeError::InvalidKey(key.clone()))
+}
+
+fn decode_array<const N: usize>(key: &raw::Key, value: &[u8]) -> Result<[u8; N], DecodeError> {
+    value.try_into().map_err(|_| DecodeError::InvalidValue {
+        key: key.clone(),
+        reason: format!("expected {} bytes, got {}", N, value.len()),
+    })
+}
+
+fn decode_sp_scan_key(key: &raw::Key) -> Result<secp256k1::PublicKey, DecodeError> {
+    secp256k1::PublicKey::from_slice(&key.key).map_err(|_| DecodeError::InvalidKey(key.clone()))
+}
+
+fn decode_sp_share(key: &raw::Key, value: &[u8]) -> Result<secp256k1::PublicKey, DecodeError> {
+    secp256k1::PublicKey::from_slice(value).map_err(|err| invalid_value(key, err))
+}
+
+fn decode_dleq_proof(key: &raw::Key, value: &[u8]) -> Result<DleqProof, DecodeError> {
+    DleqProof::from_slice(value).map_err(|err| invalid_value(key, err))
+}
+
+/// Checks a count field found in a PsbtV0 against its unsigned transaction.
+fn check_count(count: Option<&(usize, raw::Pair)>, expected: usize) -> Result<(), DecodeError> {
+    match count {
+        Some((count, pair)) if *count != expected => Err(DecodeError::InvalidValue {
+            key: pair.key.clone(),
+            reason: format!(
+                "count {} doesn't match the {} of the unsigned transaction",
+                count, expected
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn decode_count(pair: &raw::Pair) -> Result<usize, DecodeError> {
+    expect_no_key_data(&pair.key)?;
+    let VarInt(count) =
+        encode::deserialize(&pair.value).map_err(|err| invalid_value(&pair.key, err))?;
+    Ok(count as usize)
+}
+
+/// Fails if `actual` goes beyond the `max` of the named limit.
+pub(crate) fn check_limit(
+    limit: &'static str,
+    max: usize,
+    actual: usize,
+) -> Result<(), DecodeError> {
+    if actual > max {
+        Err(DecodeError::LimitExceeded { limit, max, actual })
+    } else {
+        Ok(())
+    }
+}
+
+/// The number of key and value bytes kept in an `unknown` map.
+fn unknown_size(unknown: &BTreeMap<raw::Key, Vec<u8>>) -> usize {
+    unknown
+        .iter()
+        .map(|(key, value)| 1 + key.key.len() + value.len())
+        .sum()
+}
+
+fn invalid_value<E: ToString>(key: &raw::Key, err: E) -> DecodeError {
+    DecodeError::InvalidValue {
+        key: key.clone(),
+        reason: err.to_string(),
+    }
+}
@@ -0,0 +1,341 @@
+//! A CoinJoin-style session in which several participants contribute inputs
+//! and outputs to a shared PsbtV2 and then sign their own inputs.
+//!
+//! The coordinator registers inputs and outputs while the `tx_modifiable`
+//! flags allow it, then locks the transaction for signing. Signatures
+//! submitted by a participant are verified before they are accepted, and a
+//! participant submitting an invalid one is blamed and excluded. Each round
+//! ends with a psbt the participants can combine with what they have.
+
+use core::fmt;
+
+use crate::blockdata::transaction::OutPoint;
+use crate::hash_types::Txid;
+use crate::prelude::*;
+use crate::psbt::Version;
+
+use super::diff::PsbtDiff;
+use super::index::InputIndex;
+use super::input::Input;
+use super::modifiable::{INPUTS_MODIFIABLE, OUTPUTS_MODIFIABLE};
+use super::output::Output;
+use super::poc::{PartiallySignedTransactionInner, Psbt};
+use super::sighash::SighashError;
+
+/// The id the coordinator knows a participant by.
+pub type ParticipantId = u32;
+
+/// The phase a session is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionPhase {
+    /// Participants register inputs and outputs.
+    Registration,
+    /// The transaction is locked, participants sign their inputs.
+    Signing,
+}
+
+/// Errors encountered while running a session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionError {
+    /// Sessions run on a PsbtV2 whose inputs and outputs are modifiable.
+    NotModifiable,
+    /// The session isn't in the phase the operation belongs to.
+    WrongPhase { phase: SessionPhase },
+    /// The participant was blamed for an invalid signature and is excluded.
+    Blamed { participant: ParticipantId },
+    /// Another input already spends this outpoint.
+    DuplicateInput { outpoint: OutPoint },
+    /// The psbt refused the input or output.
+    Rejected(String),
+    /// The submitted psbt describes another transaction than the session.
+    DifferentTransaction,
+    /// The participant submitted an invalid signature for the input at this
+    /// index.
+    InvalidSignature {
+        participant: ParticipantId,
+        index: usize,
+    },
+    /// The signatures of the input could not be verified.
+    Sighash(SighashError),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::NotModifiable => {
+                write!(
+                    f,
+                    "sessions need a PsbtV2 with modifiable inputs and outputs"
+                )
+            }
+            SessionError::WrongPhase { phase } => write!(f, "session is in the {:?} phase", phase),
+            SessionError::Blamed { participant } => {
+                write!(f, "participant {} is excluded", participant)
+            }
+            SessionError::DuplicateInput { outpoint } => {
+                write!(f, "outpoint {} is already registered", outpoint)
+            }
+            SessionError::Rejected(err) => write!(f, "rejected: {}", err),
+            SessionError::DifferentTransaction => {
+                write!(f, "psbt describes another transaction")
+            }
+            SessionError::InvalidSignature { participant, index } => write!(
+                f,
+                "participant {} submitted an invalid signature for input {}",
+                participant, index
+            ),
+            SessionError::Sighash(err) => write!(f, "can't verify signatures: {:?}", err),
+        }
+    }
+}
+
+/// The outcome of a round of a session.
+pub struct RoundDelta {
+    /// The psbt at the end of the round, which participants can merge into
+    /// their copy with [`Psbt::combine`].
+    pub psbt: Psbt,
+    /// What changed during the round.
+    pub diff: PsbtDiff,
+}
+
+/// A session of several participants building and signing a PsbtV2
+/// together.
+pub struct CollaborativeSession {
+    psbt: Psbt,
+    phase: SessionPhase,
+    /// The participant owning each input, `None` for the inputs the psbt
+    /// started with.
+    input_owners: Vec<Option<ParticipantId>>,
+    /// The participant owning each output, like `input_owners`.
+    output_owners: Vec<Option<ParticipantId>>,
+    blamed: BTreeSet<ParticipantId>,
+    /// The psbt at the start of the current round.
+    round_start: PartiallySignedTransactionInner,
+}
+
+impl CollaborativeSession {
+    /// Starts a session on `psbt`, a PsbtV2 whose inputs and outputs are
+    /// modifiable, typically without any input or output yet.
+    pub fn new(psbt: Psbt) -> Result<Self, SessionError> {
+        if psbt.inner.version != Version::PsbtV2
+            || !psbt.inputs_modifiable()
+            || !psbt.outputs_modifiable()
+        {
+            return Err(SessionError::NotModifiable);
+        }
+        Ok(CollaborativeSession {
+            input_owners: vec![None; psbt.inner.inputs.len()],
+            output_owners: vec![None; psbt.inner.outputs.len()],
+            round_start: psbt.inner.clone(),
+            psbt,
+            phase: SessionPhase::Registration,
+            blamed: BTreeSet::new(),
+        })
+    }
+
+    /// The shared psbt.
+    pub fn psbt(&self) -> &Psbt {
+        &self.psbt
+    }
+
+    /// The phase the session is in.
+    pub fn phase(&self) -> SessionPhase {
+        self.phase
+    }
+
+    /// The participants blamed for invalid signatures.
+    pub fn blamed(&self) -> &BTreeSet<ParticipantId> {
+        &self.blamed
+    }
+
+    /// The indices of the inputs `participant` registered.
+    pub fn inputs_of(&self, participant: ParticipantId) -> Vec<usize> {
+        owned_by(&self.input_owners, participant)
+    }
+
+    /// The indices of the outputs `participant` registered.
+    pub fn outputs_of(&self, participant: ParticipantId) -> Vec<usize> {
+        owned_by(&self.output_owners, participant)
+    }
+
+    /// The participants that still have unsigned inputs.
+    pub fn pending_participants(&self) -> BTreeSet<ParticipantId> {
+        self.input_owners
+            .iter()
+            .zip(&self.psbt.inner.inputs)
+            .filter(|(_, input)| !input.is_signed())
+            .filter_map(|(owner, _)| *owner)
+            .collect()
+    }
+
+    /// Registers an input of `participant`, returning its index.
+    pub fn register_input(
+        &mut self,
+        participant: ParticipantId,
+        input: Input,
+    ) -> Result<usize, SessionError> {
+        self.check_registration(participant)?;
+        if let (Some(txid), Some(vout)) = (input.previous_tx_id, input.output_index) {
+            let outpoint = OutPoint::new(txid.to_txid(), vout);
+            let registered = self.psbt.inner.inputs.iter().any(|registered| {
+                registered.previous_tx_id == input.previous_tx_id
+                    && registered.output_index == input.output_index
+            });
+            if registered {
+                return Err(SessionError::DuplicateInput { outpoint });
+            }
+        }
+        self.psbt.add_input(input).map_err(SessionError::Rejected)?;
+        self.input_owners.push(Some(participant));
+        Ok(self.input_owners.len() - 1)
+    }
+
+    /// Registers an output of `participant`, returning its index.
+    pub fn register_output(
+        &mut self,
+        participant: ParticipantId,
+        output: Output,
+    ) -> Result<usize, SessionError> {
+        self.check_registration(participant)?;
+        self.psbt
+            .add_output(output)
+            .map_err(SessionError::Rejected)?;
+        self.output_owners.push(Some(participant));
+        Ok(self.output_owners.len() - 1)
+    }
+
+    /// Ends the registration by clearing the flags allowing inputs and
+    /// outputs to be added, returning the unique id of the transaction
+    /// participants should check before signing.
+    pub fn start_signing(&mut self) -> Result<Txid, SessionError> {
+        if self.phase != SessionPhase::Registration {
+            return Err(SessionError::WrongPhase { phase: self.phase });
+        }
+        let unique_id = self.psbt.unique_id().map_err(SessionError::Rejected)?;
+        let flags = self.psbt.inner.tx_modifiable.unwrap_or(0);
+        self.psbt.inner.tx_modifiable = Some(flags & !(INPUTS_MODIFIABLE | OUTPUTS_MODIFIABLE));
+        self.phase = SessionPhase::Signing;
+        Ok(unique_id)
+    }
+
+    /// Accepts the signatures `participant` made on its inputs in `signed`,
+    /// its copy of the session psbt. Signatures on inputs of others are
+    /// ignored.
+    ///
+    /// Every accepted signature is verified. If one is invalid, none of the
+    /// participant's signatures are kept and the participant is blamed.
+    pub fn submit_signatures(
+        &mut self,
+        participant: ParticipantId,
+        signed: &Psbt,
+    ) -> Result<(), SessionError> {
+        if self.phase != SessionPhase::Signing {
+            return Err(SessionError::WrongPhase { phase: self.phase });
+        }
+        if self.blamed.contains(&participant) {
+            return Err(SessionError::Blamed { participant });
+        }
+        match (signed.unique_id(), self.psbt.unique_id()) {
+            (Ok(signed_id), Ok(unique_id)) if signed_id == unique_id => {}
+            _ => return Err(SessionError::DifferentTransaction),
+        }
+
+        let previous_inputs = self.psbt.inner.inputs.clone();
+        let owned = self.inputs_of(participant);
+        for &index in &owned {
+            let signed_input = match signed.inner.inputs.get(index) {
+                Some(signed_input) => signed_input,
+                None => {
+                    self.psbt.inner.inputs = previous_inputs;
+                    return Err(SessionError::DifferentTransaction);
+                }
+            };
+            let input = &mut self.psbt.inner.inputs[index];
+            input.partial_sigs.extend(signed_input.partial_sigs.clone());
+            input
+                .tap_script_sigs
+                .extend(signed_input.tap_script_sigs.clone());
+            if signed_input.tap_key_sig.is_some() {
+                input.tap_key_sig = signed_input.tap_key_sig;
+            }
+        }
+
+        for &index in &owned {
+            if let Err(err) = self.verify_input(participant, index) {
+                self.psbt.inner.inputs = previous_inputs;
+                if let SessionError::InvalidSignature { .. } = err {
+                    self.blamed.insert(participant);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the current round, returning the psbt and what changed since the
+    /// previous round ended.
+    pub fn end_round(&mut self) -> RoundDelta {
+        let previous = Psbt {
+            inner: core::mem::replace(&mut self.round_start, self.psbt.inner.clone()),
+            fee_policy: None,
+            network: self.psbt.network,
+        };
+        RoundDelta {
+            diff: previous.diff(&self.psbt),
+            psbt: Psbt {
+                inner: self.psbt.inner.clone(),
+                fee_policy: self.psbt.fee_policy,
+                network: self.psbt.network,
+            },
+        }
+    }
+
+    /// Ends the session, returning the shared psbt.
+    pub fn into_psbt(self) -> Psbt {
+        self.psbt
+    }
+
+    fn check_registration(&self, participant: ParticipantId) -> Result<(), SessionError> {
+        if self.phase != SessionPhase::Registration {
+            return Err(SessionError::WrongPhase { phase: self.phase });
+        }
+        if self.blamed.contains(&participant) {
+            return Err(SessionError::Blamed { participant });
+        }
+        Ok(())
+    }
+
+    /// Verifies the ECDSA and taproot signatures of the input at `index`.
+    fn verify_input(&self, participant: ParticipantId, index: usize) -> Result<(), SessionError> {
+        let invalid = SessionError::InvalidSignature { participant, index };
+        let input = &self.psbt.inner.inputs[index];
+        if !input.partial_sigs.is_empty()
+            && !self
+                .psbt
+                .verify_partial_sigs(InputIndex::new(index))
+                .map_err(SessionError::Sighash)?
+                .is_empty()
+        {
+            return Err(invalid);
+        }
+        if (input.tap_key_sig.is_some() || !input.tap_script_sigs.is_empty())
+            && !self
+                .psbt
+                .verify_taproot_sigs(InputIndex::new(index))
+                .map_err(SessionError::Sighash)?
+                .is_empty()
+        {
+            return Err(invalid);
+        }
+        Ok(())
+    }
+}
+
+fn owned_by(owners: &[Option<ParticipantId>], participant: ParticipantId) -> Vec<usize> {
+    owners
+        .iter()
+        .enumerate()
+        .filter(|(_, owner)| **owner == Some(participant))
+        .map(|(index, _)| index)
+        .collect()
+}
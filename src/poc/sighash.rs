@@ -0,0 +1,393 @@
+use secp256k1::Message;
+
+use crate::blockdata::script::Script;
+use crate::blockdata::transaction::{Transaction, TxOut};
+use crate::prelude::*;
+use crate::sighash::{
+    self, Annex, EcdsaSighashType, LegacySighash, Prevouts, SegwitV0Sighash, SighashCache,
+    TapSighash, TapSighashType,
+};
+use crate::taproot::TapLeafHash;
+
+use super::index::InputIndex;
+use super::input::Input;
+use super::poc::Psbt;
+
+/// The code separator position committed to when the script has no
+/// `OP_CODESEPARATOR`.
+const CODESEPARATOR_NONE: u32 = 0xffff_ffff;
+
+/// The way an input is spent, which decides the sighash algorithm to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpendKind {
+    /// Pre-segwit spend, including bare and P2SH wrapped scripts.
+    Legacy,
+    /// BIP-143 spend, either native or nested in P2SH.
+    SegwitV0,
+    /// BIP-341 spend, key path or script path.
+    Taproot,
+}
+
+/// Errors encountered while computing the sighash of a psbt input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SighashError {
+    /// The input index is out of range.
+    IndexOutOfBounds { index: usize, length: usize },
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+    /// The input has no trusted utxo, see [`Input::spent_utxo`].
+    MissingUtxo { index: usize },
+    /// The spent output is P2SH but the input has no `redeem_script`.
+    MissingRedeemScript { index: usize },
+    /// The spent output is P2WSH but the input has no `witness_script`.
+    MissingWitnessScript { index: usize },
+    /// The input's `sighash_type` is not valid for its spend kind.
+    InvalidSighashType { index: usize },
+    /// The input's `tap_annex` doesn't start with 0x50.
+    InvalidAnnex { index: usize },
+    /// The requested sighash algorithm doesn't match the way the input is spent.
+    WrongSpendKind { index: usize, spend_kind: SpendKind },
+    /// The underlying sighash computation failed.
+    Computation(sighash::Error),
+}
+
+/// Sighash computation state shared across the inputs of a psbt.
+///
+/// Segwit v0 and taproot sighashes of all inputs hash the same prevouts,
+/// amounts, sequences and outputs. The cache builds the unsigned
+/// transaction once and keeps those hashes, so computing the sighash of
+/// every input takes time linear in the size of the transaction instead of
+/// quadratic. It's only valid for the psbt it was created from, as long as
+/// the transaction and the spent outputs of that psbt don't change; adding
+/// signatures doesn't invalidate it.
+pub struct PsbtSighashCache {
+    cache: SighashCache<Transaction>,
+    /// The outputs spent by every input, once a taproot sighash needed them.
+    spent_utxos: Option<Vec<TxOut>>,
+}
+
+impl PsbtSighashCache {
+    /// Creates a cache for the sighashes of `psbt`.
+    pub fn new(psbt: &Psbt) -> Result<Self, SighashError> {
+        let tx = psbt.unsigned_tx().map_err(SighashError::UnsignedTx)?;
+        Ok(PsbtSighashCache {
+            cache: SighashCache::new(tx),
+            spent_utxos: None,
+        })
+    }
+
+    /// Returns how the input at `index` of `psbt` is spent, see
+    /// [`Psbt::spend_kind`].
+    pub fn spend_kind(&self, psbt: &Psbt, index: usize) -> Result<SpendKind, SighashError> {
+        let input = psbt.input_at(index)?;
+        let utxo = spent_utxo(self.cache.transaction(), input, index)?;
+
+        let script_pubkey = &utxo.script_pubkey;
+        if script_pubkey.is_p2tr() {
+            Ok(SpendKind::Taproot)
+        } else if script_pubkey.is_p2wpkh() || script_pubkey.is_p2wsh() {
+            Ok(SpendKind::SegwitV0)
+        } else if script_pubkey.is_p2sh() {
+            let redeem_script = input
+                .redeem_script
+                .as_ref()
+                .ok_or(SighashError::MissingRedeemScript { index })?;
+            if redeem_script.is_p2wpkh() || redeem_script.is_p2wsh() {
+                Ok(SpendKind::SegwitV0)
+            } else {
+                Ok(SpendKind::Legacy)
+            }
+        } else {
+            Ok(SpendKind::Legacy)
+        }
+    }
+
+    /// Returns the output spent by the input at `index` of `psbt`.
+    pub(crate) fn spent_txout<'a>(
+        &self,
+        psbt: &'a Psbt,
+        index: usize,
+    ) -> Result<&'a TxOut, SighashError> {
+        spent_utxo(self.cache.transaction(), psbt.input_at(index)?, index)
+    }
+
+    /// Computes the message an ECDSA signature for the input at `index` of
+    /// `psbt` committing to `sighash_type` must sign, see
+    /// [`Psbt::sighash_ecdsa_with_type`].
+    pub fn ecdsa(
+        &mut self,
+        psbt: &Psbt,
+        index: usize,
+        sighash_type: EcdsaSighashType,
+    ) -> Result<Message, SighashError> {
+        match self.spend_kind(psbt, index)? {
+            SpendKind::Legacy => {
+                let sighash = self.legacy(psbt, index, sighash_type)?;
+                Ok(Message::from_digest(sighash.to_byte_array()))
+            }
+            SpendKind::SegwitV0 => {
+                let sighash = self.segwit_v0(psbt, index, sighash_type)?;
+                Ok(Message::from_digest(sighash.to_byte_array()))
+            }
+            spend_kind @ SpendKind::Taproot => {
+                Err(SighashError::WrongSpendKind { index, spend_kind })
+            }
+        }
+    }
+
+    /// Computes the legacy (pre-segwit) sighash of the input at `index`.
+    ///
+    /// The script code is the `redeem_script` for P2SH outputs and the spent
+    /// `script_pubkey` otherwise.
+    pub fn legacy(
+        &mut self,
+        psbt: &Psbt,
+        index: usize,
+        sighash_type: EcdsaSighashType,
+    ) -> Result<LegacySighash, SighashError> {
+        let input = psbt.input_at(index)?;
+        let utxo = spent_utxo(self.cache.transaction(), input, index)?;
+
+        let script_code = if utxo.script_pubkey.is_p2sh() {
+            input
+                .redeem_script
+                .as_ref()
+                .ok_or(SighashError::MissingRedeemScript { index })?
+                .as_script()
+        } else {
+            utxo.script_pubkey.as_script()
+        };
+
+        self.cache
+            .legacy_signature_hash(index, script_code, sighash_type.to_u32())
+            .map_err(SighashError::Computation)
+    }
+
+    /// Computes the BIP-143 sighash of the input at `index`.
+    ///
+    /// Handles P2WPKH and P2WSH, both native and nested in P2SH.
+    pub fn segwit_v0(
+        &mut self,
+        psbt: &Psbt,
+        index: usize,
+        sighash_type: EcdsaSighashType,
+    ) -> Result<SegwitV0Sighash, SighashError> {
+        let input = psbt.input_at(index)?;
+        let utxo = spent_utxo(self.cache.transaction(), input, index)?.clone();
+
+        let witness_program: &Script = if utxo.script_pubkey.is_p2sh() {
+            input
+                .redeem_script
+                .as_ref()
+                .ok_or(SighashError::MissingRedeemScript { index })?
+                .as_script()
+        } else {
+            utxo.script_pubkey.as_script()
+        };
+
+        let sighash = if witness_program.is_p2wpkh() {
+            self.cache
+                .p2wpkh_signature_hash(index, witness_program, utxo.value, sighash_type)
+        } else if witness_program.is_p2wsh() {
+            let witness_script = input
+                .witness_script
+                .as_ref()
+                .ok_or(SighashError::MissingWitnessScript { index })?;
+            self.cache
+                .p2wsh_signature_hash(index, witness_script, utxo.value, sighash_type)
+        } else {
+            return Err(SighashError::WrongSpendKind {
+                index,
+                spend_kind: SpendKind::Legacy,
+            });
+        };
+
+        sighash.map_err(SighashError::Computation)
+    }
+
+    /// Computes the BIP-341 sighash of the input at `index` committing to
+    /// `sighash_type`, see [`Psbt::sighash_taproot`].
+    pub fn taproot(
+        &mut self,
+        psbt: &Psbt,
+        index: usize,
+        leaf_hash: Option<TapLeafHash>,
+        sighash_type: TapSighashType,
+    ) -> Result<TapSighash, SighashError> {
+        let input = psbt.input_at(index)?;
+        let utxo = spent_utxo(self.cache.transaction(), input, index)?;
+        if !utxo.script_pubkey.is_p2tr() {
+            let spend_kind = self.spend_kind(psbt, index)?;
+            return Err(SighashError::WrongSpendKind { index, spend_kind });
+        }
+
+        let prevouts = match sighash_type {
+            TapSighashType::AllPlusAnyoneCanPay
+            | TapSighashType::NonePlusAnyoneCanPay
+            | TapSighashType::SinglePlusAnyoneCanPay => Prevouts::One(index, utxo.clone()),
+            _ => {
+                // Collected on first use, ANYONECANPAY inputs don't need them
+                if self.spent_utxos.is_none() {
+                    let tx = self.cache.transaction();
+                    let spent_utxos = psbt
+                        .inner
+                        .inputs
+                        .iter()
+                        .enumerate()
+                        .map(|(i, input)| spent_utxo(tx, input, i).cloned())
+                        .collect::<Result<Vec<TxOut>, SighashError>>()?;
+                    self.spent_utxos = Some(spent_utxos);
+                }
+                Prevouts::All(self.spent_utxos.as_deref().unwrap_or_default())
+            }
+        };
+
+        let annex = input
+            .tap_annex
+            .as_deref()
+            .map(Annex::new)
+            .transpose()
+            .map_err(|_| SighashError::InvalidAnnex { index })?;
+        let leaf_hash = leaf_hash.map(|leaf_hash| (leaf_hash, CODESEPARATOR_NONE));
+        self.cache
+            .taproot_signature_hash(index, &prevouts, annex, leaf_hash, sighash_type)
+            .map_err(SighashError::Computation)
+    }
+}
+
+impl Psbt {
+    /// Returns how the input at `index` is spent, judging by the script of the
+    /// output it spends and, for P2SH outputs, its `redeem_script`.
+    pub fn spend_kind(&self, index: InputIndex) -> Result<SpendKind, SighashError> {
+        PsbtSighashCache::new(self)?.spend_kind(self, index.get())
+    }
+
+    /// Computes the message an ECDSA signature for the input at `index` must
+    /// commit to, using the legacy or BIP-143 algorithm as appropriate.
+    ///
+    /// Use a [`PsbtSighashCache`] to compute the sighashes of many inputs.
+    pub fn sighash_ecdsa(
+        &self,
+        index: InputIndex,
+    ) -> Result<(Message, EcdsaSighashType), SighashError> {
+        let sighash_type = ecdsa_sighash_type(self.input_at(index.get())?, index.get())?;
+        Ok((
+            self.sighash_ecdsa_with_type(index, sighash_type)?,
+            sighash_type,
+        ))
+    }
+
+    /// Same as [`Psbt::sighash_ecdsa`], but commits to `sighash_type` instead
+    /// of the input's `sighash_type`. Used to check signatures carrying their
+    /// own sighash type.
+    pub fn sighash_ecdsa_with_type(
+        &self,
+        index: InputIndex,
+        sighash_type: EcdsaSighashType,
+    ) -> Result<Message, SighashError> {
+        PsbtSighashCache::new(self)?.ecdsa(self, index.get(), sighash_type)
+    }
+
+    /// Computes the legacy (pre-segwit) sighash of the input at `index`, see
+    /// [`PsbtSighashCache::legacy`].
+    pub fn sighash_legacy(
+        &self,
+        index: InputIndex,
+    ) -> Result<(LegacySighash, EcdsaSighashType), SighashError> {
+        let sighash_type = ecdsa_sighash_type(self.input_at(index.get())?, index.get())?;
+        let sighash = PsbtSighashCache::new(self)?.legacy(self, index.get(), sighash_type)?;
+        Ok((sighash, sighash_type))
+    }
+
+    /// Computes the BIP-143 sighash of the input at `index`, see
+    /// [`PsbtSighashCache::segwit_v0`].
+    pub fn sighash_segwit_v0(
+        &self,
+        index: InputIndex,
+    ) -> Result<(SegwitV0Sighash, EcdsaSighashType), SighashError> {
+        let sighash_type = ecdsa_sighash_type(self.input_at(index.get())?, index.get())?;
+        let sighash = PsbtSighashCache::new(self)?.segwit_v0(self, index.get(), sighash_type)?;
+        Ok((sighash, sighash_type))
+    }
+
+    /// Computes the BIP-341 sighash of the input at `index`.
+    ///
+    /// With `leaf_hash` set to `None` the key path sighash is computed,
+    /// otherwise the script path sighash for that leaf. The sighash commits
+    /// to the `tap_annex` of the input, if any. Unless the sighash type is
+    /// `ANYONECANPAY`, the spent outputs of all inputs must be known.
+    pub fn sighash_taproot(
+        &self,
+        index: InputIndex,
+        leaf_hash: Option<TapLeafHash>,
+    ) -> Result<(TapSighash, TapSighashType), SighashError> {
+        let sighash_type = taproot_sighash_type(self.input_at(index.get())?, index.get())?;
+        let sighash = self.sighash_taproot_with_type(index, leaf_hash, sighash_type)?;
+        Ok((sighash, sighash_type))
+    }
+
+    /// Same as [`Psbt::sighash_taproot`], but commits to `sighash_type`
+    /// instead of the input's `sighash_type`.
+    pub fn sighash_taproot_with_type(
+        &self,
+        index: InputIndex,
+        leaf_hash: Option<TapLeafHash>,
+        sighash_type: TapSighashType,
+    ) -> Result<TapSighash, SighashError> {
+        PsbtSighashCache::new(self)?.taproot(self, index.get(), leaf_hash, sighash_type)
+    }
+
+    /// Returns the output spent by the input at `index`.
+    pub(crate) fn spent_txout(&self, index: usize) -> Result<TxOut, SighashError> {
+        let tx = self.unsigned_tx().map_err(SighashError::UnsignedTx)?;
+        spent_utxo(&tx, self.input_at(index)?, index).cloned()
+    }
+
+    pub(crate) fn input_at(&self, index: usize) -> Result<&Input, SighashError> {
+        self.inner
+            .inputs
+            .get(index)
+            .ok_or(SighashError::IndexOutOfBounds {
+                index,
+                length: self.inner.inputs.len(),
+            })
+    }
+}
+
+fn spent_utxo<'a>(
+    tx: &Transaction,
+    input: &'a Input,
+    index: usize,
+) -> Result<&'a TxOut, SighashError> {
+    let txin = tx.input.get(index).ok_or(SighashError::IndexOutOfBounds {
+        index,
+        length: tx.input.len(),
+    })?;
+    input
+        .spent_utxo(&txin.previous_output)
+        .ok_or(SighashError::MissingUtxo { index })
+}
+
+pub(crate) fn ecdsa_sighash_type(
+    input: &Input,
+    index: usize,
+) -> Result<EcdsaSighashType, SighashError> {
+    match input.sighash_type {
+        Some(psbt_sighash_type) => psbt_sighash_type
+            .ecdsa_hash_ty()
+            .map_err(|_| SighashError::InvalidSighashType { index }),
+        None => Ok(EcdsaSighashType::All),
+    }
+}
+
+pub(crate) fn taproot_sighash_type(
+    input: &Input,
+    index: usize,
+) -> Result<TapSighashType, SighashError> {
+    match input.sighash_type {
+        Some(psbt_sighash_type) => psbt_sighash_type
+            .taproot_hash_ty()
+            .map_err(|_| SighashError::InvalidSighashType { index }),
+        None => Ok(TapSighashType::Default),
+    }
+}
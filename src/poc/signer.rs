@@ -0,0 +1,484 @@
+use secp256k1::{Keypair, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+
+use crate::bip32::{self, Xpriv};
+use crate::blockdata::script::Script;
+use crate::crypto::ecdsa;
+use crate::crypto::key::{PublicKey, TapTweak};
+use crate::prelude::*;
+use crate::taproot::{self, TapLeafHash};
+
+use super::fee::FeeError;
+use super::finalizer::{parse_multisig, pays_to_key};
+use super::index::InputIndex;
+use super::input::Input;
+use super::key_origin::OriginKey;
+use super::poc::Psbt;
+use super::secret::{derive_wiped, Wiped};
+use super::sighash::{ecdsa_sighash_type, taproot_sighash_type, PsbtSighashCache, SighashError};
+
+/// Errors encountered while signing a psbt input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignError {
+    /// The sighash of the input could not be computed.
+    Sighash(SighashError),
+    /// The fee violates the psbt's fee policy or could not be checked.
+    Fee(FeeError),
+    /// The key doesn't match the `tap_internal_key` of the input.
+    KeyMismatch { index: usize },
+    /// No script in `tap_scripts` of the input hashes to this leaf hash.
+    UnknownLeaf {
+        index: usize,
+        leaf_hash: TapLeafHash,
+    },
+    /// The control block of this leaf doesn't prove it against the key of
+    /// the output the input spends, or that output is unknown.
+    UnprovableLeaf {
+        index: usize,
+        leaf_hash: TapLeafHash,
+    },
+    /// The input's `sighash_type` is not `SIGHASH_ALL` (or `SIGHASH_DEFAULT`)
+    /// and [`SignOptions::allow_all_sighashes`] is not set.
+    ForbiddenSighashType { index: usize },
+    /// A child key could not be derived from the extended private key.
+    Derivation(bip32::Error),
+}
+
+/// Options controlling what a [`PsbtSigner`] is allowed to sign.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignOptions {
+    /// Only sign the inputs at these indices, all inputs if `None`.
+    pub inputs: Option<Vec<usize>>,
+    /// Allow signing inputs whose `sighash_type` is anything other than
+    /// `SIGHASH_ALL` or `SIGHASH_DEFAULT`.
+    pub allow_all_sighashes: bool,
+    /// Sign taproot inputs for a key path spend when the signer's key is the
+    /// input's `tap_internal_key`.
+    pub sign_with_tap_internal_key: bool,
+}
+
+impl Default for SignOptions {
+    fn default() -> Self {
+        SignOptions {
+            inputs: None,
+            allow_all_sighashes: false,
+            sign_with_tap_internal_key: true,
+        }
+    }
+}
+
+impl SignOptions {
+    /// Returns `true` if the input at `index` may be signed.
+    pub fn includes(&self, index: usize) -> bool {
+        self.inputs
+            .as_ref()
+            .map_or(true, |inputs| inputs.contains(&index))
+    }
+}
+
+/// A key a signature was produced for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SignedKey {
+    /// An ECDSA signature inserted into `partial_sigs`.
+    Ecdsa(PublicKey),
+    /// A taproot key path signature stored in `tap_key_sig`.
+    TaprootKeySpend(XOnlyPublicKey),
+    /// A taproot script path signature inserted into `tap_script_sigs`.
+    TaprootScriptSpend(XOnlyPublicKey, TapLeafHash),
+}
+
+/// The signatures produced by a [`PsbtSigner`], keyed by input index.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SignReport {
+    /// The keys each input was signed for, in signing order. Inputs the
+    /// signer has no key for, or isn't allowed to sign, are absent.
+    pub signed: BTreeMap<usize, Vec<SignedKey>>,
+}
+
+impl SignReport {
+    /// Records that the input at `index` was signed for `key`.
+    pub fn record(&mut self, index: usize, key: SignedKey) {
+        self.signed.entry(index).or_default().push(key);
+    }
+
+    /// Returns `true` if no signature was produced.
+    pub fn is_empty(&self) -> bool {
+        self.signed.is_empty()
+    }
+}
+
+/// Anything able to add signatures to a psbt: software keys, HSMs, hardware
+/// wallets or mock signers in tests.
+pub trait PsbtSigner {
+    /// Signs every input of `psbt` this signer holds keys for and is allowed
+    /// to sign by `options`.
+    fn sign(&self, psbt: &mut Psbt, options: SignOptions) -> Result<SignReport, SignError>;
+}
+
+impl Psbt {
+    /// Signs every input with a key derived from `xpriv`.
+    ///
+    /// Each input's `bip32_derivation` and `tap_key_origins` are scanned for
+    /// entries with the fingerprint of `xpriv`, the child keys are derived
+    /// along the recorded paths and all matching inputs are signed in one go.
+    /// Taproot inputs are only signed for their `tap_key_origins`, other
+    /// inputs only for their `bip32_derivation`.
+    pub fn sign_with_xpriv(&mut self, xpriv: &Xpriv) -> Result<SignReport, SignError> {
+        xpriv.sign(self, SignOptions::default())
+    }
+}
+
+impl PsbtSigner for Xpriv {
+    fn sign(&self, psbt: &mut Psbt, options: SignOptions) -> Result<SignReport, SignError> {
+        let secp = Secp256k1::signing_only();
+        let fingerprint = self.fingerprint(&secp);
+
+        let mut cache = None;
+        let mut report = SignReport::default();
+        for index in 0..psbt.inner.inputs.len() {
+            if !options.includes(index) {
+                continue;
+            }
+            // Inputs of an unknown spend kind are tried with both, signing
+            // reports the missing utxo
+            let spends_taproot = spent_script(psbt, index).map(|script| script.is_p2tr());
+            let input = &psbt.inner.inputs[index];
+            let mut ecdsa_paths = Vec::new();
+            let mut taproot_paths = Vec::new();
+            for origin_key in input.keys_for_fingerprint(fingerprint) {
+                match origin_key {
+                    OriginKey::Ecdsa(public_key, path) if spends_taproot != Some(true) => {
+                        ecdsa_paths.push((public_key, path))
+                    }
+                    OriginKey::Taproot(public_key, path) if spends_taproot != Some(false) => {
+                        taproot_paths.push((public_key, path))
+                    }
+                    _ => {}
+                }
+            }
+            if ecdsa_paths.is_empty() && taproot_paths.is_empty() {
+                continue;
+            }
+            check_sighash_type(input, index, &options)?;
+
+            for (public_key, path) in ecdsa_paths {
+                let child = derive_wiped(self, &secp, &path).map_err(SignError::Derivation)?;
+                // Skip entries whose recorded key doesn't match the derivation,
+                // they belong to someone sharing our fingerprint.
+                if child.private_key.public_key(&secp) != public_key {
+                    continue;
+                }
+                let cache = sighash_cache(psbt, &mut cache)?;
+                let public_key = psbt.sign_input_with(cache, index, &child.private_key)?;
+                report.record(index, SignedKey::Ecdsa(public_key));
+            }
+
+            for (x_only_public_key, path) in taproot_paths {
+                let child = derive_wiped(self, &secp, &path).map_err(SignError::Derivation)?;
+                let keypair = Wiped(child.to_keypair(&secp));
+                if keypair.x_only_public_key().0 != x_only_public_key {
+                    continue;
+                }
+                sign_taproot_input(&keypair, psbt, &mut cache, index, &options, &mut report)?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl PsbtSigner for SecretKey {
+    fn sign(&self, psbt: &mut Psbt, options: SignOptions) -> Result<SignReport, SignError> {
+        let secp = Secp256k1::signing_only();
+        let public_key = PublicKey::new(self.public_key(&secp));
+
+        let mut cache = None;
+        let mut report = SignReport::default();
+        for index in 0..psbt.inner.inputs.len() {
+            let input = &psbt.inner.inputs[index];
+            let spent_script = spent_script(psbt, index);
+            if !options.includes(index) || !involves_key(input, spent_script, &public_key) {
+                continue;
+            }
+            check_sighash_type(input, index, &options)?;
+            let cache = sighash_cache(psbt, &mut cache)?;
+            let public_key = psbt.sign_input_with(cache, index, self)?;
+            report.record(index, SignedKey::Ecdsa(public_key));
+        }
+        Ok(report)
+    }
+}
+
+impl PsbtSigner for Keypair {
+    fn sign(&self, psbt: &mut Psbt, options: SignOptions) -> Result<SignReport, SignError> {
+        let mut cache = None;
+        let mut report = SignReport::default();
+        for index in 0..psbt.inner.inputs.len() {
+            if options.includes(index) {
+                sign_taproot_input(self, psbt, &mut cache, index, &options, &mut report)?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Signs the taproot input at `index` with `keypair`, for a key path spend
+/// if it's the input's internal key and for every leaf it has an origin for.
+///
+/// Every leaf is checked before anything is signed, so an unknown or
+/// unprovable leaf leaves the input untouched.
+fn sign_taproot_input(
+    keypair: &Keypair,
+    psbt: &mut Psbt,
+    cache: &mut Option<PsbtSighashCache>,
+    index: usize,
+    options: &SignOptions,
+    report: &mut SignReport,
+) -> Result<(), SignError> {
+    let (x_only_public_key, _) = keypair.x_only_public_key();
+    let input = &psbt.inner.inputs[index];
+    let leaf_hashes = input
+        .tap_key_origins
+        .get(&x_only_public_key)
+        .map(|(leaf_hashes, _)| leaf_hashes.clone())
+        .unwrap_or_default();
+    let key_spend =
+        options.sign_with_tap_internal_key && input.tap_internal_key == Some(x_only_public_key);
+    if !key_spend && leaf_hashes.is_empty() {
+        return Ok(());
+    }
+    check_sighash_type(input, index, options)?;
+    for leaf_hash in &leaf_hashes {
+        check_tap_leaf(psbt, index, *leaf_hash)?;
+    }
+
+    let cache = sighash_cache(psbt, cache)?;
+    if key_spend {
+        psbt.sign_taproot_key_spend_with(cache, index, keypair)?;
+        report.record(index, SignedKey::TaprootKeySpend(x_only_public_key));
+    }
+    for leaf_hash in leaf_hashes {
+        psbt.sign_taproot_script_spend_with(cache, index, keypair, leaf_hash)?;
+        report.record(
+            index,
+            SignedKey::TaprootScriptSpend(x_only_public_key, leaf_hash),
+        );
+    }
+    Ok(())
+}
+
+/// Checks that `leaf_hash` is one of the `tap_scripts` of the input at
+/// `index` and that its control block proves it against the spent output.
+fn check_tap_leaf(psbt: &Psbt, index: usize, leaf_hash: TapLeafHash) -> Result<(), SignError> {
+    let input = &psbt.inner.inputs[index];
+    let leaf_exists = input
+        .tap_scripts
+        .values()
+        .any(|(script, leaf_version)| TapLeafHash::from_script(script, *leaf_version) == leaf_hash);
+    if !leaf_exists {
+        return Err(SignError::UnknownLeaf { index, leaf_hash });
+    }
+    let spent_utxo = psbt
+        .inner
+        .previous_output(index)
+        .and_then(|outpoint| input.spent_utxo(&outpoint));
+    if input.unprovable_tap_leaves(spent_utxo).contains(&leaf_hash) {
+        return Err(SignError::UnprovableLeaf { index, leaf_hash });
+    }
+    Ok(())
+}
+
+/// Returns the sighash cache of a signing pass over `psbt`, checking the fee
+/// policy and creating the cache before the first input is signed.
+fn sighash_cache<'a>(
+    psbt: &Psbt,
+    cache: &'a mut Option<PsbtSighashCache>,
+) -> Result<&'a mut PsbtSighashCache, SignError> {
+    let sighash_cache = match cache.take() {
+        Some(sighash_cache) => sighash_cache,
+        None => {
+            psbt.check_fee_policy().map_err(SignError::Fee)?;
+            PsbtSighashCache::new(psbt)?
+        }
+    };
+    Ok(cache.insert(sighash_cache))
+}
+
+/// Returns the script of the output the input at `index` spends, from
+/// whichever of its utxos is trusted, `None` if it's unknown.
+fn spent_script(psbt: &Psbt, index: usize) -> Option<&Script> {
+    let outpoint = psbt.inner.previous_output(index)?;
+    psbt.inner.inputs[index]
+        .spent_utxo(&outpoint)
+        .map(|utxo| utxo.script_pubkey.as_script())
+}
+
+/// Returns `true` if `public_key` is one of the input's key origins, or is
+/// paid to by one of its known scripts or by the `spent_script`.
+fn involves_key(input: &Input, spent_script: Option<&Script>, public_key: &PublicKey) -> bool {
+    if input.bip32_derivation.contains_key(&public_key.inner) {
+        return true;
+    }
+    let scripts = [
+        input.redeem_script.as_deref(),
+        input.witness_script.as_deref(),
+        spent_script,
+    ];
+    scripts.into_iter().flatten().any(|script| {
+        pays_to_key(script, public_key)
+            || parse_multisig(script).map_or(false, |(_, keys)| keys.contains(public_key))
+    })
+}
+
+fn check_sighash_type(input: &Input, index: usize, options: &SignOptions) -> Result<(), SignError> {
+    const SIGHASH_DEFAULT: u32 = 0x00;
+    const SIGHASH_ALL: u32 = 0x01;
+
+    let sighash_type = input.sighash_type.map(|sighash_type| sighash_type.to_u32());
+    match sighash_type {
+        None | Some(SIGHASH_DEFAULT) | Some(SIGHASH_ALL) => Ok(()),
+        Some(_) if options.allow_all_sighashes => Ok(()),
+        Some(_) => Err(SignError::ForbiddenSighashType { index }),
+    }
+}
+
+impl From<SighashError> for SignError {
+    fn from(err: SighashError) -> Self {
+        SignError::Sighash(err)
+    }
+}
+
+impl Psbt {
+    /// Signs the non-taproot input at `index` with `secret_key`.
+    ///
+    /// The signature commits to the input's `sighash_type` (`SIGHASH_ALL` if
+    /// unset) and is inserted into `partial_sigs`. Returns the public key the
+    /// signature was made for. Like all signing methods, refuses to sign if
+    /// the fee violates the psbt's [`FeePolicy`](super::fee::FeePolicy).
+    pub fn sign_input(
+        &mut self,
+        index: InputIndex,
+        secret_key: &SecretKey,
+    ) -> Result<PublicKey, SignError> {
+        let mut cache = None;
+        let cache = sighash_cache(self, &mut cache)?;
+        self.sign_input_with(cache, index.get(), secret_key)
+    }
+
+    fn sign_input_with(
+        &mut self,
+        cache: &mut PsbtSighashCache,
+        index: usize,
+        secret_key: &SecretKey,
+    ) -> Result<PublicKey, SignError> {
+        let secp = Secp256k1::signing_only();
+        let sighash_type = ecdsa_sighash_type(self.input_at(index)?, index)?;
+        let message = cache.ecdsa(self, index, sighash_type)?;
+
+        let public_key = PublicKey::new(secret_key.public_key(&secp));
+        let signature = ecdsa::Signature {
+            sig: secp.sign_ecdsa(&message, secret_key),
+            hash_ty: sighash_type,
+        };
+        self.inner.inputs[index]
+            .partial_sigs
+            .insert(public_key, signature);
+        self.refresh_tx_modifiable();
+
+        Ok(public_key)
+    }
+
+    /// Signs the taproot input at `index` for a key path spend.
+    ///
+    /// `keypair` is the untweaked internal key, it's tweaked with the input's
+    /// `tap_merkle_root` (if any) before signing. With no `sighash_type` set
+    /// on the input, `SIGHASH_DEFAULT` is used and the signature is stored in
+    /// `tap_key_sig` without a trailing sighash byte.
+    pub fn sign_taproot_key_spend(
+        &mut self,
+        index: InputIndex,
+        keypair: &Keypair,
+    ) -> Result<XOnlyPublicKey, SignError> {
+        let mut cache = None;
+        let cache = sighash_cache(self, &mut cache)?;
+        self.sign_taproot_key_spend_with(cache, index.get(), keypair)
+    }
+
+    fn sign_taproot_key_spend_with(
+        &mut self,
+        cache: &mut PsbtSighashCache,
+        index: usize,
+        keypair: &Keypair,
+    ) -> Result<XOnlyPublicKey, SignError> {
+        let secp = Secp256k1::signing_only();
+        let sighash_type = taproot_sighash_type(self.input_at(index)?, index)?;
+        let sighash = cache.taproot(self, index, None, sighash_type)?;
+
+        let (internal_key, _) = keypair.x_only_public_key();
+        let input = &self.inner.inputs[index];
+        if input
+            .tap_internal_key
+            .map_or(false, |key| key != internal_key)
+        {
+            return Err(SignError::KeyMismatch { index });
+        }
+
+        let tweaked = Wiped(
+            keypair
+                .tap_tweak(&secp, self.inner.inputs[index].tap_merkle_root)
+                .to_inner(),
+        );
+        let message = Message::from_digest(sighash.to_byte_array());
+        let signature = taproot::Signature {
+            sig: secp.sign_schnorr_no_aux_rand(&message, &tweaked),
+            hash_ty: sighash_type,
+        };
+        self.inner.inputs[index].tap_key_sig = Some(signature);
+        self.refresh_tx_modifiable();
+
+        Ok(internal_key)
+    }
+
+    /// Signs the taproot input at `index` for a script path spend of the leaf
+    /// identified by `leaf_hash`.
+    ///
+    /// The leaf must be one of the input's `tap_scripts`, with a control block
+    /// proving it against the key of the output the input spends. The signature is made
+    /// with the untweaked `keypair` and stored in `tap_script_sigs` under
+    /// `(x_only_public_key, leaf_hash)`.
+    pub fn sign_taproot_script_spend(
+        &mut self,
+        index: InputIndex,
+        keypair: &Keypair,
+        leaf_hash: TapLeafHash,
+    ) -> Result<XOnlyPublicKey, SignError> {
+        let mut cache = None;
+        let cache = sighash_cache(self, &mut cache)?;
+        self.sign_taproot_script_spend_with(cache, index.get(), keypair, leaf_hash)
+    }
+
+    fn sign_taproot_script_spend_with(
+        &mut self,
+        cache: &mut PsbtSighashCache,
+        index: usize,
+        keypair: &Keypair,
+        leaf_hash: TapLeafHash,
+    ) -> Result<XOnlyPublicKey, SignError> {
+        let secp = Secp256k1::signing_only();
+        let sighash_type = taproot_sighash_type(self.input_at(index)?, index)?;
+        let sighash = cache.taproot(self, index, Some(leaf_hash), sighash_type)?;
+
+        check_tap_leaf(self, index, leaf_hash)?;
+
+        let (x_only_public_key, _) = keypair.x_only_public_key();
+        let message = Message::from_digest(sighash.to_byte_array());
+        let signature = taproot::Signature {
+            sig: secp.sign_schnorr_no_aux_rand(&message, keypair),
+            hash_ty: sighash_type,
+        };
+        self.inner.inputs[index]
+            .tap_script_sigs
+            .insert((x_only_public_key, leaf_hash), signature);
+        self.refresh_tx_modifiable();
+
+        Ok(x_only_public_key)
+    }
+}
@@ -0,0 +1,172 @@
+//! Silent payment fields as defined by BIP 375.
+//!
+//! Outputs paying to a silent payment address carry the address instead of a
+//! script. Once every input has contributed its ECDH share, a signer derives
+//! the output key and fills in the output script.
+
+use core::fmt;
+
+use secp256k1::PublicKey;
+
+use crate::prelude::*;
+use crate::psbt::Version;
+
+use super::output::Output;
+use super::poc::Psbt;
+
+/// The scan and spend keys of a silent payment address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SilentPaymentInfo {
+    /// The key the receiver scans the chain with.
+    pub scan_key: PublicKey,
+    /// The key the receiver spends with.
+    pub spend_key: PublicKey,
+}
+
+/// A 64 byte DLEQ proof that an ECDH share was computed with the private key
+/// of the corresponding public key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DleqProof(pub [u8; 64]);
+
+/// Errors encountered while validating the silent payment fields of a psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SilentPaymentError {
+    /// Silent payment outputs are only allowed in a PsbtV2, as a PsbtV0 fixes
+    /// every output script in its unsigned transaction.
+    NotPsbtV2,
+    /// The output at this index carries silent payment info but its script is
+    /// already set.
+    ScriptAlreadySet { index: usize },
+    /// The output at this index has a label but no silent payment info.
+    LabelWithoutInfo { index: usize },
+    /// An ECDH share for `scan_key` has no DLEQ proof, `index` is `None` for
+    /// global shares.
+    MissingDleqProof {
+        index: Option<usize>,
+        scan_key: PublicKey,
+    },
+    /// A field value has the wrong length.
+    InvalidLength { expected: usize, actual: usize },
+    /// A field value isn't a valid public key.
+    InvalidKey,
+}
+
+impl fmt::Display for SilentPaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SilentPaymentError::NotPsbtV2 => write!(f, "silent payments require a PsbtV2"),
+            SilentPaymentError::ScriptAlreadySet { index } => {
+                write!(f, "silent payment output {} already has a script", index)
+            }
+            SilentPaymentError::LabelWithoutInfo { index } => {
+                write!(f, "output {} has a label but no silent payment info", index)
+            }
+            SilentPaymentError::MissingDleqProof { index, scan_key } => match index {
+                Some(index) => write!(f, "input {} has no DLEQ proof for {}", index, scan_key),
+                None => write!(f, "no global DLEQ proof for {}", scan_key),
+            },
+            SilentPaymentError::InvalidLength { expected, actual } => {
+                write!(f, "expected {} bytes, got {}", expected, actual)
+            }
+            SilentPaymentError::InvalidKey => write!(f, "invalid public key"),
+        }
+    }
+}
+
+impl SilentPaymentInfo {
+    /// Serializes the scan key followed by the spend key, both compressed.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = self.scan_key.serialize().to_vec();
+        bytes.extend(self.spend_key.serialize());
+        bytes
+    }
+
+    /// Deserializes the 66 bytes produced by [`SilentPaymentInfo::serialize`].
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, SilentPaymentError> {
+        if bytes.len() != 66 {
+            return Err(SilentPaymentError::InvalidLength {
+                expected: 66,
+                actual: bytes.len(),
+            });
+        }
+        Ok(SilentPaymentInfo {
+            scan_key: PublicKey::from_slice(&bytes[..33])
+                .map_err(|_| SilentPaymentError::InvalidKey)?,
+            spend_key: PublicKey::from_slice(&bytes[33..])
+                .map_err(|_| SilentPaymentError::InvalidKey)?,
+        })
+    }
+}
+
+impl DleqProof {
+    /// Decodes a DLEQ proof from its 64 bytes.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, SilentPaymentError> {
+        bytes
+            .try_into()
+            .map(DleqProof)
+            .map_err(|_| SilentPaymentError::InvalidLength {
+                expected: 64,
+                actual: bytes.len(),
+            })
+    }
+}
+
+impl Output {
+    /// Returns `true` if the output pays to a silent payment address.
+    pub fn is_silent_payment(&self) -> bool {
+        self.sp_v0_info.is_some()
+    }
+
+    /// Checks the silent payment fields of an output about to be added to a
+    /// psbt: a label needs silent payment info, and an output with silent
+    /// payment info can't have a script yet since only a signer can compute
+    /// it.
+    pub fn validate_silent_payment(&self, index: usize) -> Result<(), SilentPaymentError> {
+        if self.sp_v0_label.is_some() && self.sp_v0_info.is_none() {
+            return Err(SilentPaymentError::LabelWithoutInfo { index });
+        }
+        if self.sp_v0_info.is_some() && self.script.is_some() {
+            return Err(SilentPaymentError::ScriptAlreadySet { index });
+        }
+        Ok(())
+    }
+}
+
+impl Psbt {
+    /// Checks the silent payment fields of the whole psbt.
+    ///
+    /// Silent payment outputs require a PsbtV2, labels require silent payment
+    /// info, and every ECDH share, global or per input, needs a DLEQ proof so
+    /// signers can check it before deriving output keys from it.
+    pub fn validate_silent_payments(&self) -> Result<(), SilentPaymentError> {
+        for (index, output) in self.inner.outputs.iter().enumerate() {
+            if output.sp_v0_label.is_some() && output.sp_v0_info.is_none() {
+                return Err(SilentPaymentError::LabelWithoutInfo { index });
+            }
+            if output.is_silent_payment() && self.inner.version != Version::PsbtV2 {
+                return Err(SilentPaymentError::NotPsbtV2);
+            }
+        }
+
+        for scan_key in self.inner.sp_ecdh_shares.keys() {
+            if !self.inner.sp_dleq_proofs.contains_key(scan_key) {
+                return Err(SilentPaymentError::MissingDleqProof {
+                    index: None,
+                    scan_key: *scan_key,
+                });
+            }
+        }
+        for (index, input) in self.inner.inputs.iter().enumerate() {
+            for scan_key in input.sp_ecdh_shares.keys() {
+                if !input.sp_dleq_proofs.contains_key(scan_key) {
+                    return Err(SilentPaymentError::MissingDleqProof {
+                        index: Some(index),
+                        scan_key: *scan_key,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+//! Helpers building and checking the taproot fields of inputs and outputs.
+
+use core::fmt;
+
+use secp256k1::{Secp256k1, XOnlyPublicKey};
+
+use crate::bip32::KeySource;
+use crate::blockdata::script::{Instruction, Script, ScriptBuf};
+use crate::blockdata::transaction::TxOut;
+use crate::crypto::key::{TapTweak, TweakedPublicKey};
+use crate::prelude::*;
+use crate::psbt::Version;
+use crate::taproot::{LeafVersion, NodeInfo, TapLeafHash, TapTree, TaprootBuilder};
+
+use super::input::Input;
+use super::output::Output;
+
+/// Errors encountered while building or checking taproot fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaprootError {
+    /// The leaves can't be placed in a tree at their depths.
+    InvalidTree(String),
+}
+
+impl fmt::Display for TaprootError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaprootError::InvalidTree(err) => write!(f, "invalid tap tree: {}", err),
+        }
+    }
+}
+
+impl Output {
+    /// Makes the output pay to `internal_key` tweaked with a tree of the
+    /// tapscript `leaves`, given with their depth in the tree, returning the
+    /// script paid to.
+    ///
+    /// Sets `tap_internal_key` and `tap_tree`, and adds the internal key and
+    /// the keys used by the leaves to `tap_key_origins`, each with the hashes
+    /// of the leaves using it. Keys get their origin from `key_origins`
+    /// unless the output already has one, keys without either are left out.
+    /// The output of a PsbtV2, `version`, also gets the script.
+    pub fn set_taproot(
+        &mut self,
+        version: Version,
+        internal_key: XOnlyPublicKey,
+        leaves: &[(u8, ScriptBuf)],
+        key_origins: &BTreeMap<XOnlyPublicKey, KeySource>,
+    ) -> Result<ScriptBuf, TaprootError> {
+        let builder = leaves
+            .iter()
+            .try_fold(TaprootBuilder::new(), |builder, (depth, script)| {
+                builder.add_leaf(*depth, script.clone())
+            })
+            .map_err(|err| TaprootError::InvalidTree(err.to_string()))?;
+        let tap_tree = if leaves.is_empty() {
+            None
+        } else {
+            Some(
+                TapTree::try_from(builder.clone())
+                    .map_err(|err| TaprootError::InvalidTree(err.to_string()))?,
+            )
+        };
+        let spend_info = builder
+            .finalize(&Secp256k1::verification_only(), internal_key)
+            .map_err(|_| TaprootError::InvalidTree(String::from("incomplete tree")))?;
+
+        if let Some(origin) = key_origins.get(&internal_key) {
+            self.tap_key_origins
+                .entry(internal_key)
+                .or_insert_with(|| (vec![], origin.clone()));
+        }
+        for (_, script) in leaves {
+            let leaf_hash = TapLeafHash::from_script(script, LeafVersion::TapScript);
+            for key in script_keys(script) {
+                if !self.tap_key_origins.contains_key(&key) {
+                    match key_origins.get(&key) {
+                        Some(origin) => {
+                            self.tap_key_origins.insert(key, (vec![], origin.clone()));
+                        }
+                        None => continue,
+                    }
+                }
+                if let Some((leaf_hashes, _)) = self.tap_key_origins.get_mut(&key) {
+                    if !leaf_hashes.contains(&leaf_hash) {
+                        leaf_hashes.push(leaf_hash);
+                    }
+                }
+            }
+        }
+        self.tap_internal_key = Some(internal_key);
+        self.tap_tree = tap_tree;
+
+        let script = ScriptBuf::new_p2tr_tweaked(spend_info.output_key());
+        if version == Version::PsbtV2 {
+            self.script = Some(script.clone());
+        }
+        Ok(script)
+    }
+}
+
+impl Input {
+    /// Returns the output key the spent output must pay to, tweaking
+    /// `tap_internal_key` with `tap_merkle_root`, or `None` without an
+    /// internal key.
+    pub fn taproot_output_key(&self) -> Option<TweakedPublicKey> {
+        let internal_key = self.tap_internal_key?;
+        let (output_key, _) =
+            internal_key.tap_tweak(&Secp256k1::verification_only(), self.tap_merkle_root);
+        Some(output_key)
+    }
+
+    /// Returns the hashes of the leaves in `tap_scripts` whose control block
+    /// doesn't prove them to be committed to by the key of `spent_utxo`, the
+    /// output the input spends.
+    ///
+    /// The proofs are checked against the spent output rather than the
+    /// input's own `tap_internal_key` and `tap_merkle_root`, which come from
+    /// the same untrusted psbt. Without a P2TR `spent_utxo` nothing can be
+    /// proven and every leaf is reported.
+    pub fn unprovable_tap_leaves(&self, spent_utxo: Option<&TxOut>) -> Vec<TapLeafHash> {
+        let output_key = spent_utxo.and_then(|utxo| p2tr_output_key(&utxo.script_pubkey));
+        let secp = Secp256k1::verification_only();
+        self.tap_scripts
+            .iter()
+            .filter(|(control_block, (script, _))| {
+                output_key.map_or(true, |output_key| {
+                    !control_block.verify_taproot_commitment(&secp, output_key, script)
+                })
+            })
+            .map(|(_, (script, leaf_version))| TapLeafHash::from_script(script, *leaf_version))
+            .collect()
+    }
+}
+
+impl Output {
+    /// Returns the output key the output must pay to, tweaking
+    /// `tap_internal_key` with the root of `tap_tree`, or `None` without an
+    /// internal key.
+    pub fn taproot_output_key(&self) -> Option<TweakedPublicKey> {
+        let internal_key = self.tap_internal_key?;
+        let merkle_root = self
+            .tap_tree
+            .clone()
+            .map(|tree| NodeInfo::from(tree).node_hash());
+        let (output_key, _) = internal_key.tap_tweak(&Secp256k1::verification_only(), merkle_root);
+        Some(output_key)
+    }
+}
+
+/// Returns the output key a P2TR script pays to, or `None` if the script
+/// isn't P2TR.
+pub(crate) fn p2tr_output_key(script: &Script) -> Option<XOnlyPublicKey> {
+    if !script.is_p2tr() {
+        return None;
+    }
+    XOnlyPublicKey::from_slice(&script.as_bytes()[2..]).ok()
+}
+
+/// The x-only keys pushed by a tapscript.
+fn script_keys(script: &Script) -> Vec<XOnlyPublicKey> {
+    script
+        .instructions()
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => XOnlyPublicKey::from_slice(bytes.as_bytes()).ok(),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,53 @@
+//! Access to the key-value pairs this crate doesn't know, such as fields of
+//! newer psbt versions or of BIPs it doesn't implement yet.
+//!
+//! Unknown pairs are carried untouched: parsing keeps every pair it can't
+//! give meaning to in the `unknown` map it was found in, combining keeps the
+//! pairs of both psbts, upgrading to PsbtV2 or converting to rust-bitcoin's
+//! psbt keeps every map, and serializing writes each pair back unless a
+//! field of the map now uses its key.
+
+use crate::prelude::*;
+use crate::psbt::raw;
+
+use super::poc::Psbt;
+use super::serialize::MapLocation;
+
+impl Psbt {
+    /// Iterates over the unknown key-value pairs of the global map, then of
+    /// every input and output map, along with the map they're in.
+    pub fn unknown_keys(&self) -> impl Iterator<Item = (MapLocation, &raw::Key, &[u8])> + '_ {
+        let global = self
+            .inner
+            .unknown
+            .iter()
+            .map(|(key, value)| (MapLocation::Global, key, value.as_slice()));
+        let inputs = self
+            .inner
+            .inputs
+            .iter()
+            .enumerate()
+            .flat_map(|(index, input)| {
+                input
+                    .unknown
+                    .iter()
+                    .map(move |(key, value)| (MapLocation::Input(index), key, value.as_slice()))
+            });
+        let outputs =
+            self.inner
+                .outputs
+                .iter()
+                .enumerate()
+                .flat_map(|(index, output)| {
+                    output.unknown.iter().map(move |(key, value)| {
+                        (MapLocation::Output(index), key, value.as_slice())
+                    })
+                });
+        global.chain(inputs).chain(outputs)
+    }
+
+    /// Returns `true` if any map of the psbt has unknown key-value pairs.
+    pub fn has_unknown_keys(&self) -> bool {
+        self.unknown_keys().next().is_some()
+    }
+}
@@ -0,0 +1,50 @@
+//! Conversion of a PsbtV0 into the equivalent PsbtV2 of BIP-370.
+
+use crate::prelude::*;
+use crate::psbt::Version;
+
+use super::poc::Psbt;
+
+impl Psbt {
+    /// Converts a PsbtV0 into a PsbtV2 describing the same transaction, a
+    /// PsbtV2 is left as it is.
+    ///
+    /// The unsigned transaction is split into the per-input and per-output
+    /// fields, its locktime becomes the `fallback_locktime`. No input or
+    /// output is made modifiable, so existing signatures stay valid.
+    ///
+    /// BIP 370 requires a transaction version of at least 2, so a PsbtV0 of
+    /// a version 1 transaction can't be upgraded.
+    pub fn upgrade_to_v2(&mut self) -> Result<(), String> {
+        if self.inner.version == Version::PsbtV2 {
+            return Ok(());
+        }
+        if let Some(ref tx) = self.inner.unsigned_tx {
+            if tx.version.0 < 2 {
+                return Err(format!(
+                    "PsbtV2 requires transaction version 2 or above, not {}",
+                    tx.version.0
+                ));
+            }
+        }
+        let tx = self
+            .inner
+            .unsigned_tx
+            .take()
+            .ok_or_else(|| String::from("PsbtV0 is missing the unsigned transaction"))?;
+
+        for (input, txin) in self.inner.inputs.iter_mut().zip(&tx.input) {
+            input.previous_tx_id = Some(txin.previous_output.txid.into());
+            input.output_index = Some(txin.previous_output.vout);
+            input.sequence = Some(txin.sequence);
+        }
+        for (output, txout) in self.inner.outputs.iter_mut().zip(tx.output) {
+            output.amount = Some(txout.value);
+            output.script = Some(txout.script_pubkey);
+        }
+        self.inner.tx_version = Some(tx.version.0);
+        self.inner.fallback_locktime = Some(tx.lock_time.to_consensus_u32());
+        self.inner.version = Version::PsbtV2;
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+//! Uniform Resource (BC-UR) encoding of psbts as the `crypto-psbt` type,
+//! used by air-gapped signers to pass psbts around as QR codes. Large psbts
+//! are split into fountain-coded parts shown as an animated QR code.
+
+use core::fmt;
+
+use crate::prelude::*;
+
+use super::poc::Psbt;
+use super::serialize::DecodeError;
+
+/// The UR type of a psbt.
+pub const UR_TYPE: &str = "crypto-psbt";
+
+/// Errors encountered while decoding a psbt from a UR.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrError {
+    /// The string isn't a valid UR.
+    Encoding(String),
+    /// The UR is of another type than [`UR_TYPE`].
+    WrongType(String),
+    /// The UR is one part of a multi-part UR.
+    MultiPart,
+    /// The payload of the UR isn't a CBOR byte string.
+    InvalidCbor,
+    /// The wrapped bytes aren't a valid psbt.
+    Psbt(DecodeError),
+}
+
+impl fmt::Display for UrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrError::Encoding(err) => write!(f, "invalid UR: {}", err),
+            UrError::WrongType(ur_type) => {
+                write!(f, "expected a {} UR, got {}", UR_TYPE, ur_type)
+            }
+            UrError::MultiPart => write!(f, "multi-part UR where a single part was expected"),
+            UrError::InvalidCbor => write!(f, "UR payload isn't a CBOR byte string"),
+            UrError::Psbt(err) => write!(f, "invalid psbt: {}", err),
+        }
+    }
+}
+
+impl Psbt {
+    /// Encodes the psbt as a single-part `ur:crypto-psbt/...` string.
+    pub fn to_ur(&self) -> String {
+        let payload = cbor_wrap(&self.serialize());
+        ::ur::ur::encode(&payload, &::ur::Type::Custom(UR_TYPE))
+    }
+
+    /// Decodes and validates a psbt from a single-part `crypto-psbt` UR.
+    pub fn from_ur(s: &str) -> Result<Psbt, UrError> {
+        check_ur_type(s)?;
+        let (kind, payload) =
+            ::ur::ur::decode(s).map_err(|err| UrError::Encoding(err.to_string()))?;
+        if kind != ::ur::ur::Kind::SinglePart {
+            return Err(UrError::MultiPart);
+        }
+        psbt_from_payload(&payload)
+    }
+}
+
+impl Psbt {
+    /// Splits the psbt into fountain-coded multi-part URs, each carrying at
+    /// most `max_fragment_len` bytes of the psbt.
+    ///
+    /// The returned iterator never ends: after the plain fragments it keeps
+    /// yielding mixed ones, so a [`FragmentAssembler`] can recover the psbt
+    /// from any sufficiently large subset of the parts, in any order.
+    pub fn to_fragments(&self, max_fragment_len: usize) -> Result<Fragments, UrError> {
+        let payload = cbor_wrap(&self.serialize());
+        let encoder = ::ur::Encoder::new(&payload, max_fragment_len, UR_TYPE)
+            .map_err(|err| UrError::Encoding(err.to_string()))?;
+        Ok(Fragments { encoder })
+    }
+}
+
+/// The parts of a multi-part `crypto-psbt` UR, see [`Psbt::to_fragments`].
+pub struct Fragments {
+    encoder: ::ur::Encoder,
+}
+
+impl Fragments {
+    /// The number of parts the psbt was split into, the minimum number of
+    /// parts needed to reassemble it.
+    pub fn fragment_count(&self) -> usize {
+        self.encoder.fragment_count()
+    }
+}
+
+impl Iterator for Fragments {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.encoder.next_part().ok()
+    }
+}
+
+/// Reassembles a psbt from the parts of a multi-part `crypto-psbt` UR,
+/// received in any order.
+#[derive(Default)]
+pub struct FragmentAssembler {
+    decoder: ::ur::Decoder,
+}
+
+impl FragmentAssembler {
+    /// Creates an assembler that hasn't received any part yet.
+    pub fn new() -> Self {
+        FragmentAssembler::default()
+    }
+
+    /// Adds a scanned part. Duplicates are ignored.
+    pub fn receive(&mut self, part: &str) -> Result<(), UrError> {
+        check_ur_type(part)?;
+        self.decoder
+            .receive(part)
+            .map_err(|err| UrError::Encoding(err.to_string()))
+    }
+
+    /// Returns `true` once enough parts were received to reassemble the psbt.
+    pub fn is_complete(&self) -> bool {
+        self.decoder.complete()
+    }
+
+    /// Returns the reassembled and validated psbt, `None` while parts are
+    /// still missing.
+    pub fn psbt(&self) -> Result<Option<Psbt>, UrError> {
+        let payload = self
+            .decoder
+            .message()
+            .map_err(|err| UrError::Encoding(err.to_string()))?;
+        payload
+            .map(|payload| psbt_from_payload(&payload))
+            .transpose()
+    }
+}
+
+/// Fails unless `ur` is of type [`UR_TYPE`]. URs are case-insensitive, as QR
+/// codes carry them in uppercase.
+fn check_ur_type(ur: &str) -> Result<(), UrError> {
+    let ur = ur.to_ascii_lowercase();
+    let ur_type = ur
+        .strip_prefix("ur:")
+        .and_then(|rest| rest.split('/').next())
+        .ok_or_else(|| UrError::Encoding(String::from("missing ur: prefix")))?;
+    if ur_type != UR_TYPE {
+        return Err(UrError::WrongType(ur_type.to_string()));
+    }
+    Ok(())
+}
+
+/// Unwraps and decodes the psbt carried by a `crypto-psbt` payload.
+fn psbt_from_payload(payload: &[u8]) -> Result<Psbt, UrError> {
+    let bytes = cbor_unwrap(payload).ok_or(UrError::InvalidCbor)?;
+    Psbt::deserialize(bytes).map_err(UrError::Psbt)
+}
+
+/// Wraps `bytes` in a CBOR byte string, the payload of a `crypto-psbt`.
+fn cbor_wrap(bytes: &[u8]) -> Vec<u8> {
+    const BYTE_STRING: u8 = 0x40;
+
+    let len = bytes.len() as u64;
+    let mut cbor = Vec::with_capacity(bytes.len() + 9);
+    match len {
+        0..=23 => cbor.push(BYTE_STRING | len as u8),
+        24..=0xff => {
+            cbor.push(BYTE_STRING | 24);
+            cbor.push(len as u8);
+        }
+        0x100..=0xffff => {
+            cbor.push(BYTE_STRING | 25);
+            cbor.extend((len as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            cbor.push(BYTE_STRING | 26);
+            cbor.extend((len as u32).to_be_bytes());
+        }
+        _ => {
+            cbor.push(BYTE_STRING | 27);
+            cbor.extend(len.to_be_bytes());
+        }
+    }
+    cbor.extend_from_slice(bytes);
+    cbor
+}
+
+/// Returns the content of the CBOR byte string `cbor`, or `None` if it's
+/// anything else or has trailing data.
+fn cbor_unwrap(cbor: &[u8]) -> Option<&[u8]> {
+    let (&head, rest) = cbor.split_first()?;
+    if head & 0xe0 != 0x40 {
+        return None;
+    }
+    let (len, rest) = match head & 0x1f {
+        len @ 0..=23 => (len as u64, rest),
+        24 => read_be(rest, 1)?,
+        25 => read_be(rest, 2)?,
+        26 => read_be(rest, 4)?,
+        27 => read_be(rest, 8)?,
+        _ => return None,
+    };
+    (rest.len() as u64 == len).then_some(rest)
+}
+
+/// Reads a big endian integer of `size` bytes off the front of `bytes`.
+fn read_be(bytes: &[u8], size: usize) -> Option<(u64, &[u8])> {
+    if bytes.len() < size {
+        return None;
+    }
+    let (int, rest) = bytes.split_at(size);
+    let value = int
+        .iter()
+        .fold(0u64, |value, byte| value << 8 | *byte as u64);
+    Some((value, rest))
+}
@@ -0,0 +1,142 @@
+//! Filling of the utxo fields of inputs from a source of previous
+//! transactions, such as a wallet database, a block explorer or a node.
+
+use core::fmt;
+
+use crate::blockdata::transaction::Transaction;
+use crate::hash_types::Txid;
+use crate::prelude::sync::Arc;
+use crate::prelude::*;
+
+use super::input::Input;
+use super::poc::Psbt;
+
+/// A source of the transactions spent by psbt inputs.
+pub trait UtxoProvider {
+    /// Returns the transaction with id `txid`, `None` if it's unknown.
+    fn get_tx(&self, txid: &Txid) -> Option<Transaction>;
+}
+
+impl UtxoProvider for BTreeMap<Txid, Transaction> {
+    fn get_tx(&self, txid: &Txid) -> Option<Transaction> {
+        self.get(txid).cloned()
+    }
+}
+
+/// Errors encountered while filling the utxos of a psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UtxoError {
+    /// The unsigned transaction could not be built from the psbt.
+    UnsignedTx(String),
+    /// The provider returned a transaction with another id than the one the
+    /// input at this index spends from.
+    WrongTx { index: usize, txid: Txid },
+    /// The spent transaction has no output at the index the input at this
+    /// index spends.
+    NoSuchOutput { index: usize },
+}
+
+impl fmt::Display for UtxoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UtxoError::UnsignedTx(err) => write!(f, "invalid unsigned transaction: {}", err),
+            UtxoError::WrongTx { index, txid } => {
+                write!(
+                    f,
+                    "provider returned the wrong transaction for input {}, expected {}",
+                    index, txid
+                )
+            }
+            UtxoError::NoSuchOutput { index } => {
+                write!(f, "transaction spent by input {} has no such output", index)
+            }
+        }
+    }
+}
+
+impl Psbt {
+    /// Sets the missing `non_witness_utxo` and `witness_utxo` of every input
+    /// from the transactions of `provider`.
+    ///
+    /// `non_witness_utxo` is set on all inputs but taproot ones, which
+    /// BIP-371 doesn't require it for. `witness_utxo` is set on inputs
+    /// spending a witness program, directly or through their redeem script.
+    /// Each previous transaction is requested once, and not at all for
+    /// inputs already carrying it. Returns the indices of the inputs whose
+    /// transaction the provider doesn't know, which are left untouched.
+    pub fn fill_utxos<P: UtxoProvider + ?Sized>(
+        &mut self,
+        provider: &P,
+    ) -> Result<Vec<usize>, UtxoError> {
+        let tx = self.unsigned_tx().map_err(UtxoError::UnsignedTx)?;
+
+        // Inputs spending from the same transaction share one copy of it
+        let mut transactions: BTreeMap<Txid, Option<Arc<Transaction>>> = BTreeMap::new();
+        let mut unknown = Vec::new();
+        for (index, (txin, input)) in tx.input.iter().zip(&mut self.inner.inputs).enumerate() {
+            if input.has_utxos() {
+                continue;
+            }
+            let txid = txin.previous_output.txid;
+            let previous_tx = match input.non_witness_utxo {
+                Some(ref previous_tx) => Arc::clone(previous_tx),
+                None => match transactions
+                    .entry(txid)
+                    .or_insert_with(|| provider.get_tx(&txid).map(Arc::new))
+                {
+                    Some(previous_tx) => Arc::clone(previous_tx),
+                    None => {
+                        unknown.push(index);
+                        continue;
+                    }
+                },
+            };
+            if previous_tx.txid() != txid {
+                return Err(UtxoError::WrongTx { index, txid });
+            }
+            input.fill_utxo(&previous_tx, txin.previous_output.vout, index)?;
+        }
+        Ok(unknown)
+    }
+}
+
+impl Input {
+    /// Returns `true` if the input has a taproot `witness_utxo` or both utxo
+    /// fields, so there's nothing left to fill.
+    fn has_utxos(&self) -> bool {
+        match (&self.witness_utxo, &self.non_witness_utxo) {
+            (Some(utxo), _) if utxo.script_pubkey.is_p2tr() => true,
+            (Some(_), Some(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Sets the missing utxo fields of the input, spending output `vout` of
+    /// `previous_tx`.
+    fn fill_utxo(
+        &mut self,
+        previous_tx: &Arc<Transaction>,
+        vout: u32,
+        index: usize,
+    ) -> Result<(), UtxoError> {
+        let utxo = previous_tx
+            .output
+            .get(vout as usize)
+            .ok_or(UtxoError::NoSuchOutput { index })?;
+        let script_pubkey = &utxo.script_pubkey;
+
+        let is_witness = script_pubkey.is_witness_program()
+            || (script_pubkey.is_p2sh()
+                && self
+                    .redeem_script
+                    .as_ref()
+                    .map_or(false, |script| script.is_witness_program()));
+        if is_witness && self.witness_utxo.is_none() {
+            self.witness_utxo = Some(utxo.clone());
+        }
+        if !script_pubkey.is_p2tr() && self.non_witness_utxo.is_none() {
+            self.non_witness_utxo = Some(Arc::clone(previous_tx));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,495 @@
+//! Structural validation of psbts as required by BIP 174 and BIP 370.
+
+use core::fmt;
+
+use crate::blockdata::transaction::OutPoint;
+use crate::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use crate::prelude::*;
+use crate::psbt::Version;
+
+use super::input::Input;
+use super::poc::PartiallySignedTransactionInner;
+use super::taproot::p2tr_output_key;
+
+/// A single rule of BIP 174 or BIP 370 a psbt breaks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// A PsbtV0 has no unsigned transaction.
+    MissingUnsignedTx,
+    /// A PsbtV2 carries an unsigned transaction.
+    UnexpectedUnsignedTx,
+    /// The input at this index of the unsigned transaction has a scriptSig
+    /// or a witness.
+    UnsignedTxNotEmpty { index: usize },
+    /// The number of input maps differs from the number of inputs of the
+    /// unsigned transaction.
+    InputCountMismatch { maps: usize, tx_inputs: usize },
+    /// The number of output maps differs from the number of outputs of the
+    /// unsigned transaction.
+    OutputCountMismatch { maps: usize, tx_outputs: usize },
+    /// A global field only allowed in a PsbtV2 is set in a PsbtV0.
+    UnexpectedGlobalField { field: &'static str },
+    /// A global field required in a PsbtV2 is missing.
+    MissingGlobalField { field: &'static str },
+    /// The transaction version of a PsbtV2 is below 2, which BIP 370
+    /// forbids.
+    InvalidTxVersion { version: i32 },
+    /// An input field isn't allowed or is missing for the psbt's version.
+    VersionField(VersionFieldError),
+    /// A field only allowed in a PsbtV2 is set on the output at this index of
+    /// a PsbtV0.
+    UnexpectedOutputField { index: usize, field: &'static str },
+    /// A field required in a PsbtV2 is missing on the output at this index.
+    MissingOutputField { index: usize, field: &'static str },
+    /// The input at this index is finalized but still carries `field`, which
+    /// the finalizer must have cleared.
+    FinalizedWithPartialData { index: usize, field: &'static str },
+    /// The input at this index uses `SIGHASH_SINGLE` but there's no output
+    /// at the same index for it to commit to.
+    UnpairedSighashSingle { index: usize },
+    /// A preimage of the input at this index doesn't hash to the hash it's
+    /// stored under.
+    PreimageMismatch {
+        index: usize,
+        hash_type: PreimageHashType,
+    },
+    /// The taproot internal key and merkle root of the input at this index
+    /// don't tweak to the key of the output it spends.
+    InputTaprootKeyMismatch { index: usize },
+    /// The taproot internal key and tree of the output at this index don't
+    /// tweak to the key of its script.
+    OutputTaprootKeyMismatch { index: usize },
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::MissingUnsignedTx => write!(f, "PsbtV0 has no unsigned transaction"),
+            ValidationIssue::UnexpectedUnsignedTx => {
+                write!(f, "PsbtV2 must not have an unsigned transaction")
+            }
+            ValidationIssue::UnsignedTxNotEmpty { index } => write!(
+                f,
+                "input {} of the unsigned transaction has a scriptSig or witness",
+                index
+            ),
+            ValidationIssue::InputCountMismatch { maps, tx_inputs } => write!(
+                f,
+                "{} input maps for {} transaction inputs",
+                maps, tx_inputs
+            ),
+            ValidationIssue::OutputCountMismatch { maps, tx_outputs } => write!(
+                f,
+                "{} output maps for {} transaction outputs",
+                maps, tx_outputs
+            ),
+            ValidationIssue::UnexpectedGlobalField { field } => {
+                write!(f, "PsbtV0 must not have the global {}", field)
+            }
+            ValidationIssue::MissingGlobalField { field } => {
+                write!(f, "PsbtV2 is missing the global {}", field)
+            }
+            ValidationIssue::InvalidTxVersion { version } => {
+                write!(f, "PsbtV2 transaction version {} is below 2", version)
+            }
+            ValidationIssue::VersionField(err) => write!(f, "{}", err),
+            ValidationIssue::UnexpectedOutputField { index, field } => {
+                write!(f, "output {} of a PsbtV0 must not have {}", index, field)
+            }
+            ValidationIssue::MissingOutputField { index, field } => {
+                write!(f, "output {} is missing {}", index, field)
+            }
+            ValidationIssue::FinalizedWithPartialData { index, field } => {
+                write!(f, "finalized input {} still has {}", index, field)
+            }
+            ValidationIssue::UnpairedSighashSingle { index } => write!(
+                f,
+                "input {} uses SIGHASH_SINGLE without an output at its index",
+                index
+            ),
+            ValidationIssue::PreimageMismatch { index, hash_type } => write!(
+                f,
+                "input {} has a {} preimage not matching its hash",
+                index, hash_type
+            ),
+            ValidationIssue::InputTaprootKeyMismatch { index } => write!(
+                f,
+                "taproot key of input {} doesn't match the output it spends",
+                index
+            ),
+            ValidationIssue::OutputTaprootKeyMismatch { index } => write!(
+                f,
+                "taproot key of output {} doesn't match its script",
+                index
+            ),
+        }
+    }
+}
+
+/// The hash function of a preimage field of an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PreimageHashType {
+    Ripemd160,
+    Sha256,
+    Hash160,
+    Hash256,
+}
+
+impl fmt::Display for PreimageHashType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PreimageHashType::Ripemd160 => "RIPEMD160",
+            PreimageHashType::Sha256 => "SHA256",
+            PreimageHashType::Hash160 => "HASH160",
+            PreimageHashType::Hash256 => "HASH256",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The PsbtV2 fields of an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputVersionField {
+    PreviousTxId,
+    OutputIndex,
+    Sequence,
+    RequiredTimeLocktime,
+    RequiredHeightLocktime,
+}
+
+impl fmt::Display for InputVersionField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            InputVersionField::PreviousTxId => "previous txid",
+            InputVersionField::OutputIndex => "output index",
+            InputVersionField::Sequence => "sequence",
+            InputVersionField::RequiredTimeLocktime => "required time locktime",
+            InputVersionField::RequiredHeightLocktime => "required height locktime",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An input field that doesn't fit the version of its psbt: a PsbtV2 field
+/// set in a PsbtV0, or a field a PsbtV2 requires that is missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionFieldError {
+    /// The index of the input.
+    pub input_index: usize,
+    /// The offending field.
+    pub field: InputVersionField,
+    /// The version of the psbt, telling whether the field is forbidden
+    /// (PsbtV0) or missing (PsbtV2).
+    pub version: Version,
+}
+
+impl fmt::Display for VersionFieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            Version::PsbtV0 => write!(
+                f,
+                "input {} of a PsbtV0 must not have the {}",
+                self.input_index, self.field
+            ),
+            Version::PsbtV2 => write!(
+                f,
+                "input {} of a PsbtV2 is missing the {}",
+                self.input_index, self.field
+            ),
+        }
+    }
+}
+
+impl Input {
+    /// Returns the fields of the input at `index` that don't fit a psbt of
+    /// `version`.
+    ///
+    /// A PsbtV0 input must not have any PsbtV2 field, a PsbtV2 input must
+    /// have its previous txid and output index.
+    pub fn version_field_errors(&self, version: Version, index: usize) -> Vec<VersionFieldError> {
+        let fields = [
+            (
+                InputVersionField::PreviousTxId,
+                self.previous_tx_id.is_some(),
+                true,
+            ),
+            (
+                InputVersionField::OutputIndex,
+                self.output_index.is_some(),
+                true,
+            ),
+            (InputVersionField::Sequence, self.sequence.is_some(), false),
+            (
+                InputVersionField::RequiredTimeLocktime,
+                self.required_time_locktime.is_some(),
+                false,
+            ),
+            (
+                InputVersionField::RequiredHeightLocktime,
+                self.required_height_locktime.is_some(),
+                false,
+            ),
+        ];
+
+        fields
+            .into_iter()
+            .filter(|(_, present, required)| match version {
+                Version::PsbtV0 => *present,
+                Version::PsbtV2 => *required && !*present,
+            })
+            .map(|(field, _, _)| VersionFieldError {
+                input_index: index,
+                field,
+                version,
+            })
+            .collect()
+    }
+}
+
+/// Every issue found by [`PartiallySignedTransactionInner::validate`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ValidationReport {
+    /// The issues, in the order the psbt was checked.
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Returns `true` if no issue was found.
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn push(&mut self, issue: ValidationIssue) {
+        self.issues.push(issue);
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl PartiallySignedTransactionInner {
+    /// Checks the psbt against the structural rules of its version and
+    /// reports every rule it breaks.
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        match self.version {
+            Version::PsbtV0 => self.validate_v0(&mut report),
+            Version::PsbtV2 => self.validate_v2(&mut report),
+        }
+        for (index, input) in self.inputs.iter().enumerate() {
+            for err in input.version_field_errors(self.version, index) {
+                report.push(ValidationIssue::VersionField(err));
+            }
+            validate_finalized(input, index, &mut report);
+            validate_preimages(input, index, &mut report);
+            if index >= self.outputs.len() && input.uses_sighash_single() {
+                report.push(ValidationIssue::UnpairedSighashSingle { index });
+            }
+        }
+        self.validate_taproot_keys(&mut report);
+        report
+    }
+
+    /// Reports the inputs and outputs whose taproot internal key and tree
+    /// don't tweak to the key of their script. Scripts that aren't known yet
+    /// are skipped.
+    fn validate_taproot_keys(&self, report: &mut ValidationReport) {
+        for (index, input) in self.inputs.iter().enumerate() {
+            let output_key = match input.taproot_output_key() {
+                Some(output_key) => output_key,
+                None => continue,
+            };
+            let utxo = self
+                .previous_output(index)
+                .and_then(|outpoint| input.spent_utxo(&outpoint));
+            if let Some(utxo) = utxo {
+                if p2tr_output_key(&utxo.script_pubkey) != Some(output_key.to_inner()) {
+                    report.push(ValidationIssue::InputTaprootKeyMismatch { index });
+                }
+            }
+        }
+        for (index, output) in self.outputs.iter().enumerate() {
+            let output_key = match output.taproot_output_key() {
+                Some(output_key) => output_key,
+                None => continue,
+            };
+            if let Some(script) = output.script_pubkey(self.unsigned_tx.as_ref(), index) {
+                if p2tr_output_key(script) != Some(output_key.to_inner()) {
+                    report.push(ValidationIssue::OutputTaprootKeyMismatch { index });
+                }
+            }
+        }
+    }
+
+    /// Returns the outpoint spent by the input at `index`, if known.
+    pub(crate) fn previous_output(&self, index: usize) -> Option<OutPoint> {
+        match self.version {
+            Version::PsbtV0 => Some(self.unsigned_tx.as_ref()?.input.get(index)?.previous_output),
+            Version::PsbtV2 => {
+                let input = self.inputs.get(index)?;
+                Some(OutPoint::new(
+                    input.previous_tx_id.as_ref()?.to_txid(),
+                    input.output_index?,
+                ))
+            }
+        }
+    }
+
+    fn validate_v0(&self, report: &mut ValidationReport) {
+        match self.unsigned_tx {
+            Some(ref tx) => {
+                for (index, txin) in tx.input.iter().enumerate() {
+                    if !txin.script_sig.is_empty() || !txin.witness.is_empty() {
+                        report.push(ValidationIssue::UnsignedTxNotEmpty { index });
+                    }
+                }
+                if self.inputs.len() != tx.input.len() {
+                    report.push(ValidationIssue::InputCountMismatch {
+                        maps: self.inputs.len(),
+                        tx_inputs: tx.input.len(),
+                    });
+                }
+                if self.outputs.len() != tx.output.len() {
+                    report.push(ValidationIssue::OutputCountMismatch {
+                        maps: self.outputs.len(),
+                        tx_outputs: tx.output.len(),
+                    });
+                }
+            }
+            None => report.push(ValidationIssue::MissingUnsignedTx),
+        }
+
+        let globals = [
+            ("transaction version", self.tx_version.is_some()),
+            ("fallback locktime", self.fallback_locktime.is_some()),
+            ("tx modifiable flags", self.tx_modifiable.is_some()),
+        ];
+        for (field, present) in globals {
+            if present {
+                report.push(ValidationIssue::UnexpectedGlobalField { field });
+            }
+        }
+
+        for (index, output) in self.outputs.iter().enumerate() {
+            let fields = [
+                ("amount", output.amount.is_some()),
+                ("script", output.script.is_some()),
+            ];
+            for (field, present) in fields {
+                if present {
+                    report.push(ValidationIssue::UnexpectedOutputField { index, field });
+                }
+            }
+        }
+    }
+
+    fn validate_v2(&self, report: &mut ValidationReport) {
+        if self.unsigned_tx.is_some() {
+            report.push(ValidationIssue::UnexpectedUnsignedTx);
+        }
+        match self.tx_version {
+            None => report.push(ValidationIssue::MissingGlobalField {
+                field: "transaction version",
+            }),
+            Some(version) if version < 2 => {
+                report.push(ValidationIssue::InvalidTxVersion { version })
+            }
+            Some(_) => {}
+        }
+
+        for (index, output) in self.outputs.iter().enumerate() {
+            if output.amount.is_none() {
+                report.push(ValidationIssue::MissingOutputField {
+                    index,
+                    field: "amount",
+                });
+            }
+            // The script of a silent payment output is only known once the
+            // signers computed it.
+            if output.script.is_none() && !output.is_silent_payment() {
+                report.push(ValidationIssue::MissingOutputField {
+                    index,
+                    field: "script",
+                });
+            }
+        }
+    }
+}
+
+/// Reports the fields a finalized input still carries although the finalizer
+/// must have cleared them.
+fn validate_finalized(input: &Input, index: usize, report: &mut ValidationReport) {
+    if !input.is_finalized() {
+        return;
+    }
+    let fields = [
+        ("partial signatures", !input.partial_sigs.is_empty()),
+        ("sighash type", input.sighash_type.is_some()),
+        ("redeem script", input.redeem_script.is_some()),
+        ("witness script", input.witness_script.is_some()),
+        ("bip32 derivations", !input.bip32_derivation.is_empty()),
+        ("taproot key signature", input.tap_key_sig.is_some()),
+        (
+            "taproot script signatures",
+            !input.tap_script_sigs.is_empty(),
+        ),
+        ("taproot leaf scripts", !input.tap_scripts.is_empty()),
+        ("taproot key origins", !input.tap_key_origins.is_empty()),
+        ("taproot internal key", input.tap_internal_key.is_some()),
+        ("taproot merkle root", input.tap_merkle_root.is_some()),
+    ];
+    for (field, present) in fields {
+        if present {
+            report.push(ValidationIssue::FinalizedWithPartialData { index, field });
+        }
+    }
+}
+
+/// Reports the preimage fields of an input holding a preimage that doesn't
+/// hash to its key.
+fn validate_preimages(input: &Input, index: usize, report: &mut ValidationReport) {
+    let mismatches = [
+        (
+            PreimageHashType::Ripemd160,
+            input
+                .ripemd160_preimages
+                .iter()
+                .any(|(hash, preimage)| ripemd160::Hash::hash(preimage) != *hash),
+        ),
+        (
+            PreimageHashType::Sha256,
+            input
+                .sha256_preimages
+                .iter()
+                .any(|(hash, preimage)| sha256::Hash::hash(preimage) != *hash),
+        ),
+        (
+            PreimageHashType::Hash160,
+            input
+                .hash160_preimages
+                .iter()
+                .any(|(hash, preimage)| hash160::Hash::hash(preimage) != *hash),
+        ),
+        (
+            PreimageHashType::Hash256,
+            input
+                .hash256_preimages
+                .iter()
+                .any(|(hash, preimage)| sha256d::Hash::hash(preimage) != *hash),
+        ),
+    ];
+    for (hash_type, mismatch) in mismatches {
+        if mismatch {
+            report.push(ValidationIssue::PreimageMismatch { index, hash_type });
+        }
+    }
+}
@@ -0,0 +1,185 @@
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use secp256k1::{Message, Secp256k1, XOnlyPublicKey};
+
+use crate::crypto::key::PublicKey;
+use crate::prelude::*;
+use crate::taproot::TapLeafHash;
+
+use super::index::InputIndex;
+use super::poc::Psbt;
+use super::sighash::{PsbtSighashCache, SighashError};
+
+/// The taproot signatures of an input that failed verification.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct InvalidTaprootSigs {
+    /// Whether `tap_key_sig` is present and invalid.
+    pub key_sig: bool,
+    /// Keys of `tap_script_sigs` whose signatures are invalid.
+    pub script_sigs: Vec<(XOnlyPublicKey, TapLeafHash)>,
+}
+
+impl InvalidTaprootSigs {
+    /// Returns `true` if every taproot signature of the input is valid.
+    pub fn is_empty(&self) -> bool {
+        !self.key_sig && self.script_sigs.is_empty()
+    }
+}
+
+/// The signatures of an input that failed verification.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct InvalidSigs {
+    /// Keys of `partial_sigs` whose signatures are invalid.
+    pub partial_sigs: Vec<PublicKey>,
+    /// The invalid taproot signatures.
+    pub taproot: InvalidTaprootSigs,
+}
+
+impl InvalidSigs {
+    /// Returns `true` if every signature of the input is valid.
+    pub fn is_empty(&self) -> bool {
+        self.partial_sigs.is_empty() && self.taproot.is_empty()
+    }
+}
+
+impl Psbt {
+    /// Checks every ECDSA and taproot signature of the input at `index`, see
+    /// [`Psbt::verify_partial_sigs`] and [`Psbt::verify_taproot_sigs`].
+    pub fn verify_input_sigs(&self, index: InputIndex) -> Result<InvalidSigs, SighashError> {
+        self.verify_input_sigs_with(&mut PsbtSighashCache::new(self)?, index.get())
+    }
+
+    /// Checks the signatures of every input, returning the result of
+    /// [`Psbt::verify_input_sigs`] for each one in order.
+    pub fn verify_all_sigs(&self) -> Vec<Result<InvalidSigs, SighashError>> {
+        let mut cache = PsbtSighashCache::new(self);
+        (0..self.inner.inputs.len())
+            .map(|index| match cache {
+                Ok(ref mut cache) => self.verify_input_sigs_with(cache, index),
+                Err(ref err) => Err(err.clone()),
+            })
+            .collect()
+    }
+
+    /// Checks the signatures of every input like [`Psbt::verify_all_sigs`],
+    /// verifying the inputs concurrently on the rayon thread pool.
+    ///
+    /// Sighashes are computed with a cache per job rayon splits the inputs
+    /// into rather than per thread, so a thread may build several caches and
+    /// the precomputed midstates are repeated once per job.
+    #[cfg(feature = "rayon")]
+    pub fn par_verify_all_sigs(&self) -> Vec<Result<InvalidSigs, SighashError>> {
+        (0..self.inner.inputs.len())
+            .into_par_iter()
+            .map_init(
+                || PsbtSighashCache::new(self),
+                |cache, index| match cache {
+                    Ok(cache) => self.verify_input_sigs_with(cache, index),
+                    Err(err) => Err(err.clone()),
+                },
+            )
+            .collect()
+    }
+
+    fn verify_input_sigs_with(
+        &self,
+        cache: &mut PsbtSighashCache,
+        index: usize,
+    ) -> Result<InvalidSigs, SighashError> {
+        let input = self.input_at(index)?;
+        let mut invalid = InvalidSigs {
+            partial_sigs: self.verify_partial_sigs_with(cache, index)?,
+            ..Default::default()
+        };
+        if input.tap_key_sig.is_some() || !input.tap_script_sigs.is_empty() {
+            invalid.taproot = self.verify_taproot_sigs_with(cache, index)?;
+        }
+        Ok(invalid)
+    }
+
+    /// Checks every signature in `partial_sigs` of the input at `index`
+    /// against a freshly computed sighash.
+    ///
+    /// Each signature is checked against the sighash for its own sighash type.
+    /// Returns the public keys whose signatures are invalid, so combiners can
+    /// reject garbage sent by misbehaving cosigners.
+    pub fn verify_partial_sigs(&self, index: InputIndex) -> Result<Vec<PublicKey>, SighashError> {
+        self.verify_partial_sigs_with(&mut PsbtSighashCache::new(self)?, index.get())
+    }
+
+    fn verify_partial_sigs_with(
+        &self,
+        cache: &mut PsbtSighashCache,
+        index: usize,
+    ) -> Result<Vec<PublicKey>, SighashError> {
+        let secp = Secp256k1::verification_only();
+        let input = self.input_at(index)?;
+
+        let mut invalid = Vec::new();
+        for (public_key, signature) in &input.partial_sigs {
+            let message = cache.ecdsa(self, index, signature.hash_ty)?;
+            if secp
+                .verify_ecdsa(&message, &signature.sig, &public_key.inner)
+                .is_err()
+            {
+                invalid.push(*public_key);
+            }
+        }
+        Ok(invalid)
+    }
+
+    /// Checks the taproot signatures of the input at `index`.
+    ///
+    /// `tap_key_sig` is verified against the output key found in the spent
+    /// `script_pubkey`, each `tap_script_sigs` entry against the script path
+    /// sighash of its leaf, so coordinators can reject bad signatures before
+    /// combining.
+    pub fn verify_taproot_sigs(
+        &self,
+        index: InputIndex,
+    ) -> Result<InvalidTaprootSigs, SighashError> {
+        self.verify_taproot_sigs_with(&mut PsbtSighashCache::new(self)?, index.get())
+    }
+
+    fn verify_taproot_sigs_with(
+        &self,
+        cache: &mut PsbtSighashCache,
+        index: usize,
+    ) -> Result<InvalidTaprootSigs, SighashError> {
+        let secp = Secp256k1::verification_only();
+        let input = self.input_at(index)?;
+        let script_pubkey = &cache.spent_txout(self, index)?.script_pubkey;
+        if !script_pubkey.is_p2tr() {
+            let spend_kind = cache.spend_kind(self, index)?;
+            return Err(SighashError::WrongSpendKind { index, spend_kind });
+        }
+
+        let mut invalid = InvalidTaprootSigs::default();
+        if let Some(signature) = input.tap_key_sig {
+            let sighash = cache.taproot(self, index, None, signature.hash_ty)?;
+            let message = Message::from_digest(sighash.to_byte_array());
+            // A witness program that isn't a valid point can't be spent by a
+            // key path signature at all.
+            invalid.key_sig = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]).map_or(
+                true,
+                |output_key| {
+                    secp.verify_schnorr(&signature.sig, &message, &output_key)
+                        .is_err()
+                },
+            );
+        }
+
+        for ((public_key, leaf_hash), signature) in &input.tap_script_sigs {
+            let sighash = cache.taproot(self, index, Some(*leaf_hash), signature.hash_ty)?;
+            let message = Message::from_digest(sighash.to_byte_array());
+            if secp
+                .verify_schnorr(&signature.sig, &message, public_key)
+                .is_err()
+            {
+                invalid.script_sigs.push((*public_key, *leaf_hash));
+            }
+        }
+
+        Ok(invalid)
+    }
+}
@@ -0,0 +1,389 @@
+//! Lazy deserialization of psbts.
+//!
+//! A [`PsbtView`] borrows the serialized psbt and only splits it into its
+//! maps and pairs. Values are decoded when they're asked for, so indexers
+//! reading the txid and amounts of many psbts don't pay for decoding the
+//! previous transactions, scripts and signatures those carry. Use
+//! [`PsbtView::to_psbt`] once the whole psbt is needed.
+
+use crate::blockdata::transaction::Transaction;
+use crate::consensus::encode;
+use crate::hash_types::Txid;
+use crate::hashes::Hash;
+use crate::prelude::*;
+use crate::psbt::{raw, Version};
+use crate::Amount;
+
+use super::input::Input;
+use super::output::Output;
+use super::poc::{PartiallySignedTransactionInner, Psbt};
+use super::serialize::{
+    check_limit, DecodeError, MapLocation, ParseOptions, Reader, PSBT_GLOBAL_FALLBACK_LOCKTIME,
+    PSBT_GLOBAL_INPUT_COUNT, PSBT_GLOBAL_OUTPUT_COUNT, PSBT_GLOBAL_TX_VERSION,
+    PSBT_GLOBAL_UNSIGNED_TX, PSBT_GLOBAL_VERSION, PSBT_IN_NON_WITNESS_UTXO, PSBT_IN_OUTPUT_INDEX,
+    PSBT_IN_PREVIOUS_TXID, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, PSBT_IN_REQUIRED_TIME_LOCKTIME,
+    PSBT_IN_SEQUENCE, PSBT_IN_WITNESS_UTXO, PSBT_MAGIC, PSBT_OUT_AMOUNT, PSBT_OUT_SCRIPT,
+};
+
+/// A key-value pair borrowed from a serialized psbt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawPair<'a> {
+    /// The type of the key.
+    pub type_value: u8,
+    /// The key data following the type.
+    pub key: &'a [u8],
+    /// The undecoded value.
+    pub value: &'a [u8],
+}
+
+impl RawPair<'_> {
+    /// Copies the pair into an owned [`raw::Pair`].
+    pub fn to_pair(&self) -> raw::Pair {
+        raw::Pair {
+            key: raw::Key {
+                type_value: self.type_value,
+                key: self.key.to_vec(),
+            },
+            value: self.value.to_vec(),
+        }
+    }
+}
+
+/// The pairs of one map of a serialized psbt, in serialization order.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MapView<'a> {
+    pairs: Vec<RawPair<'a>>,
+}
+
+impl<'a> MapView<'a> {
+    /// Returns the pairs of the map.
+    pub fn pairs(&self) -> &[RawPair<'a>] {
+        &self.pairs
+    }
+
+    /// Returns the pair of type `type_value` without key data, if the map
+    /// has one.
+    pub fn get(&self, type_value: u8) -> Option<RawPair<'a>> {
+        self.pairs
+            .iter()
+            .find(|pair| pair.type_value == type_value && pair.key.is_empty())
+            .copied()
+    }
+
+    /// Returns the value of the pair of type `type_value` without key data.
+    pub fn value(&self, type_value: u8) -> Option<&'a [u8]> {
+        self.get(type_value).map(|pair| pair.value)
+    }
+}
+
+/// A psbt split into its maps without decoding any value.
+///
+/// Parsing checks the magic bytes, the framing of every map, duplicate keys
+/// and the input and output counts, along with the limits of the
+/// [`ParseOptions`]. Values are only checked once decoded, so a view may hold
+/// pairs [`Psbt::deserialize`] rejects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PsbtView<'a> {
+    bytes: &'a [u8],
+    options: ParseOptions,
+    version: Version,
+    global: MapView<'a>,
+    inputs: Vec<MapView<'a>>,
+    outputs: Vec<MapView<'a>>,
+}
+
+impl<'a> PsbtView<'a> {
+    /// Splits `bytes` into the maps of a psbt, parsing strictly.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        PsbtView::parse_with(bytes, ParseOptions::default())
+    }
+
+    /// Splits `bytes` into the maps of a psbt like [`PsbtView::parse`],
+    /// parsing as strictly as `options` ask for.
+    pub fn parse_with(bytes: &'a [u8], options: ParseOptions) -> Result<Self, DecodeError> {
+        let limits = options.limits;
+        check_limit("size", limits.max_size, bytes.len())?;
+        let mut reader = Reader::new(bytes, options);
+        if reader.read_slice(PSBT_MAGIC.len())? != PSBT_MAGIC {
+            return Err(DecodeError::InvalidMagic);
+        }
+
+        let global = read_map(&mut reader, MapLocation::Global)?;
+        let version = match global.get(PSBT_GLOBAL_VERSION) {
+            Some(pair) => match read_u32(pair)? {
+                0 => Version::PsbtV0,
+                2 => Version::PsbtV2,
+                version if options.strict => {
+                    return Err(DecodeError::InvalidValue {
+                        key: pair.to_pair().key,
+                        reason: format!("unsupported psbt version {}", version),
+                    });
+                }
+                // Lenient parsing keeps an unknown version as an unknown pair
+                _ => Version::PsbtV0,
+            },
+            None => Version::PsbtV0,
+        };
+        let (input_count, output_count) = match global.get(PSBT_GLOBAL_UNSIGNED_TX) {
+            Some(pair) => tx_counts(pair)?,
+            None => (
+                read_count(&global, PSBT_GLOBAL_INPUT_COUNT, "input count")?,
+                read_count(&global, PSBT_GLOBAL_OUTPUT_COUNT, "output count")?,
+            ),
+        };
+        check_limit("inputs", limits.max_inputs, input_count)?;
+        check_limit("outputs", limits.max_outputs, output_count)?;
+
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        while !reader.is_empty() {
+            let index = inputs.len() + outputs.len();
+            if index < input_count {
+                inputs.push(read_map(&mut reader, MapLocation::Input(index))?);
+            } else {
+                let location = MapLocation::Output(index - input_count);
+                outputs.push(read_map(&mut reader, location)?);
+            }
+        }
+        if inputs.len() != input_count || outputs.len() != output_count {
+            return Err(DecodeError::CountMismatch {
+                inputs: input_count,
+                outputs: output_count,
+                maps: inputs.len() + outputs.len(),
+            });
+        }
+
+        Ok(PsbtView {
+            bytes,
+            options,
+            version,
+            global,
+            inputs,
+            outputs,
+        })
+    }
+
+    /// Returns the version of the psbt.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the global map.
+    pub fn global(&self) -> &MapView<'a> {
+        &self.global
+    }
+
+    /// Returns the input maps, in order.
+    pub fn inputs(&self) -> &[MapView<'a>] {
+        &self.inputs
+    }
+
+    /// Returns the output maps, in order.
+    pub fn outputs(&self) -> &[MapView<'a>] {
+        &self.outputs
+    }
+
+    /// Decodes the unsigned transaction of a PsbtV0, or builds the one a
+    /// PsbtV2 describes from the fields of its maps that make it up.
+    pub fn unsigned_tx(&self) -> Result<Transaction, DecodeError> {
+        if let Some(pair) = self.global.get(PSBT_GLOBAL_UNSIGNED_TX) {
+            return decode_value(pair);
+        }
+
+        let mut inner = PartiallySignedTransactionInner {
+            unsigned_tx: None,
+            version: self.version,
+            xpub: Default::default(),
+            proprietary: Default::default(),
+            unknown: Default::default(),
+            inputs: Vec::with_capacity(self.inputs.len()),
+            outputs: Vec::with_capacity(self.outputs.len()),
+            tx_version: None,
+            fallback_locktime: None,
+            tx_modifiable: None,
+            sp_ecdh_shares: Default::default(),
+            sp_dleq_proofs: Default::default(),
+        };
+        for pair in self.global.pairs() {
+            if let PSBT_GLOBAL_TX_VERSION | PSBT_GLOBAL_FALLBACK_LOCKTIME = pair.type_value {
+                inner.insert_pair(pair.to_pair(), self.options)?;
+            }
+        }
+        for map in &self.inputs {
+            let mut input = Input::default();
+            for pair in map.pairs() {
+                if let PSBT_IN_PREVIOUS_TXID
+                | PSBT_IN_OUTPUT_INDEX
+                | PSBT_IN_SEQUENCE
+                | PSBT_IN_REQUIRED_TIME_LOCKTIME
+                | PSBT_IN_REQUIRED_HEIGHT_LOCKTIME = pair.type_value
+                {
+                    input.insert_pair(pair.to_pair(), self.options)?;
+                }
+            }
+            inner.inputs.push(input);
+        }
+        for map in &self.outputs {
+            let mut output = Output::default();
+            for pair in map.pairs() {
+                if let PSBT_OUT_AMOUNT | PSBT_OUT_SCRIPT = pair.type_value {
+                    output.insert_pair(pair.to_pair())?;
+                }
+            }
+            inner.outputs.push(output);
+        }
+
+        let psbt = Psbt {
+            inner,
+            fee_policy: None,
+            network: None,
+        };
+        psbt.unsigned_tx().map_err(DecodeError::UnsignedTx)
+    }
+
+    /// Returns the txid of the unsigned transaction.
+    ///
+    /// The unsigned transaction of a PsbtV0 is hashed without being decoded.
+    pub fn txid(&self) -> Result<Txid, DecodeError> {
+        match self.global.value(PSBT_GLOBAL_UNSIGNED_TX) {
+            Some(tx) => Ok(Txid::hash(tx)),
+            None => Ok(self.unsigned_tx()?.txid()),
+        }
+    }
+
+    /// Returns the amount of the output at `index`, or `None` if there's no
+    /// such output or a PsbtV2 output lacks its amount.
+    pub fn output_amount(&self, index: usize) -> Result<Option<Amount>, DecodeError> {
+        if self.global.get(PSBT_GLOBAL_UNSIGNED_TX).is_some() {
+            let tx = self.unsigned_tx()?;
+            return Ok(tx.output.get(index).map(|output| output.value));
+        }
+        match self
+            .outputs
+            .get(index)
+            .and_then(|map| map.get(PSBT_OUT_AMOUNT))
+        {
+            Some(pair) => Ok(Some(Amount::from_sat(read_u64(pair)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the amount of the output spent by the input at `index`, or
+    /// `None` if there's no such input or it carries no utxo.
+    ///
+    /// The amount of a `witness_utxo` is read without decoding its script,
+    /// a `non_witness_utxo` is only decoded if there's no `witness_utxo`.
+    pub fn input_amount(&self, index: usize) -> Result<Option<Amount>, DecodeError> {
+        let map = match self.inputs.get(index) {
+            Some(map) => map,
+            None => return Ok(None),
+        };
+        if let Some(pair) = map.get(PSBT_IN_WITNESS_UTXO) {
+            // A serialized txout starts with its amount
+            let amount = RawPair {
+                value: &pair.value[..pair.value.len().min(8)],
+                ..pair
+            };
+            return Ok(Some(Amount::from_sat(read_u64(amount)?)));
+        }
+        let pair = match map.get(PSBT_IN_NON_WITNESS_UTXO) {
+            Some(pair) => pair,
+            None => return Ok(None),
+        };
+        let vout = match map.get(PSBT_IN_OUTPUT_INDEX) {
+            Some(pair) => read_u32(pair)?,
+            None => match self.unsigned_tx()?.input.get(index) {
+                Some(txin) => txin.previous_output.vout,
+                None => return Ok(None),
+            },
+        };
+        let tx: Transaction = decode_value(pair)?;
+        Ok(tx.output.get(vout as usize).map(|output| output.value))
+    }
+
+    /// Decodes and validates the whole psbt, like
+    /// [`Psbt::deserialize_with`] with the options the view was parsed with.
+    pub fn to_psbt(&self) -> Result<Psbt, DecodeError> {
+        Psbt::deserialize_with(self.bytes, self.options)
+    }
+}
+
+/// Reads a map off `reader`, keeping its pairs borrowed from the psbt.
+fn read_map<'a>(
+    reader: &mut Reader<&'a [u8]>,
+    location: MapLocation,
+) -> Result<MapView<'a>, DecodeError> {
+    let pairs = reader
+        .read_borrowed_map(location)?
+        .into_iter()
+        .map(|(key, value)| RawPair {
+            type_value: key[0],
+            key: &key[1..],
+            value,
+        })
+        .collect();
+    Ok(MapView { pairs })
+}
+
+/// Reads the input and output counts of the serialized unsigned transaction
+/// of a PsbtV0, skipping over its inputs without decoding them.
+fn tx_counts(pair: RawPair) -> Result<(usize, usize), DecodeError> {
+    let truncated = || DecodeError::InvalidValue {
+        key: pair.to_pair().key,
+        reason: String::from("truncated transaction"),
+    };
+    // Consensus encoding always uses minimal compact sizes
+    let mut tx = Reader::new(pair.value, ParseOptions::strict());
+    tx.read_slice(4).map_err(|_| truncated())?;
+    let input_count = tx.read_size().map_err(|_| truncated())?;
+    for _ in 0..input_count {
+        // The previous outpoint, the scriptSig and the sequence
+        tx.read_slice(36).map_err(|_| truncated())?;
+        let script_len = tx.read_size().map_err(|_| truncated())?;
+        let script_len = usize::try_from(script_len).map_err(|_| truncated())?;
+        tx.read_slice(script_len).map_err(|_| truncated())?;
+        tx.read_slice(4).map_err(|_| truncated())?;
+    }
+    let output_count = tx.read_size().map_err(|_| truncated())?;
+    Ok((input_count as usize, output_count as usize))
+}
+
+/// Reads the compact size count of type `type_value` from the global map of
+/// a PsbtV2.
+fn read_count(global: &MapView, type_value: u8, field: &'static str) -> Result<usize, DecodeError> {
+    let pair = global
+        .get(type_value)
+        .ok_or(DecodeError::MissingField(field))?;
+    let mut value = Reader::new(pair.value, ParseOptions::strict());
+    match value.read_size() {
+        Ok(count) if value.is_empty() => Ok(count as usize),
+        _ => Err(DecodeError::InvalidValue {
+            key: pair.to_pair().key,
+            reason: String::from("invalid compact size"),
+        }),
+    }
+}
+
+fn read_u32(pair: RawPair) -> Result<u32, DecodeError> {
+    Ok(u32::from_le_bytes(read_array(pair)?))
+}
+
+fn read_u64(pair: RawPair) -> Result<u64, DecodeError> {
+    Ok(u64::from_le_bytes(read_array(pair)?))
+}
+
+fn read_array<const N: usize>(pair: RawPair) -> Result<[u8; N], DecodeError> {
+    pair.value
+        .try_into()
+        .map_err(|_| DecodeError::InvalidValue {
+            key: pair.to_pair().key,
+            reason: format!("expected {} bytes, got {}", N, pair.value.len()),
+        })
+}
+
+fn decode_value<T: encode::Decodable>(pair: RawPair) -> Result<T, DecodeError> {
+    encode::deserialize(pair.value).map_err(|err| DecodeError::InvalidValue {
+        key: pair.to_pair().key,
+        reason: err.to_string(),
+    })
+}
@@ -0,0 +1,234 @@
+//! BIP-388 wallet policies carried in global proprietary keys, so
+//! coordinators can hand hardware signers that register policies, such as
+//! Ledger devices, the policy and its registration along with the psbt.
+
+use core::fmt;
+
+use crate::consensus::encode::{self, VarInt};
+use crate::prelude::*;
+use crate::psbt::raw;
+
+use super::poc::Psbt;
+
+/// The prefix of the proprietary keys holding the wallet policy.
+pub const WALLET_POLICY_PREFIX: &[u8] = b"BIP388";
+/// The subtype of the proprietary key holding the serialized policy.
+pub const WALLET_POLICY_SUBTYPE: u8 = 0x00;
+/// The subtype of the proprietary key holding the registration HMAC of the
+/// policy.
+pub const WALLET_POLICY_HMAC_SUBTYPE: u8 = 0x01;
+
+/// The maximum length of the name of a policy.
+const MAX_NAME_LEN: usize = 64;
+
+/// Errors encountered while building or reading a wallet policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletPolicyError {
+    /// The name is longer than 64 bytes or not printable ASCII.
+    InvalidName,
+    /// The template refers to the key at this index, which doesn't exist.
+    MissingKey { index: usize },
+    /// The key at this index isn't referred to by the template.
+    UnusedKey { index: usize },
+    /// The stored policy or HMAC couldn't be decoded.
+    Malformed(String),
+}
+
+impl fmt::Display for WalletPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletPolicyError::InvalidName => {
+                write!(f, "policy name isn't up to 64 printable ASCII characters")
+            }
+            WalletPolicyError::MissingKey { index } => {
+                write!(f, "template refers to missing key @{}", index)
+            }
+            WalletPolicyError::UnusedKey { index } => {
+                write!(f, "key @{} isn't used by the template", index)
+            }
+            WalletPolicyError::Malformed(err) => write!(f, "malformed wallet policy: {}", err),
+        }
+    }
+}
+
+/// A BIP-388 wallet policy: a descriptor template whose keys are
+/// placeholders `@0`, `@1`, ... for the keys of the policy.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WalletPolicy {
+    /// The name the policy is registered under.
+    pub name: String,
+    /// The descriptor template, such as `wsh(sortedmulti(2,@0/**,@1/**))`.
+    pub descriptor_template: String,
+    /// The key information of the placeholders, an extended public key
+    /// with its origin like `[f5acc2fd/48'/1'/0'/2']tpub...`.
+    pub keys: Vec<String>,
+}
+
+impl WalletPolicy {
+    /// Creates a policy, checking its name and that the template refers to
+    /// each key and only to them.
+    pub fn new(
+        name: String,
+        descriptor_template: String,
+        keys: Vec<String>,
+    ) -> Result<Self, WalletPolicyError> {
+        let policy = WalletPolicy {
+            name,
+            descriptor_template,
+            keys,
+        };
+        policy.check()?;
+        Ok(policy)
+    }
+
+    /// Expands the template into a descriptor, replacing each placeholder by
+    /// its key and `/**` by `/<0;1>/*`.
+    pub fn to_descriptor(&self) -> String {
+        let mut descriptor = String::with_capacity(self.descriptor_template.len());
+        let mut rest = self.descriptor_template.as_str();
+        while let Some(position) = rest.find('@') {
+            descriptor.push_str(&rest[..position]);
+            let (index, after) = split_placeholder(&rest[position + 1..]);
+            match index.and_then(|index| self.keys.get(index)) {
+                Some(key) => descriptor.push_str(key),
+                None => descriptor.push_str(&rest[position..rest.len() - after.len()]),
+            }
+            rest = after;
+        }
+        descriptor.push_str(rest);
+        descriptor.replace("/**", "/<0;1>/*")
+    }
+
+    fn check(&self) -> Result<(), WalletPolicyError> {
+        if self.name.len() > MAX_NAME_LEN
+            || !self.name.bytes().all(|byte| (0x20..0x7f).contains(&byte))
+        {
+            return Err(WalletPolicyError::InvalidName);
+        }
+        let mut used = vec![false; self.keys.len()];
+        let mut rest = self.descriptor_template.as_str();
+        while let Some(position) = rest.find('@') {
+            let (index, after) = split_placeholder(&rest[position + 1..]);
+            let index = index.ok_or_else(|| {
+                WalletPolicyError::Malformed(String::from("placeholder without index"))
+            })?;
+            *used
+                .get_mut(index)
+                .ok_or(WalletPolicyError::MissingKey { index })? = true;
+            rest = after;
+        }
+        match used.iter().position(|used| !used) {
+            Some(index) => Err(WalletPolicyError::UnusedKey { index }),
+            None => Ok(()),
+        }
+    }
+
+    /// Serializes the name, template and keys as length-prefixed strings,
+    /// the keys preceded by their count.
+    fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_string(&mut bytes, &self.name);
+        write_string(&mut bytes, &self.descriptor_template);
+        bytes.extend(encode::serialize(&VarInt(self.keys.len() as u64)));
+        for key in &self.keys {
+            write_string(&mut bytes, key);
+        }
+        bytes
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Self, WalletPolicyError> {
+        let mut reader = bytes;
+        let name = read_string(&mut reader)?;
+        let descriptor_template = read_string(&mut reader)?;
+        let (VarInt(count), read) = encode::deserialize_partial(reader)
+            .map_err(|err| WalletPolicyError::Malformed(err.to_string()))?;
+        reader = &reader[read..];
+        let mut keys = Vec::new();
+        for _ in 0..count {
+            keys.push(read_string(&mut reader)?);
+        }
+        if !reader.is_empty() {
+            return Err(WalletPolicyError::Malformed(String::from("trailing data")));
+        }
+        WalletPolicy::new(name, descriptor_template, keys)
+    }
+}
+
+impl Psbt {
+    /// Returns the wallet policy stored in the global proprietary map, if
+    /// any.
+    pub fn wallet_policy(&self) -> Result<Option<WalletPolicy>, WalletPolicyError> {
+        self.inner
+            .proprietary
+            .get(&wallet_policy_key(WALLET_POLICY_SUBTYPE))
+            .map(|bytes| WalletPolicy::deserialize(bytes))
+            .transpose()
+    }
+
+    /// Returns the HMAC the signer returned when registering the stored
+    /// wallet policy, if any.
+    pub fn wallet_policy_hmac(&self) -> Result<Option<[u8; 32]>, WalletPolicyError> {
+        self.inner
+            .proprietary
+            .get(&wallet_policy_key(WALLET_POLICY_HMAC_SUBTYPE))
+            .map(|hmac| {
+                hmac.as_slice()
+                    .try_into()
+                    .map_err(|_| WalletPolicyError::Malformed(String::from("HMAC isn't 32 bytes")))
+            })
+            .transpose()
+    }
+
+    /// Stores `policy` and its registration `hmac` in the global proprietary
+    /// map, replacing any previous policy. A policy that isn't registered yet
+    /// is stored without an HMAC.
+    pub fn set_wallet_policy(&mut self, policy: &WalletPolicy, hmac: Option<[u8; 32]>) {
+        let proprietary = &mut self.inner.proprietary;
+        proprietary.insert(wallet_policy_key(WALLET_POLICY_SUBTYPE), policy.serialize());
+        match hmac {
+            Some(hmac) => {
+                proprietary.insert(wallet_policy_key(WALLET_POLICY_HMAC_SUBTYPE), hmac.to_vec());
+            }
+            None => {
+                proprietary.remove(&wallet_policy_key(WALLET_POLICY_HMAC_SUBTYPE));
+            }
+        }
+    }
+
+    /// Removes the wallet policy and its HMAC from the global proprietary
+    /// map, for example before handing the psbt to a third party.
+    pub fn remove_wallet_policy(&mut self) {
+        self.inner
+            .proprietary
+            .remove(&wallet_policy_key(WALLET_POLICY_SUBTYPE));
+        self.inner
+            .proprietary
+            .remove(&wallet_policy_key(WALLET_POLICY_HMAC_SUBTYPE));
+    }
+}
+
+fn wallet_policy_key(subtype: u8) -> raw::ProprietaryKey {
+    raw::ProprietaryKey {
+        prefix: WALLET_POLICY_PREFIX.to_vec(),
+        subtype,
+        key: vec![],
+    }
+}
+
+/// Splits the decimal index off the front of what follows an `@`.
+fn split_placeholder(s: &str) -> (Option<usize>, &str) {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    (s[..digits].parse().ok(), &s[digits..])
+}
+
+fn write_string(bytes: &mut Vec<u8>, s: &str) {
+    bytes.extend(encode::serialize(&VarInt(s.len() as u64)));
+    bytes.extend(s.as_bytes());
+}
+
+fn read_string(reader: &mut &[u8]) -> Result<String, WalletPolicyError> {
+    let (bytes, read): (Vec<u8>, usize) = encode::deserialize_partial(reader)
+        .map_err(|err| WalletPolicyError::Malformed(err.to_string()))?;
+    *reader = &reader[read..];
+    String::from_utf8(bytes).map_err(|err| WalletPolicyError::Malformed(err.to_string()))
+}
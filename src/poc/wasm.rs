@@ -0,0 +1,88 @@
+//! `wasm-bindgen` bindings for browser based wallets and coordinators.
+//!
+//! Psbts cross the boundary as base64 strings, errors as [`PsbtError`]
+//! objects telling JavaScript what failed and why.
+
+use wasm_bindgen::prelude::*;
+
+use crate::prelude::*;
+
+use super::encoding::PsbtParseError;
+use super::poc::Psbt;
+
+/// An error thrown to JavaScript.
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PsbtError {
+    kind: &'static str,
+    message: String,
+}
+
+#[wasm_bindgen]
+impl PsbtError {
+    /// What failed: `"base64"`, `"decode"`, `"combine"` or `"finalize"`.
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> String {
+        self.kind.to_string()
+    }
+
+    /// A description of the error.
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+}
+
+impl PsbtError {
+    fn new<E: ToString>(kind: &'static str, err: E) -> JsValue {
+        PsbtError {
+            kind,
+            message: err.to_string(),
+        }
+        .into()
+    }
+
+    fn parse(err: PsbtParseError) -> JsValue {
+        match err {
+            PsbtParseError::Base64Encoding(err) => PsbtError::new("base64", err),
+            PsbtParseError::PsbtEncoding(err) => PsbtError::new("decode", err),
+        }
+    }
+}
+
+/// A validated psbt, exposed to JavaScript as `Psbt`.
+#[wasm_bindgen(js_name = Psbt)]
+pub struct WasmPsbt {
+    psbt: Psbt,
+}
+
+#[wasm_bindgen(js_class = Psbt)]
+impl WasmPsbt {
+    /// Parses and validates a base64 encoded psbt.
+    #[wasm_bindgen(js_name = fromBase64)]
+    pub fn from_base64(s: &str) -> Result<WasmPsbt, JsValue> {
+        let psbt = Psbt::from_base64(s).map_err(PsbtError::parse)?;
+        Ok(WasmPsbt { psbt })
+    }
+
+    /// Serializes the psbt as base64.
+    #[wasm_bindgen(js_name = toBase64)]
+    pub fn to_base64(&self) -> String {
+        self.psbt.to_base64()
+    }
+
+    /// Merges the base64 encoded psbt `other` into this one.
+    pub fn combine(&mut self, other: &str) -> Result<(), JsValue> {
+        let other = Psbt::from_base64(other).map_err(PsbtError::parse)?;
+        self.psbt
+            .combine(other)
+            .map_err(|err| PsbtError::new("combine", err))
+    }
+
+    /// Finalizes every input of the psbt.
+    pub fn finalize(&mut self) -> Result<(), JsValue> {
+        self.psbt
+            .finalize()
+            .map_err(|err| PsbtError::new("finalize", err))
+    }
+}
@@ -0,0 +1,235 @@
+//! Estimation of the weight of the finalized transaction before any
+//! signature exists, from the script type of every input.
+
+use core::iter;
+
+use crate::blockdata::opcodes::all::{OP_CHECKSIG, OP_CHECKSIGADD, OP_CHECKSIGVERIFY};
+use crate::blockdata::script::{Instruction, Script};
+use crate::blockdata::transaction::OutPoint;
+use crate::consensus::encode::VarInt;
+use crate::prelude::*;
+use crate::taproot::TapLeafHash;
+use crate::Weight;
+
+use super::fee::FeeError;
+use super::finalizer::parse_multisig;
+use super::input::Input;
+use super::poc::Psbt;
+
+/// A 72 byte DER signature including its sighash byte.
+const ECDSA_SIG: usize = 72;
+/// A compressed public key.
+const COMPRESSED_KEY: usize = 33;
+/// A 64 byte schnorr signature plus an explicit sighash byte.
+const SCHNORR_SIG: usize = 65;
+
+impl Psbt {
+    /// Predicts the weight of the finalized transaction.
+    ///
+    /// Finalized inputs contribute their actual scriptSig and witness. The
+    /// others contribute the satisfaction of their script type: single key
+    /// scripts, `OP_CHECKMULTISIG` thresholds found in their redeem or
+    /// witness script, and taproot key or script path spends, see
+    /// [`Input::satisfaction_size`].
+    pub fn estimate_weight(&self) -> Result<Weight, FeeError> {
+        let tx = self.unsigned_tx().map_err(FeeError::UnsignedTx)?;
+
+        let mut weight = tx.weight().to_wu();
+        let mut witness_sizes = Vec::with_capacity(tx.input.len());
+        for (index, (txin, input)) in tx.input.iter().zip(&self.inner.inputs).enumerate() {
+            let (script_sig_size, witness_size) = if input.is_finalized() {
+                (
+                    input
+                        .final_script_sig
+                        .as_ref()
+                        .map_or(0, |script| script.len()),
+                    input
+                        .final_script_witness
+                        .as_ref()
+                        .map_or(0, |witness| witness.size()),
+                )
+            } else {
+                let utxo = input
+                    .spent_utxo(&txin.previous_output)
+                    .ok_or(FeeError::MissingUtxo { index })?;
+                input
+                    .satisfaction_size(&utxo.script_pubkey)
+                    .ok_or(FeeError::UnknownSatisfactionSize { index })?
+            };
+            // The empty scriptSig already counts one length byte
+            let script_sig_len = VarInt(script_sig_size as u64).size() - 1;
+            weight += (script_sig_size + script_sig_len) as u64 * 4;
+            witness_sizes.push(witness_size as u64);
+        }
+
+        if witness_sizes.iter().any(|size| *size > 0) {
+            // Segwit marker and flag, plus the empty witness count of every
+            // non-witness input.
+            weight += 2;
+            weight += witness_sizes.iter().map(|size| (*size).max(1)).sum::<u64>();
+        }
+
+        Ok(Weight::from_wu(weight))
+    }
+}
+
+impl Input {
+    /// Returns the worst-case weight of the scriptSig and witness satisfying
+    /// the input, including their length prefixes, for coin selectors
+    /// weighing candidate inputs.
+    ///
+    /// The spent script is taken from `witness_utxo`, or from the output of
+    /// `non_witness_utxo` that `previous_output`, the outpoint the input
+    /// spends, points to. Unlike [`Input::satisfaction_size`], taproot inputs
+    /// are weighed by their largest spend path whatever signatures they
+    /// carry. Returns `None` if the spent script or its type is unknown.
+    pub fn max_satisfaction_weight(&self, previous_output: &OutPoint) -> Option<Weight> {
+        let script_pubkey = match self.witness_utxo {
+            Some(ref utxo) => &utxo.script_pubkey,
+            None => {
+                let tx = self.non_witness_utxo.as_ref()?;
+                &tx.output.get(previous_output.vout as usize)?.script_pubkey
+            }
+        };
+        let (script_sig_size, witness_size) = if script_pubkey.is_p2tr() {
+            (0, self.taproot_witness_size(true)?)
+        } else {
+            self.satisfaction_size(script_pubkey)?
+        };
+        let script_sig_size = VarInt(script_sig_size as u64).size() + script_sig_size;
+        Some(Weight::from_wu((script_sig_size * 4 + witness_size) as u64))
+    }
+
+    /// Returns the `(script_sig, witness)` sizes of the satisfaction of the
+    /// input spending `script_pubkey`, `None` if the script type is unknown.
+    ///
+    /// Signatures are assumed to take their maximal size. Taproot inputs are
+    /// spent by key path when they carry a key signature or no leaf scripts,
+    /// else by the leaves they carry signatures for, else by their largest
+    /// path.
+    pub fn satisfaction_size(&self, script_pubkey: &Script) -> Option<(usize, usize)> {
+        if script_pubkey.is_p2pkh() {
+            Some((script_sig_size(&[ECDSA_SIG, COMPRESSED_KEY]), 0))
+        } else if script_pubkey.is_p2wpkh() {
+            Some((0, witness_size(&[ECDSA_SIG, COMPRESSED_KEY])))
+        } else if script_pubkey.is_p2wsh() {
+            Some((0, self.p2wsh_witness_size()?))
+        } else if script_pubkey.is_p2tr() {
+            Some((0, self.taproot_witness_size(false)?))
+        } else if script_pubkey.is_p2sh() {
+            let redeem_script = self.redeem_script.as_ref()?;
+            if redeem_script.is_p2wpkh() {
+                let witness = witness_size(&[ECDSA_SIG, COMPRESSED_KEY]);
+                Some((script_sig_size(&[redeem_script.len()]), witness))
+            } else if redeem_script.is_p2wsh() {
+                let witness = self.p2wsh_witness_size()?;
+                Some((script_sig_size(&[redeem_script.len()]), witness))
+            } else {
+                let mut stack = script_stack(redeem_script)?;
+                stack.push(redeem_script.len());
+                Some((script_sig_size(&stack), 0))
+            }
+        } else {
+            Some((script_sig_size(&script_stack(script_pubkey)?), 0))
+        }
+    }
+
+    /// The witness size of the `witness_script` satisfaction and the script.
+    fn p2wsh_witness_size(&self) -> Option<usize> {
+        let witness_script = self.witness_script.as_ref()?;
+        let mut stack = script_stack(witness_script)?;
+        stack.push(witness_script.len());
+        Some(witness_size(&stack))
+    }
+
+    /// The witness size of the taproot path the input is expected to be
+    /// spent by, or of its largest path if `worst_case` is set.
+    fn taproot_witness_size(&self, worst_case: bool) -> Option<usize> {
+        let annex = self
+            .tap_annex
+            .as_ref()
+            .map_or(0, |annex| VarInt(annex.len() as u64).size() + annex.len());
+        let key_path = witness_size(&[SCHNORR_SIG]) + annex;
+        if self.tap_scripts.is_empty() || (self.tap_key_sig.is_some() && !worst_case) {
+            return Some(key_path);
+        }
+
+        let leaves: Vec<(TapLeafHash, usize)> = self
+            .tap_scripts
+            .iter()
+            .map(|(control_block, (script, leaf_version))| {
+                let mut stack = vec![SCHNORR_SIG; signature_checks(script)];
+                stack.push(script.len());
+                stack.push(control_block.serialize().len());
+                let leaf_hash = TapLeafHash::from_script(script, *leaf_version);
+                (leaf_hash, witness_size(&stack) + annex)
+            })
+            .collect();
+        let signed = leaves
+            .iter()
+            .filter(|_| !worst_case)
+            .filter(|(leaf_hash, _)| {
+                self.tap_script_sigs
+                    .keys()
+                    .any(|(_, signed_leaf)| signed_leaf == leaf_hash)
+            })
+            .map(|(_, size)| *size)
+            .max();
+        let key_path = self.tap_internal_key.map(|_| key_path);
+        signed.or_else(|| leaves.iter().map(|(_, size)| *size).chain(key_path).max())
+    }
+}
+
+/// The stack elements satisfying `script`, for bare `OP_CHECKMULTISIG` and
+/// pay-to-pubkey scripts.
+fn script_stack(script: &Script) -> Option<Vec<usize>> {
+    if let Some((threshold, _)) = parse_multisig(script) {
+        // The dummy element consumed by OP_CHECKMULTISIG
+        return Some(
+            iter::once(0)
+                .chain(iter::repeat(ECDSA_SIG).take(threshold))
+                .collect(),
+        );
+    }
+    if script.is_p2pk() {
+        return Some(vec![ECDSA_SIG]);
+    }
+    None
+}
+
+/// The number of signatures a tap leaf script checks.
+fn signature_checks(script: &Script) -> usize {
+    script
+        .instructions()
+        .filter(|instruction| {
+            matches!(
+                instruction,
+                Ok(Instruction::Op(op))
+                    if *op == OP_CHECKSIG || *op == OP_CHECKSIGVERIFY || *op == OP_CHECKSIGADD
+            )
+        })
+        .count()
+}
+
+/// The size of a scriptSig pushing elements of the given sizes, an empty
+/// element being `OP_0`.
+fn script_sig_size(stack: &[usize]) -> usize {
+    stack
+        .iter()
+        .map(|&len| match len {
+            0 => 1,
+            1..=75 => 1 + len,
+            76..=0xff => 2 + len,
+            _ => 3 + len,
+        })
+        .sum()
+}
+
+/// The size of a witness of elements of the given sizes.
+fn witness_size(stack: &[usize]) -> usize {
+    let elements: usize = stack
+        .iter()
+        .map(|&len| VarInt(len as u64).size() + len)
+        .sum();
+    VarInt(stack.len() as u64).size() + elements
+}